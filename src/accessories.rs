@@ -0,0 +1,192 @@
+use crate::api::Bridge;
+use crate::config::Config;
+use crate::targets;
+use crate::values::parse_brightness;
+use eyre::{eyre, Result};
+use serde_json::{json, Map, Value};
+
+/// Sensor types of physical switches, like dimmer switches, smart buttons, and tap dials.
+const SWITCH_TYPES: [&str; 3] = ["ZLLSwitch", "ZGPSwitch", "ZLLRelativeRotary"];
+
+/// Lists the switches connected to the bridge, with the rules and resourcelinks using them.
+pub fn list(bridge: &Bridge) -> Result<()> {
+    let sensors: Map<String, Value> = bridge.get("sensors")?;
+    let rules: Map<String, Value> = bridge.get("rules")?;
+    let links: Map<String, Value> = bridge.get("resourcelinks")?;
+
+    let mut switches: Vec<(&String, &Value)> = sensors
+        .iter()
+        .filter(|(_, sensor)| {
+            sensor["type"]
+                .as_str()
+                .is_some_and(|kind| SWITCH_TYPES.contains(&kind))
+        })
+        .collect();
+    switches.sort_by_key(|(id, _)| id.parse::<usize>().unwrap_or(usize::MAX));
+
+    for (id, sensor) in switches {
+        println!(
+            "{:>2}: {} ({}, {})",
+            id,
+            sensor["name"].as_str().unwrap_or_default(),
+            sensor["modelid"].as_str().unwrap_or_default(),
+            sensor["type"].as_str().unwrap_or_default()
+        );
+        let address = format!("/sensors/{}", id);
+        for (rule_id, rule) in &rules {
+            let conditions: Vec<String> = rule["conditions"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter(|condition| refers_to(&condition["address"], &address))
+                .map(describe_condition)
+                .collect();
+            if conditions.is_empty() {
+                continue;
+            }
+            let actions: Vec<String> = rule["actions"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(describe_action)
+                .collect();
+            println!(
+                "    rule {} {:?}: {} -> {}",
+                rule_id,
+                rule["name"].as_str().unwrap_or_default(),
+                conditions.join(" and "),
+                actions.join(", ")
+            );
+        }
+        for (link_id, link) in &links {
+            let linked = link["links"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .any(|linked| refers_to(linked, &address));
+            if linked {
+                println!(
+                    "    resourcelink {} {:?}",
+                    link_id,
+                    link["name"].as_str().unwrap_or_default()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns whether the address is the resource or one of its attributes.
+fn refers_to(address: &Value, resource: &str) -> bool {
+    address.as_str().is_some_and(|address| {
+        address == resource || address.starts_with(&format!("{}/", resource))
+    })
+}
+
+/// Describes a condition like `buttonevent eq 1002`.
+fn describe_condition(condition: &Value) -> String {
+    let address = condition["address"].as_str().unwrap_or_default();
+    let attribute = address.rsplit('/').next().unwrap_or(address);
+    let operator = condition["operator"].as_str().unwrap_or_default();
+    match condition.get("value").and_then(Value::as_str) {
+        Some(value) => format!("{} {} {}", attribute, operator, value),
+        None => format!("{} {}", attribute, operator),
+    }
+}
+
+/// Describes an action like `PUT /groups/1/action {"on":true}`.
+fn describe_action(action: &Value) -> String {
+    format!(
+        "{} {} {}",
+        action["method"].as_str().unwrap_or_default(),
+        action["address"].as_str().unwrap_or_default(),
+        action["body"]
+    )
+}
+
+/// Finds a switch given by sensor ID or name, returning its ID and name.
+fn resolve_switch(bridge: &Bridge, name: &str) -> Result<(String, String)> {
+    let sensors: Map<String, Value> = bridge.get("sensors")?;
+    sensors
+        .into_iter()
+        .filter(|(_, sensor)| {
+            sensor["type"]
+                .as_str()
+                .is_some_and(|kind| SWITCH_TYPES.contains(&kind))
+        })
+        .map(|(id, sensor)| (id, sensor["name"].as_str().unwrap_or_default().to_owned()))
+        .find(|(id, sensor_name)| id == name || sensor_name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| eyre!("No switch named {:?}", name))
+}
+
+/// Turns an action like `group:kitchen brighter 20%` into the address and body of a request to
+/// the bridge.
+fn parse_action(bridge: &Bridge, config: &Config, action: &str) -> Result<(String, Value)> {
+    let words: Vec<&str> = action.split_whitespace().collect();
+    let (spec, verb, args) = match &words[..] {
+        [spec, verb, args @ ..] => (*spec, *verb, args),
+        _ => {
+            return Err(eyre!(
+                "Invalid action {:?}, expected a target and what to do, like \"group:kitchen on\"",
+                action
+            ))
+        }
+    };
+    let amount = || match args {
+        [amount] => parse_brightness(amount).map_err(|err| eyre!(err)),
+        _ => Err(eyre!(
+            "{:?} needs a brightness, like \"{} 20%\"",
+            verb,
+            verb
+        )),
+    };
+    let body = match verb {
+        "on" => json!({"on": true}),
+        "off" => json!({"on": false}),
+        "dim" => json!({"on": true, "bri": amount()?}),
+        "brighter" => json!({"bri_inc": amount()?}),
+        "darker" => json!({"bri_inc": -(amount()? as i16)}),
+        other => {
+            return Err(eyre!(
+                "Unknown action {:?}, expected on, off, dim, brighter, or darker",
+                other
+            ))
+        }
+    };
+    let target = targets::resolve(spec, bridge, config)?;
+    Ok((target.action_address()?, body))
+}
+
+/// Creates a rule on the bridge running the action when the button is released, or repeatedly
+/// while held with `hold`. Returns the ID of the rule.
+pub fn bind(
+    bridge: &Bridge,
+    config: &Config,
+    switch: &str,
+    button: u8,
+    hold: bool,
+    action: &str,
+) -> Result<String> {
+    let (id, name) = resolve_switch(bridge, switch)?;
+    let (address, body) = parse_action(bridge, config, action)?;
+    // Button events are the button number followed by 001 for hold or 002 for short release.
+    let event = format!("{}{}", button, if hold { "001" } else { "002" });
+    let rule = json!({
+        // The bridge allows at most 32 characters.
+        "name": format!("{} button {}", name, button).chars().take(32).collect::<String>(),
+        "conditions": [
+            {
+                "address": format!("/sensors/{}/state/buttonevent", id),
+                "operator": "eq",
+                "value": event,
+            },
+            {"address": format!("/sensors/{}/state/lastupdated", id), "operator": "dx"},
+        ],
+        "actions": [{"address": address, "method": "PUT", "body": body}],
+    });
+    let response: Value = bridge.post("rules", &rule)?;
+    Ok(response[0]["success"]["id"]
+        .as_str()
+        .unwrap_or_default()
+        .to_owned())
+}
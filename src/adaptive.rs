@@ -0,0 +1,148 @@
+use crate::api::Bridge;
+use crate::config::Config;
+use crate::queue::{with_priority, Priority};
+use crate::sensors;
+use crate::target::Target;
+use crate::targets;
+use eyre::{eyre, Result};
+use hueclient::CommandLight;
+use std::thread;
+use std::time::Duration;
+
+/// How far towards the brightness that would give the wanted level of light each adjustment goes,
+/// on a log scale, so that the lights settle without overshooting.
+const GAIN: f64 = 0.5;
+
+/// Time each adjustment fades over, in deciseconds.
+const TRANSITION: u16 = 40;
+
+/// A target from `[adaptive]`, with its target and sensor resolved.
+pub struct Regulated {
+    target: Target,
+    /// ID of the light level sensor.
+    sensor: String,
+    interval: Duration,
+    regulator: Regulator,
+}
+
+/// Decides when and how much to adjust the brightness to keep a level of light, with hysteresis
+/// so that the lights don't go up and down around it.
+#[derive(Debug)]
+struct Regulator {
+    lux: f64,
+    tolerance: f64,
+    /// Whether the level drifted out of tolerance and hasn't been brought back close yet.
+    adjusting: bool,
+}
+
+/// Resolves the targets and sensors of `[adaptive]` in the config.
+pub fn regulated(bridge: &Bridge, config: &Config) -> Result<Vec<Regulated>> {
+    config
+        .adaptive
+        .iter()
+        .map(|(spec, adaptive)| {
+            let invalid = |err| eyre!("Invalid adaptive target {:?}: {}", spec, err);
+            if adaptive.lux <= 0.0 || adaptive.tolerance <= 0.0 {
+                return Err(invalid(eyre!("lux and tolerance must be above 0")));
+            }
+            Ok(Regulated {
+                target: targets::resolve(spec, bridge, config).map_err(invalid)?,
+                sensor: sensors::resolve_light_level(bridge, &adaptive.sensor).map_err(invalid)?,
+                interval: adaptive.interval,
+                regulator: Regulator {
+                    lux: adaptive.lux,
+                    tolerance: adaptive.tolerance,
+                    adjusting: false,
+                },
+            })
+        })
+        .collect()
+}
+
+/// Keeps adjusting the target's brightness to the level of light, forever.
+pub fn keep(bridge: &Bridge, mut regulated: Regulated) {
+    loop {
+        // Adjusting must not hold up commands to the lights.
+        if let Err(err) = with_priority(Priority::Background, || regulated.step(bridge)) {
+            eprintln!(
+                "Failed to adjust {} to the level of light: {:#}",
+                regulated.target, err
+            );
+        }
+        thread::sleep(regulated.interval);
+    }
+}
+
+impl Regulated {
+    fn step(&mut self, bridge: &Bridge) -> Result<()> {
+        let measured = match sensors::read_lux(bridge, &self.sensor)? {
+            Some(measured) => measured,
+            None => return Ok(()),
+        };
+        // Lights that are off are left off.
+        let bri = match self.target.brightness(bridge)? {
+            Some(bri) => bri,
+            None => {
+                self.regulator.adjusting = false;
+                return Ok(());
+            }
+        };
+        if let Some(bri) = self.regulator.adjust(measured, bri) {
+            tracing::info!(lights = %self.target, measured, bri, "adjusting");
+            let command = CommandLight {
+                transitiontime: Some(TRANSITION),
+                ..CommandLight::default().with_bri(bri)
+            };
+            self.target.set_state(bridge, &command)?;
+        }
+        Ok(())
+    }
+}
+
+impl Regulator {
+    /// Returns the brightness to change to for the measured level of light, or `None` to leave
+    /// it. The lights are assumed to make up part of the light, in proportion to their
+    /// brightness.
+    fn adjust(&mut self, measured: f64, bri: u8) -> Option<u8> {
+        let drift = (measured / self.lux - 1.0).abs();
+        let allowed = if self.adjusting {
+            self.tolerance / 2.0
+        } else {
+            self.tolerance
+        };
+        if drift <= allowed {
+            self.adjusting = false;
+            return None;
+        }
+        self.adjusting = true;
+        let wanted = bri as f64 * (self.lux / measured.max(1.0)).powf(GAIN);
+        Some(wanted.round().clamp(1.0, 254.0) as u8).filter(|&wanted| wanted != bri)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Regulator;
+
+    #[test]
+    fn brightness_is_adjusted_with_hysteresis() {
+        let mut regulator = Regulator {
+            lux: 300.0,
+            tolerance: 0.2,
+            adjusting: false,
+        };
+        // Within tolerance, nothing changes.
+        assert_eq!(regulator.adjust(250.0, 100), None);
+        // Too dark, so the lights go up part of the way.
+        assert_eq!(regulator.adjust(100.0, 100), Some(173));
+        // Once adjusting, it goes on until within half the tolerance.
+        assert_eq!(regulator.adjust(250.0, 173), Some(190));
+        assert_eq!(regulator.adjust(290.0, 190), None);
+        assert_eq!(regulator.adjust(250.0, 190), None);
+        // Too bright.
+        assert_eq!(regulator.adjust(1200.0, 200), Some(100));
+        // Lights at full brightness can't do more.
+        regulator.adjusting = false;
+        assert_eq!(regulator.adjust(10.0, 254), None);
+    }
+}
@@ -0,0 +1,366 @@
+use crate::backend::Datastore;
+use crate::http::{Client, Endpoint};
+use crate::metrics;
+use crate::queue::Queue;
+use crate::trace::{Exchange, Recorder, Replay};
+use eyre::{eyre, Result};
+use hueclient::{
+    CommandLight, Group, IdentifiedGroup, IdentifiedLight, IdentifiedScene, Light, Scene,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+#[derive(Debug, Deserialize)]
+pub struct PublicConfig {
+    pub bridgeid: String,
+    #[serde(default)]
+    pub apiversion: String,
+}
+
+/// Credentials for a user registered with the bridge.
+#[derive(Debug, Deserialize)]
+pub struct Registration {
+    pub username: String,
+    /// Key for entertainment streaming, if the bridge supports it.
+    #[serde(default)]
+    pub clientkey: Option<String>,
+}
+
+/// A client for the bridge's v1 REST API.
+pub struct Bridge {
+    /// The bridge's IP address or hostname, as given by the user or found by discovery.
+    pub host: String,
+    /// The username for authenticating with the bridge. Empty before pairing.
+    pub username: String,
+    transport: Transport,
+    recorder: Option<Arc<Recorder>>,
+    /// Shared by every thread using the bridge, so urgent requests go first.
+    queue: Arc<Queue>,
+}
+
+enum Transport {
+    /// Directly to the bridge on the local network.
+    Local(Client),
+    /// Through the Hue Remote API, which proxies the v1 API below `/route`.
+    Remote {
+        client: reqwest::blocking::Client,
+        base_url: String,
+        access_token: String,
+    },
+    /// From responses recorded with `--record`.
+    Replay(Replay),
+}
+
+impl Bridge {
+    /// Creates a bridge at a host, using `default_port` if the host has no port.
+    pub fn for_host(host: &str, default_port: u16) -> Result<Bridge> {
+        Ok(Bridge {
+            host: host.to_owned(),
+            username: String::new(),
+            transport: Transport::Local(Client::new(Endpoint::resolve(host, default_port)?)),
+            recorder: None,
+            queue: Arc::new(Queue::new(1)),
+        })
+    }
+
+    /// Creates a bridge reached through the Hue Remote API at `base_url`.
+    pub fn remote(base_url: &str, access_token: &str) -> Bridge {
+        Bridge {
+            host: base_url.to_owned(),
+            username: String::new(),
+            transport: Transport::Remote {
+                client: reqwest::blocking::Client::new(),
+                base_url: format!("{}/route", base_url),
+                access_token: access_token.to_owned(),
+            },
+            recorder: None,
+            queue: Arc::new(Queue::new(1)),
+        }
+    }
+
+    /// Creates a bridge that answers with the responses recorded in the trace at `path`.
+    pub fn replay(path: &Path) -> Result<Bridge> {
+        Ok(Bridge {
+            host: path.display().to_string(),
+            username: String::new(),
+            transport: Transport::Replay(Replay::open(path)?),
+            recorder: None,
+            queue: Arc::new(Queue::new(1)),
+        })
+    }
+
+    /// Returns the address of the bridge on the local network, or why it has none, for the parts
+    /// of its API that are only reachable there, like the v2 event stream.
+    pub fn endpoint(&self) -> Result<&Endpoint> {
+        match &self.transport {
+            Transport::Local(client) => Ok(&client.endpoint),
+            Transport::Remote { .. } => Err(eyre!(
+                "The bridge's v2 API is only reachable on the local network, not through the Remote API"
+            )),
+            Transport::Replay(_) => Err(eyre!("The bridge's v2 API can't be replayed from a trace")),
+        }
+    }
+
+    /// Records all exchanges with the bridge.
+    pub fn recording(self, recorder: Arc<Recorder>) -> Bridge {
+        Bridge {
+            recorder: Some(recorder),
+            ..self
+        }
+    }
+
+    /// Lets up to `connections` requests to the bridge run at once, instead of one.
+    pub fn with_connections(self, connections: usize) -> Bridge {
+        Bridge {
+            queue: Arc::new(Queue::new(connections)),
+            ..self
+        }
+    }
+
+    pub fn with_user(self, username: impl Into<String>) -> Bridge {
+        Bridge {
+            username: username.into(),
+            ..self
+        }
+    }
+
+    /// Registers a new user with the bridge. This fails with `LINK_BUTTON_NOT_PRESSED` unless
+    /// the bridge's link button was pressed shortly before.
+    ///
+    /// The `devicetype` identifies the user in the Hue app, like `blilys#laptop`.
+    pub fn register_user(&self, devicetype: &str) -> Result<Registration> {
+        #[derive(Deserialize)]
+        struct Success {
+            success: Registration,
+        }
+        // Bridges that don't support entertainment streaming ignore `generateclientkey`.
+        let body = serde_json::json!({ "devicetype": devicetype, "generateclientkey": true });
+        let mut resp: Vec<Success> = self.request("POST", "/api", Some(&body))?;
+        Ok(resp
+            .pop()
+            .ok_or_else(|| eyre!("Bridge did not return a username"))?
+            .success)
+    }
+
+    /// Fetches the bridge's public configuration, which doesn't require a username.
+    pub fn get_public_config(&self) -> Result<PublicConfig> {
+        self.request::<_, ()>("GET", "/api/config", None)
+    }
+
+    pub fn get_all_lights(&self) -> Result<Vec<IdentifiedLight>> {
+        identify_lights(self.get("lights")?)
+    }
+
+    pub fn get_all_groups(&self) -> Result<Vec<IdentifiedGroup>> {
+        identify_groups(self.get("groups")?)
+    }
+
+    pub fn get_all_scenes(&self) -> Result<Vec<IdentifiedScene>> {
+        Ok(identify_scenes(self.get("scenes")?))
+    }
+
+    /// Fetches the bridge's full state, with every resource, in one request.
+    pub fn get_full_state<T: DeserializeOwned>(&self) -> Result<T> {
+        self.request::<T, ()>("GET", &format!("/api/{}", self.username), None)
+    }
+
+    /// Fetches lights, groups, and scenes from the full state, in one request instead of three.
+    pub fn get_datastore(&self) -> Result<Datastore> {
+        // Other gateways leave out what they don't have, like deCONZ, which keeps scenes with
+        // their groups.
+        #[derive(Deserialize)]
+        struct FullState {
+            #[serde(default)]
+            lights: HashMap<String, Light>,
+            #[serde(default)]
+            groups: HashMap<String, Group>,
+            #[serde(default)]
+            scenes: HashMap<String, Scene>,
+        }
+        let state: FullState = self.get_full_state()?;
+        Ok(Datastore {
+            lights: identify_lights(state.lights)?,
+            groups: identify_groups(state.groups)?,
+            scenes: identify_scenes(state.scenes),
+        })
+    }
+
+    pub fn set_light_state(&self, light: usize, command: &CommandLight) -> Result<Value> {
+        self.put(&format!("lights/{}/state", light), command)
+    }
+
+    pub fn set_group_state(&self, group: usize, command: &CommandLight) -> Result<Value> {
+        self.put(&format!("groups/{}/action", group), command)
+    }
+
+    /// Fetches a resource below `/api/<username>/`.
+    pub fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        self.request::<T, ()>("GET", &self.path(path), None)
+    }
+
+    pub fn put<T: DeserializeOwned>(&self, path: &str, body: &impl Serialize) -> Result<T> {
+        self.request("PUT", &self.path(path), Some(body))
+    }
+
+    pub fn post<T: DeserializeOwned>(&self, path: &str, body: &impl Serialize) -> Result<T> {
+        self.request("POST", &self.path(path), Some(body))
+    }
+
+    pub fn delete<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        self.request::<T, ()>("DELETE", &self.path(path), None)
+    }
+
+    fn path(&self, path: &str) -> String {
+        format!("/api/{}/{}", self.username, path)
+    }
+
+    fn request<T: DeserializeOwned, B: Serialize>(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<&B>,
+    ) -> Result<T> {
+        // The username is a credential, so it is kept out of the logs.
+        let logged_path = match self.username.as_str() {
+            "" => path.to_owned(),
+            username => path.replacen(username, "<username>", 1),
+        };
+        let _span = tracing::debug_span!("request", method, path = %logged_path).entered();
+        let _turn = self.queue.wait();
+        let started = Instant::now();
+        let body = body.map(serde_json::to_string).transpose()?;
+        let (status, text) = match &self.transport {
+            Transport::Local(client) => {
+                let resp = client.request(method, path, body.as_deref())?;
+                (resp.status, resp.body)
+            }
+            Transport::Remote {
+                client,
+                base_url,
+                access_token,
+            } => {
+                let mut request = client
+                    .request(method.parse()?, &format!("{}{}", base_url, path))
+                    .bearer_auth(access_token);
+                if let Some(body) = &body {
+                    request = request
+                        .header("Content-Type", "application/json")
+                        .body(body.clone());
+                }
+                let resp = request.send()?;
+                (resp.status().as_u16(), resp.text()?)
+            }
+            Transport::Replay(replay) => replay.respond(method, path)?,
+        };
+        if let Some(recorder) = &self.recorder {
+            let exchange = Exchange {
+                method: method.to_owned(),
+                path: path.to_owned(),
+                request: body,
+                status,
+                response: text.clone(),
+            };
+            if let Err(err) = recorder.record(&self.username, exchange) {
+                eprintln!("Failed to record exchange with the bridge: {}", err);
+            }
+        }
+        metrics::request(started.elapsed());
+        tracing::debug!(
+            status,
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "response"
+        );
+        if status != 200 {
+            return Err(eyre!("The bridge responded with HTTP status {}", status));
+        }
+        let value = check(serde_json::from_str(&text)?)?;
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+fn identify_lights(lights: HashMap<String, Light>) -> Result<Vec<IdentifiedLight>> {
+    let mut lights = lights
+        .into_iter()
+        .map(|(id, light)| {
+            Ok(IdentifiedLight {
+                id: parse_id(&id)?,
+                light,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    lights.sort_by_key(|il| il.id);
+    Ok(lights)
+}
+
+fn identify_groups(groups: HashMap<String, Group>) -> Result<Vec<IdentifiedGroup>> {
+    let mut groups = groups
+        .into_iter()
+        .map(|(id, group)| {
+            Ok(IdentifiedGroup {
+                id: parse_id(&id)?,
+                group,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    groups.sort_by_key(|ig| ig.id);
+    Ok(groups)
+}
+
+fn identify_scenes(scenes: HashMap<String, Scene>) -> Vec<IdentifiedScene> {
+    let mut scenes: Vec<_> = scenes
+        .into_iter()
+        .map(|(id, scene)| IdentifiedScene { id, scene })
+        .collect();
+    scenes.sort_by(|a, b| a.id.cmp(&b.id));
+    scenes
+}
+
+fn parse_id(id: &str) -> Result<usize> {
+    id.parse()
+        .map_err(|_| eyre!("Expected a numeric ID from the bridge, got {:?}", id))
+}
+
+fn check(value: Value) -> Result<Value> {
+    if let Some(error) = value
+        .as_array()
+        .and_then(|items| items.iter().find_map(|item| item.get("error")))
+    {
+        return Err(ApiError {
+            code: error["type"].as_u64().unwrap_or(0),
+            description: error["description"]
+                .as_str()
+                .unwrap_or("unknown error")
+                .to_owned(),
+        }
+        .into());
+    }
+    Ok(value)
+}
+
+/// The error code the bridge responds with when pairing before the link button was pressed.
+pub const LINK_BUTTON_NOT_PRESSED: u64 = 101;
+
+/// An error reported by the bridge in a response body.
+#[derive(Debug)]
+pub struct ApiError {
+    pub code: u64,
+    pub description: String,
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The bridge reported error code {}: {}",
+            self.code, self.description
+        )
+    }
+}
+
+impl std::error::Error for ApiError {}
@@ -0,0 +1,193 @@
+use crate::time::{format_utc, now};
+use directories::ProjectDirs;
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+const MAX_SIZE: u64 = 1024 * 1024;
+const KEEP_ROTATED: usize = 3;
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Stops writing the audit log, for running without any files.
+pub fn disable() {
+    DISABLED.store(true, Ordering::Relaxed);
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub time: String,
+    pub target: String,
+    pub payload: Value,
+    pub result: String,
+    pub args: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caller: Option<String>,
+}
+
+/// Appends a state-changing command and its outcome to the audit log.
+///
+/// Failing to write the audit log never fails the command itself.
+pub fn log<T, E: std::fmt::Display>(
+    target: &str,
+    payload: impl Serialize,
+    result: &std::result::Result<T, E>,
+) {
+    let result = match result {
+        Ok(_) => "ok".to_owned(),
+        Err(err) => format!("error: {}", err),
+    };
+    write(target, payload, result);
+}
+
+/// Records that a long-running effect was started on a target.
+pub fn log_effect(target: &str, effect: &str) {
+    write(
+        target,
+        serde_json::json!({ "effect": effect }),
+        "started".to_owned(),
+    );
+}
+
+fn write(target: &str, payload: impl Serialize, result: String) {
+    if DISABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let entry = Entry {
+        time: format_utc(now()),
+        target: target.to_owned(),
+        payload: serde_json::to_value(payload).unwrap_or(Value::Null),
+        result,
+        args: std::env::args().collect(),
+        caller: caller(),
+    };
+    if let Err(err) = append(&entry) {
+        eprintln!("Failed to write audit log: {}", err);
+    }
+}
+
+fn append(entry: &Entry) -> Result<()> {
+    let path = get_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    if fs::metadata(&path).map(|m| m.len()).unwrap_or(0) > MAX_SIZE {
+        rotate(&path)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    Ok(())
+}
+
+fn rotate(path: &Path) -> Result<()> {
+    for i in (1..KEEP_ROTATED).rev() {
+        let from = rotated_path(path, i);
+        if from.is_file() {
+            fs::rename(&from, rotated_path(path, i + 1))?;
+        }
+    }
+    fs::rename(path, rotated_path(path, 1))?;
+    Ok(())
+}
+
+fn rotated_path(path: &Path, i: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", i));
+    PathBuf::from(name)
+}
+
+fn get_path() -> Result<PathBuf> {
+    let project_dirs = ProjectDirs::from("", "", "blilys").expect("Data dir not readable");
+    Ok(project_dirs.data_dir().join("audit.log"))
+}
+
+/// Describes the process that invoked blilys, to tell scripts apart.
+#[cfg(target_os = "linux")]
+fn caller() -> Option<String> {
+    let ppid = std::os::unix::process::parent_id();
+    let cmdline = fs::read(format!("/proc/{}/cmdline", ppid)).ok()?;
+    let cmdline = String::from_utf8_lossy(&cmdline)
+        .split('\0')
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    Some(format!("{} ({})", cmdline, ppid))
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn caller() -> Option<String> {
+    Some(format!("pid {}", std::os::unix::process::parent_id()))
+}
+
+#[cfg(not(unix))]
+fn caller() -> Option<String> {
+    None
+}
+
+/// Prints the last `lines` audit log entries, optionally following new ones.
+pub fn tail(lines: usize, follow: bool) -> Result<()> {
+    let path = get_path()?;
+    if !path.is_file() {
+        info!("No commands have been logged yet.");
+        if !follow {
+            return Ok(());
+        }
+    }
+
+    let mut position = 0;
+    if path.is_file() {
+        let file = fs::File::open(&path)?;
+        position = file.metadata()?.len();
+        let all: Vec<String> = BufReader::new(file).lines().collect::<Result<_, _>>()?;
+        for line in &all[all.len().saturating_sub(lines)..] {
+            print_line(line);
+        }
+    }
+
+    if !follow {
+        return Ok(());
+    }
+    loop {
+        std::thread::sleep(Duration::from_millis(500));
+        let mut file = match fs::File::open(&path) {
+            Ok(file) => file,
+            Err(_) => continue,
+        };
+        let len = file.metadata()?.len();
+        if len < position {
+            // The log was rotated.
+            position = 0;
+        }
+        file.seek(SeekFrom::Start(position))?;
+        let mut reader = BufReader::new(file);
+        let mut line = String::new();
+        while reader.read_line(&mut line)? > 0 {
+            print_line(line.trim_end());
+            line.clear();
+        }
+        position = len;
+    }
+}
+
+fn print_line(line: &str) {
+    match serde_json::from_str::<Entry>(line) {
+        Ok(entry) => println!(
+            "{time} {target:12} {payload} [{result}] {args}{caller}",
+            time = entry.time,
+            target = entry.target,
+            payload = entry.payload,
+            result = entry.result,
+            args = entry.args.join(" "),
+            caller = entry
+                .caller
+                .map(|c| format!(" via {}", c))
+                .unwrap_or_default(),
+        ),
+        Err(_) => println!("{}", line),
+    }
+}
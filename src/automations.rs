@@ -0,0 +1,211 @@
+use crate::api::Bridge;
+use crate::cache;
+use crate::config::Config;
+use crate::sensors;
+use crate::targets;
+use crate::time::TimeOfDay;
+use eyre::{eyre, Result};
+use serde_json::{json, Map, Value};
+use std::time::Duration;
+
+/// Class ID of the resourcelinks blilys groups the rules of each automation in, to tell them from
+/// those of other apps.
+const CLASS_ID: u64 = 20_611;
+
+/// Creates a rule on the bridge, returning its address like `/rules/3`.
+fn create_rule(bridge: &Bridge, name: &str, conditions: Value, actions: Value) -> Result<String> {
+    let rule = json!({
+        // The bridge allows at most 32 characters.
+        "name": name.chars().take(32).collect::<String>(),
+        "conditions": conditions,
+        "actions": actions,
+    });
+    let response: Value = bridge.post("rules", &rule)?;
+    Ok(format!("/rules/{}", created_id(&response)))
+}
+
+fn created_id(response: &Value) -> &str {
+    response[0]["success"]["id"].as_str().unwrap_or_default()
+}
+
+/// Formats a duration like `PT00:01:30`, as the bridge takes it in rule conditions.
+fn iso_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!(
+        "PT{:02}:{:02}:{:02}",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// A condition that the local time is between the start and end times.
+fn between_condition((start, end): (TimeOfDay, TimeOfDay)) -> Value {
+    json!({
+        "address": "/config/localtime",
+        "operator": "in",
+        "value": format!("T{}/T{}", start, end),
+    })
+}
+
+/// Creates the rules for a motion-activated light, which turns the target on at `bri` on motion,
+/// only between the given times if any, and off again after `off_after` without motion. Returns
+/// the addresses of the rules.
+pub fn motion_light(
+    bridge: &Bridge,
+    config: &Config,
+    sensor: &str,
+    target: &str,
+    bri: u8,
+    between: Option<(TimeOfDay, TimeOfDay)>,
+    off_after: Duration,
+) -> Result<Vec<String>> {
+    let sensor = sensors::resolve_presence(bridge, sensor)?;
+    let address = targets::resolve(target, bridge, config)?.action_address()?;
+    let presence = format!("/sensors/{}/state/presence", sensor);
+
+    let mut on_conditions = vec![
+        json!({"address": presence, "operator": "eq", "value": "true"}),
+        json!({"address": presence, "operator": "dx"}),
+    ];
+    let mut off_conditions = vec![
+        json!({"address": presence, "operator": "eq", "value": "false"}),
+        json!({"address": presence, "operator": "ddx", "value": iso_duration(off_after)}),
+    ];
+    if let Some(between) = between {
+        on_conditions.push(between_condition(between));
+        off_conditions.push(between_condition(between));
+    }
+
+    let on = create_rule(
+        bridge,
+        &format!("Motion light {} on", sensor),
+        Value::Array(on_conditions),
+        json!([{"address": address, "method": "PUT", "body": {"on": true, "bri": bri}}]),
+    )?;
+    let off = create_rule(
+        bridge,
+        &format!("Motion light {} off", sensor),
+        Value::Array(off_conditions),
+        json!([{"address": address, "method": "PUT", "body": {"on": false}}]),
+    )?;
+    Ok(vec![on, off])
+}
+
+/// Creates a rule turning the target off after `after` without motion. Returns the address of
+/// the rule.
+pub fn off_when_no_motion(
+    bridge: &Bridge,
+    config: &Config,
+    sensor: &str,
+    target: &str,
+    after: Duration,
+) -> Result<Vec<String>> {
+    let sensor = sensors::resolve_presence(bridge, sensor)?;
+    let address = targets::resolve(target, bridge, config)?.action_address()?;
+    let presence = format!("/sensors/{}/state/presence", sensor);
+    let rule = create_rule(
+        bridge,
+        &format!("No motion {} off", sensor),
+        json!([
+            {"address": presence, "operator": "eq", "value": "false"},
+            {"address": presence, "operator": "ddx", "value": iso_duration(after)},
+        ]),
+        json!([{"address": address, "method": "PUT", "body": {"on": false}}]),
+    )?;
+    Ok(vec![rule])
+}
+
+/// Creates a rule recalling the scene on motion while all lights are off, as when coming home to
+/// a dark house. Returns the address of the rule.
+pub fn arrival_scene(
+    bridge: &Bridge,
+    config: &Config,
+    sensor: &str,
+    scene: &str,
+    between: Option<(TimeOfDay, TimeOfDay)>,
+) -> Result<Vec<String>> {
+    let sensor = sensors::resolve_presence(bridge, sensor)?;
+    let scene = cache::resolve_scene(bridge, config.cache.ttl, scene)?;
+    let presence = format!("/sensors/{}/state/presence", sensor);
+    let mut conditions = vec![
+        json!({"address": presence, "operator": "eq", "value": "true"}),
+        json!({"address": presence, "operator": "dx"}),
+        json!({"address": "/groups/0/state/any_on", "operator": "eq", "value": "false"}),
+    ];
+    if let Some(between) = between {
+        conditions.push(between_condition(between));
+    }
+    let rule = create_rule(
+        bridge,
+        &format!("Arrival {}", sensor),
+        Value::Array(conditions),
+        json!([{"address": "/groups/0/action", "method": "PUT", "body": {"scene": scene}}]),
+    )?;
+    Ok(vec![rule])
+}
+
+/// Groups the resources created for an automation in a resourcelink, so that blilys can list and
+/// remove them later. Returns the ID of the resourcelink.
+pub fn track(
+    bridge: &Bridge,
+    template: &str,
+    description: &str,
+    links: &[String],
+) -> Result<String> {
+    let link = json!({
+        // The bridge allows at most 32 characters for names and 64 for descriptions.
+        "name": format!("blilys {}", template).chars().take(32).collect::<String>(),
+        "description": description.chars().take(64).collect::<String>(),
+        "classid": CLASS_ID,
+        "links": links,
+    });
+    let response: Value = bridge.post("resourcelinks", &link)?;
+    Ok(created_id(&response).to_owned())
+}
+
+fn tracked(bridge: &Bridge) -> Result<Vec<(String, Value)>> {
+    let links: Map<String, Value> = bridge.get("resourcelinks")?;
+    let mut tracked: Vec<(String, Value)> = links
+        .into_iter()
+        .filter(|(_, link)| link["classid"].as_u64() == Some(CLASS_ID))
+        .collect();
+    tracked.sort_by_key(|(id, _)| id.parse::<usize>().unwrap_or(usize::MAX));
+    Ok(tracked)
+}
+
+/// Lists the automations created by blilys.
+pub fn list(bridge: &Bridge) -> Result<()> {
+    for (id, link) in tracked(bridge)? {
+        let links: Vec<&str> = link["links"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .collect();
+        println!(
+            "{:>2}: {} - {} ({})",
+            id,
+            link["name"].as_str().unwrap_or_default(),
+            link["description"].as_str().unwrap_or_default(),
+            links.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Removes an automation created by blilys, with all of its rules and schedules.
+pub fn remove(bridge: &Bridge, id: &str) -> Result<()> {
+    let link = tracked(bridge)?
+        .into_iter()
+        .find(|(link_id, _)| link_id == id)
+        .map(|(_, link)| link)
+        .ok_or_else(|| eyre!("No automation with ID {}", id))?;
+    for address in link["links"].as_array().into_iter().flatten() {
+        if let Some(address) = address.as_str() {
+            bridge.delete::<Value>(address.trim_start_matches('/'))?;
+        }
+    }
+    bridge.delete::<Value>(&format!("resourcelinks/{}", id))?;
+    Ok(())
+}
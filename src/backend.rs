@@ -0,0 +1,411 @@
+use crate::api::Bridge;
+use eyre::Result;
+use hueclient::{CommandLight, IdentifiedGroup, IdentifiedLight, IdentifiedScene, LightState};
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// A change observed on the bridge.
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// A light's state changed, or the light was seen for the first time.
+    Light { id: usize, state: LightState },
+    /// The bridge was checked for changes, after any it had. Sent even when nothing changed, so
+    /// that watchers can give up waiting.
+    Polled,
+}
+
+/// The lights, groups, and scenes of a backend at one point in time.
+#[derive(Default, Clone)]
+pub struct Datastore {
+    pub lights: Vec<IdentifiedLight>,
+    pub groups: Vec<IdentifiedGroup>,
+    pub scenes: Vec<IdentifiedScene>,
+}
+
+/// Lights of each group, including group 0 with all lights, for backends that adjust commands
+/// to groups by their lights.
+#[derive(Default)]
+pub struct Members(HashMap<usize, Vec<usize>>);
+
+impl Members {
+    pub fn new(datastore: &Datastore) -> Members {
+        let mut members = HashMap::new();
+        members.insert(0, datastore.lights.iter().map(|il| il.id).collect());
+        for ig in &datastore.groups {
+            let lights = ig.group.lights.iter().filter_map(|id| id.parse().ok());
+            members.insert(ig.id, lights.collect());
+        }
+        Members(members)
+    }
+
+    /// Returns the lights of the group, or none if there is no such group.
+    pub fn of(&self, group: usize) -> &[usize] {
+        self.0.get(&group).map(Vec::as_slice).unwrap_or_default()
+    }
+}
+
+/// Something that can control lights, like a Hue bridge on the local network or through the Hue
+/// Remote API.
+pub trait LightBackend {
+    /// Where the lights are controlled, for messages.
+    fn describe(&self) -> String;
+    fn get_all_lights(&self) -> Result<Vec<IdentifiedLight>>;
+    fn get_all_groups(&self) -> Result<Vec<IdentifiedGroup>>;
+    fn get_all_scenes(&self) -> Result<Vec<IdentifiedScene>>;
+    fn set_light_state(&self, light: usize, command: &CommandLight) -> Result<()>;
+    fn set_group_state(&self, group: usize, command: &CommandLight) -> Result<()>;
+
+    /// Fetches lights, groups, and scenes together. Backends that can fetch them in one request
+    /// should, as each request to a bridge takes a while.
+    fn get_datastore(&self) -> Result<Datastore> {
+        Ok(Datastore {
+            lights: self.get_all_lights()?,
+            groups: self.get_all_groups()?,
+            scenes: self.get_all_scenes()?,
+        })
+    }
+
+    /// Returns a stream of changes. Backends without an event stream poll for changes every
+    /// `interval`.
+    fn subscribe(&self, interval: Duration) -> Box<dyn Iterator<Item = Result<Event>> + '_> {
+        Box::new(Poller::new(self, interval))
+    }
+}
+
+impl LightBackend for Bridge {
+    fn describe(&self) -> String {
+        self.host.to_owned()
+    }
+
+    fn get_all_lights(&self) -> Result<Vec<IdentifiedLight>> {
+        Bridge::get_all_lights(self)
+    }
+
+    fn get_all_groups(&self) -> Result<Vec<IdentifiedGroup>> {
+        Bridge::get_all_groups(self)
+    }
+
+    fn get_all_scenes(&self) -> Result<Vec<IdentifiedScene>> {
+        Bridge::get_all_scenes(self)
+    }
+
+    fn set_light_state(&self, light: usize, command: &CommandLight) -> Result<()> {
+        Bridge::set_light_state(self, light, command).map(|_| ())
+    }
+
+    fn set_group_state(&self, group: usize, command: &CommandLight) -> Result<()> {
+        Bridge::set_group_state(self, group, command).map(|_| ())
+    }
+
+    fn get_datastore(&self) -> Result<Datastore> {
+        Bridge::get_datastore(self)
+    }
+}
+
+/// A backend that fetches the lights, groups, and scenes once, when first asked for any of them,
+/// and answers from what it fetched after that, for looking up many targets in one request.
+pub struct Fetched<'a> {
+    inner: &'a dyn LightBackend,
+    datastore: OnceCell<Datastore>,
+}
+
+impl<'a> Fetched<'a> {
+    pub fn new(inner: &'a dyn LightBackend) -> Fetched<'a> {
+        Fetched {
+            inner,
+            datastore: OnceCell::new(),
+        }
+    }
+
+    fn datastore(&self) -> Result<&Datastore> {
+        if let Some(datastore) = self.datastore.get() {
+            return Ok(datastore);
+        }
+        let datastore = self.inner.get_datastore()?;
+        Ok(self.datastore.get_or_init(|| datastore))
+    }
+}
+
+impl LightBackend for Fetched<'_> {
+    fn describe(&self) -> String {
+        self.inner.describe()
+    }
+
+    fn get_all_lights(&self) -> Result<Vec<IdentifiedLight>> {
+        Ok(self.datastore()?.lights.clone())
+    }
+
+    fn get_all_groups(&self) -> Result<Vec<IdentifiedGroup>> {
+        Ok(self.datastore()?.groups.clone())
+    }
+
+    fn get_all_scenes(&self) -> Result<Vec<IdentifiedScene>> {
+        Ok(self.datastore()?.scenes.clone())
+    }
+
+    fn set_light_state(&self, light: usize, command: &CommandLight) -> Result<()> {
+        self.inner.set_light_state(light, command)
+    }
+
+    fn set_group_state(&self, group: usize, command: &CommandLight) -> Result<()> {
+        self.inner.set_group_state(group, command)
+    }
+
+    fn get_datastore(&self) -> Result<Datastore> {
+        self.datastore().cloned()
+    }
+}
+
+/// Turns repeated fetches of all lights into a stream of changes.
+struct Poller<'a, B: LightBackend + ?Sized> {
+    backend: &'a B,
+    interval: Duration,
+    last: HashMap<usize, LightState>,
+    pending: Vec<Event>,
+    first: bool,
+}
+
+impl<'a, B: LightBackend + ?Sized> Poller<'a, B> {
+    fn new(backend: &'a B, interval: Duration) -> Self {
+        Poller {
+            backend,
+            interval,
+            last: HashMap::new(),
+            pending: vec![],
+            first: true,
+        }
+    }
+}
+
+impl<'a, B: LightBackend + ?Sized> Iterator for Poller<'a, B> {
+    type Item = Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            if !self.first {
+                std::thread::sleep(self.interval);
+            }
+            self.first = false;
+            let lights = match self.backend.get_all_lights() {
+                Ok(lights) => lights,
+                Err(err) => return Some(Err(err)),
+            };
+            // Events are taken from the end, so this comes after the changes.
+            self.pending.push(Event::Polled);
+            for il in lights.into_iter().rev() {
+                let state = il.light.state;
+                if !self
+                    .last
+                    .get(&il.id)
+                    .is_some_and(|last| same_state(last, &state))
+                {
+                    self.last.insert(il.id, state);
+                    self.pending.push(Event::Light { id: il.id, state });
+                }
+            }
+        }
+        self.pending.pop().map(Ok)
+    }
+}
+
+fn same_state(a: &LightState, b: &LightState) -> bool {
+    a.on == b.on
+        && a.bri == b.bri
+        && a.hue == b.hue
+        && a.sat == b.sat
+        && a.ct == b.ct
+        && a.xy == b.xy
+}
+
+#[cfg(test)]
+pub mod mock {
+    use super::LightBackend;
+    use eyre::{eyre, Result};
+    use hueclient::{
+        CommandLight, Group, GroupState, IdentifiedGroup, IdentifiedLight, IdentifiedScene, Light,
+        LightState,
+    };
+    use std::cell::RefCell;
+
+    /// An in-memory backend for testing commands without a bridge.
+    #[derive(Default)]
+    pub struct MockBackend {
+        pub lights: RefCell<Vec<IdentifiedLight>>,
+        pub groups: RefCell<Vec<IdentifiedGroup>>,
+        /// Every command sent, as `light/<id>` or `group/<id>` and the command.
+        pub commands: RefCell<Vec<(String, CommandLight)>>,
+    }
+
+    impl MockBackend {
+        pub fn with_light(self, id: usize, name: &str, on: bool, bri: u8) -> Self {
+            self.lights.borrow_mut().push(IdentifiedLight {
+                id,
+                light: Light {
+                    name: name.to_owned(),
+                    modelid: "LCT007".to_owned(),
+                    swversion: "1".to_owned(),
+                    uniqueid: format!("00:17:88:01:00:00:00:{:02x}-0b", id),
+                    state: LightState {
+                        on,
+                        bri: Some(bri),
+                        hue: None,
+                        sat: None,
+                        ct: None,
+                        xy: None,
+                    },
+                },
+            });
+            self
+        }
+
+        pub fn with_group(self, id: usize, name: &str, lights: &[usize]) -> Self {
+            self.groups.borrow_mut().push(IdentifiedGroup {
+                id,
+                group: Group {
+                    name: name.to_owned(),
+                    lights: lights.iter().map(|l| l.to_string()).collect(),
+                    sensors: vec![],
+                    r#type: "Room".to_owned(),
+                    state: GroupState {
+                        all_on: false,
+                        any_on: false,
+                    },
+                    recycle: false,
+                    action: LightState {
+                        on: false,
+                        bri: None,
+                        hue: None,
+                        sat: None,
+                        ct: None,
+                        xy: None,
+                    },
+                },
+            });
+            self
+        }
+
+        fn apply(&self, light: usize, command: &CommandLight) -> Result<()> {
+            let mut lights = self.lights.borrow_mut();
+            let il = lights
+                .iter_mut()
+                .find(|il| il.id == light)
+                .ok_or_else(|| eyre!("The bridge reported error code 3: resource not available"))?;
+            let state = &mut il.light.state;
+            state.on = command.on.unwrap_or(state.on);
+            state.bri = command.bri.or(state.bri);
+            state.hue = command.hue.or(state.hue);
+            state.sat = command.sat.or(state.sat);
+            state.ct = command.ct.or(state.ct);
+            state.xy = command.xy.or(state.xy);
+            Ok(())
+        }
+    }
+
+    impl LightBackend for MockBackend {
+        fn describe(&self) -> String {
+            "mock".to_owned()
+        }
+
+        fn get_all_lights(&self) -> Result<Vec<IdentifiedLight>> {
+            Ok(self.lights.borrow().clone())
+        }
+
+        fn get_all_groups(&self) -> Result<Vec<IdentifiedGroup>> {
+            Ok(self.groups.borrow().clone())
+        }
+
+        fn get_all_scenes(&self) -> Result<Vec<IdentifiedScene>> {
+            Ok(vec![])
+        }
+
+        fn set_light_state(&self, light: usize, command: &CommandLight) -> Result<()> {
+            self.commands
+                .borrow_mut()
+                .push((format!("light/{}", light), command.clone()));
+            self.apply(light, command)
+        }
+
+        fn set_group_state(&self, group: usize, command: &CommandLight) -> Result<()> {
+            self.commands
+                .borrow_mut()
+                .push((format!("group/{}", group), command.clone()));
+            let lights = self
+                .groups
+                .borrow()
+                .iter()
+                .find(|ig| ig.id == group)
+                .ok_or_else(|| eyre!("The bridge reported error code 3: resource not available"))?
+                .group
+                .lights
+                .clone();
+            for light in lights {
+                self.apply(light.parse()?, command)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::MockBackend;
+    use super::{Event, LightBackend};
+    use hueclient::CommandLight;
+    use std::time::Duration;
+
+    #[test]
+    fn subscribe_reports_initial_states_then_changes() {
+        let backend = MockBackend::default()
+            .with_light(1, "Desk", true, 100)
+            .with_light(2, "Shelf", false, 10);
+        let mut events = backend.subscribe(Duration::from_millis(0));
+
+        let ids: Vec<Option<usize>> = (0..3)
+            .map(|_| match events.next().unwrap().unwrap() {
+                Event::Light { id, .. } => Some(id),
+                Event::Polled => None,
+            })
+            .collect();
+        assert_eq!(ids, vec![Some(1), Some(2), None]);
+
+        assert!(matches!(events.next().unwrap().unwrap(), Event::Polled));
+        backend
+            .set_light_state(2, &CommandLight::default().on())
+            .unwrap();
+        match events.next().unwrap().unwrap() {
+            Event::Light { id, state } => {
+                assert_eq!(id, 2);
+                assert!(state.on);
+            }
+            Event::Polled => panic!("Expected a change"),
+        }
+    }
+
+    #[test]
+    fn group_commands_apply_to_member_lights() {
+        let backend = MockBackend::default()
+            .with_light(1, "Desk", false, 100)
+            .with_light(2, "Shelf", false, 10)
+            .with_light(3, "Hall", false, 10)
+            .with_group(1, "Office", &[1, 2]);
+
+        backend
+            .set_group_state(1, &CommandLight::default().on().with_bri(50))
+            .unwrap();
+
+        let lights = backend.get_all_lights().unwrap();
+        let on: Vec<(usize, bool, Option<u8>)> = lights
+            .iter()
+            .map(|il| (il.id, il.light.state.on, il.light.state.bri))
+            .collect();
+        assert_eq!(
+            on,
+            vec![
+                (1, true, Some(50)),
+                (2, true, Some(50)),
+                (3, false, Some(10))
+            ]
+        );
+    }
+}
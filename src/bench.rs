@@ -0,0 +1,71 @@
+use crate::backend::LightBackend;
+use eyre::{eyre, Result};
+use hueclient::CommandLight;
+use std::time::{Duration, Instant};
+
+pub fn run(bridge: &dyn LightBackend, count: usize, light: Option<usize>) -> Result<()> {
+    if count == 0 {
+        return Err(eyre!("Count must be at least 1"));
+    }
+
+    let lights = bridge.get_all_lights()?;
+    let target = match light {
+        Some(id) => lights
+            .iter()
+            .find(|il| il.id == id)
+            .ok_or_else(|| eyre!("Light {} not found", id))?,
+        None => lights
+            .first()
+            .ok_or_else(|| eyre!("Bridge has no lights"))?,
+    };
+
+    // Write back the light's current on/off state, so the PUT is a no-op.
+    let command = if target.light.state.on {
+        CommandLight::default().on()
+    } else {
+        CommandLight::default().off()
+    };
+
+    info!(
+        "Running {} GET and PUT requests against {} ...",
+        count,
+        bridge.describe()
+    );
+
+    let mut get_times = Vec::with_capacity(count);
+    let mut put_times = Vec::with_capacity(count);
+    for _ in 0..count {
+        let start = Instant::now();
+        bridge.get_all_lights()?;
+        get_times.push(start.elapsed());
+
+        let start = Instant::now();
+        bridge.set_light_state(target.id, &command)?;
+        put_times.push(start.elapsed());
+    }
+
+    print_stats("GET /lights", &mut get_times);
+    print_stats(&format!("PUT /lights/{}/state", target.id), &mut put_times);
+
+    Ok(())
+}
+
+fn print_stats(label: &str, times: &mut [Duration]) {
+    times.sort();
+    println!(
+        "{label:24} min {min:>7.1} ms  median {median:>7.1} ms  p95 {p95:>7.1} ms",
+        label = label,
+        min = as_millis(times[0]),
+        median = as_millis(percentile(times, 50)),
+        p95 = as_millis(percentile(times, 95)),
+    );
+}
+
+fn percentile(sorted: &[Duration], pct: usize) -> Duration {
+    let index = (sorted.len() * pct).div_ceil(100);
+    sorted[index.saturating_sub(1).min(sorted.len() - 1)]
+}
+
+fn as_millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -0,0 +1,296 @@
+use crate::api::{ApiError, Bridge, LINK_BUTTON_NOT_PRESSED};
+use crate::config::{self, Config};
+use crate::discovery;
+use crate::options::ConnectionOpt;
+use crate::remote;
+use crate::time::format_duration;
+use crate::trace::Recorder;
+use eyre::{eyre, Report, Result};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Time between attempts to pair while waiting for the link button to be pressed.
+const PAIRING_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+fn unauth_bridge(opt: &ConnectionOpt, config: &Config) -> Result<Bridge> {
+    if let Some(path) = &opt.replay {
+        return Bridge::replay(path);
+    }
+    match opt.bridge.as_ref().or(config.bridge.host.as_ref()) {
+        Some(host) => for_host(opt, config, host),
+        None => for_host(
+            opt,
+            config,
+            &discovery::discover(discovery_methods(opt, config), config.bridge.compat)?,
+        ),
+    }
+}
+
+fn for_host(opt: &ConnectionOpt, config: &Config, host: &str) -> Result<Bridge> {
+    let bridge = Bridge::for_host(host, config.bridge.compat.default_port())?;
+    recording(opt, bridge)
+}
+
+/// Makes the bridge record its exchanges if asked to with `--record`.
+fn recording(opt: &ConnectionOpt, bridge: Bridge) -> Result<Bridge> {
+    match &opt.record {
+        Some(path) => Ok(bridge.recording(Arc::new(Recorder::open(path)?))),
+        None => Ok(bridge),
+    }
+}
+
+/// The kind of bridge to talk to. Other gateways speak the Hue v1 API with some quirks.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compat {
+    #[default]
+    Hue,
+    Deconz,
+    Diyhue,
+}
+
+impl Compat {
+    /// Returns the port of the v1 API for hosts given without one. deCONZ is usually set up to
+    /// serve it on 8080, as it runs as a user that can't take port 80.
+    pub fn default_port(self) -> u16 {
+        match self {
+            Compat::Hue | Compat::Diyhue => 80,
+            Compat::Deconz => 8080,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Compat::Hue => "Philips Hue bridge",
+            Compat::Deconz => "deCONZ gateway",
+            Compat::Diyhue => "diyHue bridge",
+        }
+    }
+
+    fn pairing_instructions(self) -> String {
+        match self {
+            Compat::Hue => t!("pair-instructions-hue"),
+            Compat::Deconz => t!("pair-instructions-deconz"),
+            Compat::Diyhue => t!("pair-instructions-diyhue"),
+        }
+    }
+}
+
+fn discovery_methods<'a>(opt: &'a ConnectionOpt, config: &'a Config) -> &'a [discovery::Method] {
+    if opt.discovery.is_empty() {
+        &config.bridge.discovery
+    } else {
+        &opt.discovery
+    }
+}
+
+/// Connects to the bridge given on the command line, in the config, or found by discovery.
+pub fn connect(opt: &ConnectionOpt, config: &mut Config) -> Result<Bridge> {
+    let _span = tracing::info_span!("connect").entered();
+    let bridge = connect_bridge(opt, config)?;
+    Ok(bridge.with_connections(config.bridge.connections))
+}
+
+fn connect_bridge(opt: &ConnectionOpt, config: &mut Config) -> Result<Bridge> {
+    if opt.remote {
+        return recording(opt, remote::connect(&config.remote)?);
+    }
+    if opt.replay.is_some() {
+        // The trace has the responses to whatever username was used when recording.
+        return unauth_bridge(opt, config);
+    }
+    let username = match opt
+        .username
+        .clone()
+        .or_else(|| config.bridge.username.clone())
+    {
+        Some(username) => username,
+        None if opt.auto_pair => return register(unauth_bridge(opt, config)?, config, None),
+        None => return Err(eyre!("{}", t!("not-paired"))),
+    };
+    let unauth_bridge = unauth_bridge(opt, config)?;
+    let unauth_bridge = match verify(&unauth_bridge, config) {
+        Err(err) if opt.bridge.is_none() && is_unreachable(&err) => {
+            rediscover(&unauth_bridge, opt, config, err)?
+        }
+        result => result.map(|_| unauth_bridge)?,
+    };
+    Ok(unauth_bridge.with_user(username))
+}
+
+/// Checks that the bridge is the one we paired with, so we never send our username to another
+/// bridge that has taken over the IP address.
+fn verify(unauth_bridge: &Bridge, config: &mut Config) -> Result<()> {
+    let id = unauth_bridge.get_public_config()?.bridgeid;
+    tracing::debug!(
+        host = unauth_bridge.host.as_str(),
+        id = id.as_str(),
+        "bridge identified"
+    );
+    match config.bridge.id {
+        Some(ref expected) if !expected.eq_ignore_ascii_case(&id) => Err(eyre!(
+            "The bridge at {} has ID {}, but blilys was paired with bridge {}. \
+             Refusing to send credentials to it. \
+             Pass --bridge with the right address, or run `blilys pair` to pair with this bridge.",
+            unauth_bridge.host,
+            id,
+            expected
+        )),
+        Some(_) => Ok(()),
+        None => {
+            // Paired before bridge IDs were recorded, so trust the bridge we see now.
+            config.bridge.id = Some(id);
+            if config.path.is_some() {
+                config.save()?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Pairs with the bridge given on the command line, in the config, or found by discovery.
+///
+/// With `wait`, the bridge is polled until its link button is pressed or `wait` has passed,
+/// instead of prompting on stdin.
+pub fn pair(opt: &ConnectionOpt, config: &mut Config, wait: Option<Duration>) -> Result<Bridge> {
+    let _span = tracing::info_span!("pair").entered();
+    let unauth_bridge = unauth_bridge(opt, config)?;
+    register(unauth_bridge, config, wait)
+}
+
+fn is_unreachable(err: &Report) -> bool {
+    err.chain().any(|cause| cause.is::<io::Error>())
+}
+
+/// Finds the paired bridge again after it stopped responding at the configured host, most likely
+/// because it got a new IP address.
+fn rediscover(
+    unreachable: &Bridge,
+    opt: &ConnectionOpt,
+    config: &mut Config,
+    err: Report,
+) -> Result<Bridge> {
+    let expected = match config.bridge.id {
+        Some(ref id) => id.to_owned(),
+        // Without a bridge ID, we can't tell if a discovered bridge is the one we paired with.
+        None => return Err(err),
+    };
+    info!(
+        "The bridge at {} is not responding, trying discovery ...",
+        unreachable.host
+    );
+    tracing::warn!(host = unreachable.host.as_str(), error = %err, "bridge not responding");
+    let bridge = for_host(
+        opt,
+        config,
+        &discovery::discover(discovery_methods(opt, config), config.bridge.compat)?,
+    )?;
+    let id = bridge.get_public_config()?.bridgeid;
+    if !id.eq_ignore_ascii_case(&expected) {
+        return Err(err.wrap_err(format!(
+            "Discovered bridge {} at {}, but blilys was paired with bridge {}",
+            id, bridge.host, expected
+        )));
+    }
+
+    info!("Found the bridge at {}.", bridge.host);
+    config.bridge.host = Some(bridge.host.to_owned());
+    if config.path.is_some() {
+        config.save()?;
+    }
+    Ok(bridge)
+}
+
+fn register(unauth_bridge: Bridge, config: &mut Config, wait: Option<Duration>) -> Result<Bridge> {
+    let compat = config.bridge.compat;
+    info!(
+        "{}",
+        t!(
+            "pair-discovered",
+            bridge = compat.name(),
+            host = unauth_bridge.host
+        )
+    );
+    info!("{}", compat.pairing_instructions());
+    let id = unauth_bridge.get_public_config()?.bridgeid;
+    let devicetype = device_type(&config.bridge);
+    let registration = match wait {
+        None => {
+            info!("{}", t!("pair-press-key"));
+            let mut input = String::new();
+            io::stdin().read_line(&mut input).unwrap();
+            info!("{}", t!("pair-registering"));
+            unauth_bridge.register_user(&devicetype)?
+        }
+        Some(wait) => {
+            info!("{}", t!("pair-waiting", duration = format_duration(wait)));
+            let deadline = Instant::now() + wait;
+            loop {
+                match unauth_bridge.register_user(&devicetype) {
+                    Err(err) if is_link_button_error(&err) && Instant::now() < deadline => {
+                        thread::sleep(PAIRING_POLL_INTERVAL);
+                    }
+                    result => break result?,
+                }
+            }
+        }
+    };
+    let bridge = unauth_bridge.with_user(registration.username);
+    tracing::info!(host = bridge.host.as_str(), "paired");
+    info!("{}", t!("pair-complete"));
+
+    // Keep the host in the form the user gave it, so that hostnames survive IP changes.
+    config.bridge.host = Some(bridge.host.to_owned());
+    config.bridge.username = Some(bridge.username.to_owned());
+    config.bridge.id = Some(id);
+    config.bridge.clientkey = registration.clientkey;
+    if config.path.is_some() {
+        info!("{}", t!("pair-saving"));
+        config.save()?;
+    }
+
+    Ok(bridge)
+}
+
+/// Identifies blilys on this machine in the bridge's list of connected apps, like
+/// `blilys#laptop`.
+pub fn device_type(config: &config::Bridge) -> String {
+    let device = config
+        .device_name
+        .clone()
+        .or_else(hostname)
+        .unwrap_or_default();
+    // The bridge allows at most 19 characters after the `#`.
+    let device: String = device.chars().take(19).collect();
+    if device.is_empty() {
+        "blilys".to_owned()
+    } else {
+        format!("blilys#{}", device)
+    }
+}
+
+/// Returns the name of this machine, without its domain.
+#[cfg(unix)]
+pub fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    let name = String::from_utf8_lossy(&buf[..len]);
+    // Only the first label, so `laptop.example.com` shows up as `laptop`.
+    name.split('.').next().map(str::to_owned)
+}
+
+#[cfg(not(unix))]
+pub fn hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok()
+}
+
+fn is_link_button_error(err: &Report) -> bool {
+    err.downcast_ref::<ApiError>()
+        .is_some_and(|err| err.code == LINK_BUTTON_NOT_PRESSED)
+}
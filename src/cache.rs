@@ -0,0 +1,176 @@
+use crate::backend::LightBackend;
+use crate::time::now;
+use directories::ProjectDirs;
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Stops reading and writing the cache file, for running without any files. Names are then
+/// looked up on the bridge every time.
+pub fn disable() {
+    DISABLED.store(true, Ordering::Relaxed);
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Cache {
+    /// Seconds since the Unix epoch when the cache was last refreshed.
+    pub updated: u64,
+    #[serde(default)]
+    pub lights: Vec<Entry>,
+    #[serde(default)]
+    pub groups: Vec<Entry>,
+    #[serde(default)]
+    pub scenes: Vec<Entry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub id: String,
+    pub name: String,
+}
+
+impl Cache {
+    pub fn fetch(bridge: &dyn LightBackend) -> Result<Cache> {
+        let datastore = bridge.get_datastore()?;
+        Ok(Cache {
+            updated: now(),
+            lights: datastore
+                .lights
+                .into_iter()
+                .map(|il| Entry {
+                    id: il.id.to_string(),
+                    name: il.light.name,
+                })
+                .collect(),
+            groups: datastore
+                .groups
+                .into_iter()
+                .map(|ig| Entry {
+                    id: ig.id.to_string(),
+                    name: ig.group.name,
+                })
+                .collect(),
+            scenes: datastore
+                .scenes
+                .into_iter()
+                .map(|is| Entry {
+                    id: is.id,
+                    name: is.scene.name,
+                })
+                .collect(),
+        })
+    }
+
+    pub fn load() -> Result<Option<Cache>> {
+        if DISABLED.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        let path = Cache::get_path()?;
+        if !path.is_file() {
+            return Ok(None);
+        }
+        // A cache that no longer parses is as good as no cache.
+        Ok(serde_json::from_str(&fs::read_to_string(path)?).ok())
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if DISABLED.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let path = Cache::get_path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn clear() -> Result<()> {
+        let path = Cache::get_path()?;
+        if path.is_file() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    pub fn refresh(bridge: &dyn LightBackend) -> Result<Cache> {
+        let cache = Cache::fetch(bridge)?;
+        cache.save()?;
+        Ok(cache)
+    }
+
+    pub fn is_fresh(&self, ttl: Duration) -> bool {
+        now().saturating_sub(self.updated) < ttl.as_secs()
+    }
+
+    fn get_path() -> Result<PathBuf> {
+        let project_dirs = ProjectDirs::from("", "", "blilys").expect("Cache dir not readable");
+        Ok(project_dirs.cache_dir().join("cache.json"))
+    }
+}
+
+/// Resolves a light given by ID or name to its ID.
+pub fn resolve_light(bridge: &dyn LightBackend, ttl: Duration, name: &str) -> Result<usize> {
+    resolve(bridge, ttl, name, "light", |cache| &cache.lights)
+}
+
+/// Resolves a group given by ID or name to its ID.
+pub fn resolve_group(bridge: &dyn LightBackend, ttl: Duration, name: &str) -> Result<usize> {
+    resolve(bridge, ttl, name, "group", |cache| &cache.groups)
+}
+
+/// Resolves a scene given by ID or name to its ID.
+pub fn resolve_scene(bridge: &dyn LightBackend, ttl: Duration, name: &str) -> Result<String> {
+    let find = |cache: &Cache| {
+        cache
+            .scenes
+            .iter()
+            .find(|e| e.id == name || e.name.eq_ignore_ascii_case(name))
+            .map(|e| e.id.clone())
+    };
+    if let Some(cache) = Cache::load()? {
+        if cache.is_fresh(ttl) {
+            if let Some(id) = find(&cache) {
+                return Ok(id);
+            }
+        }
+    }
+    let cache = Cache::refresh(bridge)?;
+    find(&cache).ok_or_else(|| eyre!("No scene named {:?}", name))
+}
+
+fn resolve(
+    bridge: &dyn LightBackend,
+    ttl: Duration,
+    name: &str,
+    kind: &str,
+    entries: impl Fn(&Cache) -> &[Entry],
+) -> Result<usize> {
+    if let Ok(id) = name.parse::<usize>() {
+        return Ok(id);
+    }
+
+    let find = |cache: &Cache| {
+        entries(cache)
+            .iter()
+            .find(|e| e.name.eq_ignore_ascii_case(name))
+            .and_then(|e| e.id.parse::<usize>().ok())
+    };
+
+    if let Some(cache) = Cache::load()? {
+        if cache.is_fresh(ttl) {
+            if let Some(id) = find(&cache) {
+                return Ok(id);
+            }
+        }
+    }
+
+    // The cache is missing, stale, or doesn't know the name yet.
+    let cache = Cache::refresh(bridge)?;
+    find(&cache).ok_or_else(|| eyre!("No {} named {:?}", kind, name))
+}
@@ -0,0 +1,673 @@
+use crate::config::CalendarRule;
+use crate::time::{
+    civil_from_days, days_from_civil, epoch_from_local, epoch_from_utc, Date, TimeOfDay,
+};
+use crate::zone::Zone;
+use eyre::{eyre, Result, WrapErr};
+use std::collections::HashSet;
+use std::fs;
+use std::time::Duration;
+
+/// An event in an iCalendar file, with times in seconds since the Unix epoch.
+#[derive(Debug, PartialEq)]
+pub struct Event {
+    pub summary: String,
+    pub start: u64,
+    pub end: u64,
+}
+
+/// Fetches the events of a calendar given by URL, or by path for local files.
+pub fn fetch(url: &str) -> Result<Vec<Event>> {
+    let contents = if url.starts_with("http://") || url.starts_with("https://") {
+        reqwest::blocking::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()?
+            .get(url)
+            .send()
+            .and_then(|resp| resp.error_for_status())
+            .and_then(|resp| resp.text())
+            .wrap_err("Failed to fetch the calendar")?
+    } else {
+        fs::read_to_string(url).map_err(|err| eyre!("Failed to read {}: {}", url, err))?
+    };
+    Ok(parse(&contents, crate::time::now() + HORIZON.as_secs()))
+}
+
+/// How far ahead occurrences of recurring events are looked for, far enough for the holidays of
+/// next year.
+const HORIZON: Duration = Duration::from_secs(400 * 24 * 60 * 60);
+
+/// Parses the events of an iCalendar file, with the occurrences of recurring events starting up
+/// to `until`, in seconds since the Unix epoch.
+///
+/// Times are taken in the time zone of their `TZID`, looked up in the system's time zone
+/// database, or in the system's time zone if it isn't found there. Events that can't be read are
+/// skipped, so that one odd event doesn't hide the others.
+pub fn parse(contents: &str, until: u64) -> Vec<Event> {
+    parse_with(contents, until, &|name| Zone::named(name).ok())
+}
+
+fn parse_with(contents: &str, until: u64, zones: &dyn Fn(&str) -> Option<Zone>) -> Vec<Event> {
+    let mut occurrences = vec![];
+    // Occurrences of recurring events that were moved, by UID and original start.
+    let mut moved = HashSet::new();
+    for properties in components(contents) {
+        match occurrences_of(&properties, until, zones) {
+            Ok(Occurrences {
+                uid,
+                recurrence_id,
+                events,
+            }) => {
+                if let Some(start) = recurrence_id {
+                    moved.insert((uid.clone(), start));
+                }
+                occurrences.extend(events.into_iter().map(|event| (uid.clone(), event)));
+            }
+            Err(err) => {
+                let summary = properties
+                    .iter()
+                    .find(|property| property.name == "SUMMARY")
+                    .map(|property| unescape(&property.value))
+                    .unwrap_or_default();
+                eprintln!("Skipping event {:?} in the calendar: {:#}", summary, err);
+            }
+        }
+    }
+    occurrences
+        .into_iter()
+        .filter(|(uid, event)| !(event.repeated && moved.contains(&(uid.clone(), event.start))))
+        .map(|(_, event)| Event {
+            summary: event.summary,
+            start: event.start,
+            end: event.end,
+        })
+        .collect()
+}
+
+/// A property of an event, like `DTSTART;TZID=Europe/Oslo:20261015T090000`.
+struct Property {
+    name: String,
+    params: String,
+    value: String,
+}
+
+impl Property {
+    /// Returns a parameter of the property, like `TZID`.
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .split(';')
+            .filter_map(|param| param.split_once('='))
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim_matches('"'))
+    }
+}
+
+/// Returns the properties of each event, leaving out those of components within events, like
+/// alarms.
+fn components(contents: &str) -> Vec<Vec<Property>> {
+    let mut events = vec![];
+    let mut current: Option<Vec<Property>> = None;
+    let mut nested = 0;
+    for line in unfold(contents) {
+        let (name, value) = match line.split_once(':') {
+            Some(property) => property,
+            None => continue,
+        };
+        // Parameters follow the name, like `DTSTART;TZID=Europe/Oslo`.
+        let (name, params) = name.split_once(';').unwrap_or((name, ""));
+        match (name, &mut current) {
+            ("BEGIN", None) if value == "VEVENT" => current = Some(vec![]),
+            ("BEGIN", Some(_)) => nested += 1,
+            ("END", Some(_)) if nested > 0 => nested -= 1,
+            ("END", Some(_)) if value == "VEVENT" => events.extend(current.take()),
+            (_, Some(properties)) if nested == 0 => properties.push(Property {
+                name: name.to_ascii_uppercase(),
+                params: params.to_owned(),
+                value: value.to_owned(),
+            }),
+            _ => {}
+        }
+    }
+    events
+}
+
+/// The occurrences of an event, and what identifies it among recurring events.
+struct Occurrences {
+    uid: String,
+    /// The original start of the occurrence of a recurring event this event replaces.
+    recurrence_id: Option<u64>,
+    events: Vec<Occurrence>,
+}
+
+struct Occurrence {
+    summary: String,
+    start: u64,
+    end: u64,
+    /// Whether the occurrence comes from a repetition rule, and may be replaced.
+    repeated: bool,
+}
+
+fn occurrences_of(
+    properties: &[Property],
+    until: u64,
+    zones: &dyn Fn(&str) -> Option<Zone>,
+) -> Result<Occurrences> {
+    let get = |name: &str| properties.iter().find(|property| property.name == name);
+    let start = get("DTSTART").ok_or_else(|| eyre!("The event has no start"))?;
+    let start = CalendarTime::parse(start, zones)?;
+    let end = match get("DTEND") {
+        Some(end) => CalendarTime::parse(end, zones)?,
+        None => start.clone(),
+    };
+    let summary = get("SUMMARY")
+        .map(|summary| unescape(&summary.value))
+        .unwrap_or_default();
+    let recurrence_id = match get("RECURRENCE-ID") {
+        Some(id) => Some(CalendarTime::parse(id, zones)?.epoch()),
+        None => None,
+    };
+    let excluded = properties
+        .iter()
+        .filter(|property| property.name == "EXDATE")
+        .flat_map(|exdate| {
+            exdate.value.split(',').map(move |value| {
+                let property = Property {
+                    name: exdate.name.clone(),
+                    params: exdate.params.clone(),
+                    value: value.to_owned(),
+                };
+                CalendarTime::parse(&property, zones).map(|time| time.epoch())
+            })
+        })
+        .collect::<Result<HashSet<u64>>>()?;
+
+    let dates = match get("RRULE") {
+        Some(rule) => Rule::parse(&rule.value, &start, zones)?.dates(&start, until),
+        None => vec![start.date],
+    };
+    // Ends keep their distance in days and their time of day from the starts, so that events
+    // keep their local times across changes to daylight saving time.
+    let days = days_from_civil(end.date) - days_from_civil(start.date);
+    let events = dates
+        .into_iter()
+        .map(|date| Occurrence {
+            summary: summary.clone(),
+            start: start.on(date).epoch(),
+            end: end
+                .on(civil_from_days(days_from_civil(date) + days))
+                .epoch(),
+            repeated: get("RRULE").is_some(),
+        })
+        .filter(|occurrence| !excluded.contains(&occurrence.start))
+        .collect();
+    Ok(Occurrences {
+        uid: get("UID").map(|uid| uid.value.clone()).unwrap_or_default(),
+        recurrence_id,
+        events,
+    })
+}
+
+/// A time as written in a calendar, on a date in a time zone.
+#[derive(Clone)]
+struct CalendarTime {
+    date: Date,
+    time: TimeOfDay,
+    zone: CalendarZone,
+}
+
+#[derive(Clone)]
+enum CalendarZone {
+    Utc,
+    /// The system's time zone, for times without a zone, all-day events, and unknown zones.
+    Local,
+    Named(Zone),
+}
+
+impl CalendarTime {
+    /// Parses a time like `20261015T090000Z` in UTC, `20261015T090000` in the time zone of the
+    /// `TZID` parameter or local time, or a date like `20261015` for all-day events, starting at
+    /// local midnight.
+    fn parse(property: &Property, zones: &dyn Fn(&str) -> Option<Zone>) -> Result<CalendarTime> {
+        let value = property.value.as_str();
+        let invalid = || eyre!("Invalid time {:?} in calendar", value);
+        let number = |range: std::ops::Range<usize>| -> Result<u32> {
+            value
+                .get(range)
+                .and_then(|digits| digits.parse().ok())
+                .ok_or_else(invalid)
+        };
+        let date: Date = (number(0..4)?.into(), number(4..6)?, number(6..8)?);
+        if !is_valid(date) {
+            return Err(invalid());
+        }
+        if property.param("VALUE") == Some("DATE") || value.len() == 8 {
+            return Ok(CalendarTime {
+                date,
+                time: MIDNIGHT,
+                zone: CalendarZone::Local,
+            });
+        }
+        if value.get(8..9) != Some("T") {
+            return Err(invalid());
+        }
+        let time = TimeOfDay {
+            hour: number(9..11)? as u8,
+            minute: number(11..13)? as u8,
+            second: number(13..15)? as u8,
+        };
+        let zone = if value.ends_with('Z') {
+            CalendarZone::Utc
+        } else {
+            // Zones not in the system's database, like Windows' names in calendars from Outlook,
+            // are most likely the system's own.
+            match property.param("TZID").and_then(zones) {
+                Some(zone) => CalendarZone::Named(zone),
+                None => CalendarZone::Local,
+            }
+        };
+        Ok(CalendarTime { date, time, zone })
+    }
+
+    /// Returns the same time of day on another date.
+    fn on(&self, date: Date) -> CalendarTime {
+        CalendarTime {
+            date,
+            ..self.clone()
+        }
+    }
+
+    fn epoch(&self) -> u64 {
+        match &self.zone {
+            CalendarZone::Utc => epoch_from_utc(self.date, self.time),
+            CalendarZone::Local => epoch_from_local(self.date, self.time),
+            CalendarZone::Named(zone) => zone.epoch(self.date, self.time),
+        }
+    }
+}
+
+const MIDNIGHT: TimeOfDay = TimeOfDay {
+    hour: 0,
+    minute: 0,
+    second: 0,
+};
+
+/// Whether the date exists, unlike February 30.
+fn is_valid(date: Date) -> bool {
+    (1..=12).contains(&date.1) && civil_from_days(days_from_civil(date)) == date
+}
+
+/// A repetition rule, like `FREQ=WEEKLY;BYDAY=MO,WE;COUNT=10`.
+///
+/// Only rules repeating daily, weekly on some days, monthly on the day of the month, or yearly on
+/// the date are supported.
+struct Rule {
+    freq: Freq,
+    interval: i64,
+    count: Option<usize>,
+    /// Seconds since the Unix epoch of the last start.
+    until: Option<u64>,
+    /// Days of the week, from Monday as 0, for weekly rules.
+    weekdays: Vec<i64>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Freq {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+impl Rule {
+    fn parse(
+        rule: &str,
+        start: &CalendarTime,
+        zones: &dyn Fn(&str) -> Option<Zone>,
+    ) -> Result<Rule> {
+        let invalid = |part: &str| eyre!("Unsupported repetition rule {:?} at {:?}", rule, part);
+        let mut freq = None;
+        let mut parsed = Rule {
+            freq: Freq::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            weekdays: vec![],
+        };
+        for part in rule.split(';').filter(|part| !part.is_empty()) {
+            let (key, value) = part.split_once('=').ok_or_else(|| invalid(part))?;
+            match key {
+                "FREQ" => {
+                    freq = Some(match value {
+                        "DAILY" => Freq::Daily,
+                        "WEEKLY" => Freq::Weekly,
+                        "MONTHLY" => Freq::Monthly,
+                        "YEARLY" => Freq::Yearly,
+                        _ => return Err(invalid(part)),
+                    })
+                }
+                "INTERVAL" => {
+                    parsed.interval = value
+                        .parse()
+                        .ok()
+                        .filter(|&interval| interval > 0)
+                        .ok_or_else(|| invalid(part))?
+                }
+                "COUNT" => parsed.count = Some(value.parse().map_err(|_| invalid(part))?),
+                "UNTIL" => {
+                    // Unless in UTC, the end is in the same time zone as the start.
+                    let property = Property {
+                        name: "UNTIL".to_owned(),
+                        params: String::new(),
+                        value: value.to_owned(),
+                    };
+                    let until = CalendarTime::parse(&property, zones)?;
+                    let until = match until.zone {
+                        CalendarZone::Utc => until,
+                        _ => CalendarTime {
+                            zone: start.zone.clone(),
+                            ..until
+                        },
+                    };
+                    parsed.until = Some(until.epoch());
+                }
+                "BYDAY" => {
+                    parsed.weekdays = value
+                        .split(',')
+                        .map(|day| WEEKDAYS.iter().position(|name| *name == day))
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or_else(|| invalid(part))?
+                        .into_iter()
+                        .map(|day| day as i64)
+                        .collect();
+                }
+                // Weeks only matter to rules on several days of weeks more than one apart.
+                "WKST" => {}
+                _ => return Err(invalid(part)),
+            }
+        }
+        parsed.freq = freq.ok_or_else(|| eyre!("Repetition rule {:?} has no FREQ", rule))?;
+        if !parsed.weekdays.is_empty() && parsed.freq != Freq::Weekly {
+            return Err(invalid("BYDAY"));
+        }
+        Ok(parsed)
+    }
+
+    /// Returns the dates the rule repeats on from `time`, starting up to `until`.
+    fn dates(&self, time: &CalendarTime, until: u64) -> Vec<Date> {
+        let start = time.date;
+        let first = days_from_civil(start);
+        let last = self.until.map_or(until, |rule_until| rule_until.min(until));
+        let past = |date: Date| time.on(date).epoch() > last;
+        let limit = self.count.unwrap_or(usize::MAX);
+        let mut dates = vec![];
+        for period in 0.. {
+            let candidates: Vec<Date> = match self.freq {
+                Freq::Daily => vec![civil_from_days(first + period * self.interval)],
+                Freq::Weekly if self.weekdays.is_empty() => {
+                    vec![civil_from_days(first + period * self.interval * 7)]
+                }
+                Freq::Weekly => {
+                    let monday = first - (first + 3).rem_euclid(7) + period * self.interval * 7;
+                    let mut days = self.weekdays.clone();
+                    days.sort_unstable();
+                    days.dedup();
+                    days.into_iter()
+                        .map(|day| monday + day)
+                        .filter(|&day| day >= first)
+                        .map(civil_from_days)
+                        .collect()
+                }
+                Freq::Monthly => {
+                    let months = start.1 as i64 - 1 + period * self.interval;
+                    let year = start.0 + months.div_euclid(12);
+                    vec![(year, months.rem_euclid(12) as u32 + 1, start.2)]
+                }
+                Freq::Yearly => vec![(start.0 + period * self.interval, start.1, start.2)],
+            };
+            // Months without the day, like February 30, are skipped, once their first day is
+            // still before the end.
+            if candidates
+                .first()
+                .is_some_and(|&(year, month, _)| past((year, month, 1)))
+            {
+                break;
+            }
+            let in_period = candidates.into_iter().filter(|&date| is_valid(date));
+            for date in in_period {
+                if past(date) || dates.len() == limit {
+                    return dates;
+                }
+                dates.push(date);
+            }
+            if dates.len() == limit {
+                break;
+            }
+        }
+        dates
+    }
+}
+
+/// Days of the week in repetition rules, from Monday.
+const WEEKDAYS: [&str; 7] = ["MO", "TU", "WE", "TH", "FR", "SA", "SU"];
+
+/// Joins lines folded onto continuation lines starting with a space or tab.
+fn unfold(contents: &str) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    for line in contents.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ => lines.push(line.to_owned()),
+        }
+    }
+    lines
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("\\n", " ")
+        .replace("\\N", " ")
+        .replace("\\,", ",")
+        .replace("\\;", ";")
+        .replace("\\\\", "\\")
+}
+
+/// Returns the command lines of the rules due after `after` and up to `until`, in seconds since
+/// the Unix epoch.
+pub fn due<'a>(
+    events: &[Event],
+    rules: &'a [CalendarRule],
+    after: u64,
+    until: u64,
+) -> Vec<&'a str> {
+    let mut commands = vec![];
+    for event in events {
+        let summary = event.summary.to_lowercase();
+        for rule in rules {
+            if !summary.contains(&rule.summary.to_lowercase()) {
+                continue;
+            }
+            let start = event.start.saturating_sub(rule.before.as_secs());
+            if after < start && start <= until {
+                commands.push(rule.start.as_str());
+            }
+            if let Some(end) = &rule.end {
+                if after < event.end && event.end <= until {
+                    commands.push(end.as_str());
+                }
+            }
+        }
+    }
+    commands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CALENDAR: &str = "\
+BEGIN:VCALENDAR\r
+VERSION:2.0\r
+BEGIN:VEVENT\r
+UID:1\r
+SUMMARY:Team meeting\\, weekly\r
+DTSTART:20261015T090000Z\r
+DTEND:20261015T093000Z\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:2\r
+SUMMARY:Lunch with a very long name that is folded onto\r
+  another line\r
+DTSTART:20261015T110000Z\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+    #[test]
+    fn parses_events() {
+        let events = parse(CALENDAR, u64::MAX);
+        assert_eq!(
+            events,
+            vec![
+                Event {
+                    summary: "Team meeting, weekly".to_owned(),
+                    start: 1_792_054_800,
+                    end: 1_792_056_600,
+                },
+                Event {
+                    summary: "Lunch with a very long name that is folded onto another line"
+                        .to_owned(),
+                    start: 1_792_062_000,
+                    end: 1_792_062_000,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rules_are_due_before_start_and_at_end() {
+        let events = parse(CALENDAR, u64::MAX);
+        let rules = [CalendarRule {
+            summary: "MEETING".to_owned(),
+            before: Duration::from_secs(5 * 60),
+            start: "office on --color red".to_owned(),
+            end: Some("office off".to_owned()),
+        }];
+        let start = 1_792_054_800 - 5 * 60;
+        assert!(due(&events, &rules, start - 10, start - 1).is_empty());
+        assert_eq!(
+            due(&events, &rules, start - 1, start),
+            vec!["office on --color red"]
+        );
+        assert!(due(&events, &rules, start, start + 10).is_empty());
+        assert_eq!(
+            due(&events, &rules, 1_792_056_590, 1_792_056_600),
+            vec!["office off"]
+        );
+    }
+
+    fn starts(calendar: &str, until: u64) -> Vec<u64> {
+        let oslo = Zone::posix("CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+        parse_with(calendar, until, &|name| {
+            Some(oslo.clone()).filter(|_| name == "Europe/Oslo")
+        })
+        .into_iter()
+        .map(|event| event.start)
+        .collect()
+    }
+
+    #[test]
+    fn recurring_events_are_expanded() {
+        let weekly = "\
+BEGIN:VEVENT\r
+DTSTART:20261015T090000Z\r
+RRULE:FREQ=WEEKLY;BYDAY=TH,FR;COUNT=3\r
+END:VEVENT\r
+";
+        let day = 24 * 60 * 60;
+        assert_eq!(
+            starts(weekly, u64::MAX),
+            [1_792_054_800, 1_792_054_800 + day, 1_792_054_800 + 7 * day]
+        );
+        let daily = "\
+BEGIN:VEVENT\r
+DTSTART:20261015T090000Z\r
+RRULE:FREQ=DAILY;INTERVAL=2;UNTIL=20261019T090000Z\r
+END:VEVENT\r
+";
+        assert_eq!(
+            starts(daily, u64::MAX),
+            [
+                1_792_054_800,
+                1_792_054_800 + 2 * day,
+                1_792_054_800 + 4 * day
+            ]
+        );
+        assert_eq!(starts(daily, 1_792_054_800 + day), [1_792_054_800]);
+    }
+
+    #[test]
+    fn moved_and_excluded_occurrences_are_left_out() {
+        let calendar = "\
+BEGIN:VEVENT\r
+UID:standup\r
+DTSTART:20261015T090000Z\r
+RRULE:FREQ=DAILY;COUNT=3\r
+EXDATE:20261017T090000Z\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:standup\r
+RECURRENCE-ID:20261016T090000Z\r
+DTSTART:20261016T100000Z\r
+END:VEVENT\r
+";
+        assert_eq!(
+            starts(calendar, u64::MAX),
+            [1_792_054_800, 1_792_054_800 + 25 * 60 * 60]
+        );
+    }
+
+    #[test]
+    fn times_are_read_in_their_time_zone() {
+        // 09:00 in Oslo is 07:00 UTC in summer time, and 08:00 UTC after it ends on October 25.
+        let calendar = "\
+BEGIN:VEVENT\r
+DTSTART;TZID=Europe/Oslo:20261022T090000\r
+RRULE:FREQ=WEEKLY;COUNT=2\r
+END:VEVENT\r
+";
+        let first = epoch_from_utc(
+            (2026, 10, 22),
+            TimeOfDay {
+                hour: 7,
+                minute: 0,
+                second: 0,
+            },
+        );
+        let second = epoch_from_utc(
+            (2026, 10, 29),
+            TimeOfDay {
+                hour: 8,
+                minute: 0,
+                second: 0,
+            },
+        );
+        assert_eq!(starts(calendar, u64::MAX), [first, second]);
+    }
+
+    #[test]
+    fn malformed_events_are_skipped() {
+        let calendar = "\
+BEGIN:VEVENT\r
+SUMMARY:No start\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+DTSTART:20261015T090000Z\r
+RRULE:FREQ=MONTHLY;BYSETPOS=-1\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+DTSTART:20261015T090000Z\r
+END:VEVENT\r
+";
+        assert_eq!(starts(calendar, u64::MAX), [1_792_054_800]);
+    }
+}
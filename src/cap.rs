@@ -0,0 +1,111 @@
+use crate::backend::{Datastore, LightBackend, Members};
+use crate::config::Config;
+use crate::target::Target;
+use crate::targets;
+use crate::values::parse_brightness;
+use eyre::{eyre, Result};
+use hueclient::{CommandLight, IdentifiedGroup, IdentifiedLight, IdentifiedScene};
+use std::collections::HashMap;
+
+/// A backend that clamps the brightness of commands to the caps configured in `[caps]`.
+///
+/// A cap on a group also applies to each of its lights, and a command to a group is clamped to
+/// the lowest cap of any of its lights.
+pub struct Capped<'a> {
+    inner: &'a dyn LightBackend,
+    /// Caps of lights, including those set on their groups.
+    lights: HashMap<usize, u8>,
+    members: &'a Members,
+}
+
+impl<'a> Capped<'a> {
+    pub fn new(
+        inner: &'a dyn LightBackend,
+        config: &Config,
+        members: &'a Members,
+    ) -> Result<Capped<'a>> {
+        let mut capped = Capped {
+            inner,
+            lights: HashMap::new(),
+            members,
+        };
+        for (spec, cap) in &config.caps {
+            let cap = parse_brightness(cap)
+                .map_err(|err| eyre!("Invalid cap for {:?}: {}", spec, err))?;
+            let target = targets::resolve(spec, inner, config)
+                .map_err(|err| eyre!("Invalid target {:?} in caps: {}", spec, err))?;
+            let group = match target {
+                Target::Light(id) => {
+                    lower(&mut capped.lights, id, cap);
+                    continue;
+                }
+                Target::Group(id) => id,
+                Target::All => 0,
+                Target::Lights(ids) => {
+                    for id in ids {
+                        lower(&mut capped.lights, id, cap);
+                    }
+                    continue;
+                }
+            };
+            for &id in members.of(group) {
+                lower(&mut capped.lights, id, cap);
+            }
+        }
+        Ok(capped)
+    }
+
+    fn group_cap(&self, group: usize) -> Option<u8> {
+        self.members
+            .of(group)
+            .iter()
+            .filter_map(|id| self.lights.get(id))
+            .copied()
+            .min()
+    }
+}
+
+fn lower(caps: &mut HashMap<usize, u8>, id: usize, cap: u8) {
+    let entry = caps.entry(id).or_insert(cap);
+    *entry = (*entry).min(cap);
+}
+
+fn clamp(command: &CommandLight, cap: Option<u8>) -> CommandLight {
+    let mut command = command.clone();
+    if let (Some(bri), Some(cap)) = (command.bri, cap) {
+        command.bri = Some(bri.min(cap));
+    }
+    command
+}
+
+impl LightBackend for Capped<'_> {
+    fn describe(&self) -> String {
+        self.inner.describe()
+    }
+
+    fn get_all_lights(&self) -> Result<Vec<IdentifiedLight>> {
+        self.inner.get_all_lights()
+    }
+
+    fn get_all_groups(&self) -> Result<Vec<IdentifiedGroup>> {
+        self.inner.get_all_groups()
+    }
+
+    fn get_all_scenes(&self) -> Result<Vec<IdentifiedScene>> {
+        self.inner.get_all_scenes()
+    }
+
+    fn set_light_state(&self, light: usize, command: &CommandLight) -> Result<()> {
+        let cap = self.lights.get(&light).copied();
+        self.inner.set_light_state(light, &clamp(command, cap))
+    }
+
+    fn set_group_state(&self, group: usize, command: &CommandLight) -> Result<()> {
+        self.inner
+            .set_group_state(group, &clamp(command, self.group_cap(group)))
+    }
+
+    fn get_datastore(&self) -> Result<Datastore> {
+        self.inner.get_datastore()
+    }
+}
@@ -0,0 +1,148 @@
+use crate::backend::LightBackend;
+use crate::target::Target;
+use crate::values::parse_color;
+use eyre::{eyre, Result, WrapErr};
+use hueclient::CommandLight;
+use reqwest::blocking::Client;
+use serde::Deserialize;
+use std::env;
+use std::thread;
+use std::time::Duration;
+
+/// Environment variable with a GitHub token, needed for private repos and higher rate limits.
+const TOKEN_VAR: &str = "GITHUB_TOKEN";
+
+/// Status of a repo's latest workflow run, as shown by the light.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Status {
+    Passing,
+    Failing,
+    Running,
+}
+
+impl Status {
+    fn color(self) -> &'static str {
+        match self {
+            Status::Passing => "green",
+            Status::Failing => "red",
+            Status::Running => "yellow",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Runs {
+    workflow_runs: Vec<Run>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Run {
+    status: String,
+    conclusion: Option<String>,
+}
+
+impl Run {
+    /// Returns the status, or `None` for runs that neither passed nor failed, like cancelled
+    /// ones.
+    fn status(&self) -> Option<Status> {
+        match (self.status.as_str(), self.conclusion.as_deref()) {
+            ("completed", Some("success")) => Some(Status::Passing),
+            ("completed", Some("failure" | "timed_out" | "startup_failure")) => {
+                Some(Status::Failing)
+            }
+            ("completed", _) => None,
+            _ => Some(Status::Running),
+        }
+    }
+}
+
+fn fetch(client: &Client, repo: &str, branch: Option<&str>) -> Result<Option<Status>> {
+    let mut request = client
+        .get(&format!(
+            "https://api.github.com/repos/{}/actions/runs",
+            repo
+        ))
+        .query(&[("per_page", "1")]);
+    if let Some(branch) = branch {
+        request = request.query(&[("branch", branch)]);
+    }
+    if let Ok(token) = env::var(TOKEN_VAR) {
+        request = request.bearer_auth(token);
+    }
+    let runs: Runs = request
+        .send()
+        .and_then(|resp| resp.error_for_status())
+        .and_then(|resp| resp.json())
+        .wrap_err_with(|| format!("Failed to fetch workflow runs of {}", repo))?;
+    let run = runs
+        .workflow_runs
+        .first()
+        .ok_or_else(|| eyre!("{} has no workflow runs", repo))?;
+    Ok(run.status())
+}
+
+/// Colors the target by the status of the GitHub repo's latest workflow run every `interval`,
+/// until stopped.
+pub fn watch(
+    backend: &dyn LightBackend,
+    target: Target,
+    repo: &str,
+    branch: Option<&str>,
+    interval: Duration,
+) -> Result<()> {
+    let client = Client::builder()
+        .user_agent(concat!("blilys/", env!("CARGO_PKG_VERSION")))
+        .timeout(Duration::from_secs(10))
+        .build()?;
+    info!(
+        "Showing the CI status of {} on {}. Press Ctrl-C to stop.",
+        repo, target
+    );
+    let mut last = None;
+    loop {
+        match fetch(&client, repo, branch) {
+            Ok(Some(status)) if last != Some(status) => {
+                info!("{} is now {:?}.", repo, status);
+                let command = CommandLight {
+                    on: Some(true),
+                    xy: parse_color(status.color()).ok(),
+                    ..CommandLight::default()
+                };
+                match target.set_state(backend, &command) {
+                    Ok(()) => last = Some(status),
+                    Err(err) => eprintln!("Failed to set {}: {}", target, err),
+                }
+            }
+            Ok(_) => {}
+            Err(err) => eprintln!("{:#}", err),
+        }
+        thread::sleep(interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Run, Status};
+
+    fn run(status: &str, conclusion: Option<&str>) -> Run {
+        Run {
+            status: status.to_owned(),
+            conclusion: conclusion.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn runs_are_classified() {
+        assert_eq!(
+            run("completed", Some("success")).status(),
+            Some(Status::Passing)
+        );
+        assert_eq!(
+            run("completed", Some("timed_out")).status(),
+            Some(Status::Failing)
+        );
+        assert_eq!(run("completed", Some("cancelled")).status(), None);
+        assert_eq!(run("in_progress", None).status(), Some(Status::Running));
+        assert_eq!(run("queued", None).status(), Some(Status::Running));
+    }
+}
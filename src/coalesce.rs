@@ -0,0 +1,106 @@
+use eyre::Result;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Paces commands to each target to what the bridge can take, replacing commands that are still
+/// waiting with newer ones, like the stream of brightness changes from a volume knob.
+#[derive(Default)]
+pub struct Coalescer {
+    targets: Mutex<HashMap<String, Slot>>,
+}
+
+#[derive(Default)]
+struct Slot {
+    /// When the next command to the target may be sent.
+    next_send: Option<Instant>,
+    /// The latest command to the target, if it is waiting and may be replaced, and when it will
+    /// be sent.
+    pending: Option<(Arc<AtomicBool>, Instant)>,
+}
+
+impl Coalescer {
+    /// Calls `send` once the target given by `key` may take another command, at most once per
+    /// `interval`, in the order the commands arrived. A `replaceable` command is dropped if
+    /// another replaceable command to the target arrives while it waits, which then takes its
+    /// turn.
+    pub fn run(
+        &self,
+        key: &str,
+        interval: Duration,
+        replaceable: bool,
+        send: impl FnOnce() -> Result<()>,
+    ) -> Result<()> {
+        let replaced = Arc::new(AtomicBool::new(false));
+        let at = {
+            let mut targets = self.targets.lock().expect("Coalescer lock poisoned");
+            let slot = targets.entry(key.to_owned()).or_default();
+            let at = match slot.pending.take() {
+                Some((previous, at)) if replaceable => {
+                    previous.store(true, Ordering::Relaxed);
+                    at
+                }
+                _ => {
+                    let now = Instant::now();
+                    let at = slot.next_send.filter(|&next| next > now).unwrap_or(now);
+                    slot.next_send = Some(at + interval);
+                    at
+                }
+            };
+            if replaceable {
+                slot.pending = Some((replaced.clone(), at));
+            }
+            at
+        };
+
+        thread::sleep(at.saturating_duration_since(Instant::now()));
+        {
+            let mut targets = self.targets.lock().expect("Coalescer lock poisoned");
+            if replaced.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+            if let Some(slot) = targets.get_mut(key) {
+                if slot
+                    .pending
+                    .as_ref()
+                    .is_some_and(|(pending, _)| Arc::ptr_eq(pending, &replaced))
+                {
+                    slot.pending = None;
+                }
+            }
+        }
+        send()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Coalescer;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn waiting_commands_are_replaced_by_newer_ones() {
+        let coalescer = Coalescer::default();
+        let sent = Mutex::new(vec![]);
+        let run = |n: u32, replaceable: bool| {
+            coalescer
+                .run("light/1", Duration::from_millis(200), replaceable, || {
+                    sent.lock().unwrap().push(n);
+                    Ok(())
+                })
+                .unwrap()
+        };
+        thread::scope(|scope| {
+            for (n, replaceable) in [(1, true), (2, true), (3, true), (4, false), (5, true)] {
+                scope.spawn(move || run(n, replaceable));
+                thread::sleep(Duration::from_millis(20));
+            }
+        });
+        // 2 was replaced by 3, while 4 can't be replaced and keeps 3 from being replaced by 5.
+        assert_eq!(*sent.lock().unwrap(), vec![1, 3, 4, 5]);
+    }
+}
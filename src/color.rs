@@ -0,0 +1,246 @@
+//! Conversions between the color spaces of the bridge and of screens.
+
+/// The triangle of colors a light can show in CIE xy, as red, green, and blue corners.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gamut {
+    red: (f32, f32),
+    green: (f32, f32),
+    blue: (f32, f32),
+}
+
+/// Gamut A, of LivingColors and LightStrips before 2016.
+pub const GAMUT_A: Gamut = Gamut {
+    red: (0.704, 0.296),
+    green: (0.2151, 0.7106),
+    blue: (0.138, 0.08),
+};
+
+/// Gamut B, of the first generations of Hue bulbs.
+pub const GAMUT_B: Gamut = Gamut {
+    red: (0.675, 0.322),
+    green: (0.409, 0.518),
+    blue: (0.167, 0.04),
+};
+
+/// Gamut C, of Hue color lights since 2016.
+pub const GAMUT_C: Gamut = Gamut {
+    red: (0.6915, 0.3083),
+    green: (0.17, 0.7),
+    blue: (0.1532, 0.0475),
+};
+
+impl Gamut {
+    /// Returns the gamut of a light by its model ID, like "LCT007". Models not known to have an
+    /// older gamut are assumed to have gamut C, as all color lights made since 2016 do.
+    pub fn of_model(model: &str) -> Gamut {
+        match model {
+            "LLC001" | "LLC005" | "LLC006" | "LLC007" | "LLC010" | "LLC011" | "LLC012"
+            | "LLC013" | "LLC014" | "LST001" => GAMUT_A,
+            "LCT001" | "LCT002" | "LCT003" | "LCT007" | "LLM001" => GAMUT_B,
+            _ => GAMUT_C,
+        }
+    }
+
+    pub fn contains(&self, (x, y): (f32, f32)) -> bool {
+        let side = |(ax, ay): (f32, f32), (bx, by): (f32, f32)| {
+            (bx - ax) * (y - ay) - (by - ay) * (x - ax)
+        };
+        let sides = [
+            side(self.red, self.green),
+            side(self.green, self.blue),
+            side(self.blue, self.red),
+        ];
+        sides.iter().all(|&s| s >= 0.0) || sides.iter().all(|&s| s <= 0.0)
+    }
+
+    /// Returns the color itself if the gamut has it, or else the closest color on the edge of the
+    /// gamut, like the lights do.
+    pub fn clamp(&self, xy: (f32, f32)) -> (f32, f32) {
+        if self.contains(xy) {
+            return xy;
+        }
+        let distance = |(ax, ay): (f32, f32)| (ax - xy.0).powi(2) + (ay - xy.1).powi(2);
+        let round = |v: f32| (v * 10_000.0).round() / 10_000.0;
+        [
+            closest_on_edge(self.red, self.green, xy),
+            closest_on_edge(self.green, self.blue, xy),
+            closest_on_edge(self.blue, self.red, xy),
+        ]
+        .iter()
+        .copied()
+        .min_by(|a, b| distance(*a).total_cmp(&distance(*b)))
+        .map(|(x, y)| (round(x), round(y)))
+        .expect("a triangle to have edges")
+    }
+}
+
+/// Returns the point on the line segment from `a` to `b` closest to `p`.
+fn closest_on_edge(a: (f32, f32), b: (f32, f32), p: (f32, f32)) -> (f32, f32) {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let t = (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / (dx * dx + dy * dy)).clamp(0.0, 1.0);
+    (a.0 + dx * t, a.1 + dy * t)
+}
+
+/// Converts sRGB to CIE xy, using the wide gamut conversion recommended by Philips.
+pub fn rgb_to_xy(r: u8, g: u8, b: u8) -> (f32, f32) {
+    let linear = |c: u8| {
+        let c = c as f32 / 255.0;
+        if c > 0.04045 {
+            ((c + 0.055) / 1.055).powf(2.4)
+        } else {
+            c / 12.92
+        }
+    };
+    let (r, g, b) = (linear(r), linear(g), linear(b));
+    let x = r * 0.664_511 + g * 0.154_324 + b * 0.162_028;
+    let y = r * 0.283_881 + g * 0.668_433 + b * 0.047_685;
+    let z = r * 0.000_088 + g * 0.072_310 + b * 0.986_039;
+    let sum = x + y + z;
+    if sum == 0.0 {
+        // Black has no chromaticity, so use the white point.
+        return (0.3227, 0.329);
+    }
+    let round = |v: f32| (v * 10_000.0).round() / 10_000.0;
+    (round(x / sum), round(y / sum))
+}
+
+/// Converts CIE xy to sRGB at full brightness, the inverse of `rgb_to_xy`.
+pub fn xy_to_rgb(x: f32, y: f32) -> (u8, u8, u8) {
+    if y <= 0.0 {
+        return (0, 0, 0);
+    }
+    let (big_x, big_z) = (x / y, (1.0 - x - y) / y);
+    let r = big_x * 1.656_492 - 0.354_851 - big_z * 0.255_038;
+    let g = -big_x * 0.707_196 + 1.655_397 + big_z * 0.036_152;
+    let b = big_x * 0.051_713 - 0.121_364 + big_z * 1.011_53;
+    // Scale so that the brightest component is at full brightness, as bri is shown separately.
+    let max = r.max(g).max(b);
+    if max <= 0.0 {
+        return (0, 0, 0);
+    }
+    let gamma = |c: f32| {
+        let c = (c / max).max(0.0);
+        let c = if c <= 0.003_130_8 {
+            12.92 * c
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        };
+        (c * 255.0).round().clamp(0.0, 255.0) as u8
+    };
+    (gamma(r), gamma(g), gamma(b))
+}
+
+/// Converts a color temperature in mireds to sRGB, approximating the color of a black body.
+pub fn mired_to_rgb(mired: u16) -> (u8, u8, u8) {
+    let kelvin = 1_000_000.0 / f32::from(mired.max(1));
+    let t = kelvin / 100.0;
+    let r = if t <= 66.0 {
+        255.0
+    } else {
+        329.699 * (t - 60.0).powf(-0.133_205)
+    };
+    let g = if t <= 66.0 {
+        99.470_8 * t.ln() - 161.119_57
+    } else {
+        288.122_17 * (t - 60.0).powf(-0.075_514_85)
+    };
+    let b = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.517_73 * (t - 10.0).ln() - 305.044_8
+    };
+    let clamp = |c: f32| c.round().clamp(0.0, 255.0) as u8;
+    (clamp(r), clamp(g), clamp(b))
+}
+
+/// Converts a hue from 0 to 65535 and saturation from 0 to 254, as the bridge has them, to sRGB
+/// at full brightness.
+pub fn hue_sat_to_rgb(hue: u16, sat: u8) -> (u8, u8, u8) {
+    let h = f32::from(hue) / 65_536.0 * 6.0;
+    let s = f32::from(sat) / 254.0;
+    let f = h.fract();
+    let (p, q, t) = (1.0 - s, 1.0 - s * f, 1.0 - s * (1.0 - f));
+    let (r, g, b) = match h as u8 {
+        0 => (1.0, t, p),
+        1 => (q, 1.0, p),
+        2 => (p, 1.0, t),
+        3 => (p, q, 1.0),
+        4 => (t, p, 1.0),
+        _ => (1.0, p, q),
+    };
+    let scale = |c: f32| (c * 255.0).round() as u8;
+    (scale(r), scale(g), scale(b))
+}
+
+/// Converts a color temperature in Kelvin to mireds, the bridge's unit.
+pub fn kelvin_to_mired(kelvin: u32) -> u16 {
+    (1_000_000.0 / kelvin.max(1) as f64).round() as u16
+}
+
+/// Converts CIE xy to CIE u'v', where equal distances look about equally different, unlike in xy.
+pub fn xy_to_uv((x, y): (f32, f32)) -> (f64, f64) {
+    let (x, y) = (x as f64, y as f64);
+    let d = -2.0 * x + 12.0 * y + 3.0;
+    (4.0 * x / d, 9.0 * y / d)
+}
+
+/// Converts CIE u'v' to CIE xy, the inverse of `xy_to_uv`.
+pub fn uv_to_xy((u, v): (f64, f64)) -> (f32, f32) {
+    let d = 6.0 * u - 16.0 * v + 12.0;
+    let round = |c: f64| ((c * 10_000.0).round() / 10_000.0) as f32;
+    (round(9.0 * u / d), round(4.0 * v / d))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors_convert_to_rgb() {
+        let (x, y) = rgb_to_xy(255, 128, 0);
+        let (r, g, b) = xy_to_rgb(x, y);
+        assert_eq!(r, 255);
+        assert!((120..=136).contains(&g), "{}", g);
+        assert!(b < 10, "{}", b);
+        assert_eq!(mired_to_rgb(153), (255, 255, 251));
+        assert_eq!(mired_to_rgb(500), (255, 137, 14));
+        assert_eq!(hue_sat_to_rgb(0, 254), (255, 0, 0));
+        assert_eq!(hue_sat_to_rgb(21_845, 254), (0, 255, 0));
+        assert_eq!(hue_sat_to_rgb(12_000, 0), (255, 255, 255));
+        assert_eq!(kelvin_to_mired(2700), 370);
+    }
+
+    #[test]
+    fn primaries_match_philips_reference_values() {
+        assert_eq!(rgb_to_xy(255, 0, 0), (0.7006, 0.2993));
+        assert_eq!(rgb_to_xy(0, 255, 0), (0.1724, 0.7468));
+        assert_eq!(rgb_to_xy(0, 0, 255), (0.1355, 0.0399));
+        assert_eq!(rgb_to_xy(255, 255, 255), (0.3227, 0.329));
+    }
+
+    #[test]
+    fn colors_are_clamped_to_the_gamut() {
+        let white = (0.3227, 0.329);
+        for gamut in [GAMUT_A, GAMUT_B, GAMUT_C].iter() {
+            assert!(gamut.contains(white));
+            assert_eq!(gamut.clamp(white), white);
+        }
+        // Screen red is beyond the red corner of gamut B, and blue beyond its blue corner.
+        assert_eq!(GAMUT_B.clamp((0.7006, 0.2993)), (0.675, 0.322));
+        assert_eq!(GAMUT_B.clamp((0.1355, 0.0399)), (0.167, 0.04));
+        // Screen green is beyond the green corner of gamut C.
+        assert_eq!(GAMUT_C.clamp((0.1724, 0.7468)), (0.17, 0.7));
+        assert!(!GAMUT_A.contains((0.1724, 0.7468)));
+        assert_eq!(Gamut::of_model("LCT007"), GAMUT_B);
+        assert_eq!(Gamut::of_model("LST001"), GAMUT_A);
+        assert_eq!(Gamut::of_model("LCA001"), GAMUT_C);
+    }
+
+    #[test]
+    fn uv_converts_back_to_xy() {
+        assert_eq!(uv_to_xy(xy_to_uv((0.3227, 0.329))), (0.3227, 0.329));
+        assert_eq!(uv_to_xy(xy_to_uv((0.675, 0.322))), (0.675, 0.322));
+    }
+}
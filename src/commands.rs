@@ -0,0 +1,662 @@
+use crate::audit;
+use crate::backend::{Datastore, Event, LightBackend};
+use crate::crossfade::{self, LONGEST_TRANSITION};
+use crate::metrics;
+use crate::options::{Action, GroupBy, LightMode, LightOperation, Power};
+use crate::outcome::Outcomes;
+use crate::output::Style;
+use crate::progress::Progress;
+use crate::queue::{with_priority, Priority};
+use crate::shutdown;
+use crate::snapshot::{SavedLight, Snapshot};
+use crate::table::{Align, Cell, Table};
+use crate::target::Target;
+use crate::template::Template;
+use crate::time::format_duration;
+use eyre::{eyre, Result, WrapErr};
+use hueclient::{CommandLight, IdentifiedLight};
+use rand::distributions::{Distribution, Uniform};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::time::{Duration, Instant};
+
+/// Fields of each group for `blilys groups --format`.
+pub const GROUP_FIELDS: &[&str] = &["id", "name", "type", "lights", "any_on", "all_on", "bri"];
+
+/// Fields of each light for `blilys lights --format`.
+pub const LIGHT_FIELDS: &[&str] = &[
+    "id", "name", "room", "on", "bri", "hue", "sat", "ct", "model",
+];
+
+/// Lists the groups with whether any or all of their lights are on, and the average brightness
+/// of the lights that are on.
+pub fn list_groups(backend: &dyn LightBackend, format: Option<&Template>) -> Result<()> {
+    let datastore = backend.get_datastore()?;
+    let brightness = brightness_of_lights_on(&datastore);
+    let mut table = Table::new(&[
+        Align::Right,
+        Align::Left,
+        Align::Left,
+        Align::Left,
+        Align::Left,
+    ])
+    .shrinking(1);
+    for ig in datastore.groups {
+        let lights = sorted_lights(&ig.group.lights);
+        let average = average_brightness(&brightness, &lights);
+        let state = &ig.group.state;
+        if let Some(format) = format {
+            println!(
+                "{}",
+                format.render(|field| match field {
+                    "id" => ig.id.to_string(),
+                    "name" => ig.group.name.to_owned(),
+                    "type" => ig.group.r#type.to_owned(),
+                    "any_on" => state.any_on.to_string(),
+                    "all_on" => state.all_on.to_string(),
+                    "bri" => average.map(|bri| bri.to_string()).unwrap_or_default(),
+                    _ => lights.join(","),
+                })
+            );
+            continue;
+        }
+        let power = if state.all_on {
+            Cell::styled(format!("[{}]", t!("list-all-on")), Style::Green)
+        } else if state.any_on {
+            Cell::styled(format!("[{}]", t!("list-some-on")), Style::Green)
+        } else {
+            Cell::styled(format!("[{}]", t!("list-off")), Style::Dim)
+        };
+        let average = average.map(|bri| bri.to_string()).unwrap_or_default();
+        table.row(vec![
+            format!("{}:", ig.id).into(),
+            ig.group.name.into(),
+            power,
+            format!("[bri {:>3}]", average).into(),
+            format!("[{}]", lights.join(", ")).into(),
+        ]);
+    }
+    table.print();
+    Ok(())
+}
+
+/// Prints the groups as JSON, with the fields of `blilys groups --format`, and the bridge's own
+/// JSON of each group under `raw` if given.
+pub fn print_groups_json(
+    backend: &dyn LightBackend,
+    raw: Option<&Map<String, Value>>,
+) -> Result<()> {
+    let datastore = backend.get_datastore()?;
+    let brightness = brightness_of_lights_on(&datastore);
+    let groups = datastore
+        .groups
+        .iter()
+        .map(|ig| {
+            let lights = sorted_lights(&ig.group.lights);
+            json!({
+                "id": ig.id,
+                "name": ig.group.name,
+                "type": ig.group.r#type,
+                "lights": lights,
+                "any_on": ig.group.state.any_on,
+                "all_on": ig.group.state.all_on,
+                "bri": average_brightness(&brightness, &lights),
+            })
+        })
+        .collect();
+    print_json(groups, raw)
+}
+
+/// Prints the lights as JSON, with the fields of `blilys lights --format`, and the bridge's own
+/// JSON of each light under `raw` if given.
+pub fn print_lights_json(
+    backend: &dyn LightBackend,
+    raw: Option<&Map<String, Value>>,
+) -> Result<()> {
+    let datastore = backend.get_datastore()?;
+    let rooms = rooms(&datastore);
+    let lights = datastore
+        .lights
+        .iter()
+        .map(|il| {
+            let state = &il.light.state;
+            json!({
+                "id": il.id,
+                "name": il.light.name,
+                "room": rooms.get(&il.id),
+                "on": state.on,
+                "bri": state.bri,
+                "hue": state.hue,
+                "sat": state.sat,
+                "ct": state.ct,
+                "model": il.light.modelid,
+            })
+        })
+        .collect();
+    print_json(lights, raw)
+}
+
+/// Prints resources as a JSON array, adding the bridge's JSON of each by ID under `raw`, for
+/// scripts needing fields blilys doesn't know.
+fn print_json(mut resources: Vec<Value>, raw: Option<&Map<String, Value>>) -> Result<()> {
+    if let Some(raw) = raw {
+        for resource in &mut resources {
+            let id = resource["id"].to_string();
+            resource["raw"] = raw.get(&id).cloned().unwrap_or_default();
+        }
+    }
+    println!("{}", serde_json::to_string_pretty(&resources)?);
+    Ok(())
+}
+
+/// Returns the brightness of each light that is on, by ID.
+fn brightness_of_lights_on(datastore: &Datastore) -> HashMap<String, u8> {
+    datastore
+        .lights
+        .iter()
+        .filter(|il| il.light.state.on)
+        .map(|il| (il.id.to_string(), il.light.state.bri.unwrap_or(0)))
+        .collect()
+}
+
+fn sorted_lights(lights: &[String]) -> Vec<String> {
+    let mut lights = lights.to_owned();
+    lights.sort_by_key(|l| l.parse::<usize>().expect("Light ID to be a number"));
+    lights
+}
+
+/// Returns the average brightness of the lights that are on, if any are.
+fn average_brightness(brightness: &HashMap<String, u8>, lights: &[String]) -> Option<u32> {
+    let on: Vec<u32> = lights
+        .iter()
+        .filter_map(|id| brightness.get(id))
+        .map(|&bri| u32::from(bri))
+        .collect();
+    match on.len() {
+        0 => None,
+        n => Some(on.iter().sum::<u32>() / n as u32),
+    }
+}
+
+/// Returns the room each light is in, by light ID.
+fn rooms(datastore: &Datastore) -> HashMap<usize, String> {
+    datastore
+        .groups
+        .iter()
+        .filter(|ig| ig.group.r#type == "Room")
+        .flat_map(|ig| {
+            ig.group
+                .lights
+                .iter()
+                .filter_map(|id| id.parse().ok())
+                .map(move |id| (id, ig.group.name.to_owned()))
+        })
+        .collect()
+}
+
+/// Lists the lights with the room each is in, optionally under a heading per room.
+pub fn list_lights(
+    backend: &dyn LightBackend,
+    format: Option<&Template>,
+    group_by: Option<GroupBy>,
+) -> Result<()> {
+    let datastore = backend.get_datastore()?;
+    let rooms = rooms(&datastore);
+    let room = |il: &IdentifiedLight| rooms.get(&il.id).cloned().unwrap_or_default();
+
+    if let Some(format) = format {
+        for il in &datastore.lights {
+            let state = &il.light.state;
+            let optional = |value: Option<String>| value.unwrap_or_default();
+            println!(
+                "{}",
+                format.render(|field| match field {
+                    "id" => il.id.to_string(),
+                    "name" => il.light.name.to_owned(),
+                    "room" => room(il),
+                    "on" => state.on.to_string(),
+                    "bri" => optional(state.bri.map(|bri| bri.to_string())),
+                    "hue" => optional(state.hue.map(|hue| hue.to_string())),
+                    "sat" => optional(state.sat.map(|sat| sat.to_string())),
+                    "ct" => optional(state.ct.map(|ct| ct.to_string())),
+                    _ => il.light.modelid.to_owned(),
+                })
+            );
+        }
+        return Ok(());
+    }
+
+    match group_by {
+        None => {
+            let mut table = light_table(true);
+            for il in &datastore.lights {
+                table.row(light_row(il, Some(room(il))));
+            }
+            table.print();
+        }
+        Some(GroupBy::Room) => {
+            let mut by_room: BTreeMap<String, Vec<&IdentifiedLight>> = BTreeMap::new();
+            for il in &datastore.lights {
+                by_room.entry(room(il)).or_default().push(il);
+            }
+            // Lights in no room last.
+            let no_room = by_room.remove("");
+            let by_room = by_room
+                .into_iter()
+                .chain(no_room.map(|lights| (t!("list-no-room"), lights)));
+            for (i, (room, lights)) in by_room.enumerate() {
+                if i > 0 {
+                    println!();
+                }
+                println!("{}:", room);
+                let mut table = light_table(false);
+                for il in lights {
+                    table.row(light_row(il, None));
+                }
+                table.print();
+            }
+        }
+    }
+    Ok(())
+}
+
+fn light_table(with_room: bool) -> Table {
+    let mut aligns = vec![Align::Right, Align::Left, Align::Left, Align::Left];
+    if with_room {
+        aligns.push(Align::Left);
+    }
+    Table::new(&aligns).shrinking(1)
+}
+
+/// A row of the lights listing, with the room unless the lights are listed by room.
+fn light_row(il: &IdentifiedLight, room: Option<String>) -> Vec<Cell> {
+    let on = if il.light.state.on {
+        Cell::styled(format!("[{:3}]", t!("list-on")), Style::Green)
+    } else {
+        Cell::styled(format!("[{:3}]", t!("list-off")), Style::Dim)
+    };
+    let mut row = vec![format!("{}:", il.id).into(), il.light.name.as_str().into()];
+    if let Some(room) = room {
+        row.push(room.into());
+    }
+    row.push(on);
+    row.push(
+        format!(
+            "[bri {:>3}] [hue {:>5}]",
+            il.light.state.bri.unwrap_or(0),
+            il.light.state.hue.unwrap_or(0)
+        )
+        .into(),
+    );
+    row
+}
+
+/// Blinks the lights one by one, asking if it is the one the user is looking for.
+pub fn which(backend: &dyn LightBackend, group: Option<usize>) -> Result<Option<IdentifiedLight>> {
+    let members = match group {
+        Some(group) => Some(
+            backend
+                .get_all_groups()?
+                .into_iter()
+                .find(|ig| ig.id == group)
+                .ok_or_else(|| eyre!("No group with ID {}", group))?
+                .group
+                .lights,
+        ),
+        None => None,
+    };
+    let lights = backend.get_all_lights()?.into_iter().filter(|il| {
+        members
+            .as_ref()
+            .is_none_or(|m| m.contains(&il.id.to_string()))
+    });
+
+    for il in lights {
+        backend.set_light_state(il.id, &alert("lselect"))?;
+        let answer = prompt("Is this the one that is blinking? [y/N] ");
+        backend.set_light_state(il.id, &alert("none"))?;
+        match answer? {
+            Some(answer) if answer.eq_ignore_ascii_case("y") => return Ok(Some(il)),
+            _ => continue,
+        }
+    }
+    Ok(None)
+}
+
+fn alert(alert: &str) -> CommandLight {
+    CommandLight {
+        alert: Some(alert.to_owned()),
+        ..CommandLight::default()
+    }
+}
+
+/// Asks the user for a line of input, returning `None` if it is empty.
+pub fn prompt(question: &str) -> Result<Option<String>> {
+    eprint!("{}", question);
+    let mut input = String::new();
+    if io::stdin().read_line(&mut input)? == 0 {
+        return Err(eyre!("No answer, stdin was closed"));
+    }
+    let input = input.trim();
+    Ok(if input.is_empty() {
+        None
+    } else {
+        Some(input.to_owned())
+    })
+}
+
+/// Watches the target for changes, checking every `interval`, until it is in the given state,
+/// failing if `timeout` passes first.
+pub fn wait(
+    backend: &dyn LightBackend,
+    target: Target,
+    until: Power,
+    interval: Duration,
+    timeout: Option<Duration>,
+) -> Result<()> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let progress = Progress::new(
+        format!(
+            "Waiting for {} to turn {}",
+            target,
+            if until == Power::On { "on" } else { "off" }
+        ),
+        timeout,
+    );
+    let ids = target.lights(backend)?;
+    let mut on = HashMap::new();
+    for event in backend.subscribe(interval) {
+        match event? {
+            Event::Light { id, state } => {
+                on.insert(id, state.on);
+                continue;
+            }
+            Event::Polled => {}
+        }
+        progress.update("");
+        if let Some(id) = ids.iter().find(|id| !on.contains_key(id)) {
+            return Err(eyre!("No light with ID {}", id));
+        }
+        let any_on = ids.iter().any(|id| on[id]);
+        if any_on == (until == Power::On) {
+            return Ok(());
+        }
+        if let (Some(deadline), Some(timeout)) = (deadline, timeout) {
+            if Instant::now() >= deadline {
+                return Err(eyre!(
+                    "Timed out after {} waiting for {} to turn {}",
+                    format_duration(timeout),
+                    target,
+                    if until == Power::On { "on" } else { "off" }
+                ));
+            }
+        }
+    }
+    Err(eyre!("Stopped getting changes from {}", backend.describe()))
+}
+
+/// Applies a light operation to the target. Unless `strict`, effects on several lights carry on
+/// while some of them fail.
+pub fn apply(
+    backend: &dyn LightBackend,
+    target: Target,
+    op: &LightOperation,
+    strict: bool,
+) -> Result<()> {
+    match op.to_action() {
+        Action::Set(command) => set_state(backend, target, command, strict),
+        Action::Fade { start, end, over } => fade(backend, target, start, end, over, strict),
+        Action::Toggle { transitiontime } => {
+            let command = if target.is_on(backend)? {
+                CommandLight::default().off()
+            } else {
+                CommandLight::default().on()
+            };
+            set_state(
+                backend,
+                target,
+                CommandLight {
+                    transitiontime,
+                    ..command
+                },
+                strict,
+            )
+        }
+        Action::Effect {
+            mode,
+            duration,
+            stagger,
+            colors,
+        } => {
+            audit::log_effect(&target.to_string(), mode.name());
+            let deadline = duration.map(|d| Instant::now() + d);
+            if !stagger {
+                return with_priority(Priority::Effect, || {
+                    run_effect(mode, deadline, &colors, &[0.0], strict, |_, command| {
+                        target.set_state(backend, command)
+                    })
+                });
+            }
+            if !mode.is_cyclic() {
+                return Err(eyre!("The {} mode can't be staggered", mode.name()));
+            }
+            let lights = target.lights(backend)?;
+            let phases: Vec<f64> = (0..lights.len())
+                .map(|i| i as f64 / lights.len() as f64)
+                .collect();
+            with_priority(Priority::Effect, || {
+                run_effect(mode, deadline, &colors, &phases, strict, |i, command| {
+                    backend
+                        .set_light_state(lights[i], command)
+                        .wrap_err_with(|| format!("Failed to set light {}", lights[i]))
+                })
+            })
+        }
+    }
+}
+
+/// Sets the target's state. Lights picked by a selector are set one by one, carrying on past
+/// failures unless `strict`.
+fn set_state(
+    backend: &dyn LightBackend,
+    target: Target,
+    command: CommandLight,
+    strict: bool,
+) -> Result<()> {
+    if let Target::Lights(ids) = &target {
+        let mut outcomes = Outcomes::new(strict);
+        for &id in ids {
+            let light = Target::Light(id);
+            let result = light.set_state(backend, &command);
+            audit::log(&light.to_string(), &command, &result);
+            outcomes.record(light, result)?;
+        }
+        return outcomes.finish().map(|_| ());
+    }
+    let result = target.set_state(backend, &command);
+    audit::log(&target.to_string(), &command, &result);
+    result
+}
+
+/// Fades the target from `start`, or its current state, to `end` over `over`. Fades that fit in
+/// one transition are left to the bulbs, and longer ones chain transitions through the states in
+/// between, light by light.
+fn fade(
+    backend: &dyn LightBackend,
+    target: Target,
+    start: Option<CommandLight>,
+    end: CommandLight,
+    over: Duration,
+    strict: bool,
+) -> Result<()> {
+    if let Some(start) = start {
+        set_state(backend, target.clone(), start, strict)?;
+    }
+    // Fading to a color while turning off is fading to it at the lowest brightness, then off.
+    let dim_then_off = end.on == Some(false) && (end.ct.is_some() || end.xy.is_some());
+    if !dim_then_off && over <= LONGEST_TRANSITION {
+        let transitiontime = Some((over.as_millis() / 100) as u16);
+        return set_state(
+            backend,
+            target,
+            CommandLight {
+                transitiontime,
+                ..end
+            },
+            strict,
+        );
+    }
+    let end = if dim_then_off {
+        end.on().with_bri(1)
+    } else {
+        end
+    };
+    let lights = target.lights(backend)?;
+    let from = Snapshot::take(backend)?;
+    let from = Snapshot {
+        lights: from
+            .lights
+            .into_iter()
+            .filter(|(id, _)| lights.contains(id))
+            .collect(),
+        ..from
+    };
+    let to = Snapshot {
+        time: from.time,
+        lights: from
+            .lights
+            .iter()
+            .map(|(&id, light)| {
+                let state = crossfade::after(&light.state, &end);
+                let light = SavedLight {
+                    state,
+                    ..light.clone()
+                };
+                (id, light)
+            })
+            .collect(),
+    };
+    crossfade::fade(backend, &from, &to, over)?;
+    if dim_then_off {
+        set_state(backend, target, CommandLight::default().off(), strict)?;
+    }
+    Ok(())
+}
+
+/// Time between updates of cyclic modes, which each fade to the next step over this time.
+const CYCLE_STEP: Duration = Duration::from_millis(500);
+
+/// Runs an effect until the deadline, or until stopped if there is none. The effect picks from
+/// `colors`, or keeps the current color if there are none.
+///
+/// Cyclic modes run one timeline per phase, given as fractions of the cycle, calling `set_state`
+/// with the index of the phase. Other modes only use the first phase. Unless `strict`, failing
+/// phases are skipped until every phase fails.
+fn run_effect(
+    mode: LightMode,
+    deadline: Option<Instant>,
+    colors: &[(f32, f32)],
+    phases: &[f64],
+    strict: bool,
+    set_state: impl Fn(usize, &CommandLight) -> Result<()>,
+) -> Result<()> {
+    let running =
+        || deadline.is_none_or(|deadline| Instant::now() < deadline) && !shutdown::is_stopping();
+    let progress = Progress::new(
+        format!("Running {}", mode.name()),
+        deadline.map(|deadline| deadline.saturating_duration_since(Instant::now())),
+    );
+    match mode {
+        LightMode::Halloween => {
+            let flicker = |low, high| {
+                let command = CommandLight {
+                    xy: rand_color(colors),
+                    ..CommandLight::default().with_bri(rand_bri(low, high))
+                };
+                progress.update(&format!("bri {}", command.bri.unwrap_or_default()));
+                command
+            };
+            while running() {
+                set_state(0, &flicker(1, 50))?;
+                sleep_a_bit();
+
+                set_state(0, &flicker(70, 120))?;
+                sleep_a_bit();
+            }
+        }
+        LightMode::Breathe { period, min, max } => {
+            let start = Instant::now();
+            let mut failing = vec![false; phases.len()];
+            let mut last_step: Option<Instant> = None;
+            while running() {
+                // Steps are planned a cycle step apart, so any more is lost to requests or sleep.
+                if let Some(last_step) = last_step {
+                    metrics::tick_lateness(last_step.elapsed().saturating_sub(CYCLE_STEP));
+                }
+                last_step = Some(Instant::now());
+                let elapsed = start.elapsed().as_secs_f64() / period.as_secs_f64();
+                progress.update(&format!("bri {}", breathe_bri(elapsed, min, max)));
+                for (i, phase) in phases.iter().enumerate() {
+                    let position = elapsed + phase;
+                    // Change color at the bottom of each pulse.
+                    let xy = match colors.len() {
+                        0 => None,
+                        len => Some(colors[position.floor() as usize % len]),
+                    };
+                    let result = set_state(
+                        i,
+                        &CommandLight {
+                            xy,
+                            transitiontime: Some((CYCLE_STEP.as_millis() / 100) as u16),
+                            ..CommandLight::default().with_bri(breathe_bri(position, min, max))
+                        },
+                    );
+                    match result {
+                        Ok(()) => failing[i] = false,
+                        Err(err) if !strict && phases.len() > 1 => {
+                            if !failing[i] {
+                                eprintln!("{:#}, carrying on with the others.", err);
+                            }
+                            failing[i] = true;
+                        }
+                        Err(err) => return Err(err),
+                    }
+                }
+                if failing.iter().all(|&failing| failing) {
+                    return Err(eyre!("Failed to set every light"));
+                }
+                std::thread::sleep(CYCLE_STEP);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the brightness at a position in the breathe cycle, where each whole number is one
+/// cycle starting at `min`.
+fn breathe_bri(position: f64, min: u8, max: u8) -> u8 {
+    let level = (1.0 - (position * 2.0 * std::f64::consts::PI).cos()) / 2.0;
+    (min as f64 + (max as f64 - min as f64) * level).round() as u8
+}
+
+fn rand_bri(low: u8, high: u8) -> u8 {
+    let between = Uniform::from(low..high);
+    let mut rng = rand::thread_rng();
+    between.sample(&mut rng)
+}
+
+fn rand_color(colors: &[(f32, f32)]) -> Option<(f32, f32)> {
+    if colors.is_empty() {
+        return None;
+    }
+    let between = Uniform::from(0..colors.len());
+    let mut rng = rand::thread_rng();
+    Some(colors[between.sample(&mut rng)])
+}
+
+fn sleep_a_bit() {
+    let between = Uniform::from(200..1000);
+    let mut rng = rand::thread_rng();
+    std::thread::sleep(Duration::from_millis(between.sample(&mut rng)));
+}
@@ -1,76 +1,822 @@
+use crate::bridge::Compat;
+use crate::discovery;
 use directories::ProjectDirs;
-use eyre::Result;
+use eyre::{eyre, Result};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::env;
 use std::fs;
-use std::net::IpAddr;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+
+/// The config format version written by this version of blilys.
+pub const VERSION: u32 = 3;
+
+/// Migrations upgrading the config format, indexed by the version they upgrade from.
+const MIGRATIONS: [fn(&mut Map<String, Value>); VERSION as usize] =
+    [migrate_v0, migrate_v1, migrate_v2];
+
+/// Config file names in order of precedence. The format is detected from the extension.
+const FILE_NAMES: [&str; 4] = ["config.toml", "config.yaml", "config.yml", "config.json"];
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
     #[serde(skip_serializing)]
     pub path: Option<PathBuf>,
 
+    #[serde(default)]
+    pub version: u32,
+
+    /// Target of commands like `blilys on` that don't name one, like "group:Living room".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_target: Option<String>,
+
+    /// Language of messages, like "nb" for Norwegian bokmål. Defaults to the one from LANG.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+
+    /// Public holidays for schedules with `skip_holidays`, as a country code like "NO", or the
+    /// URL or path of an iCalendar file with the holidays as events.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub holidays: Option<String>,
+
+    /// File that `blilys daemon` and `blilys export --watch` keep their own metrics in, like
+    /// request latencies and retries, in the Prometheus text format for node_exporter's textfile
+    /// collector.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<PathBuf>,
+
+    /// Make lights warmer as they dim, like incandescent bulbs, as with `--warm-dim`.
+    #[serde(default)]
+    pub warm_dim: bool,
+
     pub bridge: Bridge,
+
+    #[serde(default)]
+    pub cache: Cache,
+
+    #[serde(default)]
+    pub energy: Energy,
+
+    #[serde(default)]
+    pub remote: Remote,
+
+    /// Names for targets, like `desk = "light:3"`.
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+
+    /// Labels for lights by ID, like `reading = [3, 5]`, targeted like `tag:reading`.
+    #[serde(default)]
+    pub tags: BTreeMap<String, Vec<usize>>,
+
+    /// Shortcuts for command lines, like `bright = "all on --bri 100%"`.
+    #[serde(default)]
+    pub commands: BTreeMap<String, String>,
+
+    /// Settings for `on` without explicit values, per target, like `[defaults."group:Bedroom"]`.
+    #[serde(default)]
+    pub defaults: BTreeMap<String, TargetDefaults>,
+
+    /// Maximum brightness per target, like `"group:Nursery" = "40%"`.
+    #[serde(default)]
+    pub caps: BTreeMap<String, String>,
+
+    /// Tuning per light model ID, like `[model."LCT007"]`, for lights that dim or take colors
+    /// differently from Hue's own.
+    #[serde(default)]
+    pub model: BTreeMap<String, ModelProfile>,
+
+    /// Named sets of colors for modes, like `[palette.halloween]`.
+    #[serde(default)]
+    pub palette: BTreeMap<String, Palette>,
+
+    #[serde(default)]
+    pub presence: Presence,
+
+    #[serde(default)]
+    pub history: History,
+
+    #[serde(default)]
+    pub calendar: Calendar,
+
+    #[serde(default)]
+    pub daemon: Daemon,
+
+    /// Hue Tap Dials that dim a target in `blilys daemon`, by their names in the Hue app, like
+    /// `[dials."Living room dial"]`.
+    #[serde(default)]
+    pub dials: BTreeMap<String, Dial>,
+
+    /// Commands run by `blilys daemon` on presses of buttons on switches, like `[[buttons]]`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub buttons: Vec<Button>,
+
+    /// Targets whose brightness `blilys daemon` adjusts to keep a level of light measured by a
+    /// motion sensor, like `[adaptive."group:Living room"]`.
+    #[serde(default)]
+    pub adaptive: BTreeMap<String, Adaptive>,
+
+    /// Commands run by `blilys daemon` at times of day, like `[[schedules]]`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub schedules: Vec<Schedule>,
+
+    /// Where `blilys daemon` forwards the bridge's events, like `[[sinks]]`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sinks: Vec<Sink>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Bridge {
-    pub ip: Option<IpAddr>,
+    /// IP address or hostname.
+    pub host: Option<String>,
     pub username: Option<String>,
+    /// ID of the paired bridge, to detect when the host points to another bridge.
+    pub id: Option<String>,
+    /// Key for entertainment streaming, generated when pairing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clientkey: Option<String>,
+    /// Name of this machine in the Hue app's list of connected apps. Defaults to the hostname.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_name: Option<String>,
+    /// Kind of bridge: hue, deconz, or diyhue. Sets the port used for hosts given without one.
+    #[serde(default)]
+    pub compat: Compat,
+    /// Discovery methods to try in order when no host is set.
+    #[serde(default = "discovery::default_methods")]
+    pub discovery: Vec<discovery::Method>,
+    /// Requests sent to the bridge at once, each on a connection of its own. Others wait their
+    /// turn, with commands to the lights before effects and polling.
+    #[serde(default = "default_connections")]
+    pub connections: usize,
+}
+
+fn default_connections() -> usize {
+    1
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Cache {
+    /// Time before cached light and group names are refreshed from the bridge, like "1d".
+    #[serde(with = "crate::time::humane")]
+    pub ttl: Duration,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Cache {
+            ttl: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Energy {
+    /// Watts at full brightness for light models without a known wattage.
+    pub default_watts: f64,
+    /// Watts at full brightness per light model ID, overriding the built-in table.
+    #[serde(default)]
+    pub watts: BTreeMap<String, f64>,
+}
+
+impl Default for Energy {
+    fn default() -> Self {
+        Energy {
+            default_watts: 9.0,
+            watts: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TargetDefaults {
+    /// Brightness, like "30%".
+    pub bri: Option<String>,
+    /// Color temperature, like "2700K".
+    pub ct: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Palette {
+    /// Colors as hex codes like "#ff6600" or names like "orange".
+    pub colors: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Dial {
+    /// Target to dim, like "group:Living room".
+    pub target: String,
+    /// How the brightness follows turns of the dial.
+    #[serde(default)]
+    pub curve: DialCurve,
+    /// Change in brightness per step of the dial, like "1%". The dial reports about 75 steps per
+    /// full turn.
+    #[serde(default = "default_dial_step")]
+    pub step: String,
+}
+
+fn default_dial_step() -> String {
+    "1%".to_owned()
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DialCurve {
+    /// The same change in brightness value at every level.
+    Linear,
+    /// The same change in how bright it looks at every level, in finer steps when dim.
+    #[default]
+    Perceptual,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ModelProfile {
+    /// How brightness values are turned into the model's own.
+    #[serde(default)]
+    pub bri_curve: BriCurve,
+    /// Lowest brightness the model shines steadily at, like "5%". Lower brightnesses are raised
+    /// to it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_bri: Option<String>,
+    /// Coolest color temperature the model takes, in mireds, like 153.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_ct: Option<u16>,
+    /// Warmest color temperature the model takes, in mireds, like 500.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_ct: Option<u16>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BriCurve {
+    /// Brightness values as they are, for models that dim like Hue lights.
+    #[default]
+    Linear,
+    /// The square law dimming curve of the IES, for models whose light output follows the
+    /// brightness value.
+    Ies,
+    /// A gamma of 2.2, for models that are even brighter than that when dim.
+    Gamma,
+}
+
+/// Commands for the gestures of a button on a Hue switch, like a dimmer switch or smart button.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Button {
+    /// Name of the switch in the Hue app, like "Hallway dimmer".
+    pub switch: String,
+    /// Number of the button on the switch, from 1 at the top.
+    #[serde(default = "default_button")]
+    pub button: u64,
+    /// Command line to run on a single press, like "toggle hall".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub press: Option<String>,
+    /// Command line to run on two presses in quick succession. Single presses wait a moment
+    /// for a second one when this is set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub double: Option<String>,
+    /// Command line to run when the button is held down.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hold: Option<String>,
+}
+
+fn default_button() -> u64 {
+    1
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Adaptive {
+    /// The motion sensor measuring the light in the room, by the ID or name of any of its
+    /// sensors.
+    pub sensor: String,
+    /// Level of light to keep, in lux, like 300 for a living room.
+    pub lux: f64,
+    /// How far the level of light may drift from `lux` before the lights are adjusted, as a
+    /// fraction. Adjusting goes on until it is within half of this.
+    #[serde(default = "default_tolerance")]
+    pub tolerance: f64,
+    /// Time between readings of the sensor, like "1m".
+    #[serde(default = "default_adaptive_interval", with = "crate::time::humane")]
+    pub interval: Duration,
+}
+
+fn default_tolerance() -> f64 {
+    0.2
+}
+
+fn default_adaptive_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+/// A destination for the bridge's events, which are updated v2 resources as JSON.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Sink {
+    /// Types of resources whose events are forwarded, like `["light", "button"]`. Defaults to
+    /// all.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub types: Vec<String>,
+    #[serde(flatten)]
+    pub output: SinkOutput,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum SinkOutput {
+    /// Appends each event as a line of JSON to a file.
+    File { path: PathBuf },
+    /// POSTs each event as JSON to a URL.
+    Webhook { url: String },
+    /// Publishes each event to an MQTT broker, under `<topic>/<type>`.
+    Mqtt {
+        host: String,
+        #[serde(default = "default_mqtt_port")]
+        port: u16,
+        #[serde(default = "default_mqtt_topic")]
+        topic: String,
+        /// Client ID to connect with, which must differ between clients of the broker. Defaults
+        /// to one from the hostname, like "blilys-laptop".
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        client_id: Option<String>,
+    },
+    /// Logs each event to the local syslog.
+    Syslog,
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_mqtt_topic() -> String {
+    "blilys".to_owned()
+}
+
+/// Presence detection in `blilys daemon`, from scans of the household's phones on the local
+/// network.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Presence {
+    /// Time between scans, like "30s".
+    #[serde(with = "crate::time::humane")]
+    pub interval: Duration,
+    /// Time without any device seen before everyone counts as gone, like "10m".
+    #[serde(with = "crate::time::humane")]
+    pub away_after: Duration,
+    /// Command line to run when someone comes home, like "group:Hallway on".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arrive: Option<String>,
+    /// Only run the arrive command after sunset, by the bridge's daylight sensor.
+    #[serde(default)]
+    pub arrive_only_when_dark: bool,
+    /// Command line to run when everyone has left, like "all off".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub leave: Option<String>,
+    /// Devices by name, like `[presence.devices.phone]`.
+    #[serde(default)]
+    pub devices: BTreeMap<String, Device>,
+}
+
+/// Recording of light states and sensor readings by `blilys daemon`, like `blilys history record`
+/// does on its own.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct History {
+    /// Record history while the daemon runs.
+    pub record: bool,
+    /// Time between polls of the bridge, like "10s".
+    #[serde(with = "crate::time::humane")]
+    pub interval: Duration,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History {
+            record: false,
+            interval: Duration::from_secs(10),
+        }
+    }
+}
+
+impl Default for Presence {
+    fn default() -> Self {
+        Presence {
+            interval: Duration::from_secs(30),
+            away_after: Duration::from_secs(10 * 60),
+            arrive: None,
+            arrive_only_when_dark: false,
+            leave: None,
+            devices: BTreeMap::new(),
+        }
+    }
+}
+
+/// A device on the local network, found by IP address, MAC address, or both.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Device {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    /// MAC address like "aa:bb:cc:dd:ee:ff", looked up at the IP address in the ARP table after
+    /// pinging it. Only on Linux.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mac: Option<String>,
+}
+
+/// Commands run by `blilys daemon` around events in an iCalendar feed.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Calendar {
+    /// URL of the feed, or path of a local file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Time between fetches of the feed, like "15m".
+    #[serde(with = "crate::time::humane")]
+    pub interval: Duration,
+    /// Commands to run for matching events, like `[[calendar.rules]]`.
+    #[serde(default)]
+    pub rules: Vec<CalendarRule>,
+}
+
+impl Default for Calendar {
+    fn default() -> Self {
+        Calendar {
+            url: None,
+            interval: Duration::from_secs(15 * 60),
+            rules: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CalendarRule {
+    /// Text in the summary of matching events, ignoring case, like "meeting".
+    pub summary: String,
+    /// Time before the event to run the start command, like "5m".
+    #[serde(default, with = "crate::time::humane")]
+    pub before: Duration,
+    /// Command line to run before the event, like "busy-light on --color red".
+    pub start: String,
+    /// Command line to run when the event ends, like "busy-light off".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<String>,
+}
+
+/// A command line that `blilys daemon` runs at a time of day.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Schedule {
+    /// Time of day, like "07:00".
+    pub at: String,
+    /// Time zone of the time of day, like "Europe/Oslo". Defaults to the system's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    /// Days of the week, like "mon-fri". Defaults to every day.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub days: Option<String>,
+    /// Command line to run, like "group Bedroom on --bri 100%".
+    pub command: String,
+    /// Longest time to run after `at`, at a random time that changes from day to day, like
+    /// "10m". Makes the lights look less automated to anyone watching the house.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jitter: Option<String>,
+    /// Don't run on the public holidays in `holidays`, like for a wake-up light on workdays.
+    #[serde(default)]
+    pub skip_holidays: bool,
+    /// What to do when the daemon wasn't running or the computer was asleep at the time:
+    /// "skip", "run_once_late", or "run_if_within 30m". Defaults to "skip".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub missed: Option<String>,
+}
+
+/// Settings of `blilys daemon`: commands it runs on signals, like `kill -USR1`, for hotkeys, and
+/// whether other invocations go through it.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Daemon {
+    /// Command line to run on SIGUSR1.
+    pub sigusr1: String,
+    /// Command line to run on SIGUSR2.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sigusr2: Option<String>,
+    /// Send commands controlling lights through the daemon, starting it if it isn't running, to
+    /// reuse its bridge connection. Ignored on other platforms than Unix.
+    pub forward: bool,
+    /// Command lines running modes to keep running, like "light Strip mode breathe". They are
+    /// restarted if they fail, and resumed when the daemon starts again.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub effects: Vec<String>,
+    /// Snapshot to restore the lights to when the daemon is stopped with SIGTERM, after its
+    /// effects have stopped, like one saved with `blilys snapshot save`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub restore: Option<String>,
+}
+
+impl Default for Daemon {
+    fn default() -> Self {
+        Daemon {
+            sigusr1: "toggle".to_owned(),
+            sigusr2: None,
+            forward: false,
+            effects: vec![],
+            restore: None,
+        }
+    }
+}
+
+/// Credentials of an app registered at the Hue developer portal, for the Hue Remote API.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Remote {
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
             path: None,
+            version: VERSION,
             bridge: Bridge {
-                ip: None,
+                host: None,
                 username: None,
+                id: None,
+                clientkey: None,
+                device_name: None,
+                compat: Default::default(),
+                discovery: discovery::default_methods(),
+                connections: default_connections(),
             },
+            default_target: None,
+            language: None,
+            cache: Default::default(),
+            energy: Default::default(),
+            remote: Default::default(),
+            aliases: Default::default(),
+            tags: Default::default(),
+            commands: Default::default(),
+            defaults: Default::default(),
+            caps: Default::default(),
+            model: Default::default(),
+            palette: Default::default(),
+            dials: Default::default(),
+            buttons: Default::default(),
+            adaptive: Default::default(),
+            schedules: Default::default(),
+            sinks: Default::default(),
+            holidays: None,
+            metrics: None,
+            warm_dim: false,
+            presence: Default::default(),
+            history: Default::default(),
+            calendar: Default::default(),
+            daemon: Default::default(),
         }
     }
 }
 
 impl Config {
     pub fn from_file() -> Result<Config> {
-        Ok(Config::read_file(&Config::get_path()?)?)
+        Config::read_file(&Config::get_path()?)
     }
 
-    fn get_path() -> Result<PathBuf> {
+    pub fn get_path() -> Result<PathBuf> {
         let project_dirs = ProjectDirs::from("", "", "blilys").expect("Config dir not readable");
         let config_dir = project_dirs.config_dir();
-        if !config_dir.is_dir() {
-            fs::create_dir_all(config_dir)?;
-        }
-        let config_path = config_dir.join("config.toml");
-        Ok(config_path)
+        Ok(FILE_NAMES
+            .iter()
+            .map(|name| config_dir.join(name))
+            .find(|path| path.is_file())
+            .unwrap_or_else(|| config_dir.join(FILE_NAMES[0])))
     }
 
-    fn read_file(path: &Path) -> Result<Config> {
-        let mut config: Config = match path.is_file() {
-            true => toml::from_str(String::from_utf8(fs::read(path)?)?.as_ref())?,
-            false => Default::default(),
-        };
+    /// Reads the config, migrating older versions in memory only. The file is left as it is
+    /// until the config is saved.
+    pub fn read_file(path: &Path) -> Result<Config> {
+        if !path.is_file() {
+            return Ok(Config {
+                path: Some(path.to_owned()),
+                ..Default::default()
+            });
+        }
+
+        let format = Format::from_path(Some(path))?;
+        let (mut config, _) = Config::parse(&fs::read_to_string(path)?, format)?;
         config.path = Some(path.to_owned());
         Ok(config)
     }
 
+    /// Parses and migrates a config, returning it along with the version it was written in.
+    fn parse(contents: &str, format: Format) -> Result<(Config, u32)> {
+        let mut table: Map<String, Value> = format.deserialize(contents)?;
+        let version = match table.get("version") {
+            Some(value) => value
+                .as_u64()
+                .ok_or_else(|| eyre!("Config version must be an integer"))?,
+            None => 0,
+        };
+        let version = u32::try_from(version)
+            .ok()
+            .filter(|&version| version <= VERSION)
+            .ok_or_else(|| {
+                eyre!(
+                    "Config version {} is newer than this version of blilys supports ({})",
+                    version,
+                    VERSION
+                )
+            })?;
+        for migrate in &MIGRATIONS[version as usize..] {
+            migrate(&mut table);
+        }
+        table.insert("version".to_owned(), VERSION.into());
+
+        Ok((serde_json::from_value(Value::Object(table))?, version))
+    }
+
+    /// Checks that the config file at `path` parses, reporting the location of any error, and
+    /// returns the version it was written in.
+    pub fn validate(path: &Path) -> Result<u32> {
+        if !path.is_file() {
+            return Err(eyre!("{} does not exist", path.display()));
+        }
+        let format = Format::from_path(Some(path))?;
+        let contents = fs::read_to_string(path)?;
+        // Deserializing directly from the text, rather than via the migrated table, gives errors
+        // with line and column numbers.
+        format
+            .deserialize::<Config>(&contents)
+            .map_err(|err| eyre!("{}: {}", path.display(), err))?;
+        let (_, version) =
+            Config::parse(&contents, format).map_err(|err| eyre!("{}: {}", path.display(), err))?;
+        Ok(version)
+    }
+
+    /// Opens the config file in the user's editor until it is valid or the user gives up. A
+    /// config in an older version is upgraded first, so that it is edited in the current format.
+    pub fn edit(path: &Path) -> Result<()> {
+        if !path.is_file() {
+            Config {
+                path: Some(path.to_owned()),
+                ..Default::default()
+            }
+            .save()?;
+        } else {
+            let version = Config::validate(path)?;
+            if version < VERSION {
+                info!(
+                    "Upgrading config from version {} to {}, keeping the old one as {} ...",
+                    version,
+                    VERSION,
+                    with_suffix(path, ".bak").display()
+                );
+                Config::read_file(path)?.save()?;
+            }
+        }
+
+        let editor = env::var("VISUAL")
+            .or_else(|_| env::var("EDITOR"))
+            .unwrap_or_else(|_| "vi".to_owned());
+        let mut words = editor.split_whitespace();
+        let program = words
+            .next()
+            .ok_or_else(|| eyre!("Editor command is empty"))?;
+
+        loop {
+            let status = process::Command::new(program)
+                .args(words.clone())
+                .arg(path)
+                .status()?;
+            if !status.success() {
+                return Err(eyre!("Editor exited with {}", status));
+            }
+            match Config::validate(path) {
+                Ok(_) => return Ok(()),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    eprint!("Edit again? [Y/n] ");
+                    let mut input = String::new();
+                    let read = io::stdin().read_line(&mut input)?;
+                    if read == 0 || input.trim().eq_ignore_ascii_case("n") {
+                        return Err(eyre!("Config is invalid"));
+                    }
+                }
+            }
+        }
+    }
+
     pub fn save(&self) -> Result<()> {
         let path = self
             .path
             .as_ref()
             .expect("Config must have a path to be saved.");
-        let contents = toml::to_string(self)?;
-        fs::write(path, contents)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let contents = Format::from_path(Some(path))?.serialize(self)?;
+
+        // Write to a temporary file and rename it into place, so that a crash never leaves a
+        // half-written config behind.
+        let tmp_path = with_suffix(path, ".tmp");
+        let mut file = create_private(&tmp_path)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+
+        if path.is_file() {
+            let bak_path = with_suffix(path, ".bak");
+            let mut bak = create_private(&bak_path)?;
+            bak.write_all(&fs::read(path)?)?;
+        }
+        fs::rename(&tmp_path, path)?;
         Ok(())
     }
 
     pub fn print(&self) -> Result<()> {
         if let Some(path) = &self.path {
-            eprintln!("# {}", path.display());
+            info!("# {}", path.display());
         }
-        print!("{}", toml::to_string(self)?);
+        print!(
+            "{}",
+            Format::from_path(self.path.as_deref())?.serialize(self)?
+        );
         Ok(())
     }
 }
+
+/// A file format, detected from the file extension.
+#[derive(Debug, Clone, Copy)]
+pub enum Format {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl Format {
+    pub fn from_path(path: Option<&Path>) -> Result<Format> {
+        match path.and_then(|p| p.extension()).and_then(|e| e.to_str()) {
+            None | Some("toml") => Ok(Format::Toml),
+            Some("yaml") | Some("yml") => Ok(Format::Yaml),
+            Some("json") => Ok(Format::Json),
+            Some(other) => Err(eyre!("Unsupported file format {:?}", other)),
+        }
+    }
+
+    pub fn deserialize<T: DeserializeOwned>(self, contents: &str) -> Result<T> {
+        Ok(match self {
+            Format::Toml => toml::from_str(contents)?,
+            Format::Yaml => serde_yaml::from_str(contents)?,
+            Format::Json => serde_json::from_str(contents)?,
+        })
+    }
+
+    fn serialize<T: Serialize>(self, value: &T) -> Result<String> {
+        Ok(match self {
+            Format::Toml => toml::to_string(value)?,
+            Format::Yaml => serde_yaml::to_string(value)?,
+            Format::Json => serde_json::to_string_pretty(value)? + "\n",
+        })
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Creates or truncates a file only readable by the current user, for files like the config that
+/// contain credentials granting full control of the lights.
+pub fn create_private(path: &Path) -> io::Result<fs::File> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        let file = options.open(path)?;
+        // The mode only applies to newly created files.
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        Ok(file)
+    }
+    #[cfg(not(unix))]
+    options.open(path)
+}
+
+/// Version 0 is the original, unversioned format. Version 1 only adds the version field.
+fn migrate_v0(_config: &mut Map<String, Value>) {}
+
+/// Version 2 renames `bridge.ip` to `bridge.host`, which also accepts hostnames.
+fn migrate_v1(config: &mut Map<String, Value>) {
+    if let Some(Value::Object(bridge)) = config.get_mut("bridge") {
+        if let Some(ip) = bridge.remove("ip") {
+            bridge.insert("host".to_owned(), ip);
+        }
+    }
+}
+
+/// Version 3 writes `cache.ttl` as a duration like "1d" instead of a number of seconds.
+fn migrate_v2(config: &mut Map<String, Value>) {
+    if let Some(Value::Object(cache)) = config.get_mut("cache") {
+        if let Some(secs) = cache.get("ttl").and_then(Value::as_u64) {
+            let ttl = crate::time::format_duration(Duration::from_secs(secs));
+            cache.insert("ttl".to_owned(), ttl.into());
+        }
+    }
+}
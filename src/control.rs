@@ -0,0 +1,168 @@
+use directories::ProjectDirs;
+use eyre::{eyre, Result};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::IntoRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::Duration;
+use std::{env, fs, thread};
+
+/// Path of the socket the daemon listens on for commands from `blilys ctl`, one line each.
+pub fn socket_path() -> PathBuf {
+    let project_dirs = ProjectDirs::from("", "", "blilys").expect("Data dir not readable");
+    project_dirs.data_dir().join("daemon.sock")
+}
+
+/// Listens on the control socket, replacing a socket left behind by a daemon that is no longer
+/// running.
+pub fn listen() -> Result<UnixListener> {
+    let path = socket_path();
+    if path.exists() {
+        if UnixStream::connect(&path).is_ok() {
+            return Err(eyre!("The daemon is already running"));
+        }
+        fs::remove_file(&path)?;
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let listener = UnixListener::bind(&path)
+        .map_err(|err| eyre!("Failed to listen on {}: {}", path.display(), err))?;
+    // Anyone who can connect can control the lights.
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Runs each command line received on the socket, answering `ok` or `error: <message>`.
+/// Connections are handled on their own threads, so long-running commands don't hold up others.
+pub fn serve(listener: UnixListener, handle: &(dyn Fn(&str) -> Result<()> + Sync)) {
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    scope.spawn(move || answer(stream, handle));
+                }
+                Err(err) => eprintln!("Failed to accept a control connection: {}", err),
+            }
+        }
+    });
+}
+
+fn answer(mut stream: UnixStream, handle: &(dyn Fn(&str) -> Result<()> + Sync)) {
+    let mut line = String::new();
+    if let Err(err) = BufReader::new(&stream).read_line(&mut line) {
+        eprintln!("Failed to read a control command: {}", err);
+        return;
+    }
+    let reply = match handle(line.trim()) {
+        Ok(()) => "ok\n".to_owned(),
+        // Replies are one line each.
+        Err(err) => format!("error: {}\n", err.to_string().replace('\n', " ")),
+    };
+    if let Err(err) = stream.write_all(reply.as_bytes()) {
+        eprintln!("Failed to answer a control command: {}", err);
+    }
+}
+
+/// Sends a command line to the running daemon, returning its error if it failed.
+pub fn send(line: &str) -> Result<()> {
+    let path = socket_path();
+    let mut stream = UnixStream::connect(&path)
+        .map_err(|_| eyre!("The daemon isn't running. Start it with `blilys daemon`."))?;
+    stream.write_all(format!("{}\n", line).as_bytes())?;
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    match reply.trim_end().strip_prefix("error: ") {
+        Some(err) => Err(eyre!("{}", err)),
+        None if reply.trim_end() == "ok" => Ok(()),
+        None => Err(eyre!("Unexpected reply from the daemon: {:?}", reply)),
+    }
+}
+
+/// Sends a command line to the daemon like `send`, starting the daemon in the background first if
+/// it isn't running. Returns `None` if it couldn't be started, to connect directly instead.
+pub fn forward(line: &str) -> Option<Result<()>> {
+    if UnixStream::connect(socket_path()).is_err() {
+        if let Err(err) = start() {
+            eprintln!("Failed to start the daemon, connecting directly: {}", err);
+            return None;
+        }
+    }
+    Some(send(line))
+}
+
+/// Starts the daemon, logging to `daemon.log` next to the socket, and waits for it to listen.
+fn start() -> Result<()> {
+    let path = socket_path();
+    let log_path = path.with_file_name("daemon.log");
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let log = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)?;
+    // The daemon outlives this process, so it is never waited for.
+    let mut child = Command::new(env::current_exe()?)
+        .arg("daemon")
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        // Outside the terminal's process group, so that Ctrl-C there doesn't stop it.
+        .process_group(0)
+        .spawn()?;
+    for _ in 0..50 {
+        if UnixStream::connect(&path).is_ok() {
+            return Ok(());
+        }
+        if let Some(status) = child.try_wait()? {
+            return Err(eyre!("Exited with {}, see {}", status, log_path.display()));
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    Err(eyre!("Not listening yet, see {}", log_path.display()))
+}
+
+/// Write end of the pipe the signal handler passes signals through.
+static SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn on_signal(signal: libc::c_int) {
+    let byte = signal as u8;
+    // Writing to a pipe is one of the few things that are safe to do in a signal handler.
+    unsafe {
+        libc::write(
+            SIGNAL_PIPE.load(Ordering::Relaxed),
+            &byte as *const u8 as *const libc::c_void,
+            1,
+        );
+    }
+}
+
+/// Calls `handle` with each of the given signals received, on a new thread.
+pub fn on_signals<'scope>(
+    scope: &'scope thread::Scope<'scope, '_>,
+    signals: &[libc::c_int],
+    handle: impl Fn(libc::c_int) + Send + 'scope,
+) -> Result<()> {
+    let (reader, writer) = UnixStream::pair()?;
+    SIGNAL_PIPE.store(writer.into_raw_fd(), Ordering::Relaxed);
+    for &signal in signals {
+        let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        // SAFETY: The handler only does async-signal-safe work.
+        if unsafe { libc::signal(signal, handler) } == libc::SIG_ERR {
+            return Err(eyre!("Failed to handle signal {}", signal));
+        }
+    }
+    scope.spawn(move || {
+        let mut reader = reader;
+        let mut signal = [0];
+        while reader.read_exact(&mut signal).is_ok() {
+            handle(signal[0].into());
+        }
+    });
+    Ok(())
+}
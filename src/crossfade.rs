@@ -0,0 +1,273 @@
+use crate::backend::LightBackend;
+use crate::color::{hue_sat_to_rgb, mired_to_rgb, rgb_to_xy, uv_to_xy, xy_to_uv};
+use crate::dial::GAMMA;
+use crate::progress::Progress;
+use crate::snapshot::Snapshot;
+use eyre::Result;
+use hueclient::{CommandLight, LightState};
+use std::thread;
+use std::time::Duration;
+
+/// Longest time between steps of a crossfade, each fading to the next over this time.
+const STEP: Duration = Duration::from_secs(1);
+
+/// Longest transition the bridge takes, of 65535 times 100 ms, about 1h49m.
+pub const LONGEST_TRANSITION: Duration = Duration::from_millis(u16::MAX as u64 * 100);
+
+/// A color as the bridge takes it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Color {
+    Xy(f32, f32),
+    /// Color temperature in mireds.
+    Ct(u16),
+}
+
+/// Sets every light to its state in the snapshot.
+pub fn restore(backend: &dyn LightBackend, snapshot: &Snapshot) -> Result<()> {
+    for (id, light) in &snapshot.lights {
+        backend.set_light_state(*id, &restore_command(&light.state))?;
+    }
+    Ok(())
+}
+
+/// Sets the lights to their state in `from`, then fades every light in both snapshots to its
+/// state in `to` over `duration`, stepping through the colors in between.
+pub fn crossfade(
+    backend: &dyn LightBackend,
+    from: &Snapshot,
+    to: &Snapshot,
+    duration: Duration,
+) -> Result<()> {
+    restore(backend, from)?;
+    fade_in_steps(backend, from, to, duration, STEP, "Crossfading")
+}
+
+/// Fades every light in both snapshots from its state in `from` to its state in `to` over
+/// `duration`, chaining the bridge's longest transitions for fades too long for one, and waiting
+/// for the last to end.
+pub fn fade(
+    backend: &dyn LightBackend,
+    from: &Snapshot,
+    to: &Snapshot,
+    duration: Duration,
+) -> Result<()> {
+    fade_in_steps(backend, from, to, duration, LONGEST_TRANSITION, "Fading")
+}
+
+fn fade_in_steps(
+    backend: &dyn LightBackend,
+    from: &Snapshot,
+    to: &Snapshot,
+    duration: Duration,
+    longest: Duration,
+    message: &str,
+) -> Result<()> {
+    let (steps, step) = steps(duration, longest);
+    let progress = Progress::new(message, Some(duration));
+    for i in 1..=steps {
+        progress.update(&format!("step {}/{}", i, steps));
+        let t = i as f64 / steps as f64;
+        for (id, light) in &from.lights {
+            let command = to
+                .lights
+                .get(id)
+                .and_then(|target| blend(&light.state, &target.state, t));
+            if let Some(command) = command {
+                backend.set_light_state(
+                    *id,
+                    &CommandLight {
+                        transitiontime: Some((step.as_millis() / 100) as u16),
+                        ..command
+                    },
+                )?;
+            }
+        }
+        thread::sleep(step);
+    }
+    Ok(())
+}
+
+/// Splits a fade into the fewest even steps no longer than `longest`, returning their number and
+/// length.
+fn steps(duration: Duration, longest: Duration) -> (u32, Duration) {
+    let steps = (duration.as_secs_f64() / longest.as_secs_f64())
+        .ceil()
+        .max(1.0) as u32;
+    (steps, duration / steps)
+}
+
+/// Returns the state a light ends up in after the command.
+pub fn after(state: &LightState, command: &CommandLight) -> LightState {
+    let mut state = LightState {
+        on: command.on.unwrap_or(state.on),
+        bri: command.bri.or(state.bri),
+        ..*state
+    };
+    if command.xy.is_some() || command.ct.is_some() {
+        state.xy = command.xy;
+        state.ct = command.ct;
+        state.hue = None;
+        state.sat = None;
+    }
+    state
+}
+
+fn restore_command(state: &LightState) -> CommandLight {
+    if !state.on {
+        return CommandLight::default().off();
+    }
+    let command = with_color(CommandLight::default().on(), color(state));
+    match state.bri {
+        Some(bri) => command.with_bri(bri),
+        None => command,
+    }
+}
+
+/// Returns the command for the point `t` of the way from one state to another, or `None` if the
+/// light stays off. Lights that are off fade from or to their dimmest, keeping the color of the
+/// side that is on.
+fn blend(from: &LightState, to: &LightState, t: f64) -> Option<CommandLight> {
+    if !from.on && !to.on {
+        return None;
+    }
+    if !to.on && t >= 1.0 {
+        return Some(CommandLight::default().off());
+    }
+    // Brightness changes evenly in how bright it looks, not in its value.
+    let level = |state: &LightState| match state.bri {
+        Some(bri) if state.on => (bri as f64 / 254.0).powf(1.0 / GAMMA),
+        Some(_) => 0.0,
+        None => 1.0,
+    };
+    let level = level(from) + (level(to) - level(from)) * t;
+    let bri = (level.powf(GAMMA) * 254.0).round().clamp(1.0, 254.0) as u8;
+    let color = match (color(from), color(to)) {
+        (Some(a), Some(b)) if from.on && to.on => Some(blend_color(a, b, t)),
+        (a, b) if from.on => a.or(b),
+        (a, b) => b.or(a),
+    };
+    Some(with_color(
+        CommandLight::default().on().with_bri(bri),
+        color,
+    ))
+}
+
+/// Interpolates between colors, evenly in how different the colors look. Color temperatures stay
+/// on the black body curve, and other colors are interpolated in CIE u'v'.
+fn blend_color(from: Color, to: Color, t: f64) -> Color {
+    if let (Color::Ct(a), Color::Ct(b)) = (from, to) {
+        return Color::Ct((a as f64 + (b as f64 - a as f64) * t).round() as u16);
+    }
+    let (u1, v1) = xy_to_uv(xy(from));
+    let (u2, v2) = xy_to_uv(xy(to));
+    let lerp = |a: f64, b: f64| a + (b - a) * t;
+    let (x, y) = uv_to_xy((lerp(u1, u2), lerp(v1, v2)));
+    Color::Xy(x, y)
+}
+
+fn color(state: &LightState) -> Option<Color> {
+    match (state.xy, state.ct, state.hue, state.sat) {
+        (Some((x, y)), _, _, _) => Some(Color::Xy(x, y)),
+        (None, Some(ct), _, _) => Some(Color::Ct(ct)),
+        (None, None, Some(hue), Some(sat)) => {
+            let (r, g, b) = hue_sat_to_rgb(hue, sat);
+            let (x, y) = rgb_to_xy(r, g, b);
+            Some(Color::Xy(x, y))
+        }
+        _ => None,
+    }
+}
+
+fn with_color(command: CommandLight, color: Option<Color>) -> CommandLight {
+    match color {
+        Some(Color::Xy(x, y)) => command.with_xy(x, y),
+        Some(Color::Ct(ct)) => command.with_ct(ct),
+        None => command,
+    }
+}
+
+fn xy(color: Color) -> (f32, f32) {
+    match color {
+        Color::Xy(x, y) => (x, y),
+        Color::Ct(ct) => {
+            let (r, g, b) = mired_to_rgb(ct);
+            rgb_to_xy(r, g, b)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{after, blend, blend_color, steps, Color, LONGEST_TRANSITION};
+    use hueclient::{CommandLight, LightState};
+    use std::time::Duration;
+
+    fn state(on: bool, bri: u8, xy: Option<(f32, f32)>, ct: Option<u16>) -> LightState {
+        LightState {
+            on,
+            bri: Some(bri),
+            hue: None,
+            sat: None,
+            ct,
+            xy,
+        }
+    }
+
+    #[test]
+    fn colors_are_interpolated_evenly() {
+        assert_eq!(
+            blend_color(Color::Ct(153), Color::Ct(500), 0.5),
+            Color::Ct(327)
+        );
+        let red = Color::Xy(0.675, 0.322);
+        let blue = Color::Xy(0.167, 0.04);
+        assert_eq!(blend_color(red, blue, 0.0), Color::Xy(0.675, 0.322));
+        assert_eq!(blend_color(red, blue, 1.0), Color::Xy(0.167, 0.04));
+        // Halfway in u'v' is closer to blue in xy, as xy stretches the greens and reds.
+        match blend_color(red, blue, 0.5) {
+            Color::Xy(x, y) => assert!(x < 0.421 && y < 0.181, "{}, {}", x, y),
+            color => panic!("{:?}", color),
+        }
+    }
+
+    #[test]
+    fn lights_fade_on_and_off() {
+        let off = state(false, 200, Some((0.3, 0.3)), None);
+        let on = state(true, 254, Some((0.5, 0.4)), None);
+
+        let halfway = blend(&off, &on, 0.5).unwrap();
+        assert_eq!(halfway.on, Some(true));
+        assert_eq!(halfway.bri, Some(55));
+        assert_eq!(halfway.xy, Some((0.5, 0.4)));
+
+        assert_eq!(blend(&on, &off, 0.5).unwrap().bri, Some(55));
+        assert_eq!(blend(&on, &off, 1.0).unwrap().on, Some(false));
+        assert!(blend(&off, &off, 0.5).is_none());
+    }
+
+    #[test]
+    fn long_fades_chain_the_longest_transitions() {
+        let hour = Duration::from_secs(60 * 60);
+        assert_eq!(steps(hour, LONGEST_TRANSITION), (1, hour));
+        assert_eq!(steps(3 * hour, LONGEST_TRANSITION), (2, hour * 3 / 2));
+        assert_eq!(
+            steps(Duration::from_millis(2500), Duration::from_secs(1)),
+            (3, Duration::from_millis(2500) / 3)
+        );
+    }
+
+    #[test]
+    fn commands_change_only_what_they_set() {
+        let on = state(true, 254, Some((0.5, 0.4)), None);
+        let dimmed = after(&on, &CommandLight::default().with_bri(1).with_ct(454));
+        assert_eq!(
+            (dimmed.on, dimmed.bri, dimmed.xy, dimmed.ct),
+            (true, Some(1), None, Some(454))
+        );
+        let off = after(&dimmed, &CommandLight::default().off());
+        assert_eq!(
+            (off.on, off.bri, off.xy, off.ct),
+            (false, Some(1), None, Some(454))
+        );
+    }
+}
@@ -0,0 +1,282 @@
+use crate::adaptive;
+use crate::api::Bridge;
+use crate::calendar;
+use crate::coalesce::Coalescer;
+use crate::config::Config;
+#[cfg(unix)]
+use crate::control;
+use crate::crossfade;
+use crate::dial;
+use crate::gesture;
+use crate::history::History;
+use crate::holidays::{self, Holidays};
+use crate::metrics;
+use crate::presence::{self, Event, Tracker};
+use crate::progress;
+use crate::queue::{with_priority, Priority};
+use crate::reload::Live;
+use crate::schedule::{self, Job};
+use crate::shutdown;
+use crate::sinks;
+use crate::snapshot::Snapshot;
+use crate::supervisor;
+use crate::time;
+use eyre::{eyre, Result};
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::process::{self, Command};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Time between checks for anything to do.
+const TICK: Duration = Duration::from_secs(5);
+
+/// Time between fetches of the holidays, and before trying again after failing.
+const HOLIDAYS_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+const HOLIDAYS_RETRY: Duration = Duration::from_secs(60 * 60);
+
+/// Longest time to wait for running commands and effects to end when stopping.
+#[cfg(unix)]
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Runs the background features set up in the config until stopped, taking commands from
+/// `blilys ctl` and signals on the bridge connection that is already set up, keeping the effects
+/// from `daemon.effects` running, dimming with the dials from `[dials]`, acting on the gestures
+/// of the buttons from `[[buttons]]`, keeping the levels of light from `[adaptive]`, and recording
+/// history if `history.record` is set.
+///
+/// Changes to the config file are picked up as the daemon runs, except for those to the parts
+/// used by the threads it starts.
+pub fn run(bridge: &Bridge, config: Config) -> Result<()> {
+    // Effects and fades run side by side, so their progress would garble the output.
+    progress::disable();
+    let live = Live::new(config);
+    let config = &*live.get();
+    let jobs = schedule::jobs(&config.schedules, config.holidays.as_deref())?;
+    presence::check(&config.presence)?;
+    let coalescer = Coalescer::default();
+    let handle = |line: &str| {
+        let _busy = shutdown::Busy::start().ok_or_else(|| eyre!("The daemon is stopping"))?;
+        let _span = tracing::info_span!("command", line).entered();
+        let result = crate::run_light_command(bridge, &live.get(), &coalescer, line);
+        match &result {
+            Ok(()) => tracing::info!("done"),
+            Err(err) => tracing::error!(error = %err, "failed"),
+        }
+        result
+    };
+    thread::scope(|scope| {
+        #[cfg(unix)]
+        {
+            let listener = control::listen()?;
+            scope.spawn(|| control::serve(listener, &handle));
+            let signals = [libc::SIGUSR1, libc::SIGUSR2, libc::SIGTERM];
+            control::on_signals(scope, &signals, |signal| {
+                let config = live.get();
+                let command = match signal {
+                    libc::SIGTERM => stop(bridge, &config),
+                    libc::SIGUSR1 => Some(&config.daemon.sigusr1),
+                    _ => config.daemon.sigusr2.as_ref(),
+                };
+                if let Some(command) = command {
+                    if let Err(err) = handle(command) {
+                        eprintln!("Failed to run {:?}: {}", command, err);
+                    }
+                }
+            })?;
+        }
+        supervisor::start(scope, bridge, config)?;
+        if !config.dials.is_empty() {
+            let dimmers = dial::dimmers(bridge, config)?;
+            scope.spawn(move || dial::watch(bridge, &dimmers));
+        }
+        for regulated in adaptive::regulated(bridge, config)? {
+            scope.spawn(move || adaptive::keep(bridge, regulated));
+        }
+        if !config.buttons.is_empty() {
+            let handle = &handle;
+            scope.spawn(move || gesture::watch(scope, bridge, &config.buttons, handle));
+        }
+        if config.history.record {
+            let interval = config.history.interval;
+            scope.spawn(move || {
+                let result = History::open().and_then(|history| history.record(bridge, interval));
+                if let Err(err) = result {
+                    eprintln!("Failed to record history: {:#}", err);
+                }
+            });
+        }
+        if !config.sinks.is_empty() {
+            let outputs = sinks::open(&config.sinks)?;
+            scope.spawn(move || sinks::forward(bridge, outputs));
+        }
+        info!("{}", t!("daemon-running"));
+        watch(bridge, &live, jobs)
+    })
+}
+
+/// Stops the daemon gracefully: takes no more commands, waits for the running commands and
+/// effects to end, restores the snapshot in `daemon.restore`, and exits.
+#[cfg(unix)]
+fn stop(bridge: &Bridge, config: &Config) -> ! {
+    info!("Stopping.");
+    if !shutdown::stop(STOP_TIMEOUT) {
+        eprintln!(
+            "Commands still running after {}, stopping anyway.",
+            time::format_duration(STOP_TIMEOUT)
+        );
+    }
+    if let Some(name) = &config.daemon.restore {
+        let result =
+            Snapshot::load(name).and_then(|snapshot| crossfade::restore(bridge, &snapshot));
+        if let Err(err) = result {
+            eprintln!("Failed to restore snapshot {:?}: {:#}", name, err);
+        }
+    }
+    metrics::update(config.metrics.as_deref());
+    // Remove the socket, so that `blilys ctl` doesn't wait for a daemon that is gone.
+    let _ = fs::remove_file(control::socket_path());
+    let _ = io::stdout().flush();
+    process::exit(0)
+}
+
+/// Acts on presence detection, calendar events, and schedules, if set up, and picks up changes to
+/// the config, forever.
+fn watch(bridge: &Bridge, live: &Live, mut jobs: Vec<Job>) -> Result<()> {
+    let mut tracker = Tracker::new(live.get().presence.away_after);
+    let mut next_scan = Instant::now();
+    let mut next_fetch = Instant::now();
+    let mut events = vec![];
+    let mut last_check = time::now();
+    let mut last_scheduled = schedule::last_check().unwrap_or(last_check);
+    let mut holidays = Holidays::new();
+    let mut next_holidays_fetch = Instant::now();
+    loop {
+        if live.check() {
+            // The calendars may be other ones now.
+            next_fetch = Instant::now();
+            next_holidays_fetch = Instant::now();
+            let config = live.get();
+            match schedule::jobs(&config.schedules, config.holidays.as_deref()) {
+                Ok(reloaded) => jobs = reloaded,
+                Err(err) => eprintln!("{:#}, keeping the old schedules.", err),
+            }
+        }
+        let config = live.get();
+        let presence = &config.presence;
+        let calendar = &config.calendar;
+        let calendar_url = calendar.url.as_ref().filter(|_| !calendar.rules.is_empty());
+
+        if !presence.devices.is_empty() && Instant::now() >= next_scan {
+            next_scan = Instant::now() + presence.interval;
+            let anyone_seen = match presence::scan(presence) {
+                Ok(anyone_seen) => Some(anyone_seen),
+                Err(err) => {
+                    eprintln!("Failed to scan for devices: {:#}", err);
+                    None
+                }
+            };
+            match anyone_seen.and_then(|seen| tracker.update(seen, Instant::now())) {
+                Some(Event::Arrive) => {
+                    info!("Someone came home.");
+                    if let Some(command) = &presence.arrive {
+                        if !presence.arrive_only_when_dark || is_dark(bridge) {
+                            run_command(command);
+                        }
+                    }
+                }
+                Some(Event::Leave) => {
+                    info!("Everyone left.");
+                    if let Some(command) = &presence.leave {
+                        run_command(command);
+                    }
+                }
+                None => {}
+            }
+        }
+
+        if let Some(url) = calendar_url {
+            if Instant::now() >= next_fetch {
+                next_fetch = Instant::now() + calendar.interval;
+                match calendar::fetch(url) {
+                    Ok(fetched) => events = fetched,
+                    Err(err) => eprintln!("{:#}", err),
+                }
+            }
+            let now = time::now();
+            for command in calendar::due(&events, &calendar.rules, last_check, now) {
+                run_command(command);
+            }
+            last_check = now;
+        }
+
+        if let Some(source) = config
+            .holidays
+            .as_ref()
+            .filter(|_| schedule::need_holidays(&jobs))
+        {
+            if Instant::now() >= next_holidays_fetch {
+                // Until the holidays are known, jobs run as on any other day.
+                match holidays::fetch(source) {
+                    Ok(fetched) => {
+                        holidays = fetched;
+                        next_holidays_fetch = Instant::now() + HOLIDAYS_INTERVAL;
+                    }
+                    Err(err) => {
+                        eprintln!("{:#}", err);
+                        next_holidays_fetch = Instant::now() + HOLIDAYS_RETRY;
+                    }
+                }
+            }
+        }
+        if !jobs.is_empty() {
+            let now = time::now();
+            for job in schedule::due(&jobs, &holidays, last_scheduled, now) {
+                run_command(&job.command);
+            }
+            last_scheduled = now;
+            if let Err(err) = schedule::save_last_check(now) {
+                eprintln!(
+                    "Failed to save the time of the last schedule check: {:#}",
+                    err
+                );
+            }
+        }
+
+        metrics::update(config.metrics.as_deref());
+        thread::sleep(TICK);
+    }
+}
+
+/// Returns whether it is after sunset, erring on the side of dark when the bridge can't tell.
+fn is_dark(bridge: &Bridge) -> bool {
+    match with_priority(Priority::Background, || presence::is_dark(bridge)) {
+        Ok(Some(dark)) => dark,
+        Ok(None) => {
+            eprintln!("The bridge has no configured daylight sensor, assuming it is dark.");
+            true
+        }
+        Err(err) => {
+            eprintln!("Failed to check daylight, assuming it is dark: {}", err);
+            true
+        }
+    }
+}
+
+/// Runs a command line like "all off" as a separate blilys process, so that a failing command
+/// doesn't stop the daemon.
+fn run_command(command: &str) {
+    if let Err(err) = try_run_command(command) {
+        eprintln!("Failed to run {:?}: {}", command, err);
+    }
+}
+
+fn try_run_command(command: &str) -> Result<()> {
+    let args = shell_words::split(command).map_err(|err| eyre!("Invalid command: {}", err))?;
+    let status = Command::new(env::current_exe()?).args(&args).status()?;
+    if !status.success() {
+        return Err(eyre!("Exited with {}", status));
+    }
+    Ok(())
+}
@@ -0,0 +1,331 @@
+use crate::api::Bridge;
+use crate::config::{Config, Format};
+use crate::outcome::Outcomes;
+use crate::output::{paint, Style};
+use eyre::{eyre, Result};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Attributes of a bridge resource, as the bridge's API takes them.
+type Attributes = Map<String, Value>;
+
+/// A declarative description of bridge resources and aliases, as read by `blilys apply`.
+///
+/// Resources are matched to the bridge's by name. Sections left out of the file are left alone,
+/// even when pruning.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DesiredState {
+    pub aliases: Option<BTreeMap<String, String>>,
+    pub groups: Option<BTreeMap<String, Attributes>>,
+    pub scenes: Option<BTreeMap<String, Attributes>>,
+    pub schedules: Option<BTreeMap<String, Attributes>>,
+}
+
+impl DesiredState {
+    pub fn read(path: &Path) -> Result<DesiredState> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| eyre!("Failed to read {}: {}", path.display(), err))?;
+        Format::from_path(Some(path))?
+            .deserialize(&contents)
+            .map_err(|err| eyre!("{}: {}", path.display(), err))
+    }
+}
+
+/// A change needed to bring the bridge or config to the desired state.
+#[derive(Debug)]
+pub enum Change {
+    Create {
+        kind: Kind,
+        name: String,
+        attributes: Attributes,
+    },
+    /// Sets the attributes that differ from the desired ones.
+    Update {
+        kind: Kind,
+        id: String,
+        name: String,
+        attributes: Attributes,
+    },
+    Delete {
+        kind: Kind,
+        id: String,
+        name: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Alias,
+    Group,
+    Scene,
+    Schedule,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Alias => "alias",
+            Kind::Group => "group",
+            Kind::Scene => "scene",
+            Kind::Schedule => "schedule",
+        }
+    }
+
+    /// Path of the resources on the bridge.
+    fn resource(self) -> &'static str {
+        match self {
+            Kind::Alias => unreachable!("Aliases are only in the config"),
+            Kind::Group => "groups",
+            Kind::Scene => "scenes",
+            Kind::Schedule => "schedules",
+        }
+    }
+}
+
+impl Change {
+    /// What the change is to, like `group "Kitchen"`.
+    fn subject(&self) -> String {
+        let (Change::Create { kind, name, .. }
+        | Change::Update { kind, name, .. }
+        | Change::Delete { kind, name, .. }) = self;
+        format!("{} {:?}", kind.name(), name)
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Create { kind, name, .. } => {
+                write!(f, "{} {} {:?}", paint("+", Style::Green), kind.name(), name)
+            }
+            Change::Update {
+                kind,
+                name,
+                attributes,
+                ..
+            } => {
+                let keys: Vec<&str> = attributes.keys().map(String::as_str).collect();
+                write!(f, "~ {} {:?}: {}", kind.name(), name, keys.join(", "))
+            }
+            Change::Delete { kind, name, .. } => {
+                write!(f, "{} {} {:?}", paint("-", Style::Dim), kind.name(), name)
+            }
+        }
+    }
+}
+
+/// Lists the changes needed to reach the desired state, deleting resources not in it if `prune`.
+pub fn plan(
+    bridge: &Bridge,
+    config: &Config,
+    desired: &DesiredState,
+    prune: bool,
+) -> Result<Vec<Change>> {
+    let mut changes = vec![];
+
+    if let Some(aliases) = &desired.aliases {
+        let current: Vec<Resource> = config
+            .aliases
+            .iter()
+            .map(|(name, target)| Resource {
+                id: name.clone(),
+                name: name.clone(),
+                attributes: alias_attributes(target),
+            })
+            .collect();
+        let wanted = aliases
+            .iter()
+            .map(|(name, target)| (name.clone(), alias_attributes(target)))
+            .collect();
+        reconcile(Kind::Alias, &current, &wanted, prune, &mut changes)?;
+    }
+
+    for (kind, wanted) in [
+        (Kind::Group, &desired.groups),
+        (Kind::Scene, &desired.scenes),
+        (Kind::Schedule, &desired.schedules),
+    ] {
+        if let Some(wanted) = wanted {
+            let resources: Map<String, Value> = bridge.get(kind.resource())?;
+            let mut current: Vec<Resource> = resources
+                .into_iter()
+                .filter_map(|(id, resource)| match resource {
+                    Value::Object(attributes) => {
+                        let name = attributes.get("name")?.as_str()?.to_owned();
+                        Some(Resource {
+                            id,
+                            name,
+                            attributes,
+                        })
+                    }
+                    _ => None,
+                })
+                .collect();
+            if kind == Kind::Scene {
+                // Lists of scenes leave out the lights' states, so those are fetched one by one.
+                for scene in &mut current {
+                    if wanted
+                        .get(&scene.name)
+                        .is_some_and(|wanted| wanted.contains_key("lightstates"))
+                    {
+                        scene.attributes = bridge.get(&format!("scenes/{}", scene.id))?;
+                    }
+                }
+            }
+            reconcile(kind, &current, wanted, prune, &mut changes)?;
+        }
+    }
+
+    Ok(changes)
+}
+
+fn alias_attributes(target: &str) -> Attributes {
+    let mut attributes = Map::new();
+    attributes.insert("target".to_owned(), target.into());
+    attributes
+}
+
+/// A current resource on the bridge, or alias in the config.
+struct Resource {
+    id: String,
+    name: String,
+    attributes: Attributes,
+}
+
+/// Compares the current resources with the wanted ones, matching them by name. Names on the
+/// bridge aren't unique, so a wanted resource matching several is an error, as there's no telling
+/// which one to change.
+fn reconcile(
+    kind: Kind,
+    current: &[Resource],
+    wanted: &BTreeMap<String, Attributes>,
+    prune: bool,
+    changes: &mut Vec<Change>,
+) -> Result<()> {
+    for (name, attributes) in wanted {
+        let matching: Vec<&Resource> = current.iter().filter(|r| &r.name == name).collect();
+        match matching.as_slice() {
+            [] => changes.push(Change::Create {
+                kind,
+                name: name.clone(),
+                attributes: attributes.clone(),
+            }),
+            [existing] => {
+                let differing: Attributes = attributes
+                    .iter()
+                    .filter(|(key, value)| existing.attributes.get(*key) != Some(value))
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect();
+                if !differing.is_empty() {
+                    changes.push(Change::Update {
+                        kind,
+                        id: existing.id.clone(),
+                        name: name.clone(),
+                        attributes: differing,
+                    });
+                }
+            }
+            several => {
+                let ids: Vec<&str> = several.iter().map(|r| r.id.as_str()).collect();
+                return Err(eyre!(
+                    "The bridge has several {}s named {:?}, with IDs {}; rename or delete all \
+                     but one of them",
+                    kind.name(),
+                    name,
+                    ids.join(", ")
+                ));
+            }
+        }
+    }
+    if prune {
+        for resource in current {
+            if !wanted.contains_key(&resource.name) {
+                changes.push(Change::Delete {
+                    kind,
+                    id: resource.id.clone(),
+                    name: resource.name.clone(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Makes the planned changes, saving the config if any aliases changed, and returns how many
+/// were made. Unless `strict`, a failing change doesn't stop the rest.
+pub fn execute(
+    bridge: &Bridge,
+    config: &mut Config,
+    changes: Vec<Change>,
+    strict: bool,
+) -> Result<usize> {
+    let mut aliases_changed = false;
+    let mut outcomes = Outcomes::new(strict);
+    let mut stopped = Ok(());
+    for change in changes {
+        let label = change.subject();
+        let result = match change {
+            Change::Create {
+                kind: Kind::Alias,
+                name,
+                attributes,
+            }
+            | Change::Update {
+                kind: Kind::Alias,
+                name,
+                attributes,
+                ..
+            } => {
+                let target = attributes["target"].as_str().unwrap_or_default();
+                config.aliases.insert(name, target.to_owned());
+                aliases_changed = true;
+                Ok(())
+            }
+            Change::Delete {
+                kind: Kind::Alias,
+                name,
+                ..
+            } => {
+                config.aliases.remove(&name);
+                aliases_changed = true;
+                Ok(())
+            }
+            Change::Create {
+                kind,
+                name,
+                mut attributes,
+            } => {
+                attributes.insert("name".to_owned(), name.into());
+                bridge
+                    .post::<Value>(kind.resource(), &attributes)
+                    .map(|_| ())
+            }
+            Change::Update {
+                kind,
+                id,
+                attributes,
+                ..
+            } => bridge
+                .put::<Value>(&format!("{}/{}", kind.resource(), id), &attributes)
+                .map(|_| ()),
+            Change::Delete { kind, id, .. } => bridge
+                .delete::<Value>(&format!("{}/{}", kind.resource(), id))
+                .map(|_| ()),
+        };
+        stopped = outcomes.record(label, result);
+        if stopped.is_err() {
+            break;
+        }
+    }
+    // Keep the aliases that were changed, even if a later change failed.
+    if aliases_changed && config.path.is_some() {
+        config.save()?;
+    }
+    stopped?;
+    outcomes.finish()
+}
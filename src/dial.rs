@@ -0,0 +1,208 @@
+use crate::api::Bridge;
+use crate::config::{Config, DialCurve};
+use crate::eventstream;
+use crate::target::Target;
+use crate::targets;
+use crate::values::parse_brightness;
+use eyre::{eyre, Result};
+use hueclient::CommandLight;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// How much brighter lights look than their brightness value, for the perceptual curve.
+pub const GAMMA: f64 = 2.2;
+
+/// A dial from `[dials]`, with its target resolved.
+pub struct Dimmer {
+    name: String,
+    target: Target,
+    curve: DialCurve,
+    /// Change in brightness per step, as a fraction of the full range.
+    step: f64,
+}
+
+/// A turn of a dial, as reported while it is being turned.
+#[derive(Debug, PartialEq)]
+struct Rotation {
+    /// Whether this is the first report of the turn, rather than one while it goes on.
+    starting: bool,
+    /// Steps turned, positive clockwise.
+    steps: i64,
+    /// Time the reported steps took.
+    duration: Duration,
+}
+
+/// Resolves the targets of the dials in the config.
+pub fn dimmers(bridge: &Bridge, config: &Config) -> Result<Vec<Dimmer>> {
+    config
+        .dials
+        .iter()
+        .map(|(name, dial)| {
+            let invalid = |err| eyre!("Invalid dial {:?}: {}", name, err);
+            Ok(Dimmer {
+                name: name.to_owned(),
+                target: targets::resolve(&dial.target, bridge, config).map_err(invalid)?,
+                curve: dial.curve,
+                step: parse_brightness(&dial.step).map_err(|err| invalid(eyre!(err)))? as f64
+                    / 254.0,
+            })
+        })
+        .collect()
+}
+
+/// Dims the dials' targets as they are turned, forever, reconnecting to the bridge's event
+/// stream whenever it is lost.
+pub fn watch(bridge: &Bridge, dimmers: &[Dimmer]) {
+    eventstream::follow_forever(bridge, |stream| follow(bridge, dimmers, stream));
+}
+
+fn follow(
+    bridge: &Bridge,
+    dimmers: &[Dimmer],
+    stream: impl Iterator<Item = Result<Value>>,
+) -> Result<()> {
+    // Turns are reported by the ID of the dial's device in the v2 API.
+    let devices = eventstream::get_resources(bridge, "device")?;
+    let mut by_device = HashMap::new();
+    for dimmer in dimmers {
+        let device = devices
+            .iter()
+            .find(|device| device["metadata"]["name"] == dimmer.name.as_str())
+            .and_then(|device| device["id"].as_str());
+        match device {
+            Some(id) => {
+                by_device.insert(id.to_owned(), dimmer);
+            }
+            None => eprintln!("No dial named {:?} on the bridge.", dimmer.name),
+        }
+    }
+
+    let mut levels: HashMap<&str, Option<u8>> = HashMap::new();
+    for resource in stream {
+        let resource = resource?;
+        let (dimmer, rotation) = match (
+            resource["owner"]["rid"]
+                .as_str()
+                .and_then(|id| by_device.get(id)),
+            rotation(&resource),
+        ) {
+            (Some(dimmer), Some(rotation)) => (dimmer, rotation),
+            _ => continue,
+        };
+        // The lights may have been changed by others since the last turn.
+        if rotation.starting || !levels.contains_key(dimmer.name.as_str()) {
+            levels.insert(&dimmer.name, dimmer.target.brightness(bridge)?);
+        }
+        let level = levels
+            .get_mut(dimmer.name.as_str())
+            .expect("level to be known");
+        // Turning down doesn't turn lights on.
+        if level.is_none() && rotation.steps < 0 {
+            continue;
+        }
+        let bri = turn(
+            level.unwrap_or(0),
+            rotation.steps,
+            dimmer.step,
+            dimmer.curve,
+        );
+        let command = CommandLight {
+            transitiontime: Some((rotation.duration.as_millis() / 100) as u16),
+            ..CommandLight::default().on().with_bri(bri)
+        };
+        if let Err(err) = dimmer.target.set_state(bridge, &command) {
+            eprintln!(
+                "Failed to dim {} with dial {:?}: {}",
+                dimmer.target, dimmer.name, err
+            );
+        }
+        *level = Some(bri);
+    }
+    Err(eyre!("The event stream ended"))
+}
+
+/// Reads a turn from an updated v2 resource, if it is a dial's.
+fn rotation(resource: &Value) -> Option<Rotation> {
+    if resource["type"] != "relative_rotary" {
+        return None;
+    }
+    let rotary = &resource["relative_rotary"];
+    // Newer firmware reports turns in `rotary_report`, older in `last_event`.
+    let report = match &rotary["rotary_report"] {
+        Value::Null => &rotary["last_event"],
+        report => report,
+    };
+    let rotation = &report["rotation"];
+    let steps = rotation["steps"].as_i64()?;
+    Some(Rotation {
+        starting: report["action"] == "start",
+        steps: match rotation["direction"].as_str()? {
+            "clock_wise" => steps,
+            _ => -steps,
+        },
+        duration: Duration::from_millis(rotation["duration"].as_u64().unwrap_or(0)),
+    })
+}
+
+/// Returns the brightness after turning `steps` from `bri`, changing by `step` of the full range
+/// per step, either in brightness value or in how bright it looks.
+fn turn(bri: u8, steps: i64, step: f64, curve: DialCurve) -> u8 {
+    let level = bri as f64 / 254.0;
+    let change = steps as f64 * step;
+    let level = match curve {
+        DialCurve::Linear => level + change,
+        DialCurve::Perceptual => (level.powf(1.0 / GAMMA) + change)
+            .clamp(0.0, 1.0)
+            .powf(GAMMA),
+    };
+    (level * 254.0).round().clamp(1.0, 254.0) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rotation, turn, Rotation};
+    use crate::config::DialCurve;
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[test]
+    fn turns_are_read_from_both_report_formats() {
+        let old = json!({
+            "type": "relative_rotary",
+            "relative_rotary": {"last_event": {
+                "action": "start",
+                "rotation": {"direction": "clock_wise", "steps": 30, "duration": 400},
+            }},
+        });
+        let new = json!({
+            "type": "relative_rotary",
+            "relative_rotary": {"rotary_report": {
+                "action": "repeat",
+                "rotation": {"direction": "counter_clock_wise", "steps": 8, "duration": 200},
+            }},
+        });
+        assert_eq!(
+            rotation(&old),
+            Some(Rotation {
+                starting: true,
+                steps: 30,
+                duration: Duration::from_millis(400),
+            })
+        );
+        assert_eq!(rotation(&new).map(|rotation| rotation.steps), Some(-8));
+        assert_eq!(rotation(&json!({"type": "button"})), None);
+    }
+
+    #[test]
+    fn perceptual_turns_are_finer_when_dim() {
+        assert_eq!(turn(127, 10, 0.01, DialCurve::Linear), 152);
+        assert_eq!(turn(10, -10, 0.01, DialCurve::Linear), 1);
+        assert_eq!(turn(250, 10, 0.01, DialCurve::Linear), 254);
+
+        let dim = turn(10, 10, 0.01, DialCurve::Perceptual) - 10;
+        let bright = turn(200, 10, 0.01, DialCurve::Perceptual) - 200;
+        assert!(dim < bright, "{} < {}", dim, bright);
+        assert_eq!(turn(0, 1, 0.01, DialCurve::Perceptual), 1);
+    }
+}
@@ -0,0 +1,234 @@
+use crate::bridge::Compat;
+use crate::progress::Progress;
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, UdpSocket};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+const MDNS_SERVICE: &str = "_hue._tcp.local";
+const MDNS_TIMEOUT: Duration = Duration::from_secs(3);
+const NUPNP_URL: &str = "https://discovery.meethue.com/";
+const DECONZ_NUPNP_URL: &str = "https://phoscon.de/discover";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Method {
+    /// Query for `_hue._tcp` on the local network with multicast DNS.
+    Mdns,
+    /// Ask Philips' cloud discovery endpoint for bridges registered from this network.
+    Nupnp,
+    /// Never discover; the bridge must be given with `--bridge` or in the config.
+    Manual,
+}
+
+impl FromStr for Method {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mdns" => Ok(Method::Mdns),
+            "nupnp" => Ok(Method::Nupnp),
+            "manual" => Ok(Method::Manual),
+            _ => Err(format!(
+                "Unknown discovery method {:?}, expected mdns, nupnp, or manual",
+                s
+            )),
+        }
+    }
+}
+
+pub fn default_methods() -> Vec<Method> {
+    vec![Method::Mdns, Method::Nupnp]
+}
+
+/// Tries each discovery method in order, returning the host of the first bridge found.
+pub fn discover(methods: &[Method], compat: Compat) -> Result<String> {
+    let _span = tracing::info_span!("discovery").entered();
+    let mut errors = vec![];
+    for method in methods {
+        let result = match (method, compat) {
+            (Method::Mdns, Compat::Deconz) => {
+                Err(eyre!("deCONZ gateways don't announce themselves over mDNS"))
+            }
+            // diyHue announces itself like a Hue bridge.
+            (Method::Mdns, _) => discover_mdns(MDNS_SERVICE),
+            (Method::Nupnp, Compat::Deconz) => discover_nupnp(DECONZ_NUPNP_URL),
+            (Method::Nupnp, _) => discover_nupnp(NUPNP_URL),
+            (Method::Manual, _) => Err(eyre!(
+                "Discovery is set to manual. Use --bridge or set bridge.host in the config."
+            )),
+        };
+        match result {
+            Ok(host) => {
+                tracing::info!(?method, host = host.as_str(), "found bridge");
+                return Ok(host);
+            }
+            Err(err) => {
+                tracing::warn!(?method, error = %err, "no bridge found");
+                errors.push(format!("{:?}: {}", method, err))
+            }
+        }
+    }
+    Err(eyre!("No bridge found. {}", errors.join(". ")))
+}
+
+/// Sends a one-shot mDNS query for the service's PTR records on IPv4 and IPv6, and returns the
+/// address of the first responder.
+pub fn discover_mdns(service: &str) -> Result<String> {
+    let query = mdns_query(service);
+    let mut sockets = vec![];
+    let mut errors = vec![];
+
+    let sent = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).and_then(|socket| {
+        socket.send_to(&query, (Ipv4Addr::new(224, 0, 0, 251), 5353))?;
+        Ok(socket)
+    });
+    match sent {
+        Ok(socket) => sockets.push(socket),
+        Err(err) => errors.push(format!("on IPv4: {}", err)),
+    }
+    match send_mdns_v6(&query) {
+        Ok(socket) => sockets.push(socket),
+        Err(err) => errors.push(format!("on IPv6: {}", err)),
+    }
+    if sockets.is_empty() {
+        return Err(eyre!("Failed to send mDNS query {}", errors.join(", ")));
+    }
+
+    let deadline = Instant::now() + MDNS_TIMEOUT;
+    let mut buf = [0; 4096];
+    let progress = Progress::new("Searching for a bridge", Some(MDNS_TIMEOUT));
+    while Instant::now() < deadline {
+        progress.update("");
+        for socket in &sockets {
+            socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+            if let Ok((len, from)) = socket.recv_from(&mut buf) {
+                if is_mdns_answer(&buf[..len], service) {
+                    return Ok(match from {
+                        SocketAddr::V4(v4) => v4.ip().to_string(),
+                        SocketAddr::V6(v6) if v6.scope_id() != 0 => {
+                            format!("{}%{}", v6.ip(), v6.scope_id())
+                        }
+                        SocketAddr::V6(v6) => v6.ip().to_string(),
+                    });
+                }
+            }
+        }
+    }
+    Err(eyre!("No response to mDNS query for {}", service))
+}
+
+/// Sends the query to the link-local mDNS group on each network interface with IPv6, as the
+/// group is on every link and a query without an interface only goes out on the default route's.
+fn send_mdns_v6(query: &[u8]) -> Result<UdpSocket> {
+    let socket = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))?;
+    let group = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+    let interfaces = ipv6_interfaces();
+    if interfaces.is_empty() {
+        return Err(eyre!("no network interface with IPv6 multicast"));
+    }
+    let mut failed = interfaces
+        .iter()
+        .filter_map(|&interface| {
+            let addr = SocketAddrV6::new(group, 5353, 0, interface);
+            socket.send_to(query, addr).err()
+        })
+        .collect::<Vec<_>>();
+    if failed.len() == interfaces.len() {
+        let err = failed.pop().expect("at least one interface");
+        return Err(eyre!("failed on every interface with IPv6: {}", err));
+    }
+    Ok(socket)
+}
+
+/// Returns the indexes of the network interfaces that are up and have IPv6 multicast, other
+/// than the loopback interface.
+#[cfg(unix)]
+fn ipv6_interfaces() -> Vec<u32> {
+    let mut interfaces = vec![];
+    let mut addrs: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut addrs) } != 0 {
+        return interfaces;
+    }
+    let mut next = addrs;
+    while let Some(ifa) = unsafe { next.as_ref() } {
+        next = ifa.ifa_next;
+        let flags = ifa.ifa_flags as libc::c_int;
+        let usable = flags & libc::IFF_UP != 0
+            && flags & libc::IFF_MULTICAST != 0
+            && flags & libc::IFF_LOOPBACK == 0;
+        let is_ipv6 = unsafe { ifa.ifa_addr.as_ref() }
+            .is_some_and(|addr| addr.sa_family as libc::c_int == libc::AF_INET6);
+        if !usable || !is_ipv6 {
+            continue;
+        }
+        let index = unsafe { libc::if_nametoindex(ifa.ifa_name) };
+        if index != 0 && !interfaces.contains(&index) {
+            interfaces.push(index);
+        }
+    }
+    unsafe { libc::freeifaddrs(addrs) };
+    interfaces
+}
+
+/// Only the default interface, as listing interfaces isn't supported on this platform.
+#[cfg(not(unix))]
+fn ipv6_interfaces() -> Vec<u32> {
+    vec![0]
+}
+
+fn mdns_query(service: &str) -> Vec<u8> {
+    // Header: ID 0, standard query, one question.
+    let mut query = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    query.extend(encode_name(service));
+    // Type PTR, class IN with the unicast-response bit set, as we're not listening on port 5353.
+    query.extend(&[0, 12, 0x80, 1]);
+    query
+}
+
+fn encode_name(name: &str) -> Vec<u8> {
+    let mut encoded = vec![];
+    for label in name.split('.') {
+        encoded.push(label.len() as u8);
+        encoded.extend(label.as_bytes());
+    }
+    encoded.push(0);
+    encoded
+}
+
+fn is_mdns_answer(packet: &[u8], service: &str) -> bool {
+    let is_response = packet.len() > 12 && packet[2] & 0x80 != 0;
+    let answers = packet.len() > 12 && (packet[6] != 0 || packet[7] != 0);
+    // Compare without the root label, as responders may compress the end of the name.
+    let name = encode_name(service);
+    let name = &name[..name.len() - 1];
+    is_response && answers && packet.windows(name.len()).any(|w| w == name)
+}
+
+pub fn discover_nupnp(url: &str) -> Result<String> {
+    #[derive(Deserialize)]
+    struct Found {
+        internalipaddress: String,
+        // deCONZ gateways are often moved off port 80, and report where they are.
+        #[serde(alias = "internalport")]
+        port: Option<u16>,
+    }
+    let found: Vec<Found> = reqwest::blocking::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()?
+        .get(url)
+        .send()?
+        .error_for_status()?
+        .json()?;
+    let found = found
+        .into_iter()
+        .next()
+        .ok_or_else(|| eyre!("No bridges registered from this network"))?;
+    Ok(match found.port {
+        Some(port) if port != 80 && port != 443 => {
+            format!("{}:{}", found.internalipaddress, port)
+        }
+        _ => found.internalipaddress,
+    })
+}
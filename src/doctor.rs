@@ -0,0 +1,336 @@
+use crate::api::Bridge;
+use crate::config::Config;
+use crate::discovery::{self, Method};
+use crate::options::ConnectionOpt;
+use crate::output::{paint, Style};
+use crate::time::{epoch_from_utc, now, parse_time_of_day};
+use directories::ProjectDirs;
+use eyre::{eyre, Result};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+/// The oldest bridge API version blilys supports.
+const MIN_API_VERSION: (u32, u32) = (1, 16);
+
+/// How far the bridge's clock may be off before schedules and time-based rules misbehave.
+const MAX_CLOCK_SKEW_SECS: u64 = 60;
+
+enum Status {
+    Pass,
+    /// Something that doesn't stop blilys from working now, but may later.
+    Warn,
+    Fail,
+}
+
+/// The outcome of one check, with how to fix it unless it passed.
+struct Check {
+    status: Status,
+    detail: String,
+    hint: Option<String>,
+}
+
+impl Check {
+    fn pass(detail: impl Into<String>) -> Check {
+        Check {
+            status: Status::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(detail: impl Into<String>, hint: impl Into<String>) -> Check {
+        Check {
+            status: Status::Warn,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn fail(detail: impl Into<String>, hint: impl Into<String>) -> Check {
+        Check {
+            status: Status::Fail,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+/// Runs checks of the setup from the config file to the bridge, printing each result with a hint
+/// on how to fix it. Fails if any check failed.
+pub fn run(opt: &ConnectionOpt) -> Result<()> {
+    let mut report = Report::default();
+
+    let path = Config::get_path()?;
+    let config = match Config::validate(&path).and_then(|_| Config::from_file()) {
+        _ if !path.is_file() => {
+            report.add(
+                "Config file",
+                Check::pass(format!("none yet at {}", path.display())),
+            );
+            Config::default()
+        }
+        Ok(config) => {
+            report.add("Config file", Check::pass(path.display().to_string()));
+            config
+        }
+        Err(err) => {
+            report.add(
+                "Config file",
+                Check::fail(
+                    format!("{:#}", err),
+                    "Fix it with `blilys config edit`, or move it away to start over.",
+                ),
+            );
+            Config::default()
+        }
+    };
+
+    if let Some(dirs) = ProjectDirs::from("", "", "blilys") {
+        for (name, dir) in [
+            ("Config directory", dirs.config_dir()),
+            ("Cache directory", dirs.cache_dir()),
+            ("Data directory", dirs.data_dir()),
+        ] {
+            report.add(name, check_writable(dir));
+        }
+    } else {
+        report.add(
+            "Directories",
+            Check::fail(
+                "no home directory",
+                "Set HOME, or pass --bridge and --username to run without any files.",
+            ),
+        );
+    }
+
+    let host = opt.bridge.clone().or_else(|| config.bridge.host.clone());
+    let methods = if opt.discovery.is_empty() {
+        &config.bridge.discovery
+    } else {
+        &opt.discovery
+    };
+    let mut discovered = None;
+    for &method in methods.iter().filter(|&&method| method != Method::Manual) {
+        let name = match method {
+            Method::Mdns => "Discovery with mDNS",
+            _ => "Discovery with N-UPnP",
+        };
+        match discovery::discover(&[method], config.bridge.compat) {
+            Ok(found) => {
+                report.add(name, Check::pass(format!("found {}", found)));
+                discovered.get_or_insert(found);
+            }
+            // Discovery only matters if blilys doesn't know where the bridge is.
+            Err(err) if host.is_some() => report.add(
+                name,
+                Check::warn(
+                    format!("{:#}", err),
+                    "Fine while the bridge keeps its address, but blilys can't find it if it \
+                     gets a new one.",
+                ),
+            ),
+            Err(err) => report.add(
+                name,
+                Check::fail(
+                    format!("{:#}", err),
+                    "Set bridge.host in the config, or pass --bridge.",
+                ),
+            ),
+        }
+    }
+
+    let host = match host.or(discovered) {
+        Some(host) => host,
+        None => {
+            report.add(
+                "Bridge",
+                Check::fail(
+                    "no address known",
+                    "Set bridge.host in the config, pass --bridge, or enable discovery.",
+                ),
+            );
+            return report.finish();
+        }
+    };
+    let bridge =
+        match Bridge::for_host(&host, config.bridge.compat.default_port()).and_then(|bridge| {
+            let public = bridge.get_public_config()?;
+            Ok((bridge, public))
+        }) {
+            Ok((bridge, public)) => {
+                report.add("Bridge", Check::pass(format!("reachable at {}", host)));
+                report.add("Bridge ID", check_bridge_id(&config, &public.bridgeid));
+                report.add("API version", check_api_version(&public.apiversion));
+                bridge
+            }
+            Err(err) => {
+                report.add(
+                    "Bridge",
+                    Check::fail(
+                        format!("not reachable at {}: {:#}", host, err),
+                        "Check that the bridge is powered on and on the same network as this \
+                     machine.",
+                    ),
+                );
+                return report.finish();
+            }
+        };
+
+    let username = match opt
+        .username
+        .clone()
+        .or_else(|| config.bridge.username.clone())
+    {
+        Some(username) => username,
+        None => {
+            report.add(
+                "Pairing",
+                Check::fail("not paired", "Run `blilys pair` to pair with the bridge."),
+            );
+            return report.finish();
+        }
+    };
+    match bridge.with_user(username).get::<Value>("config") {
+        Ok(bridge_config) => {
+            report.add("Pairing", Check::pass("the bridge accepts the username"));
+            report.add("Clock", check_clock(&bridge_config));
+        }
+        Err(err) => report.add(
+            "Pairing",
+            Check::fail(
+                format!("{:#}", err),
+                "The username may have been removed from the bridge. Run `blilys pair` again.",
+            ),
+        ),
+    }
+    report.finish()
+}
+
+/// Prints checks as they are done, counting the failures.
+#[derive(Default)]
+struct Report {
+    failed: usize,
+}
+
+impl Report {
+    fn add(&mut self, name: &str, check: Check) {
+        let status = match check.status {
+            Status::Pass => paint("ok  ", Style::Green),
+            Status::Warn => paint("warn", Style::Yellow),
+            Status::Fail => {
+                self.failed += 1;
+                paint("FAIL", Style::Red)
+            }
+        };
+        println!("{} {}: {}", status, name, check.detail);
+        if let Some(hint) = check.hint {
+            println!("     {}", hint);
+        }
+    }
+
+    fn finish(self) -> Result<()> {
+        match self.failed {
+            0 => Ok(()),
+            1 => Err(eyre!("1 check failed")),
+            n => Err(eyre!("{} checks failed", n)),
+        }
+    }
+}
+
+/// Checks that files can be created in the directory, creating it if needed.
+fn check_writable(dir: &Path) -> Check {
+    let probe = dir.join(".blilys-doctor");
+    let result = fs::create_dir_all(dir)
+        .and_then(|_| fs::write(&probe, b""))
+        .and_then(|_| fs::remove_file(&probe));
+    match result {
+        Ok(()) => Check::pass(format!("{} is writable", dir.display())),
+        Err(err) => Check::fail(
+            format!("{}: {}", dir.display(), err),
+            "Make the directory writable by this user.",
+        ),
+    }
+}
+
+fn check_bridge_id(config: &Config, id: &str) -> Check {
+    match &config.bridge.id {
+        Some(expected) if !expected.eq_ignore_ascii_case(id) => Check::fail(
+            format!("{}, but blilys was paired with {}", id, expected),
+            "Another bridge has taken over the address. Pass --bridge with the right address, \
+             or run `blilys pair` to pair with this bridge.",
+        ),
+        _ => Check::pass(id),
+    }
+}
+
+fn check_api_version(version: &str) -> Check {
+    let mut parts = version.split('.').map(|part| part.parse::<u32>().ok());
+    match (parts.next().flatten(), parts.next().flatten()) {
+        (Some(major), Some(minor)) if (major, minor) >= MIN_API_VERSION => Check::pass(version),
+        (Some(_), Some(_)) => Check::fail(
+            format!(
+                "{}, older than {}.{}",
+                version, MIN_API_VERSION.0, MIN_API_VERSION.1
+            ),
+            "Update the bridge's software in the Hue app.",
+        ),
+        _ => Check::warn(
+            format!("unknown version {:?}", version),
+            "The bridge may not be a Hue bridge. Set bridge.compat in the config if it is a \
+             deCONZ or diyHue gateway.",
+        ),
+    }
+}
+
+/// Compares the bridge's clock, in the `UTC` field of its config, with this machine's.
+fn check_clock(bridge_config: &Value) -> Check {
+    let utc = match bridge_config["UTC"].as_str() {
+        Some(utc) => utc,
+        None => return Check::warn("the bridge doesn't tell its time", "Nothing to do."),
+    };
+    let bridge_time = match parse_bridge_time(utc) {
+        Some(time) => time,
+        None => return Check::warn(format!("unknown time format {:?}", utc), "Nothing to do."),
+    };
+    let skew = now().abs_diff(bridge_time);
+    if skew <= MAX_CLOCK_SKEW_SECS {
+        Check::pass(format!("within {}s of this machine", skew))
+    } else {
+        Check::fail(
+            format!("{}s off from this machine, at {}", skew, utc),
+            "Check the time of this machine, and that the bridge can reach the internet to set \
+             its own.",
+        )
+    }
+}
+
+/// Parses a time like `2024-05-01T12:00:00`, as the bridge gives it in UTC.
+fn parse_bridge_time(s: &str) -> Option<u64> {
+    let (date, time) = s.split_once('T')?;
+    let mut date = date.split('-');
+    let year = date.next()?.parse().ok()?;
+    let month = date.next()?.parse().ok()?;
+    let day = date.next()?.parse().ok()?;
+    let time = parse_time_of_day(time).ok()?;
+    Some(epoch_from_utc((year, month, day), time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_api_version, parse_bridge_time, Status};
+
+    #[test]
+    fn bridge_times_are_parsed() {
+        assert_eq!(parse_bridge_time("1970-01-02T00:00:01"), Some(86401));
+        assert_eq!(parse_bridge_time("2024-05-01"), None);
+    }
+
+    #[test]
+    fn old_api_versions_fail() {
+        let check = |version| check_api_version(version).status;
+        assert!(matches!(check("1.50.0"), Status::Pass));
+        assert!(matches!(check("1.2.1"), Status::Fail));
+        assert!(matches!(check(""), Status::Warn));
+    }
+}
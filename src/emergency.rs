@@ -0,0 +1,42 @@
+use crate::audit;
+use crate::backend::LightBackend;
+use crate::crossfade;
+use crate::snapshot::Snapshot;
+use eyre::{eyre, Result};
+use hueclient::CommandLight;
+
+/// Name of the snapshot of the lights from before `blilys panic`, restored by `blilys allclear`.
+pub const SNAPSHOT: &str = "before-panic";
+
+/// Color temperature of the lights in a panic, in mireds, a neutral white at which Hue lights
+/// are at their brightest.
+const PANIC_CT: u16 = 250;
+
+/// Turns every light on at full brightness at once, ignoring caps, after saving how the lights
+/// were. A second panic keeps the snapshot from the first, so that all clear goes back to before
+/// both.
+pub fn panic(backend: &dyn LightBackend) -> Result<()> {
+    if Snapshot::load(SNAPSHOT).is_err() {
+        // Light matters more than being able to go back, so carry on without the snapshot.
+        match Snapshot::take(backend).and_then(|snapshot| snapshot.save(SNAPSHOT)) {
+            Ok(()) => {}
+            Err(err) => eprintln!("Failed to save the state of the lights: {:#}", err),
+        }
+    }
+    let command = CommandLight {
+        transitiontime: Some(0),
+        ..CommandLight::default().on().with_bri(254).with_ct(PANIC_CT)
+    };
+    // Group 0 has all lights, so this is a single request to the bridge.
+    let result = backend.set_group_state(0, &command);
+    audit::log("all", &command, &result);
+    result
+}
+
+/// Sets the lights back to how they were before `panic`.
+pub fn all_clear(backend: &dyn LightBackend) -> Result<()> {
+    let snapshot = Snapshot::load(SNAPSHOT)
+        .map_err(|_| eyre!("No panic to clear, or its snapshot is gone"))?;
+    crossfade::restore(backend, &snapshot)?;
+    Snapshot::delete(SNAPSHOT)
+}
@@ -0,0 +1,194 @@
+use crate::backend::LightBackend;
+use crate::config;
+use crate::history::{History, Transition};
+use crate::table::{Align, Table};
+use crate::time::{format_duration, now};
+use eyre::Result;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Time since the last poll after which the history counts as no longer being recorded.
+const STALE_AFTER: Duration = Duration::from_secs(5 * 60);
+
+/// Approximate maximum power draw in watts of common Hue models at full brightness.
+const MODEL_WATTS: &[(&str, f64)] = &[
+    ("LCT001", 8.5),
+    ("LCT003", 6.5),
+    ("LCT007", 9.0),
+    ("LCT010", 9.0),
+    ("LCT011", 9.5),
+    ("LCT012", 6.5),
+    ("LCT014", 9.0),
+    ("LCT015", 9.0),
+    ("LCT016", 9.0),
+    ("LLC020", 6.0),
+    ("LST001", 20.0),
+    ("LST002", 20.0),
+    ("LTW001", 9.5),
+    ("LTW004", 9.5),
+    ("LTW010", 9.5),
+    ("LTW012", 5.5),
+    ("LTW015", 9.5),
+    ("LWB004", 9.0),
+    ("LWB006", 9.0),
+    ("LWB010", 9.0),
+    ("LWB014", 9.0),
+];
+
+pub fn run(
+    bridge: &dyn LightBackend,
+    settings: &config::Energy,
+    history: &History,
+    since: Duration,
+) -> Result<()> {
+    let now = now();
+    let start = now.saturating_sub(since.as_secs());
+    // The lights' states are only known up to the last poll, as the recorder may have stopped.
+    let end = history.last_poll()?.unwrap_or(now).min(now);
+    if now - end > STALE_AFTER.as_secs() {
+        info!(
+            "History was last recorded {} ago, so the time since isn't counted.",
+            format_duration(Duration::from_secs(now - end))
+        );
+    }
+
+    let transitions = history.light_transitions(start)?;
+    let names: HashMap<usize, String> = transitions
+        .iter()
+        .map(|t| (t.light, t.name.clone()))
+        .collect();
+    let kwh = integrate(&transitions, start, end, |model| {
+        model_watts(settings, model)
+    });
+
+    if kwh.is_empty() {
+        info!("No history recorded. Run `blilys history record` to start collecting data.");
+        return Ok(());
+    }
+
+    let mut lights: Vec<_> = kwh.iter().collect();
+    lights.sort_by(|a, b| b.1.total_cmp(a.1));
+    println!("Lights:");
+    let mut table = Table::new(&[Align::Right, Align::Left, Align::Right]).shrinking(1);
+    for (id, kwh) in &lights {
+        table.row(vec![
+            format!("{}:", id).into(),
+            names[id].as_str().into(),
+            format!("{:.3} kWh", kwh).into(),
+        ]);
+    }
+    table.print();
+
+    let mut rooms: Vec<(String, f64)> = bridge
+        .get_all_groups()?
+        .into_iter()
+        .filter(|ig| ig.group.r#type == "Room")
+        .map(|ig| {
+            let total = ig
+                .group
+                .lights
+                .iter()
+                .filter_map(|l| l.parse::<usize>().ok())
+                .filter_map(|l| kwh.get(&l))
+                .sum();
+            (ig.group.name, total)
+        })
+        .collect();
+    rooms.sort_by(|a, b| b.1.total_cmp(&a.1));
+    println!("Rooms:");
+    let mut table = Table::new(&[Align::Left, Align::Right]).shrinking(0);
+    for (name, kwh) in &rooms {
+        table.row(vec![name.as_str().into(), format!("{:.3} kWh", kwh).into()]);
+    }
+    table.print();
+
+    println!(
+        "Total: {:.3} kWh over {:.1} days",
+        kwh.values().sum::<f64>(),
+        since.as_secs_f64() / 86400.0
+    );
+    Ok(())
+}
+
+/// Adds up the energy in kWh used by each light from `start` until `end`, from the light
+/// transitions ordered by light and time. Each state lasts until the light's next transition, and
+/// the power scales with the brightness.
+fn integrate(
+    transitions: &[Transition],
+    start: u64,
+    end: u64,
+    model_watts: impl Fn(&str) -> f64,
+) -> HashMap<usize, f64> {
+    let mut kwh: HashMap<usize, f64> = HashMap::new();
+    for (i, t) in transitions.iter().enumerate() {
+        let until = match transitions.get(i + 1) {
+            Some(next) if next.light == t.light => next.time.min(end),
+            _ => end,
+        };
+        let from = t.time.max(start);
+        let entry = kwh.entry(t.light).or_insert(0.0);
+        if !t.on || until <= from {
+            continue;
+        }
+        let watts = model_watts(&t.model) * f64::from(t.bri.unwrap_or(254).max(1)) / 254.0;
+        *entry += watts * (until - from) as f64 / 3600.0 / 1000.0;
+    }
+    kwh
+}
+
+fn model_watts(settings: &config::Energy, model: &str) -> f64 {
+    settings
+        .watts
+        .get(model)
+        .copied()
+        .or_else(|| {
+            MODEL_WATTS
+                .iter()
+                .find(|(m, _)| *m == model)
+                .map(|(_, watts)| *watts)
+        })
+        .unwrap_or(settings.default_watts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::integrate;
+    use crate::history::Transition;
+
+    fn transition(time: u64, light: usize, on: bool, bri: u8) -> Transition {
+        Transition {
+            time,
+            light,
+            name: format!("Light {}", light),
+            model: "LCT007".to_owned(),
+            on,
+            bri: Some(bri),
+        }
+    }
+
+    #[test]
+    fn energy_is_integrated_over_the_time_lights_are_on() {
+        let transitions = [
+            // On at full brightness from before the start, then at half, then off.
+            transition(0, 1, true, 254),
+            transition(7200, 1, true, 127),
+            transition(10800, 1, false, 127),
+            // On since before the start, until the last poll.
+            transition(0, 2, true, 254),
+        ];
+
+        let kwh = integrate(&transitions, 3600, 14400, |_| 10.0);
+
+        assert!((kwh[&1] - 0.015).abs() < 1e-9);
+        assert!((kwh[&2] - 0.030).abs() < 1e-9);
+    }
+
+    #[test]
+    fn energy_is_not_counted_after_the_last_poll() {
+        let transitions = [transition(0, 1, true, 254), transition(7200, 1, false, 254)];
+
+        let kwh = integrate(&transitions, 0, 3600, |_| 10.0);
+
+        assert!((kwh[&1] - 0.010).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,188 @@
+use crate::api::Bridge;
+use eyre::{eyre, Result, WrapErr};
+use reqwest::blocking::Response;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader};
+use std::net::SocketAddr;
+use std::thread;
+use std::time::Duration;
+
+/// Time to wait before reconnecting to the event stream after losing it.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Updates to resources of the bridge's v2 API, like turns of a Tap Dial, as they happen.
+pub struct EventStream<R> {
+    reader: R,
+    pending: VecDeque<Value>,
+}
+
+/// Follows the bridge's event stream forever, giving each connection to `follow` to read until it
+/// fails or ends, and reconnecting whenever it is lost. Bridges whose event stream can't be
+/// reached at all, like through the Remote API, are given up on at once.
+pub fn follow_forever(
+    bridge: &Bridge,
+    mut follow: impl FnMut(EventStream<BufReader<Response>>) -> Result<()>,
+) {
+    if let Err(err) = base_url(bridge) {
+        eprintln!("Can't follow the bridge's event stream: {:#}", err);
+        return;
+    }
+    loop {
+        if let Err(err) = connect(bridge).and_then(&mut follow) {
+            eprintln!(
+                "Lost the bridge's event stream, reconnecting in {}s: {:#}",
+                RECONNECT_DELAY.as_secs(),
+                err
+            );
+        }
+        thread::sleep(RECONNECT_DELAY);
+    }
+}
+
+/// Connects to the bridge's v2 event stream.
+pub fn connect(bridge: &Bridge) -> Result<EventStream<BufReader<Response>>> {
+    let resp = client()?
+        .get(&format!("{}/eventstream/clip/v2", base_url(bridge)?))
+        .header("hue-application-key", &bridge.username)
+        .header("Accept", "text/event-stream")
+        .send()
+        .and_then(|resp| resp.error_for_status())
+        .wrap_err("Failed to connect to the bridge's event stream")?;
+    Ok(EventStream::new(BufReader::new(resp)))
+}
+
+/// Fetches the bridge's v2 resources of a kind, like "device".
+pub fn get_resources(bridge: &Bridge, kind: &str) -> Result<Vec<Value>> {
+    let body: Value = client()?
+        .get(&format!("{}/clip/v2/resource/{}", base_url(bridge)?, kind))
+        .header("hue-application-key", &bridge.username)
+        .send()?
+        .error_for_status()?
+        .json()?;
+    match body["data"].as_array() {
+        Some(resources) => Ok(resources.clone()),
+        None => Err(eyre!("The bridge gave no {} resources", kind)),
+    }
+}
+
+/// Returns the URL of the bridge's v2 API, which is only served over HTTPS on the usual port, even
+/// for bridges whose v1 API is on another port.
+fn base_url(bridge: &Bridge) -> Result<String> {
+    match bridge.endpoint()?.addr {
+        SocketAddr::V4(addr) => Ok(format!("https://{}", addr.ip())),
+        SocketAddr::V6(addr) if addr.scope_id() == 0 => Ok(format!("https://[{}]", addr.ip())),
+        SocketAddr::V6(addr) => Err(eyre!(
+            "The bridge's v2 API can't be reached at the link-local address {} with a zone ID; \
+             give its IPv4 or global IPv6 address instead",
+            addr.ip()
+        )),
+    }
+}
+
+fn client() -> Result<reqwest::blocking::Client> {
+    // Bridges have self-signed certificates, and the event stream stays open, so neither
+    // certificates nor the usual timeout apply. Connecting still times out, for bridges that
+    // don't serve HTTPS at all.
+    Ok(reqwest::blocking::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(None)
+        .build()?)
+}
+
+impl<R: BufRead> EventStream<R> {
+    pub fn new(reader: R) -> EventStream<R> {
+        EventStream {
+            reader,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Reads the next server-sent event, giving back its data lines joined, or `None` at the end
+    /// of the stream.
+    fn read_event(&mut self) -> Result<Option<String>> {
+        let mut data = String::new();
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            match line.trim_end_matches(['\r', '\n']) {
+                "" if !data.is_empty() => return Ok(Some(data)),
+                "" => {}
+                line => {
+                    // Other fields, like `id:`, and comments, like the `: hi` sent on connecting,
+                    // aren't needed.
+                    if let Some(value) = line.strip_prefix("data:") {
+                        if !data.is_empty() {
+                            data.push('\n');
+                        }
+                        data.push_str(value.trim_start());
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for EventStream<R> {
+    type Item = Result<Value>;
+
+    /// Returns the next updated resource, with only the attributes that changed.
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            let data = match self.read_event() {
+                Ok(Some(data)) => data,
+                Ok(None) => return None,
+                Err(err) => return Some(Err(err)),
+            };
+            let events: Vec<Value> = match serde_json::from_str(&data) {
+                Ok(events) => events,
+                Err(err) => return Some(Err(eyre!("Invalid event from the bridge: {}", err))),
+            };
+            for event in events.into_iter().filter(|event| event["type"] == "update") {
+                if let Value::Array(resources) = &event["data"] {
+                    self.pending.extend(resources.iter().cloned());
+                }
+            }
+        }
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{base_url, EventStream};
+    use crate::api::Bridge;
+    use serde_json::json;
+    use std::io::Cursor;
+
+    #[test]
+    fn v2_api_is_reached_over_https_at_the_bridges_address() {
+        let url = |host| base_url(&Bridge::for_host(host, 80).unwrap());
+        assert_eq!(url("192.168.1.2:8080").unwrap(), "https://192.168.1.2");
+        assert_eq!(url("[fd00::2]:8080").unwrap(), "https://[fd00::2]");
+        assert!(url("fe80::2%1").is_err());
+        assert!(base_url(&Bridge::remote("https://api.meethue.com", "token")).is_err());
+    }
+
+    #[test]
+    fn updates_are_read_from_the_stream() {
+        let stream = ": hi\n\n\
+            id: 1:0\n\
+            data: [{\"type\":\"update\",\"data\":[{\"id\":\"a\"},{\"id\":\"b\"}]},\n\
+            data: {\"type\":\"add\",\"data\":[{\"id\":\"c\"}]}]\n\
+            \n\
+            data: [{\"type\":\"update\",\"data\":[{\"id\":\"d\"}]}]\r\n\r\n";
+        let updates: Vec<_> = EventStream::new(Cursor::new(stream))
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            updates,
+            vec![json!({"id": "a"}), json!({"id": "b"}), json!({"id": "d"})]
+        );
+    }
+}
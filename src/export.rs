@@ -0,0 +1,194 @@
+use crate::api::Bridge;
+use crate::config::create_private;
+use crate::eventstream;
+use crate::metrics;
+use crate::options::ExportFormat;
+use crate::queue::{with_priority, Priority};
+use eyre::Result;
+use serde_json::{Map, Value};
+use std::io::Write;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+/// Resources included in an export.
+const RESOURCES: [&str; 4] = ["lights", "groups", "scenes", "rules"];
+
+/// Attributes of each resource that change as the lights are used, rather than with the setup,
+/// left out so that an export only changes when the setup does.
+const RUNTIME: [(&str, &[&str]); 4] = [
+    ("lights", &["state"]),
+    ("groups", &["state", "action"]),
+    ("scenes", &["lastupdated"]),
+    ("rules", &["lasttriggered", "timestriggered"]),
+];
+
+/// Types of v2 resources that report their state all the time, whose updates only change the
+/// setup if they have new metadata, like a new name.
+const STATEFUL: [&str; 9] = [
+    "light",
+    "grouped_light",
+    "button",
+    "relative_rotary",
+    "motion",
+    "light_level",
+    "temperature",
+    "zigbee_connectivity",
+    "device_power",
+];
+
+/// Fetches the bridge's lights, groups, scenes, and rules as one JSON object, in one request.
+/// Resources the bridge's state leaves out, like deCONZ does with scenes, are exported as empty.
+///
+/// Objects keep their keys sorted, and the lights' state and other attributes that change as
+/// they're used are left out, so exporting an unchanged setup gives identical output.
+pub fn fetch(bridge: &Bridge) -> Result<Value> {
+    let mut state: Map<String, Value> = bridge.get_full_state()?;
+    let mut export = Map::new();
+    for resource in &RESOURCES {
+        let mut value = state
+            .remove(*resource)
+            .unwrap_or_else(|| Value::Object(Map::new()));
+        without_runtime(resource, &mut value);
+        export.insert(resource.to_string(), value);
+    }
+    Ok(Value::Object(export))
+}
+
+/// Removes the attributes in `RUNTIME` from each of the resources of a kind, keyed by ID.
+fn without_runtime(resource: &str, resources: &mut Value) {
+    let attributes = RUNTIME
+        .iter()
+        .find(|(name, _)| *name == resource)
+        .map(|(_, attributes)| *attributes)
+        .unwrap_or_default();
+    if let Value::Object(resources) = resources {
+        for value in resources.values_mut() {
+            if let Value::Object(value) = value {
+                for attribute in attributes {
+                    value.remove(*attribute);
+                }
+            }
+        }
+    }
+}
+
+/// Whether an update from the bridge's event stream may change what is exported.
+fn changes_setup(resource: &Value) -> bool {
+    let kind = resource["type"].as_str().unwrap_or_default();
+    !STATEFUL.contains(&kind) || !resource["metadata"].is_null()
+}
+
+pub fn format(export: &Value, format: ExportFormat, pretty: bool) -> Result<String> {
+    Ok(match format {
+        ExportFormat::Json if pretty => serde_json::to_string_pretty(export)? + "\n",
+        ExportFormat::Json => serde_json::to_string(export)? + "\n",
+        // TOML has no null, so leave out the attributes without a value.
+        ExportFormat::Toml => toml::to_string(&toml::Value::try_from(without_nulls(export))?)?,
+    })
+}
+
+fn without_nulls(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| (key.to_owned(), without_nulls(value)))
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.iter().map(without_nulls).collect()),
+        value => value.clone(),
+    }
+}
+
+/// Exports to `path`, or stdout without a path.
+pub fn run(bridge: &Bridge, path: Option<&Path>, format: ExportFormat, pretty: bool) -> Result<()> {
+    write(path, &self::format(&fetch(bridge)?, format, pretty)?)
+}
+
+/// Exports to `path` forever, again whenever the setup changes, and writes blilys' own metrics to
+/// `metrics` if given.
+///
+/// Changes are picked up from the bridge's event stream. Without one, like through the Remote
+/// API, or while it is lost, the bridge is polled every `interval` instead.
+pub fn watch(
+    bridge: &Bridge,
+    path: &Path,
+    format: ExportFormat,
+    pretty: bool,
+    interval: Duration,
+    metrics: Option<&Path>,
+) -> Result<()> {
+    info!(
+        "Exporting to {} on changes. Press Ctrl-C to stop.",
+        path.display()
+    );
+    let mut last = None;
+    let mut export = || -> Result<()> {
+        match with_priority(Priority::Background, || fetch(bridge)) {
+            Ok(export) if last.as_ref() != Some(&export) => {
+                write(Some(path), &self::format(&export, format, pretty)?)?;
+                info!("Exported to {}.", path.display());
+                last = Some(export);
+            }
+            Ok(_) => {}
+            Err(err) => eprintln!("Failed to fetch from the bridge: {}", err),
+        }
+        metrics::update(metrics);
+        Ok(())
+    };
+    loop {
+        export()?;
+        if let Ok(stream) = eventstream::connect(bridge) {
+            for resource in stream {
+                match resource {
+                    Ok(resource) if changes_setup(&resource) => export()?,
+                    Ok(_) => {}
+                    Err(_) => break,
+                }
+            }
+        }
+        thread::sleep(interval);
+    }
+}
+
+fn write(path: Option<&Path>, contents: &str) -> Result<()> {
+    match path {
+        // Scenes and rules can reveal the household's routines, so keep the file private.
+        Some(path) => create_private(path)?.write_all(contents.as_bytes())?,
+        None => print!("{}", contents),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{changes_setup, without_runtime};
+    use serde_json::json;
+
+    #[test]
+    fn runtime_attributes_are_left_out() {
+        let mut lights = json!({
+            "1": {"name": "Desk", "state": {"on": true, "reachable": true}},
+        });
+        without_runtime("lights", &mut lights);
+        assert_eq!(lights, json!({"1": {"name": "Desk"}}));
+
+        let mut rules = json!({
+            "1": {"name": "Switch", "lasttriggered": "none", "timestriggered": 0},
+        });
+        without_runtime("rules", &mut rules);
+        assert_eq!(rules, json!({"1": {"name": "Switch"}}));
+    }
+
+    #[test]
+    fn only_updates_to_the_setup_are_exported() {
+        assert!(!changes_setup(
+            &json!({"type": "light", "on": {"on": true}})
+        ));
+        assert!(changes_setup(
+            &json!({"type": "light", "metadata": {"name": "Desk"}})
+        ));
+        assert!(changes_setup(&json!({"type": "scene", "status": {}})));
+    }
+}
@@ -0,0 +1,261 @@
+use crate::api::Bridge;
+use crate::config::Button;
+use crate::eventstream;
+use eyre::{eyre, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread::{self, Scope};
+use std::time::{Duration, Instant};
+
+/// Time after a press within which a second press makes a double press.
+const DOUBLE_PRESS_WINDOW: Duration = Duration::from_millis(400);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Gesture {
+    Press,
+    Double,
+    Hold,
+}
+
+/// Tells gestures apart from the presses and releases of buttons as the bridge reports them.
+#[derive(Default)]
+struct Detector {
+    buttons: HashMap<String, ButtonState>,
+}
+
+#[derive(Default)]
+struct ButtonState {
+    /// When the button was last released after a short press, if it may become a double press.
+    pending: Option<Instant>,
+    /// Whether the button is held down, so the hold isn't acted on again.
+    held: bool,
+}
+
+impl Detector {
+    /// Takes an event of a button, like "short_release", returning the gesture it completes.
+    /// Single presses of buttons that have a double press wait for `expire`.
+    fn event(
+        &mut self,
+        button: &str,
+        event: &str,
+        now: Instant,
+        has_double: bool,
+    ) -> Option<Gesture> {
+        let state = self.buttons.entry(button.to_owned()).or_default();
+        match event {
+            "initial_press" => {
+                state.held = false;
+                None
+            }
+            // Held buttons are reported by a long press, or by repeats on older firmware.
+            "long_press" | "repeat" if !state.held => {
+                state.held = true;
+                state.pending = None;
+                Some(Gesture::Hold)
+            }
+            "short_release" => match state.pending.take() {
+                Some(at) if now.duration_since(at) <= DOUBLE_PRESS_WINDOW => Some(Gesture::Double),
+                _ if has_double => {
+                    state.pending = Some(now);
+                    None
+                }
+                _ => Some(Gesture::Press),
+            },
+            // Newer firmware tells double presses apart itself.
+            "double_short_release" => {
+                state.pending = None;
+                Some(Gesture::Double)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the buttons whose single press has waited out the window for a second one.
+    fn expire(&mut self, now: Instant) -> Vec<String> {
+        self.buttons
+            .iter_mut()
+            .filter(|(_, state)| {
+                state
+                    .pending
+                    .is_some_and(|at| now.duration_since(at) > DOUBLE_PRESS_WINDOW)
+            })
+            .map(|(button, state)| {
+                state.pending = None;
+                button.to_owned()
+            })
+            .collect()
+    }
+
+    /// Returns when the next single press waiting for a second one is done waiting.
+    fn next_deadline(&self) -> Option<Instant> {
+        self.buttons
+            .values()
+            .filter_map(|state| state.pending)
+            .min()
+            .map(|at| at + DOUBLE_PRESS_WINDOW)
+    }
+}
+
+/// Runs the commands for the gestures of the buttons in the config, forever, reconnecting to the
+/// bridge's event stream whenever it is lost. Commands run on threads of the scope, as some, like
+/// modes, go on for a while.
+pub fn watch<'scope>(
+    scope: &'scope Scope<'scope, '_>,
+    bridge: &Bridge,
+    buttons: &'scope [Button],
+    handle: &'scope (dyn Fn(&str) -> Result<()> + Sync),
+) {
+    eventstream::follow_forever(bridge, |stream| {
+        follow(scope, bridge, buttons, handle, stream)
+    });
+}
+
+fn follow<'scope>(
+    scope: &'scope Scope<'scope, '_>,
+    bridge: &Bridge,
+    buttons: &'scope [Button],
+    handle: &'scope (dyn Fn(&str) -> Result<()> + Sync),
+    stream: impl Iterator<Item = Result<Value>> + Send + 'static,
+) -> Result<()> {
+    let by_id = find_buttons(bridge, buttons)?;
+    // The stream is read on a thread of its own, so that single presses can be acted on once
+    // no second press came in time.
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for resource in stream {
+            if sender.send(resource).is_err() {
+                break;
+            }
+        }
+    });
+
+    let run = |button: &'scope Button, gesture: Gesture| {
+        let command = match gesture {
+            Gesture::Press => &button.press,
+            Gesture::Double => &button.double,
+            Gesture::Hold => &button.hold,
+        };
+        if let Some(command) = command {
+            scope.spawn(move || {
+                if let Err(err) = handle(command) {
+                    eprintln!("Failed to run {:?}: {}", command, err);
+                }
+            });
+        }
+    };
+    let mut detector = Detector::default();
+    loop {
+        let timeout = detector
+            .next_deadline()
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+            .unwrap_or(Duration::from_secs(60));
+        match receiver.recv_timeout(timeout) {
+            Ok(resource) => {
+                let resource = resource?;
+                let id = resource["id"].as_str().unwrap_or_default();
+                if let (Some(&button), Some(event)) = (by_id.get(id), button_event(&resource)) {
+                    let gesture =
+                        detector.event(id, event, Instant::now(), button.double.is_some());
+                    if let Some(gesture) = gesture {
+                        run(button, gesture);
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Err(eyre!("The event stream ended")),
+        }
+        for id in detector.expire(Instant::now()) {
+            run(by_id[id.as_str()], Gesture::Press);
+        }
+    }
+}
+
+/// Finds the v2 button resources of the buttons in the config, by the names of their switches
+/// and their numbers.
+fn find_buttons<'a>(bridge: &Bridge, buttons: &'a [Button]) -> Result<HashMap<String, &'a Button>> {
+    let devices = eventstream::get_resources(bridge, "device")?;
+    let resources = eventstream::get_resources(bridge, "button")?;
+    let mut by_id = HashMap::new();
+    for button in buttons {
+        let device = devices
+            .iter()
+            .find(|device| device["metadata"]["name"] == button.switch.as_str())
+            .and_then(|device| device["id"].as_str());
+        let resource = resources
+            .iter()
+            .filter(|resource| device.is_some() && resource["owner"]["rid"].as_str() == device)
+            .find(|resource| resource["metadata"]["control_id"] == button.button)
+            .and_then(|resource| resource["id"].as_str());
+        match resource {
+            Some(id) => {
+                by_id.insert(id.to_owned(), button);
+            }
+            None => eprintln!(
+                "No button {} on a switch named {:?} on the bridge.",
+                button.button, button.switch
+            ),
+        }
+    }
+    Ok(by_id)
+}
+
+/// Reads the event from an updated v2 resource, if it is a button's.
+fn button_event(resource: &Value) -> Option<&str> {
+    if resource["type"] != "button" {
+        return None;
+    }
+    let button = &resource["button"];
+    // Newer firmware reports events in `button_report`, older in `last_event`.
+    match &button["button_report"] {
+        Value::Null => button["last_event"].as_str(),
+        report => report["event"].as_str(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Detector, Gesture, DOUBLE_PRESS_WINDOW};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn presses_are_told_apart() {
+        let mut detector = Detector::default();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        // Without a double press, single presses are done on release.
+        detector.event("a", "initial_press", at(0), false);
+        assert_eq!(
+            detector.event("a", "short_release", at(100), false),
+            Some(Gesture::Press)
+        );
+
+        detector.event("b", "initial_press", at(0), true);
+        assert_eq!(detector.event("b", "short_release", at(100), true), None);
+        detector.event("b", "initial_press", at(200), true);
+        assert_eq!(
+            detector.event("b", "short_release", at(300), true),
+            Some(Gesture::Double)
+        );
+        assert!(detector.expire(at(1000)).is_empty());
+
+        detector.event("b", "initial_press", at(2000), true);
+        detector.event("b", "short_release", at(2100), true);
+        assert_eq!(
+            detector.next_deadline(),
+            Some(at(2100) + DOUBLE_PRESS_WINDOW)
+        );
+        assert!(detector.expire(at(2200)).is_empty());
+        assert_eq!(detector.expire(at(2600)), vec!["b".to_owned()]);
+
+        detector.event("c", "initial_press", at(0), true);
+        assert_eq!(
+            detector.event("c", "long_press", at(800), true),
+            Some(Gesture::Hold)
+        );
+        assert_eq!(detector.event("c", "repeat", at(1600), true), None);
+        assert_eq!(detector.event("c", "long_release", at(2000), true), None);
+        assert_eq!(detector.next_deadline(), None);
+    }
+}
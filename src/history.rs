@@ -0,0 +1,277 @@
+use crate::api::Bridge;
+use crate::queue::{with_priority, Priority};
+use crate::time::now;
+use directories::ProjectDirs;
+use eyre::Result;
+use rusqlite::{params, Connection};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS light_state (
+    time INTEGER NOT NULL,
+    light INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    model TEXT NOT NULL,
+    is_on INTEGER NOT NULL,
+    bri INTEGER,
+    hue INTEGER,
+    sat INTEGER,
+    ct INTEGER
+);
+CREATE INDEX IF NOT EXISTS light_state_time ON light_state (light, time);
+
+CREATE TABLE IF NOT EXISTS sensor_reading (
+    time INTEGER NOT NULL,
+    sensor TEXT NOT NULL,
+    name TEXT NOT NULL,
+    type TEXT NOT NULL,
+    state TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS sensor_reading_time ON sensor_reading (sensor, time);
+
+CREATE TABLE IF NOT EXISTS last_poll (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    time INTEGER NOT NULL
+);
+";
+
+pub struct History {
+    conn: Connection,
+}
+
+/// A recorded light state, in effect from `time` until the light's next transition.
+#[derive(Debug)]
+pub struct Transition {
+    pub time: u64,
+    pub light: usize,
+    pub name: String,
+    pub model: String,
+    pub on: bool,
+    pub bri: Option<u8>,
+}
+
+#[derive(Debug, PartialEq)]
+struct LightRow {
+    on: bool,
+    bri: Option<u8>,
+    hue: Option<u16>,
+    sat: Option<u8>,
+    ct: Option<u16>,
+}
+
+impl History {
+    pub fn open() -> Result<History> {
+        let path = History::get_path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(History { conn })
+    }
+
+    fn get_path() -> Result<PathBuf> {
+        let project_dirs = ProjectDirs::from("", "", "blilys").expect("Data dir not readable");
+        Ok(project_dirs.data_dir().join("history.sqlite"))
+    }
+
+    /// Polls the bridge forever, recording every light state transition and sensor update.
+    pub fn record(&self, bridge: &Bridge, interval: Duration) -> Result<()> {
+        let mut lights = self.last_light_rows()?;
+        let mut sensors = self.last_sensor_updates()?;
+
+        // Recording must not hold up commands to the lights.
+        with_priority(Priority::Background, || loop {
+            if let Err(err) = self.record_lights(bridge, &mut lights) {
+                eprintln!("Failed to record light states: {}", err);
+            }
+            if let Err(err) = self.record_sensors(bridge, &mut sensors) {
+                eprintln!("Failed to record sensor readings: {}", err);
+            }
+            std::thread::sleep(interval);
+        })
+    }
+
+    fn record_lights(&self, bridge: &Bridge, last: &mut HashMap<usize, LightRow>) -> Result<()> {
+        let time = now();
+        for il in bridge.get_all_lights()? {
+            let state = il.light.state;
+            let row = LightRow {
+                on: state.on,
+                bri: state.bri,
+                hue: state.hue,
+                sat: state.sat,
+                ct: state.ct,
+            };
+            if last.get(&il.id) == Some(&row) {
+                continue;
+            }
+            self.conn.execute(
+                "INSERT INTO light_state (time, light, name, model, is_on, bri, hue, sat, ct)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    time,
+                    il.id,
+                    il.light.name,
+                    il.light.modelid,
+                    row.on,
+                    row.bri,
+                    row.hue,
+                    row.sat,
+                    row.ct
+                ],
+            )?;
+            last.insert(il.id, row);
+        }
+        self.conn.execute(
+            "INSERT OR REPLACE INTO last_poll (id, time) VALUES (1, ?1)",
+            params![time],
+        )?;
+        Ok(())
+    }
+
+    fn record_sensors(&self, bridge: &Bridge, last: &mut HashMap<String, String>) -> Result<()> {
+        let time = now();
+        let sensors = bridge.get::<serde_json::Value>("sensors")?;
+        for (id, sensor) in sensors.as_object().into_iter().flatten() {
+            let state = &sensor["state"];
+            let updated = match state["lastupdated"].as_str() {
+                Some(updated) if updated != "none" => updated.to_owned(),
+                _ => continue,
+            };
+            if last.get(id) == Some(&updated) {
+                continue;
+            }
+            self.conn.execute(
+                "INSERT INTO sensor_reading (time, sensor, name, type, state)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    time,
+                    id,
+                    sensor["name"].as_str().unwrap_or_default(),
+                    sensor["type"].as_str().unwrap_or_default(),
+                    state.to_string()
+                ],
+            )?;
+            last.insert(id.to_owned(), updated);
+        }
+        Ok(())
+    }
+
+    fn last_light_rows(&self) -> Result<HashMap<usize, LightRow>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT light, is_on, bri, hue, sat, ct FROM light_state
+             WHERE rowid IN (SELECT MAX(rowid) FROM light_state GROUP BY light)",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get(0)?,
+                LightRow {
+                    on: row.get(1)?,
+                    bri: row.get(2)?,
+                    hue: row.get(3)?,
+                    sat: row.get(4)?,
+                    ct: row.get(5)?,
+                },
+            ))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn last_sensor_updates(&self) -> Result<HashMap<String, String>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT sensor, json_extract(state, '$.lastupdated') FROM sensor_reading
+             WHERE rowid IN (SELECT MAX(rowid) FROM sensor_reading GROUP BY sensor)",
+        )?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Returns when the lights were last polled, which is as far as the recorded states are
+    /// known to hold.
+    pub fn last_poll(&self) -> Result<Option<u64>> {
+        let mut stmt = self.conn.prepare("SELECT time FROM last_poll")?;
+        let mut rows = stmt.query_map([], |row| row.get(0))?;
+        Ok(rows.next().transpose()?)
+    }
+
+    /// Returns the transitions of all lights since `start`, including the state each light was in
+    /// at `start`, ordered by light and time.
+    pub fn light_transitions(&self, start: u64) -> Result<Vec<Transition>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT time, light, name, model, is_on, bri FROM light_state
+             WHERE time >= ?1
+                OR rowid IN (SELECT MAX(rowid) FROM light_state WHERE time < ?1 GROUP BY light)
+             ORDER BY light, time",
+        )?;
+        let rows = stmt.query_map(params![start], |row| {
+            Ok(Transition {
+                time: row.get(0)?,
+                light: row.get(1)?,
+                name: row.get(2)?,
+                model: row.get(3)?,
+                on: row.get(4)?,
+                bri: row.get(5)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Prints the recorded history of the light or sensor with the given ID or name.
+    pub fn print(&self, target: &str, since: Duration) -> Result<()> {
+        let start = now().saturating_sub(since.as_secs());
+        let light_id: i64 = target.parse().unwrap_or(-1);
+        let mut found = false;
+
+        let mut stmt = self.conn.prepare(
+            "SELECT datetime(time, 'unixepoch', 'localtime'), name, is_on, bri, ct, hue, sat
+             FROM light_state
+             WHERE (light = ?1 OR name = ?2 COLLATE NOCASE) AND time >= ?3
+             ORDER BY time",
+        )?;
+        let mut rows = stmt.query(params![light_id, target, start])?;
+        while let Some(row) = rows.next()? {
+            found = true;
+            let time: String = row.get(0)?;
+            let name: String = row.get(1)?;
+            let on: bool = row.get(2)?;
+            let bri: Option<u8> = row.get(3)?;
+            let ct: Option<u16> = row.get(4)?;
+            let hue: Option<u16> = row.get(5)?;
+            let sat: Option<u8> = row.get(6)?;
+            println!(
+                "{time} {name:30} [{on:3}] [bri {bri:>3}] [ct {ct:>3}] [hue {hue:>5}] [sat {sat:>3}]",
+                time = time,
+                name = name,
+                on = if on { "on" } else { "off" },
+                bri = bri.unwrap_or(0),
+                ct = ct.unwrap_or(0),
+                hue = hue.unwrap_or(0),
+                sat = sat.unwrap_or(0),
+            );
+        }
+
+        let mut stmt = self.conn.prepare(
+            "SELECT datetime(time, 'unixepoch', 'localtime'), name, state
+             FROM sensor_reading
+             WHERE (sensor = ?1 OR name = ?1 COLLATE NOCASE) AND time >= ?2
+             ORDER BY time",
+        )?;
+        let mut rows = stmt.query(params![target, start])?;
+        while let Some(row) = rows.next()? {
+            found = true;
+            let time: String = row.get(0)?;
+            let name: String = row.get(1)?;
+            let state: String = row.get(2)?;
+            println!("{} {:30} {}", time, name, state);
+        }
+
+        if !found {
+            info!("No history recorded for {:?}.", target);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,147 @@
+use crate::calendar;
+use crate::time::{self, epoch_from_local, local_date, next_day, Date, TimeOfDay};
+use eyre::{eyre, Result, WrapErr};
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+/// Public holidays by country, from the Nager.Date API.
+const NAGER_URL: &str = "https://date.nager.at/api/v3/PublicHolidays";
+
+/// Dates of public holidays.
+pub type Holidays = BTreeSet<Date>;
+
+#[derive(Debug, Deserialize)]
+struct PublicHoliday {
+    /// Date like "2026-05-17".
+    date: String,
+    /// Whether the holiday is in the whole country, and not only some regions.
+    global: bool,
+}
+
+/// Fetches the holidays of this year and the next, from a country code like "NO", or the
+/// all-day events of an iCalendar file given by URL or path, including those repeating yearly.
+pub fn fetch(source: &str) -> Result<Holidays> {
+    if source.len() == 2 && source.chars().all(|c| c.is_ascii_alphabetic()) {
+        let year = local_date(time::now()).0;
+        let mut holidays = fetch_country(source, year)?;
+        holidays.extend(fetch_country(source, year + 1)?);
+        return Ok(holidays);
+    }
+    Ok(from_events(&calendar::fetch(source)?))
+}
+
+fn fetch_country(country: &str, year: i64) -> Result<Holidays> {
+    let url = format!("{}/{}/{}", NAGER_URL, year, country.to_uppercase());
+    let text = reqwest::blocking::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()?
+        .get(&url)
+        .send()
+        .and_then(|resp| resp.error_for_status())
+        .and_then(|resp| resp.text())
+        .wrap_err_with(|| format!("Failed to fetch the holidays of {}", country))?;
+    parse_country(&text)
+}
+
+/// Parses holidays from the Nager.Date API, leaving out those only in some regions.
+fn parse_country(text: &str) -> Result<Holidays> {
+    let holidays: Vec<PublicHoliday> = serde_json::from_str(text)?;
+    holidays
+        .iter()
+        .filter(|holiday| holiday.global)
+        .map(|holiday| parse_date(&holiday.date))
+        .collect()
+}
+
+fn parse_date(s: &str) -> Result<Date> {
+    let invalid = || eyre!("Invalid date {:?}", s);
+    let mut parts = s.splitn(3, '-');
+    let mut part = || parts.next().and_then(|part| part.parse::<u32>().ok());
+    let (year, month, day) = (part(), part(), part());
+    Ok((
+        year.ok_or_else(invalid)?.into(),
+        month.ok_or_else(invalid)?,
+        day.ok_or_else(invalid)?,
+    ))
+}
+
+/// Returns the dates covered by the events, in the system's time zone.
+fn from_events(events: &[calendar::Event]) -> Holidays {
+    let midnight = TimeOfDay {
+        hour: 0,
+        minute: 0,
+        second: 0,
+    };
+    let mut holidays = Holidays::new();
+    for event in events {
+        let mut date = local_date(event.start);
+        loop {
+            holidays.insert(date);
+            date = next_day(date);
+            if epoch_from_local(date, midnight) >= event.end {
+                break;
+            }
+        }
+    }
+    holidays
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_events, parse_country};
+    use crate::calendar;
+    use crate::time::{epoch_from_local, TimeOfDay};
+
+    #[test]
+    fn holidays_are_read_by_country() {
+        let holidays = parse_country(
+            r#"[
+                {"date": "2026-05-17", "localName": "Grunnlovsdag", "global": true},
+                {"date": "2026-06-23", "localName": "Sankthansaften", "global": false}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(holidays.into_iter().collect::<Vec<_>>(), [(2026, 5, 17)]);
+    }
+
+    #[test]
+    fn holidays_are_read_from_all_day_events() {
+        let events = calendar::parse(
+            "BEGIN:VEVENT\r\nSUMMARY:Christmas\r\nDTSTART;VALUE=DATE:20261224\r\n\
+             DTEND;VALUE=DATE:20261227\r\nEND:VEVENT\r\n\
+             BEGIN:VEVENT\r\nSUMMARY:May 17\r\nDTSTART;VALUE=DATE:20260517\r\nEND:VEVENT\r\n",
+            u64::MAX,
+        );
+        assert_eq!(
+            from_events(&events).into_iter().collect::<Vec<_>>(),
+            [
+                (2026, 5, 17),
+                (2026, 12, 24),
+                (2026, 12, 25),
+                (2026, 12, 26)
+            ]
+        );
+    }
+
+    #[test]
+    fn holidays_are_read_from_yearly_events() {
+        let until = epoch_from_local(
+            (2027, 12, 31),
+            TimeOfDay {
+                hour: 0,
+                minute: 0,
+                second: 0,
+            },
+        );
+        let events = calendar::parse(
+            "BEGIN:VEVENT\r\nSUMMARY:May 17\r\nDTSTART;VALUE=DATE:20260517\r\n\
+             DTEND;VALUE=DATE:20260518\r\nRRULE:FREQ=YEARLY\r\nEND:VEVENT\r\n",
+            until,
+        );
+        assert_eq!(
+            from_events(&events).into_iter().collect::<Vec<_>>(),
+            [(2026, 5, 17), (2027, 5, 17)]
+        );
+    }
+}
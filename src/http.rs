@@ -0,0 +1,376 @@
+use crate::metrics;
+use eyre::{eyre, Result, WrapErr};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6, TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A resolved HTTP server address.
+#[derive(Debug, Clone)]
+pub struct Endpoint {
+    pub addr: SocketAddr,
+    /// The value of the `Host` header, with IPv6 addresses in brackets.
+    pub host_header: String,
+}
+
+impl Endpoint {
+    /// Resolves a host given as an IPv4 or IPv6 address or a hostname, optionally with a port.
+    ///
+    /// IPv6 addresses may be given with or without brackets, and link-local addresses may include
+    /// a zone ID, like `fe80::1%eth0` or `[fe80::1%eth0]:8080`.
+    pub fn resolve(host: &str, default_port: u16) -> Result<Endpoint> {
+        let (name, port) = split_port(host, default_port)?;
+
+        let addr = if let Some(addr) = parse_ipv6(name)? {
+            SocketAddr::V6(SocketAddrV6::new(addr.0, port, 0, addr.1))
+        } else if let Ok(ip) = name.parse::<IpAddr>() {
+            SocketAddr::new(ip, port)
+        } else {
+            (name, port)
+                .to_socket_addrs()
+                .map_err(|err| eyre!("Failed to resolve host {:?}: {}", name, err))?
+                .next()
+                .ok_or_else(|| eyre!("Host {:?} did not resolve to any address", name))?
+        };
+
+        let host_header = match addr {
+            SocketAddr::V6(v6) if port == default_port => format!("[{}]", v6.ip()),
+            SocketAddr::V6(v6) => format!("[{}]:{}", v6.ip(), port),
+            SocketAddr::V4(_) if port == default_port => name.to_owned(),
+            SocketAddr::V4(_) => format!("{}:{}", name, port),
+        };
+
+        Ok(Endpoint { addr, host_header })
+    }
+}
+
+/// Splits an optional port off a host, taking care not to mistake IPv6 colons for a port.
+fn split_port(host: &str, default_port: u16) -> Result<(&str, u16)> {
+    let parse_port = |port: &str| {
+        port.parse::<u16>()
+            .map_err(|_| eyre!("Invalid port in host {:?}", host))
+    };
+    if let Some(rest) = host.strip_prefix('[') {
+        let end = rest
+            .find(']')
+            .ok_or_else(|| eyre!("Missing ']' in host {:?}", host))?;
+        let port = match &rest[end + 1..] {
+            "" => default_port,
+            port => parse_port(port.strip_prefix(':').unwrap_or(port))?,
+        };
+        return Ok((&rest[..end], port));
+    }
+    match host.rsplit_once(':') {
+        // More than one colon means an IPv6 address without brackets, so no port.
+        Some((name, port)) if !name.contains(':') => Ok((name, parse_port(port)?)),
+        _ => Ok((host, default_port)),
+    }
+}
+
+/// Parses an IPv6 address with an optional zone ID into the address and scope ID.
+fn parse_ipv6(name: &str) -> Result<Option<(Ipv6Addr, u32)>> {
+    let (addr, zone) = match name.split_once('%') {
+        Some((addr, zone)) => (addr, Some(zone)),
+        None => (name, None),
+    };
+    let addr = match addr.parse::<Ipv6Addr>() {
+        Ok(addr) => addr,
+        Err(_) => return Ok(None),
+    };
+    let scope_id = match zone {
+        None => 0,
+        Some(zone) => match zone.parse::<u32>() {
+            Ok(index) => index,
+            Err(_) => interface_index(zone)?,
+        },
+    };
+    Ok(Some((addr, scope_id)))
+}
+
+#[cfg(unix)]
+fn interface_index(name: &str) -> Result<u32> {
+    let c_name = std::ffi::CString::new(name)?;
+    match unsafe { libc::if_nametoindex(c_name.as_ptr()) } {
+        0 => Err(eyre!("Unknown network interface {:?}", name)),
+        index => Ok(index),
+    }
+}
+
+#[cfg(not(unix))]
+fn interface_index(name: &str) -> Result<u32> {
+    Err(eyre!(
+        "Zone IDs must be numeric interface indexes on this platform, not {:?}",
+        name
+    ))
+}
+
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+/// A minimal HTTP/1.1 client for talking to a single server, reusing connections between
+/// requests. Concurrent requests each get a connection of their own.
+pub struct Client {
+    pub endpoint: Endpoint,
+    /// Open connections not in use by a request.
+    idle: Mutex<Vec<BufReader<TcpStream>>>,
+}
+
+impl Client {
+    pub fn new(endpoint: Endpoint) -> Client {
+        Client {
+            endpoint,
+            idle: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn request(&self, method: &str, path: &str, body: Option<&str>) -> Result<Response> {
+        let connection = self
+            .idle
+            .lock()
+            .expect("HTTP connection lock poisoned")
+            .pop();
+        let reused = connection.is_some();
+        let (response, connection) = match self.send(connection, method, path, body) {
+            // The server may have closed an idle connection before getting the request, so retry
+            // once on a fresh one, unless sending it twice could do something twice.
+            Err(failure) if reused && failure.closed && is_idempotent(method) => {
+                metrics::retry();
+                self.send(None, method, path, body)
+            }
+            result => result,
+        }
+        .map_err(|failure| failure.err)?;
+        if let Some(connection) = connection {
+            self.idle
+                .lock()
+                .expect("HTTP connection lock poisoned")
+                .push(connection);
+        }
+        Ok(response)
+    }
+
+    /// Sends the request on the connection, or a new one if none, giving back the connection if
+    /// it can be reused.
+    fn send(
+        &self,
+        connection: Option<BufReader<TcpStream>>,
+        method: &str,
+        path: &str,
+        body: Option<&str>,
+    ) -> std::result::Result<(Response, Option<BufReader<TcpStream>>), Failure> {
+        let mut reader = match connection {
+            Some(connection) => connection,
+            None => self.connect().map_err(Failure::other)?,
+        };
+
+        let body = body.unwrap_or("");
+        let mut request = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: keep-alive\r\nContent-Length: {}\r\n",
+            method,
+            path,
+            self.endpoint.host_header,
+            body.len()
+        );
+        if !body.is_empty() {
+            request.push_str("Content-Type: application/json\r\n");
+        }
+        request.push_str("\r\n");
+        request.push_str(body);
+        reader
+            .get_mut()
+            .write_all(request.as_bytes())
+            .map_err(Failure::closed)?;
+
+        // Only a connection closed before the response starts means the server didn't get the
+        // request. After a timeout, it may well have.
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) => return Err(Failure::closed(eyre!("Connection closed by server"))),
+            Ok(_) => {}
+            Err(err) if is_closed(&err) => return Err(Failure::closed(err)),
+            Err(err) => return Err(Failure::other(err)),
+        }
+        let (response, keep_alive) = read_response(&mut reader, &line).map_err(Failure::other)?;
+        Ok((response, Some(reader).filter(|_| keep_alive)))
+    }
+
+    fn connect(&self) -> Result<BufReader<TcpStream>> {
+        let stream = TcpStream::connect_timeout(&self.endpoint.addr, CONNECT_TIMEOUT)
+            .wrap_err_with(|| format!("Failed to connect to {}", self.endpoint.addr))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        stream.set_nodelay(true)?;
+        Ok(BufReader::new(stream))
+    }
+}
+
+/// A failed request, and whether the connection was closed before the server could have got it.
+struct Failure {
+    err: eyre::Report,
+    closed: bool,
+}
+
+impl Failure {
+    fn closed(err: impl Into<eyre::Report>) -> Failure {
+        Failure {
+            err: err.into(),
+            closed: true,
+        }
+    }
+
+    fn other(err: impl Into<eyre::Report>) -> Failure {
+        Failure {
+            err: err.into(),
+            closed: false,
+        }
+    }
+}
+
+fn is_closed(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+    )
+}
+
+/// Whether sending the request twice does the same as sending it once.
+fn is_idempotent(method: &str) -> bool {
+    matches!(method, "GET" | "PUT" | "DELETE")
+}
+
+/// Reads the rest of a response after its status line.
+fn read_response(reader: &mut BufReader<TcpStream>, status_line: &str) -> Result<(Response, bool)> {
+    let mut line = String::new();
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse::<u16>().ok())
+        .ok_or_else(|| eyre!("Invalid HTTP status line {:?}", status_line.trim_end()))?;
+
+    let mut content_length = None;
+    let mut chunked = false;
+    let mut keep_alive = true;
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "content-length" => content_length = value.parse::<usize>().ok(),
+                "transfer-encoding" => chunked = value.eq_ignore_ascii_case("chunked"),
+                "connection" => keep_alive = !value.eq_ignore_ascii_case("close"),
+                _ => {}
+            }
+        }
+    }
+
+    let mut body = Vec::new();
+    if chunked {
+        loop {
+            line.clear();
+            reader.read_line(&mut line)?;
+            let size = usize::from_str_radix(line.trim().split(';').next().unwrap_or(""), 16)
+                .map_err(|_| eyre!("Invalid chunk size {:?}", line.trim()))?;
+            let mut chunk = vec![0; size + 2];
+            reader.read_exact(&mut chunk)?;
+            if size == 0 {
+                break;
+            }
+            body.extend_from_slice(&chunk[..size]);
+        }
+    } else if let Some(length) = content_length {
+        body.resize(length, 0);
+        reader.read_exact(&mut body)?;
+    } else {
+        reader.read_to_end(&mut body)?;
+        keep_alive = false;
+    }
+
+    Ok((
+        Response {
+            status,
+            body: String::from_utf8(body)?,
+        },
+        keep_alive,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Client, Endpoint};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    /// Starts a server that answers the first request on each connection, and closes the
+    /// connection when it gets the second, as if it had timed out. Returns a client for it and
+    /// the number of requests it got.
+    fn closing_server() -> (Client, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let received = Arc::new(AtomicUsize::new(0));
+        let counter = received.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut reader = BufReader::new(stream.unwrap());
+                for answer in [true, false].iter().copied() {
+                    let mut length = 0;
+                    loop {
+                        let mut line = String::new();
+                        if reader.read_line(&mut line).unwrap() == 0 {
+                            return;
+                        }
+                        if let Some(value) = line.strip_prefix("Content-Length:") {
+                            length = value.trim().parse().unwrap();
+                        }
+                        if line == "\r\n" {
+                            break;
+                        }
+                    }
+                    reader.read_exact(&mut vec![0; length]).unwrap();
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    if answer {
+                        let response = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n[]";
+                        reader.get_mut().write_all(response.as_bytes()).unwrap();
+                    }
+                }
+            }
+        });
+        let endpoint = Endpoint::resolve(&addr.to_string(), 80).unwrap();
+        (Client::new(endpoint), received)
+    }
+
+    #[test]
+    fn idempotent_requests_are_retried_on_closed_connections() {
+        let (client, received) = closing_server();
+
+        client.request("GET", "/", None).unwrap();
+        let response = client.request("PUT", "/", Some("{}")).unwrap();
+
+        assert_eq!(response.body, "[]");
+        assert_eq!(received.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn posts_are_not_retried() {
+        let (client, received) = closing_server();
+
+        client.request("GET", "/", None).unwrap();
+        assert!(client.request("POST", "/", Some("{}")).is_err());
+
+        assert_eq!(received.load(Ordering::SeqCst), 2);
+    }
+}
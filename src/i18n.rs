@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::OnceLock;
+
+/// Looks up a message in the user's language, like `t!("pair-waiting", duration = "60s")`.
+macro_rules! t {
+    ($id:expr) => {
+        $crate::i18n::message($id, &[])
+    };
+    ($id:expr, $($name:ident = $value:expr),+ $(,)?) => {
+        $crate::i18n::message($id, &[$((stringify!($name), &$value as &dyn std::fmt::Display)),+])
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum Language {
+    English,
+    /// Norwegian bokmål.
+    Bokmal,
+}
+
+static LANGUAGE: AtomicU8 = AtomicU8::new(Language::English as u8);
+
+impl Language {
+    /// Finds the language for a code like `nb`, or a locale like `nb_NO.UTF-8` from `LANG`.
+    fn from_code(code: &str) -> Option<Language> {
+        let code = code.split(['_', '-', '.']).next().unwrap_or_default();
+        match code.to_lowercase().as_str() {
+            "en" => Some(Language::English),
+            // Plain "no" usually means bokmål.
+            "nb" | "no" => Some(Language::Bokmal),
+            _ => None,
+        }
+    }
+
+    fn messages(self) -> &'static HashMap<String, String> {
+        static ENGLISH: OnceLock<HashMap<String, String>> = OnceLock::new();
+        static BOKMAL: OnceLock<HashMap<String, String>> = OnceLock::new();
+        match self {
+            Language::English => ENGLISH.get_or_init(|| parse(include_str!("locales/en.ftl"))),
+            Language::Bokmal => BOKMAL.get_or_init(|| parse(include_str!("locales/nb.ftl"))),
+        }
+    }
+}
+
+/// Selects the language of messages: the one from the config if given, or else the one from the
+/// usual locale environment variables, or else English.
+pub fn init(configured: Option<&str>) {
+    let language = configured
+        .and_then(Language::from_code)
+        .or_else(|| from_env(|var| env::var(var).ok()))
+        .unwrap_or(Language::English);
+    LANGUAGE.store(language as u8, Ordering::Relaxed);
+}
+
+/// Returns the language of the first of the locale variables that is set, like POSIX does, or
+/// `None` if its locale is one without a language, like C, or one with no messages.
+fn from_env(var: impl Fn(&str) -> Option<String>) -> Option<Language> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|name| var(name))
+        .find(|locale| !locale.is_empty())
+        .and_then(|locale| Language::from_code(&locale))
+}
+
+fn language() -> Language {
+    match LANGUAGE.load(Ordering::Relaxed) {
+        1 => Language::Bokmal,
+        _ => Language::English,
+    }
+}
+
+/// Returns the message in the selected language, falling back to English, with `{ $name }`
+/// replaced by the argument of that name.
+pub fn message(id: &str, args: &[(&str, &dyn Display)]) -> String {
+    let template = language()
+        .messages()
+        .get(id)
+        .or_else(|| Language::English.messages().get(id))
+        .map(String::as_str)
+        .unwrap_or(id);
+    format(template, args)
+}
+
+/// Parses the simple messages of the Fluent syntax, like `pair-waiting = Waiting up to
+/// { $duration } ...`, one per line. Comments start with `#`.
+fn parse(source: &str) -> HashMap<String, String> {
+    source
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .filter_map(|line| line.split_once(" = "))
+        .map(|(id, value)| (id.trim().to_owned(), value.trim().to_owned()))
+        .collect()
+}
+
+fn format(template: &str, args: &[(&str, &dyn Display)]) -> String {
+    let mut message = template.to_owned();
+    for (name, value) in args {
+        message = message.replace(&format!("{{ ${} }}", name), &value.to_string());
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format, from_env, parse, Language};
+
+    #[test]
+    fn languages_are_found_from_locales() {
+        assert_eq!(Language::from_code("nb_NO.UTF-8"), Some(Language::Bokmal));
+        assert_eq!(Language::from_code("no"), Some(Language::Bokmal));
+        assert_eq!(Language::from_code("en-GB"), Some(Language::English));
+        assert_eq!(Language::from_code("C"), None);
+    }
+
+    #[test]
+    fn the_first_locale_variable_set_wins() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        assert_eq!(
+            from_env(env(&[("LC_ALL", "C"), ("LANG", "nb_NO.UTF-8")])),
+            None
+        );
+        assert_eq!(
+            from_env(env(&[("LC_ALL", ""), ("LANG", "nb_NO.UTF-8")])),
+            Some(Language::Bokmal)
+        );
+        assert_eq!(
+            from_env(env(&[("LC_MESSAGES", "en_US"), ("LANG", "nb_NO.UTF-8")])),
+            Some(Language::English)
+        );
+        assert_eq!(from_env(env(&[])), None);
+    }
+
+    #[test]
+    fn every_language_has_every_message() {
+        let english = Language::English.messages();
+        let bokmal = Language::Bokmal.messages();
+        let mut missing: Vec<_> = english
+            .keys()
+            .filter(|id| !bokmal.contains_key(*id))
+            .collect();
+        missing.sort();
+        assert!(missing.is_empty(), "Missing in nb.ftl: {:?}", missing);
+    }
+
+    #[test]
+    fn arguments_are_filled_in() {
+        let messages = parse("# Comment\nwaiting = Waiting up to { $duration } ...\n");
+        let duration = "1m";
+        assert_eq!(
+            format(&messages["waiting"], &[("duration", &duration)]),
+            "Waiting up to 1m ..."
+        );
+    }
+}
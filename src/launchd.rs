@@ -0,0 +1,157 @@
+use directories::{BaseDirs, ProjectDirs};
+use eyre::{eyre, Result};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Prefix of the labels of the agents blilys writes, which also name their plist files.
+const LABEL_PREFIX: &str = "com.github.jodal.blilys.";
+
+/// Directory of the user's launch agents.
+fn agents_dir() -> Result<PathBuf> {
+    let base_dirs = BaseDirs::new().ok_or_else(|| eyre!("Home directory not found"))?;
+    Ok(base_dirs.home_dir().join("Library/LaunchAgents"))
+}
+
+/// Directory for the agents' output, under Application Support on macOS.
+fn logs_dir() -> PathBuf {
+    let project_dirs = ProjectDirs::from("", "", "blilys").expect("Data dir not readable");
+    project_dirs.data_dir().join("logs")
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Returns a plist for an agent running blilys with the arguments at login, restarting it
+/// whenever it exits.
+fn plist(label: &str, args: &[String], logs: &Path) -> Result<String> {
+    let exe = env::current_exe()?.to_string_lossy().into_owned();
+    let arguments: String = std::iter::once(exe)
+        .chain(args.iter().cloned())
+        .map(|arg| format!("        <string>{}</string>\n", escape(&arg)))
+        .collect();
+    let name = label.trim_start_matches(LABEL_PREFIX);
+    let log = |kind: &str| {
+        escape(
+            &logs
+                .join(format!("{}.{}.log", name, kind))
+                .to_string_lossy(),
+        )
+    };
+    Ok(format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{}</string>
+    <key>ProgramArguments</key>
+    <array>
+{}    </array>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <true/>
+    <key>ThrottleInterval</key>
+    <integer>30</integer>
+    <key>StandardOutPath</key>
+    <string>{}</string>
+    <key>StandardErrorPath</key>
+    <string>{}</string>
+</dict>
+</plist>
+"#,
+        label,
+        arguments,
+        log("out"),
+        log("err")
+    ))
+}
+
+/// Writes launch agents running the daemon, or exporting to `export` on changes, and loads them
+/// unless `load` is false.
+pub fn install(daemon: bool, export: Option<&Path>, load: bool) -> Result<()> {
+    let dir = agents_dir()?;
+    let logs = logs_dir();
+    fs::create_dir_all(&dir)?;
+    fs::create_dir_all(&logs)?;
+
+    let mut agents = vec![];
+    if daemon {
+        agents.push(("daemon", vec!["daemon".to_owned()]));
+    }
+    if let Some(path) = export {
+        // The agent doesn't run in the current directory.
+        let path = env::current_dir()?.join(path);
+        agents.push((
+            "export",
+            vec![
+                "export".to_owned(),
+                "--watch".to_owned(),
+                "--output".to_owned(),
+                path.to_string_lossy().into_owned(),
+            ],
+        ));
+    }
+
+    for (name, args) in agents {
+        let label = format!("{}{}", LABEL_PREFIX, name);
+        let path = dir.join(format!("{}.plist", label));
+        if load && path.is_file() {
+            // Reloading picks up the changed arguments.
+            launchctl(&["unload", &path.to_string_lossy()]);
+        }
+        fs::write(&path, plist(&label, &args, &logs)?)?;
+        info!("Wrote {}, logging to {}.", path.display(), logs.display());
+        if load {
+            launchctl(&["load", "-w", &path.to_string_lossy()]);
+        } else {
+            info!("Load with: launchctl load -w {}", path.display());
+        }
+    }
+    Ok(())
+}
+
+/// Removes the launch agents written by `install`, unloading them first unless `unload` is
+/// false.
+pub fn uninstall(unload: bool) -> Result<()> {
+    let dir = agents_dir()?;
+    let mut files: Vec<PathBuf> = match fs::read_dir(&dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| {
+                path.file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with(LABEL_PREFIX))
+            })
+            .collect(),
+        Err(_) => vec![],
+    };
+    if files.is_empty() {
+        info!("No launch agents to remove.");
+        return Ok(());
+    }
+    files.sort();
+    for path in &files {
+        if unload {
+            launchctl(&["unload", "-w", &path.to_string_lossy()]);
+        }
+        fs::remove_file(path)?;
+        info!("Removed {}.", path.display());
+    }
+    Ok(())
+}
+
+/// Runs `launchctl`, only warning on failure, as the plist files are written either way.
+fn launchctl(args: &[&str]) {
+    let result = Command::new("launchctl").args(args).status();
+    if !result.is_ok_and(|status| status.success()) {
+        eprintln!(
+            "Failed to run `launchctl {}`, run it yourself to finish.",
+            args.join(" ")
+        );
+    }
+}
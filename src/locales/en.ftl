@@ -0,0 +1,21 @@
+# Messages in English, the fallback for messages missing in other languages.
+
+pair-discovered = Discovered { $bridge } at { $host }.
+pair-instructions-hue = To pair, press the button on your bridge now.
+pair-instructions-deconz = To pair, open the Phoscon app and click "Authenticate app" under Gateway > Advanced now.
+pair-instructions-diyhue = To pair, click "Link Button" in the diyHue web interface now.
+pair-press-key = Then, press any key to continue pairing ...
+pair-registering = Registering user ...
+pair-waiting = Waiting up to { $duration } ...
+pair-complete = Pairing complete.
+pair-saving = Saving configuration ...
+not-paired = Not paired with a bridge. Run `blilys pair`, or pass --auto-pair to pair now.
+
+list-on = on
+list-off = off
+list-all-on = all on
+list-some-on = some on
+list-no-room = No room
+
+no-target = No target given. Name one, or set default_target in the config, like "group:Living room".
+daemon-running = Running. Press Ctrl-C to stop.
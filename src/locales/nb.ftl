@@ -0,0 +1,21 @@
+# Meldinger på norsk bokmål.
+
+pair-discovered = Fant { $bridge } på { $host }.
+pair-instructions-hue = For å koble til, trykk på knappen på broen nå.
+pair-instructions-deconz = For å koble til, åpne Phoscon-appen og klikk «Authenticate app» under Gateway > Advanced nå.
+pair-instructions-diyhue = For å koble til, klikk «Link Button» i nettgrensesnittet til diyHue nå.
+pair-press-key = Trykk deretter på en tast for å fortsette ...
+pair-registering = Registrerer bruker ...
+pair-waiting = Venter i opptil { $duration } ...
+pair-complete = Tilkoblingen er klar.
+pair-saving = Lagrer innstillingene ...
+not-paired = Ikke koblet til en bro. Kjør `blilys pair`, eller bruk --auto-pair for å koble til nå.
+
+list-on = på
+list-off = av
+list-all-on = alle på
+list-some-on = noen på
+list-no-room = Uten rom
+
+no-target = Ingen mål er oppgitt. Oppgi et, eller sett default_target i innstillingene, som "group:Stue".
+daemon-running = Kjører. Trykk Ctrl-C for å stoppe.
@@ -0,0 +1,241 @@
+use crate::time::{format_utc, now};
+use clap::ValueEnum;
+use eyre::Result;
+use serde_json::{json, Map, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum LogFormat {
+    /// One line of text per event, like `DEBUG connect:request{method=GET}: response status=200`.
+    Text,
+    /// One JSON object per line, for log collectors.
+    Json,
+}
+
+/// Writes log events from the whole program to a file, each with the spans it happened in.
+pub fn init(path: &Path, format: LogFormat) -> Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let logger = FileLogger {
+        format,
+        file: Mutex::new(file),
+        spans: Mutex::new(HashMap::new()),
+        next_id: AtomicU64::new(1),
+    };
+    tracing::subscriber::set_global_default(logger)?;
+    Ok(())
+}
+
+struct FileLogger {
+    format: LogFormat,
+    file: Mutex<File>,
+    spans: Mutex<HashMap<u64, SpanData>>,
+    next_id: AtomicU64,
+}
+
+struct SpanData {
+    name: &'static str,
+    fields: Map<String, Value>,
+    /// How many handles to the span are alive. It is closed when the last one is dropped.
+    refs: usize,
+}
+
+thread_local! {
+    /// The spans entered on this thread, innermost last.
+    static ENTERED: RefCell<Vec<u64>> = const { RefCell::new(vec![]) };
+}
+
+/// Collects the fields of a span or event as JSON values.
+struct Fields<'a>(&'a mut Map<String, Value>);
+
+impl Visit for Fields<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_owned(), json!(format!("{:?}", value)));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_owned(), json!(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_owned(), json!(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_owned(), json!(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_owned(), json!(value));
+    }
+}
+
+impl FileLogger {
+    fn write(&self, metadata: &Metadata, mut fields: Map<String, Value>) {
+        let spans: Vec<(&str, Map<String, Value>)> = {
+            let spans = self.spans.lock().expect("Log spans lock poisoned");
+            ENTERED.with(|entered| {
+                entered
+                    .borrow()
+                    .iter()
+                    .filter_map(|id| spans.get(id))
+                    .map(|span| (span.name, span.fields.clone()))
+                    .collect()
+            })
+        };
+        let message = fields.remove("message");
+        let line = match self.format {
+            LogFormat::Text => {
+                let spans: Vec<String> = spans
+                    .iter()
+                    .map(|(name, fields)| {
+                        if fields.is_empty() {
+                            name.to_string()
+                        } else {
+                            format!("{}{{{}}}", name, format_fields(fields))
+                        }
+                    })
+                    .collect();
+                let message = message.as_ref().map(display).unwrap_or_default();
+                let mut line = format!("{} {:5} ", format_utc(now()), metadata.level().as_str());
+                if !spans.is_empty() {
+                    line += &format!("{}: ", spans.join(":"));
+                }
+                line += &format!("{}: {}", metadata.target(), message);
+                if !fields.is_empty() {
+                    line += &format!(" {}", format_fields(&fields));
+                }
+                line
+            }
+            LogFormat::Json => {
+                let spans: Vec<Value> = spans
+                    .into_iter()
+                    .map(|(name, mut fields)| {
+                        fields.insert("name".to_owned(), json!(name));
+                        Value::Object(fields)
+                    })
+                    .collect();
+                json!({
+                    "time": format_utc(now()),
+                    "level": metadata.level().as_str(),
+                    "target": metadata.target(),
+                    "message": message,
+                    "fields": fields,
+                    "spans": spans,
+                })
+                .to_string()
+            }
+        };
+        // Logging must never fail the command, so write errors are ignored.
+        let mut file = self.file.lock().expect("Log file lock poisoned");
+        let _ = writeln!(file, "{}", line);
+    }
+}
+
+/// Formats a value for text logs, with strings unquoted.
+fn display(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.to_owned(),
+        value => value.to_string(),
+    }
+}
+
+fn format_fields(fields: &Map<String, Value>) -> String {
+    fields
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, display(value)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl Subscriber for FileLogger {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn new_span(&self, attributes: &Attributes) -> Id {
+        let mut fields = Map::new();
+        attributes.record(&mut Fields(&mut fields));
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let span = SpanData {
+            name: attributes.metadata().name(),
+            fields,
+            refs: 1,
+        };
+        self.spans
+            .lock()
+            .expect("Log spans lock poisoned")
+            .insert(id, span);
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record) {
+        if let Some(span) = self
+            .spans
+            .lock()
+            .expect("Log spans lock poisoned")
+            .get_mut(&span.into_u64())
+        {
+            values.record(&mut Fields(&mut span.fields));
+        }
+    }
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event) {
+        let mut fields = Map::new();
+        event.record(&mut Fields(&mut fields));
+        self.write(event.metadata(), fields);
+    }
+
+    fn enter(&self, span: &Id) {
+        ENTERED.with(|entered| entered.borrow_mut().push(span.into_u64()));
+    }
+
+    fn exit(&self, span: &Id) {
+        ENTERED.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            if let Some(i) = entered.iter().rposition(|&id| id == span.into_u64()) {
+                entered.remove(i);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(data) = self
+            .spans
+            .lock()
+            .expect("Log spans lock poisoned")
+            .get_mut(&span.into_u64())
+        {
+            data.refs += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let mut spans = self.spans.lock().expect("Log spans lock poisoned");
+        let closed = match spans.get_mut(&span.into_u64()) {
+            Some(data) if data.refs > 1 => {
+                data.refs -= 1;
+                false
+            }
+            Some(_) => true,
+            None => false,
+        };
+        if closed {
+            spans.remove(&span.into_u64());
+        }
+        closed
+    }
+}
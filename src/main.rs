@@ -1,97 +1,683 @@
+use crate::backend::{Datastore, Fetched, LightBackend, Members};
+use crate::cache::Cache;
+use crate::cap::Capped;
+use crate::coalesce::Coalescer;
 use crate::config::Config;
-use crate::options::{Command, LightMode, LightOperation, Opt};
-use eyre::Result;
-use hueclient::CommandLight;
-use rand::distributions::{Distribution, Uniform};
-use std::io;
+use crate::desired::DesiredState;
+use crate::history::History;
+use crate::logging::LogFormat;
+use crate::options::{
+    AccessoryOperation, AutomationOperation, CacheOperation, Command, ConfigOperation,
+    ConnectionOpt, HistoryOperation, LaunchdOperation, LightOperation, LogOperation, NestedCommand,
+    Opt, Power, RemoteOperation, SceneOperation, SensorOperation, SnapshotOperation,
+    SystemdOperation, TagOperation, WeatherProvider,
+};
+use crate::profile::Tuned;
+use crate::sensors::SensorConfig;
+use crate::snapshot::Snapshot;
+use crate::target::Target;
+use crate::warm::Warm;
+use clap::error::{ContextKind, ContextValue, ErrorKind};
+use clap::Parser;
+use eyre::{eyre, Result};
+use std::env;
+use std::ffi::OsString;
+use std::iter;
 use std::time::Duration;
-use structopt::StructOpt;
 
+#[macro_use]
+mod i18n;
+#[macro_use]
+mod output;
+
+mod accessories;
+mod adaptive;
+mod api;
+mod audit;
+mod automations;
+mod backend;
+mod bench;
+mod bridge;
+mod cache;
+mod calendar;
+mod cap;
+mod ci;
+mod coalesce;
+mod color;
+mod commands;
 mod config;
+#[cfg(unix)]
+mod control;
+mod crossfade;
+mod daemon;
+mod desired;
+mod dial;
+mod discovery;
+mod doctor;
+mod emergency;
+mod energy;
+mod eventstream;
+mod export;
+mod gesture;
+mod history;
+mod holidays;
+mod http;
+mod launchd;
+mod logging;
+mod man;
+mod metrics;
 mod options;
+mod outcome;
+mod presence;
+mod profile;
+mod progress;
+mod queue;
+mod reload;
+mod remote;
+mod scene;
+mod schedule;
+mod sensors;
+mod shutdown;
+mod sinks;
+mod snapshot;
+mod supervisor;
+mod systemd;
+mod table;
+mod tags;
+mod target;
+mod targets;
+mod template;
+mod time;
+mod trace;
+mod update;
+mod values;
+mod warm;
+mod weather;
+mod zone;
 
 fn main() -> Result<()> {
-    let mut config = Config::from_file()?;
-    let opt = Opt::from_args();
-
-    let unauth_bridge = match opt.bridge {
-        Some(ip) => hueclient::Bridge::for_ip(ip),
-        None => match config.bridge.ip {
-            Some(ip) => hueclient::Bridge::for_ip(ip),
-            None => hueclient::Bridge::discover_required(),
-        },
-    };
+    let (opt, args) = parse_args()?;
+    output::init(opt.quiet, opt.no_color, opt.wide);
+    i18n::init(None);
+    if let Some(path) = &opt.log_file {
+        let format = match (opt.log_format, &opt.cmd) {
+            (Some(format), _) => format,
+            (None, Command::Daemon) => LogFormat::Json,
+            (None, _) => LogFormat::Text,
+        };
+        logging::init(path, format)?;
+    }
+    #[cfg(unix)]
+    if forwards_to_daemon(&opt) {
+        let line = shell_words::join(args.iter().skip(1).map(|arg| arg.to_string_lossy()));
+        if let Some(result) = control::forward(&line) {
+            return result;
+        }
+    }
+    run(opt)
+}
+
+/// Returns whether the command goes through the daemon instead of connecting to the bridge, as
+/// set up in the config. Commands with connection options always connect themselves.
+#[cfg(unix)]
+fn forwards_to_daemon(opt: &Opt) -> bool {
+    !opt.no_daemon
+        && !opt.no_config
+        && is_default_connection(&opt.connection)
+        && is_light_command(&opt.cmd)
+        && Config::from_file().is_ok_and(|config| config.daemon.forward)
+}
+
+fn is_default_connection(connection: &ConnectionOpt) -> bool {
+    connection.bridge.is_none()
+        && connection.username.is_none()
+        && connection.discovery.is_empty()
+        && !connection.remote
+        && !connection.auto_pair
+        && connection.record.is_none()
+        && connection.replay.is_none()
+}
+
+fn run(opt: Opt) -> Result<()> {
+    // Config commands must work even if the config is invalid or there is no bridge.
+    if let Command::Config { op } = opt.cmd {
+        return match op.unwrap_or(ConfigOperation::Show) {
+            ConfigOperation::Show => Config::from_file()?.print(),
+            ConfigOperation::Path => {
+                println!("{}", Config::get_path()?.display());
+                Ok(())
+            }
+            ConfigOperation::Edit => Config::edit(&Config::get_path()?),
+            ConfigOperation::Validate => {
+                let path = Config::get_path()?;
+                let version = Config::validate(&path)?;
+                info!("{} is valid.", path.display());
+                if version < config::VERSION {
+                    info!(
+                        "It is in config version {}, and is upgraded to {} by `blilys config edit` \
+                         or the next time blilys saves it.",
+                        version,
+                        config::VERSION
+                    );
+                }
+                Ok(())
+            }
+        };
+    }
 
-    let bridge = if let Command::Pair = opt.cmd {
-        pair(unauth_bridge, &mut config)?
+    // Doctor checks the config itself, so it must not fail to load it first.
+    if let Command::Doctor = opt.cmd {
+        return doctor::run(&opt.connection);
+    }
+    if let Command::Man { out_dir } = &opt.cmd {
+        return man::generate(out_dir.as_deref());
+    }
+    if let Command::Systemd { op } = opt.cmd {
+        return match op {
+            SystemdOperation::Install {
+                daemon,
+                timer,
+                no_enable,
+            } => systemd::install(daemon, &timer, !no_enable),
+            SystemdOperation::Uninstall { no_disable } => systemd::uninstall(!no_disable),
+        };
+    }
+    if let Command::Launchd { op } = opt.cmd {
+        return match op {
+            LaunchdOperation::Install {
+                daemon,
+                export,
+                no_load,
+            } => launchd::install(daemon, export.as_deref(), !no_load),
+            LaunchdOperation::Uninstall { no_unload } => launchd::uninstall(!no_unload),
+        };
+    }
+    if let Command::Ctl { command } = opt.cmd {
+        #[cfg(unix)]
+        return control::send(&shell_words::join(command));
+        #[cfg(not(unix))]
+        {
+            let _ = command;
+            return Err(eyre!(
+                "Sending commands to the daemon is only supported on Unix, as it listens on a \
+                 Unix domain socket"
+            ));
+        }
+    }
+    if let Command::SelfUpdate { check } = opt.cmd {
+        return update::self_update(check);
+    }
+
+    let stateless = opt.connection.username.is_some();
+    if stateless {
+        cache::disable();
+        audit::disable();
+    }
+    let mut config = if opt.no_config || stateless {
+        Config::default()
     } else {
-        match config.bridge.username {
-            Some(ref username) => unauth_bridge.with_user(username),
-            None => pair(unauth_bridge, &mut config)?,
+        Config::from_file()?
+    };
+    i18n::init(config.language.as_deref());
+    let cache_ttl = config.cache.ttl;
+
+    let cmd = match light_command(opt.cmd) {
+        Ok((target, op)) => {
+            let backend: &dyn LightBackend = &bridge::connect(&opt.connection, &mut config)?;
+            let target = resolve_target(backend, &config, target)?;
+            return apply(
+                backend,
+                &config,
+                opt.override_cap,
+                opt.warm_dim,
+                opt.strict,
+                target,
+                op,
+            );
         }
+        Err(cmd) => cmd,
     };
 
-    match opt.cmd {
-        Command::Pair => {
-            // Pairing is handled above, when creating the authenticated Bridge.
-        }
-        Command::Config => {
-            config.print()?;
-        }
-        Command::Groups => {
-            for ig in bridge.get_all_groups()? {
-                let mut lights = ig.group.lights.to_owned();
-                lights.sort_by_key(|l| l.parse::<usize>().expect("Light ID to be a number"));
-                println!(
-                    "{id:2}: {name:30} [{lights}]",
-                    id = ig.id,
-                    name = ig.group.name,
-                    lights = lights.join(", ")
+    match cmd {
+        Command::Pair { wait, json } => {
+            let bridge = bridge::pair(&opt.connection, &mut config, wait)?;
+            if json {
+                let credentials = serde_json::json!({
+                    "host": bridge.host,
+                    "id": config.bridge.id,
+                    "username": bridge.username,
+                });
+                println!("{}", serde_json::to_string_pretty(&credentials)?);
+            } else if !output::is_quiet() {
+                config.print()?;
+            }
+        }
+        Command::Config { .. }
+        | Command::Doctor
+        | Command::Man { .. }
+        | Command::Systemd { .. }
+        | Command::Launchd { .. }
+        | Command::Ctl { .. }
+        | Command::SelfUpdate { .. } => {
+            // These commands are handled above, before loading the config.
+        }
+        Command::Group { .. }
+        | Command::Light { .. }
+        | Command::On { .. }
+        | Command::Off { .. }
+        | Command::Toggle { .. }
+        | Command::Dim { .. }
+        | Command::All { .. } => {
+            // Commands controlling lights are handled above, once connected.
+        }
+        Command::Groups { format, json, full } => {
+            let bridge = bridge::connect(&opt.connection, &mut config)?;
+            if json {
+                let raw = if full {
+                    Some(bridge.get("groups")?)
+                } else {
+                    None
+                };
+                commands::print_groups_json(&bridge, raw.as_ref())?;
+            } else {
+                commands::list_groups(&bridge, format.as_ref())?;
+            }
+        }
+        Command::Lights {
+            format,
+            group_by,
+            json,
+            full,
+        } => {
+            let bridge = bridge::connect(&opt.connection, &mut config)?;
+            if json {
+                let raw = if full {
+                    Some(bridge.get("lights")?)
+                } else {
+                    None
+                };
+                commands::print_lights_json(&bridge, raw.as_ref())?;
+            } else {
+                commands::list_lights(&bridge, format.as_ref(), group_by)?;
+            }
+        }
+        Command::Which { group } => {
+            let backend: &dyn LightBackend = &bridge::connect(&opt.connection, &mut config)?;
+            let group = match group {
+                Some(group) => Some(cache::resolve_group(backend, cache_ttl, &group)?),
+                None => None,
+            };
+            match commands::which(backend, group)? {
+                Some(il) => {
+                    println!("{}: {}", il.id, il.light.name);
+                    if let Some(alias) =
+                        commands::prompt("Name an alias for it, or press Enter to skip: ")?
+                    {
+                        config
+                            .aliases
+                            .insert(alias.clone(), format!("light:{}", il.id));
+                        if config.path.is_some() {
+                            config.save()?;
+                        }
+                        info!("Added alias {:?} for light {}.", alias, il.id);
+                    }
+                }
+                None => return Err(eyre!("No more lights to try")),
+            }
+        }
+        Command::Tag { op } => match op {
+            TagOperation::Add { target, tags } => {
+                let backend: &dyn LightBackend = &bridge::connect(&opt.connection, &mut config)?;
+                let lights = targets::resolve(&target, backend, &config)?.lights(backend)?;
+                tags::add(&mut config, &lights, &tags);
+                if config.path.is_some() {
+                    config.save()?;
+                }
+                info!("Tagged {} lights with {}.", lights.len(), tags.join(", "));
+            }
+            TagOperation::Remove { target, tags } => {
+                let backend: &dyn LightBackend = &bridge::connect(&opt.connection, &mut config)?;
+                let lights = targets::resolve(&target, backend, &config)?.lights(backend)?;
+                tags::remove(&mut config, &lights, &tags)?;
+                if config.path.is_some() {
+                    config.save()?;
+                }
+                info!("Untagged {} lights.", lights.len());
+            }
+            TagOperation::List => {
+                for (tag, lights) in &config.tags {
+                    let lights: Vec<String> = lights.iter().map(usize::to_string).collect();
+                    println!("{}: [{}]", tag, lights.join(", "));
+                }
+            }
+        },
+        Command::Wait {
+            target,
+            until,
+            timeout,
+            interval,
+        } => {
+            let backend: &dyn LightBackend = &bridge::connect(&opt.connection, &mut config)?;
+            let target = targets::resolve(&target, backend, &config)?;
+            commands::wait(backend, target, until, interval, timeout)?;
+        }
+        Command::If {
+            target,
+            is,
+            command,
+        } => {
+            let backend: &dyn LightBackend = &bridge::connect(&opt.connection, &mut config)?;
+            let target = targets::resolve(&target, backend, &config)?;
+            if target.is_on(backend)? == (is == Power::On) {
+                let nested =
+                    NestedCommand::parse_from(iter::once("blilys".to_owned()).chain(command));
+                return run(Opt {
+                    cmd: nested.cmd,
+                    ..opt
+                });
+            }
+        }
+        Command::Accessories => {
+            let bridge = bridge::connect(&opt.connection, &mut config)?;
+            accessories::list(&bridge)?;
+        }
+        Command::Accessory { switch, op } => match op {
+            AccessoryOperation::Bind {
+                button,
+                hold,
+                action,
+            } => {
+                let bridge = bridge::connect(&opt.connection, &mut config)?;
+                let rule = accessories::bind(&bridge, &config, &switch, button, hold, &action)?;
+                info!(
+                    "Created rule {} for button {} of {:?}.",
+                    rule, button, switch
                 );
             }
+        },
+        Command::Sensor { sensor, op } => match op {
+            SensorOperation::Config {
+                sensitivity,
+                on,
+                off,
+                daylight_threshold,
+            } => {
+                let bridge = bridge::connect(&opt.connection, &mut config)?;
+                let changes = SensorConfig {
+                    enabled: if on || off { Some(on) } else { None },
+                    sensitivity,
+                    dark_below_lux: daylight_threshold,
+                };
+                sensors::configure(&bridge, &sensor, &changes)?;
+            }
+        },
+        Command::Automation { op } => {
+            let bridge = bridge::connect(&opt.connection, &mut config)?;
+            let (template, description, links) = match op {
+                AutomationOperation::Nightlight {
+                    sensor,
+                    light,
+                    bri,
+                    between,
+                    off_after,
+                } => (
+                    "nightlight",
+                    format!("{} on motion at {} at night", light, sensor),
+                    automations::motion_light(
+                        &bridge,
+                        &config,
+                        &sensor,
+                        &light,
+                        bri,
+                        Some(between),
+                        off_after,
+                    )?,
+                ),
+                AutomationOperation::MotionLight {
+                    sensor,
+                    light,
+                    bri,
+                    between,
+                    off_after,
+                } => (
+                    "motion light",
+                    format!("{} on motion at {}", light, sensor),
+                    automations::motion_light(
+                        &bridge, &config, &sensor, &light, bri, between, off_after,
+                    )?,
+                ),
+                AutomationOperation::OffWhenNoMotion {
+                    sensor,
+                    light,
+                    after,
+                } => (
+                    "off when no motion",
+                    format!("{} off without motion at {}", light, sensor),
+                    automations::off_when_no_motion(&bridge, &config, &sensor, &light, after)?,
+                ),
+                AutomationOperation::ArrivalScene {
+                    sensor,
+                    scene,
+                    between,
+                } => (
+                    "arrival scene",
+                    format!("{} on arrival at {}", scene, sensor),
+                    automations::arrival_scene(&bridge, &config, &sensor, &scene, between)?,
+                ),
+                AutomationOperation::List => return automations::list(&bridge),
+                AutomationOperation::Remove { id } => {
+                    automations::remove(&bridge, &id)?;
+                    info!("Removed automation {}.", id);
+                    return Ok(());
+                }
+            };
+            let id = automations::track(&bridge, template, &description, &links)?;
+            info!("Created automation {} with {}.", id, links.join(", "));
         }
-        Command::Group { group, op } => match op {
-            LightOperation::Mode { mode } => match mode {
-                LightMode::Halloween => loop {
-                    let command = CommandLight::default().with_bri(rand_bri(1, 50));
-                    bridge.set_group_state(group, &command)?;
-                    sleep_a_bit();
-
-                    let command = CommandLight::default().with_bri(rand_bri(70, 120));
-                    bridge.set_group_state(group, &command)?;
-                    sleep_a_bit();
-                },
-            },
-            light_operation => {
-                bridge.set_group_state(group, &light_operation.to_hue_command())?;
+        Command::Scene { scene, op } => {
+            let bridge = bridge::connect(&opt.connection, &mut config)?;
+            let named =
+                |scene: Option<String>| scene.ok_or_else(|| eyre!("Missing the scene ID or name"));
+            let resolve = |scene: &str| cache::resolve_scene(&bridge, cache_ttl, scene);
+            match op {
+                SceneOperation::Random { group, pattern } => {
+                    if let Some(scene) = scene {
+                        return Err(eyre!(
+                            "A random scene is picked, so {:?} can't be given",
+                            scene
+                        ));
+                    }
+                    let group = group
+                        .map(|group| cache::resolve_group(&bridge, cache_ttl, &group))
+                        .transpose()?;
+                    let name = scene::random(&bridge, group, pattern.as_deref())?;
+                    info!("Recalled {:?}.", name);
+                }
+                SceneOperation::Import { file, remap } => {
+                    let scene = named(scene)?;
+                    let id = scene::import(&bridge, &scene, &file, remap)?;
+                    info!("Imported {:?} as scene {}.", scene, id);
+                }
+                SceneOperation::Recall => scene::recall(&bridge, &resolve(&named(scene)?)?)?,
+                SceneOperation::Show => scene::show(&bridge, &resolve(&named(scene)?)?)?,
+                SceneOperation::Export => scene::export(&bridge, &resolve(&named(scene)?)?)?,
+                SceneOperation::Schedule { at, days } => {
+                    let scene = named(scene)?;
+                    let id = resolve(&scene)?;
+                    let schedule = scene::schedule(&bridge, &id, &scene, at, days)?;
+                    info!(
+                        "Created schedule {} recalling {:?} at {}.",
+                        schedule, scene, at
+                    );
+                }
+            }
+        }
+        Command::Bench { count, light } => {
+            let bridge = bridge::connect(&opt.connection, &mut config)?;
+            bench::run(&bridge, count, light)?;
+        }
+        Command::Cache { op } => match op {
+            CacheOperation::Refresh => {
+                let bridge = bridge::connect(&opt.connection, &mut config)?;
+                let cache = Cache::refresh(&bridge)?;
+                info!(
+                    "Cached {} lights, {} groups, and {} scenes.",
+                    cache.lights.len(),
+                    cache.groups.len(),
+                    cache.scenes.len()
+                );
+            }
+            CacheOperation::Clear => {
+                Cache::clear()?;
+            }
+        },
+        Command::Snapshot { op } => match op {
+            SnapshotOperation::Save { name } => {
+                let bridge = bridge::connect(&opt.connection, &mut config)?;
+                let snapshot = Snapshot::take(&bridge)?;
+                snapshot.save(&name)?;
+                info!("Saved {} lights as {:?}.", snapshot.lights.len(), name);
+            }
+            SnapshotOperation::List => {
+                for name in Snapshot::list()? {
+                    println!("{}", name);
+                }
+            }
+            SnapshotOperation::Delete { name } => {
+                Snapshot::delete(&name)?;
             }
         },
-        Command::Lights => {
-            for il in bridge.get_all_lights()? {
-                println!(
-                    "{id:2}: {name:30} [{on:3}] [bri {bri:>3}] [hue {hue:>5}]",
-                    id = il.id,
-                    name = il.light.name,
-                    on = if il.light.state.on { "on" } else { "off" },
-                    bri = il.light.state.bri.unwrap_or(0).to_string(),
-                    hue = il.light.state.hue.unwrap_or(0).to_string()
+        Command::Diff { from, to } => {
+            let from = Snapshot::load(&from)?;
+            let to = match to {
+                Some(to) => Snapshot::load(&to)?,
+                None => Snapshot::take(&bridge::connect(&opt.connection, &mut config)?)?,
+            };
+            snapshot::print_diff(&from, &to);
+        }
+        Command::Panic => {
+            let bridge = bridge::connect(&opt.connection, &mut config)?;
+            emergency::panic(&bridge)?;
+        }
+        Command::Allclear => {
+            let bridge = bridge::connect(&opt.connection, &mut config)?;
+            emergency::all_clear(&bridge)?;
+        }
+        Command::Preset {
+            name,
+            crossfade_to,
+            duration,
+        } => {
+            let from = Snapshot::load(&name)?;
+            let to = crossfade_to.as_deref().map(Snapshot::load).transpose()?;
+            let bridge = bridge::connect(&opt.connection, &mut config)?;
+            match to {
+                Some(to) => crossfade::crossfade(&bridge, &from, &to, duration)?,
+                None => crossfade::restore(&bridge, &from)?,
+            }
+        }
+        Command::Export {
+            pretty,
+            format,
+            output,
+            watch,
+            interval,
+        } => {
+            let bridge = bridge::connect(&opt.connection, &mut config)?;
+            match output {
+                Some(path) if watch => export::watch(
+                    &bridge,
+                    &path,
+                    format,
+                    pretty,
+                    interval,
+                    config.metrics.as_deref(),
+                )?,
+                path => export::run(&bridge, path.as_deref(), format, pretty)?,
+            }
+        }
+        Command::Apply {
+            file,
+            prune,
+            dry_run,
+            yes,
+        } => {
+            let desired = DesiredState::read(&file)?;
+            let bridge = bridge::connect(&opt.connection, &mut config)?;
+            let changes = desired::plan(&bridge, &config, &desired, prune)?;
+            if changes.is_empty() {
+                info!("Nothing to change.");
+                return Ok(());
+            }
+            for change in &changes {
+                println!("{}", change);
+            }
+            if dry_run {
+                return Ok(());
+            }
+            let confirmed = yes
+                || commands::prompt("Make these changes? [y/N] ")?
+                    .is_some_and(|answer| answer.eq_ignore_ascii_case("y"));
+            if !confirmed {
+                return Err(eyre!("Cancelled"));
+            }
+            let count = desired::execute(&bridge, &mut config, changes, opt.strict)?;
+            info!("Made {} changes.", count);
+        }
+        Command::History { target, since, op } => match op {
+            Some(HistoryOperation::Record { interval }) => {
+                let bridge = bridge::connect(&opt.connection, &mut config)?;
+                let history = History::open()?;
+                info!(
+                    "Recording history every {:?}. Press Ctrl-C to stop.",
+                    interval
                 );
+                history.record(&bridge, interval)?;
             }
+            None => {
+                let target = target.ok_or_else(|| eyre!("Specify a light or sensor"))?;
+                History::open()?.print(&target, since)?;
+            }
+        },
+        Command::Energy { since } => {
+            let bridge = bridge::connect(&opt.connection, &mut config)?;
+            energy::run(&bridge, &config.energy, &History::open()?, since)?;
         }
-        Command::Light { light, op } => match op {
-            LightOperation::Mode { mode } => match mode {
-                LightMode::Halloween => loop {
-                    let command = CommandLight::default().with_bri(rand_bri(1, 50));
-                    bridge.set_light_state(light, &command)?;
-                    sleep_a_bit();
-
-                    let command = CommandLight::default().with_bri(rand_bri(70, 120));
-                    bridge.set_light_state(light, &command)?;
-                    sleep_a_bit();
-                },
-            },
-            light_operation => {
-                bridge.set_light_state(light, &light_operation.to_hue_command())?;
+        Command::Remote { op } => match op {
+            RemoteOperation::Login => {
+                remote::login(&config.remote, &bridge::device_type(&config.bridge))?;
+            }
+            RemoteOperation::Logout => {
+                remote::logout()?;
+            }
+        },
+        Command::Daemon => {
+            let bridge = bridge::connect(&opt.connection, &mut config)?;
+            daemon::run(&bridge, config)?;
+        }
+        Command::WeatherSync {
+            provider: WeatherProvider::MetNo,
+            location,
+            light,
+            interval,
+        } => {
+            let backend: &dyn LightBackend = &bridge::connect(&opt.connection, &mut config)?;
+            let target = targets::resolve(&light, backend, &config)?;
+            weather::sync(backend, target, location, interval)?;
+        }
+        Command::CiLight {
+            github,
+            branch,
+            light,
+            interval,
+        } => {
+            let backend: &dyn LightBackend = &bridge::connect(&opt.connection, &mut config)?;
+            let target = targets::resolve(&light, backend, &config)?;
+            ci::watch(backend, target, &github, branch.as_deref(), interval)?;
+        }
+        Command::Log { op } => match op {
+            LogOperation::Tail { lines, follow } => {
+                audit::tail(lines, follow)?;
             }
         },
     }
@@ -99,34 +685,308 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-fn pair(unauth_bridge: hueclient::UnauthBridge, config: &mut Config) -> Result<hueclient::Bridge> {
-    eprintln!("Discovered Philips Hue bridge at {}.", unauth_bridge.ip);
-    eprintln!("To pair, press the button on your bridge now.");
-    eprintln!("Then, press any key to continue pairing ...");
-    let mut input = String::new();
-    io::stdin().read_line(&mut input).unwrap();
+/// Parses the command line, expanding commands defined in the config's `[commands]` table and
+/// moving a target given before a verb after it.
+fn parse_args() -> Result<(Opt, Vec<OsString>)> {
+    let args: Vec<OsString> = env::args_os().collect();
+    let err = match Opt::try_parse_from(&args) {
+        Ok(opt) => return Ok((opt, args)),
+        Err(err) => err,
+    };
+    let name = match err.get(ContextKind::InvalidSubcommand) {
+        Some(ContextValue::String(name)) if err.kind() == ErrorKind::InvalidSubcommand => name,
+        _ => err.exit(),
+    };
+    let position = args
+        .iter()
+        .position(|arg| arg.to_str() == Some(name.as_str()))
+        .expect("Unknown subcommand to be one of the arguments");
+    // A target before a verb, like `blilys tag:reading on`, is the same as after it.
+    let verb_follows = args
+        .get(position + 1)
+        .and_then(|arg| arg.to_str())
+        .is_some_and(|arg| ["on", "off", "toggle", "dim"].contains(&arg));
+    if verb_follows && (name.contains(':') || name.starts_with('@')) {
+        let mut swapped = args.clone();
+        swapped.swap(position, position + 1);
+        return Ok((Opt::parse_from(&swapped), swapped));
+    }
+    let no_config = args
+        .iter()
+        .any(|arg| arg == "--no-config" || arg.to_string_lossy().starts_with("--username"));
+    if no_config {
+        err.exit();
+    }
+    let expansion = match Config::from_file()
+        .ok()
+        .and_then(|config| config.commands.get(name).cloned())
+    {
+        Some(expansion) => expansion,
+        None => err.exit(),
+    };
+    let words = shell_words::split(&expansion)
+        .map_err(|e| eyre!("Invalid command {:?} in config: {}", name, e))?;
+    let expanded: Vec<OsString> = args[..position]
+        .iter()
+        .cloned()
+        .chain(words.into_iter().map(OsString::from))
+        .chain(args[position + 1..].iter().cloned())
+        .collect();
+    Ok((Opt::parse_from(&expanded), expanded))
+}
 
-    eprintln!("Registering user ...");
-    let bridge = unauth_bridge.register_user("blilys")?;
-    eprintln!("Pairing complete.");
+/// Runs a command line controlling lights, like "toggle" or "--override-cap light desk on", on a
+/// connected backend, as sent to the daemon. Commands setting a target's state outright replace
+/// earlier ones to the same target that are still waiting for their turn.
+fn run_light_command(
+    backend: &dyn LightBackend,
+    config: &Config,
+    coalescer: &Coalescer,
+    line: &str,
+) -> Result<()> {
+    let (flags, target, op) = parse_light_command(line)?;
+    let target = resolve_target(backend, config, target)?;
+    // The bridge takes about 10 light commands and 1 group command per second.
+    let interval = match &target {
+        Target::Light(_) => Duration::from_millis(100),
+        Target::Lights(ids) => Duration::from_millis(100) * ids.len() as u32,
+        _ => Duration::from_secs(1),
+    };
+    let replaceable = matches!(
+        op,
+        LightOperation::On(_)
+            | LightOperation::Off(_)
+            | LightOperation::Dim(_)
+            | LightOperation::Recipe { .. }
+    );
+    coalescer.run(&target.to_string(), interval, replaceable, || {
+        apply(
+            backend,
+            config,
+            flags.override_cap,
+            flags.warm_dim,
+            flags.strict,
+            target,
+            op,
+        )
+    })
+}
 
-    eprintln!("Saving configuration ...");
-    config.bridge.ip = Some(bridge.ip);
-    config.bridge.username = Some(bridge.username.to_owned());
-    config.save()?;
-    config.print()?;
+/// Returns how long an effect command line from `daemon.effects` runs, or `None` if until
+/// stopped, failing if it isn't a valid command running a mode.
+fn effect_duration(line: &str) -> Result<Option<Duration>> {
+    match parse_light_command(line)? {
+        (_, _, LightOperation::Mode { duration, .. }) => Ok(duration),
+        _ => Err(eyre!("Not a command running a mode")),
+    }
+}
+
+/// Runs an effect command line from `daemon.effects`, for `remaining` instead of its own
+/// duration if given.
+fn run_effect_command(
+    backend: &dyn LightBackend,
+    config: &Config,
+    line: &str,
+    remaining: Option<Duration>,
+) -> Result<()> {
+    let (flags, target, mut op) = parse_light_command(line)?;
+    if let (LightOperation::Mode { duration, .. }, Some(remaining)) = (&mut op, remaining) {
+        *duration = Some(remaining);
+    }
+    let target = resolve_target(backend, config, target)?;
+    apply(
+        backend,
+        config,
+        flags.override_cap,
+        flags.warm_dim,
+        flags.strict,
+        target,
+        op,
+    )
+}
+
+/// The flags of a command line sent to the daemon that change how it is applied.
+struct LightFlags {
+    override_cap: bool,
+    warm_dim: bool,
+    strict: bool,
+}
+
+/// Parses a command line sent to the daemon, which must control lights.
+fn parse_light_command(line: &str) -> Result<(LightFlags, TargetArg, LightOperation)> {
+    let words = shell_words::split(line).map_err(|err| eyre!("Invalid command: {}", err))?;
+    let opt = Opt::try_parse_from(iter::once("blilys".to_owned()).chain(words))
+        .map_err(|err| eyre!("{}", err.render().to_string().trim()))?;
+    if !is_default_connection(&opt.connection) {
+        return Err(eyre!("Connection options can't be sent to the daemon"));
+    }
+    let flags = LightFlags {
+        override_cap: opt.override_cap,
+        warm_dim: opt.warm_dim,
+        strict: opt.strict,
+    };
+    match light_command(opt.cmd) {
+        Ok((target, op)) => Ok((flags, target, op)),
+        Err(_) => Err(eyre!(
+            "Only commands controlling lights can be sent to the daemon"
+        )),
+    }
+}
+
+/// The target of a command controlling lights, as given on the command line.
+enum TargetArg {
+    Light(String),
+    Group(String),
+    /// The target of a top-level verb like `blilys on`, or the default target if not given.
+    Verb(Option<String>),
+    All,
+}
 
-    Ok(bridge)
+fn is_light_command(cmd: &Command) -> bool {
+    matches!(
+        cmd,
+        Command::Group { .. }
+            | Command::Light { .. }
+            | Command::On { .. }
+            | Command::Off { .. }
+            | Command::Toggle { .. }
+            | Command::Dim { .. }
+            | Command::All { .. }
+    )
 }
 
-fn rand_bri(low: u8, high: u8) -> u8 {
-    let between = Uniform::from(low..high);
-    let mut rng = rand::thread_rng();
-    return between.sample(&mut rng);
+/// Splits a command controlling lights into its target and operation, giving back other
+/// commands.
+fn light_command(cmd: Command) -> std::result::Result<(TargetArg, LightOperation), Command> {
+    Ok(match cmd {
+        Command::Group { group, op } => (TargetArg::Group(group), op),
+        Command::Light { light, op } => (TargetArg::Light(light), op),
+        Command::On { args, target } => (TargetArg::Verb(target), LightOperation::On(args)),
+        Command::Off { args, target } => (TargetArg::Verb(target), LightOperation::Off(args)),
+        Command::Toggle { args, target } => (TargetArg::Verb(target), LightOperation::Toggle(args)),
+        Command::Dim { args, target } => (TargetArg::Verb(target), LightOperation::Dim(args)),
+        Command::All { op } => (TargetArg::All, op),
+        cmd => return Err(cmd),
+    })
 }
 
-fn sleep_a_bit() {
-    let between = Uniform::from(200..1000);
-    let mut rng = rand::thread_rng();
-    std::thread::sleep(Duration::from_millis(between.sample(&mut rng)));
+/// Resolves the target of a command controlling lights. Names of lights and groups are looked up
+/// among lights and groups only, while verbs take any target spec.
+fn resolve_target(
+    backend: &dyn LightBackend,
+    config: &Config,
+    target: TargetArg,
+) -> Result<Target> {
+    let ttl = config.cache.ttl;
+    Ok(match target {
+        TargetArg::Light(light) => match config.aliases.get(&light) {
+            Some(_) => targets::resolve(&light, backend, config)?,
+            None => Target::Light(cache::resolve_light(backend, ttl, &light)?),
+        },
+        TargetArg::Group(group) => match config.aliases.get(&group) {
+            Some(_) => targets::resolve(&group, backend, config)?,
+            None => Target::Group(cache::resolve_group(backend, ttl, &group)?),
+        },
+        TargetArg::Verb(spec) => {
+            let spec = spec
+                .or_else(|| config.default_target.clone())
+                .ok_or_else(|| eyre!("{}", t!("no-target")))?;
+            targets::resolve(&spec, backend, config)?
+        }
+        TargetArg::All => Target::All,
+    })
+}
+
+/// Applies the operation to the target, with the settings from the config: the brightness and
+/// color temperature of `on` from `[defaults]` for the target when not given, the colors of a
+/// mode's palette from `[palette]`, the brightness kept within `[caps]` unless `override_cap` is
+/// set, and lights getting warmer as `on`, `dim`, and `fade` dim them if `warm_dim` or the config
+/// says so.
+fn apply(
+    backend: &dyn LightBackend,
+    config: &Config,
+    override_cap: bool,
+    warm_dim: bool,
+    strict: bool,
+    target: Target,
+    op: LightOperation,
+) -> Result<()> {
+    // The targets of the defaults, and the lights for the backends adjusting commands, are looked
+    // up from one fetch of the lights, groups, and scenes, if needed.
+    let fetched = Fetched::new(backend);
+    let op = match op {
+        LightOperation::On(mut args) => {
+            for (spec, defaults) in &config.defaults {
+                let needs_ct = args.ct.is_none() && args.color.is_none();
+                if args.bri.is_some() && !needs_ct {
+                    break;
+                }
+                // A stale target, like a group that was renamed, doesn't stop turning on others.
+                let resolved = match targets::resolve(spec, &fetched, config) {
+                    Ok(resolved) => resolved,
+                    Err(err) => {
+                        eprintln!("Skipping invalid target {:?} in defaults: {}", spec, err);
+                        continue;
+                    }
+                };
+                if resolved != target {
+                    continue;
+                }
+                let invalid = |err| eyre!("Invalid defaults for {:?}: {}", spec, err);
+                if let (None, Some(bri)) = (args.bri, &defaults.bri) {
+                    args.bri = Some(values::parse_brightness(bri).map_err(invalid)?);
+                }
+                if let (true, Some(ct)) = (needs_ct, &defaults.ct) {
+                    args.ct = Some(values::parse_kelvin(ct).map_err(invalid)?);
+                }
+            }
+            LightOperation::On(args)
+        }
+        LightOperation::Mode {
+            duration,
+            stagger,
+            palette: Some(name),
+            mode,
+            ..
+        } => {
+            let palette = config
+                .palette
+                .get(&name)
+                .ok_or_else(|| eyre!("No palette named {:?} in the config", name))?;
+            let colors = palette
+                .colors
+                .iter()
+                .map(|color| values::parse_color(color))
+                .collect::<Result<_, _>>()
+                .map_err(|err| eyre!("Invalid color in palette {:?}: {}", name, err))?;
+            LightOperation::Mode {
+                duration,
+                stagger,
+                palette: Some(name),
+                colors,
+                mode,
+            }
+        }
+        op => op,
+    };
+    let warm_dim = (warm_dim || config.warm_dim)
+        && matches!(
+            op,
+            LightOperation::On(_) | LightOperation::Dim(_) | LightOperation::Fade(_)
+        );
+    let capped = !override_cap && !config.caps.is_empty();
+    // The lights are only fetched when something adjusts commands to them, once for all.
+    let datastore = if warm_dim || capped || !config.model.is_empty() {
+        fetched.get_datastore()?
+    } else {
+        Datastore::default()
+    };
+    let members = Members::new(&datastore);
+    let tuned = Tuned::new(backend, config, &datastore, &members)?;
+    let warm = Warm::new(&tuned, warm_dim, &datastore, &members);
+    if override_cap {
+        commands::apply(&warm, target, &op, strict)
+    } else {
+        commands::apply(&Capped::new(&warm, config, &members)?, target, &op, strict)
+    }
 }
@@ -0,0 +1,41 @@
+use crate::options::Opt;
+use clap::CommandFactory;
+use eyre::{Result, WrapErr};
+use std::fs;
+use std::path::Path;
+
+/// Prints the man page for blilys, or writes pages for blilys and every subcommand to `out_dir`.
+pub fn generate(out_dir: Option<&Path>) -> Result<()> {
+    let mut cmd = Opt::command();
+    cmd.build();
+    match out_dir {
+        None => {
+            clap_mangen::Man::new(cmd).render(&mut std::io::stdout())?;
+            Ok(())
+        }
+        Some(dir) => {
+            fs::create_dir_all(dir)?;
+            write_pages(&cmd, dir)
+        }
+    }
+}
+
+/// Writes the page for the command, named like `blilys-light-on.1`, and the pages of its
+/// subcommands.
+fn write_pages(cmd: &clap::Command, dir: &Path) -> Result<()> {
+    // Building the command names subcommands after their parents, like `blilys-light-on`.
+    let name = cmd.get_display_name().unwrap_or_else(|| cmd.get_name());
+    let path = dir.join(format!("{}.1", name));
+    let mut page = vec![];
+    clap_mangen::Man::new(cmd.clone()).render(&mut page)?;
+    fs::write(&path, page).wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+    info!("Wrote {}", path.display());
+
+    for sub in cmd.get_subcommands() {
+        if sub.get_name() == "help" {
+            continue;
+        }
+        write_pages(sub, dir)?;
+    }
+    Ok(())
+}
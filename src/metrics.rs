@@ -0,0 +1,191 @@
+use crate::config::create_private;
+use eyre::Result;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds of the buckets of the histograms, in seconds.
+const BUCKETS: [f64; 10] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+static METRICS: Metrics = Metrics::new();
+
+/// Counts of how blilys itself is doing, to tell whether it or the bridge is slow.
+struct Metrics {
+    requests: Histogram,
+    retries: AtomicU64,
+    waiting: AtomicU64,
+    effect_restarts: AtomicU64,
+    tick_lateness: Histogram,
+}
+
+struct Histogram {
+    /// Observations in each bucket and below, as Prometheus counts them.
+    buckets: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+/// Records how long the bridge took to answer a request.
+pub fn request(duration: Duration) {
+    METRICS.requests.observe(duration);
+}
+
+/// Records a request sent again on a fresh connection after the reused one failed.
+pub fn retry() {
+    METRICS.retries.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records a request starting or ending its wait for a turn in the request queue.
+pub fn queued(waiting: bool) {
+    if waiting {
+        METRICS.waiting.fetch_add(1, Ordering::Relaxed);
+    } else {
+        METRICS.waiting.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Records an effect in `daemon.effects` being restarted after failing.
+pub fn effect_restart() {
+    METRICS.effect_restarts.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records how much later than planned a step of an effect was sent.
+pub fn tick_lateness(lateness: Duration) {
+    METRICS.tick_lateness.observe(lateness);
+}
+
+/// Writes the metrics to `path` in the Prometheus text format, if a path is set. Failures are
+/// only reported, as missing metrics aren't worth stopping for.
+pub fn update(path: Option<&Path>) {
+    if let Some(path) = path {
+        if let Err(err) = write(path) {
+            eprintln!("Failed to write metrics to {}: {:#}", path.display(), err);
+        }
+    }
+}
+
+/// Replaces the file at once, so that a collector reading it never sees half of it.
+fn write(path: &Path) -> Result<()> {
+    let temp = path.with_extension("tmp");
+    create_private(&temp)?.write_all(METRICS.render().as_bytes())?;
+    fs::rename(&temp, path)?;
+    Ok(())
+}
+
+impl Metrics {
+    const fn new() -> Metrics {
+        Metrics {
+            requests: Histogram::new(),
+            retries: AtomicU64::new(0),
+            waiting: AtomicU64::new(0),
+            effect_restarts: AtomicU64::new(0),
+            tick_lateness: Histogram::new(),
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        self.requests.render(
+            &mut out,
+            "blilys_request_duration_seconds",
+            "Time taken by the bridge to answer requests, after their turn in the queue.",
+        );
+        counter(
+            &mut out,
+            "blilys_request_retries_total",
+            "Requests sent again after a reused connection to the bridge failed.",
+            self.retries.load(Ordering::Relaxed),
+        );
+        let _ = writeln!(
+            out,
+            "# HELP blilys_queue_waiting Requests waiting for their turn to be sent to the bridge.\n\
+             # TYPE blilys_queue_waiting gauge\n\
+             blilys_queue_waiting {}",
+            self.waiting.load(Ordering::Relaxed)
+        );
+        counter(
+            &mut out,
+            "blilys_effect_restarts_total",
+            "Effects in daemon.effects restarted after failing.",
+            self.effect_restarts.load(Ordering::Relaxed),
+        );
+        self.tick_lateness.render(
+            &mut out,
+            "blilys_effect_tick_lateness_seconds",
+            "How much later than planned each step of an effect was sent.",
+        );
+        out
+    }
+}
+
+impl Histogram {
+    const fn new() -> Histogram {
+        Histogram {
+            buckets: [const { AtomicU64::new(0) }; BUCKETS.len()],
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bound, bucket) in BUCKETS.iter().zip(&self.buckets) {
+            if seconds <= *bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}\n# TYPE {} histogram", name, help, name);
+        for (bound, bucket) in BUCKETS.iter().zip(&self.buckets) {
+            let _ = writeln!(
+                out,
+                "{}_bucket{{le=\"{}\"}} {}",
+                name,
+                bound,
+                bucket.load(Ordering::Relaxed)
+            );
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let _ = writeln!(out, "{}_sum {}\n{}_count {}", name, sum, name, count);
+    }
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(
+        out,
+        "# HELP {} {}\n# TYPE {} counter\n{} {}",
+        name, help, name, name, value
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Metrics;
+    use std::time::Duration;
+
+    #[test]
+    fn metrics_are_rendered_for_prometheus() {
+        let metrics = Metrics::new();
+        metrics.requests.observe(Duration::from_millis(30));
+        metrics.requests.observe(Duration::from_millis(200));
+        metrics.requests.observe(Duration::from_secs(10));
+        let out = metrics.render();
+        assert!(out.contains("\nblilys_request_duration_seconds_bucket{le=\"0.025\"} 0\n"));
+        assert!(out.contains("\nblilys_request_duration_seconds_bucket{le=\"0.05\"} 1\n"));
+        assert!(out.contains("\nblilys_request_duration_seconds_bucket{le=\"5\"} 2\n"));
+        assert!(out.contains("\nblilys_request_duration_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(out.contains("\nblilys_request_duration_seconds_sum 10.23\n"));
+        assert!(out.contains("\nblilys_request_duration_seconds_count 3\n"));
+        assert!(out.contains("# TYPE blilys_queue_waiting gauge\nblilys_queue_waiting 0\n"));
+    }
+}
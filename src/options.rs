@@ -1,79 +1,1286 @@
+use crate::color::kelvin_to_mired;
+use crate::commands::{GROUP_FIELDS, LIGHT_FIELDS};
+use crate::discovery::Method;
+use crate::logging::LogFormat;
+use crate::systemd::{parse_timer, Timer};
+use crate::template::Template;
+use crate::time::{parse_duration, parse_time_of_day, parse_time_range, parse_weekdays, TimeOfDay};
+use crate::values::{parse_brightness, parse_color, parse_kelvin, parse_location};
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
 use hueclient::CommandLight;
-use structopt::StructOpt;
 
-use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
 
-#[derive(Debug, StructOpt)]
-#[structopt(
+#[derive(Debug, Parser)]
+#[command(
     name = "blilys",
-    about = "Control Philips Hue lights from the command line."
+    version,
+    about = "Control Philips Hue lights from the command line.",
+    after_help = "Examples:
+  blilys pair
+  blilys lights
+  blilys light desk on --bri 50% --ct 2700K
+  blilys group kitchen off"
 )]
 pub struct Opt {
-    /// IP address. If not provided, auto discovery is attempted.
-    #[structopt(short, long)]
-    pub bridge: Option<IpAddr>,
-    #[structopt(subcommand)]
+    #[command(flatten)]
+    pub connection: ConnectionOpt,
+    /// Don't read or write the config file.
+    #[arg(long)]
+    pub no_config: bool,
+    /// Only print results and errors.
+    #[arg(short, long)]
+    pub quiet: bool,
+    /// Don't use colors. Setting NO_COLOR does the same.
+    #[arg(long)]
+    pub no_color: bool,
+    /// Don't shorten long names in tables to fit the terminal.
+    #[arg(long)]
+    pub wide: bool,
+    /// Allow brightness above the caps in the config.
+    #[arg(long)]
+    pub override_cap: bool,
+    /// Make lights warmer as they dim, like incandescent bulbs, when `on`, `dim`, or `fade` sets
+    /// only the brightness. Setting warm_dim in the config does the same.
+    #[arg(long)]
+    pub warm_dim: bool,
+    /// Fail as soon as one light or change fails, instead of carrying on with the rest and only
+    /// failing if all of them do.
+    #[arg(long)]
+    pub strict: bool,
+    /// Connect to the bridge directly, even if daemon.forward is set in the config.
+    #[arg(long)]
+    pub no_daemon: bool,
+    /// Append a log of discovery, pairing, and every request to the bridge to a file.
+    #[arg(long)]
+    pub log_file: Option<PathBuf>,
+    /// Format of the log file. Defaults to json for `blilys daemon` and text otherwise.
+    #[arg(long, value_enum, requires = "log_file")]
+    pub log_format: Option<LogFormat>,
+    #[command(subcommand)]
     pub cmd: Command,
 }
 
-#[derive(Debug, StructOpt)]
+/// A command given as arguments to another, like the one run by `blilys if`.
+#[derive(Debug, Parser)]
+#[command(name = "blilys")]
+pub struct NestedCommand {
+    #[command(subcommand)]
+    pub cmd: Command,
+}
+
+#[derive(Debug, Args)]
+pub struct ConnectionOpt {
+    /// IP address or hostname. If not provided, auto discovery is attempted.
+    #[arg(short, long)]
+    pub bridge: Option<String>,
+    /// Username from pairing, to connect to the bridge given by --bridge without any files:
+    /// the config, cache, and audit log are neither read nor written.
+    #[arg(long, requires = "bridge")]
+    pub username: Option<String>,
+    /// Comma-separated discovery methods to try in order: mdns, nupnp, or manual.
+    #[arg(long, value_delimiter = ',')]
+    pub discovery: Vec<Method>,
+    /// Control the lights through the Hue Remote API instead of the local network.
+    #[arg(long)]
+    pub remote: bool,
+    /// Pair with the bridge first if not paired yet, instead of failing.
+    #[arg(long)]
+    pub auto_pair: bool,
+    /// Append all HTTP exchanges with the bridge to a file, for reproducing bugs.
+    #[arg(long)]
+    pub record: Option<PathBuf>,
+    /// Answer with the responses in a file written by --record instead of talking to a bridge.
+    #[arg(long, conflicts_with_all = ["bridge", "remote"])]
+    pub replay: Option<PathBuf>,
+}
+
+const TARGET_HELP: &str = "Group or light name, light:<id or name>, group:<id or name>, all, \
+                           an alias, room:<pattern>, @on, @off, or several of these added and \
+                           removed like \"kitchen + light:desk - @off\". Defaults to \
+                           default_target from the config";
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum GroupBy {
+    Room,
+}
+
+#[derive(Debug, Subcommand)]
 pub enum Command {
     /// Pair with bridge to get a username.
-    Pair,
-    /// Show config.
-    Config,
+    #[command(after_help = "Examples:
+  blilys pair
+  blilys --bridge 192.168.1.2 pair --wait 60s --json")]
+    Pair {
+        /// Wait this long for the link button to be pressed, like 60s, instead of prompting.
+        #[arg(short, long, value_parser = parse_duration)]
+        wait: Option<Duration>,
+        /// Print the bridge host, ID, and username as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show or edit config.
+    Config {
+        #[command(subcommand)]
+        op: Option<ConfigOperation>,
+    },
     /// List available groups.
-    Groups,
-    // Control a group.
+    #[command(after_help = "Examples:
+  blilys groups
+  blilys groups --format '{id}\\t{name}\\t{lights}'
+  blilys groups --json")]
+    Groups {
+        /// Print each group like `{id}\\t{name}`, with the fields id, name, type, lights, any_on,
+        /// all_on, and bri, the average brightness of the lights that are on.
+        #[arg(long, value_parser = parse_group_format)]
+        format: Option<Template>,
+        /// Print the groups as JSON, with the same fields as `--format`.
+        #[arg(long, conflicts_with = "format")]
+        json: bool,
+        /// Also include the bridge's own JSON of each group under a `raw` key, with fields
+        /// blilys doesn't know.
+        #[arg(long, requires = "json")]
+        full: bool,
+    },
+    /// Control a group.
     Group {
-        group: usize,
-        #[structopt(subcommand)]
+        /// Group ID or name.
+        group: String,
+        #[command(subcommand)]
         op: LightOperation,
     },
     /// List available lights.
-    Lights,
+    #[command(after_help = "Examples:
+  blilys lights
+  blilys lights --group-by room
+  blilys lights --format '{id}\\t{name}\\t{bri}'
+  blilys lights --json --full")]
+    Lights {
+        /// Print each light like `{id}\\t{name}`, with the fields id, name, room, on, bri, hue,
+        /// sat, ct, and model.
+        #[arg(long, value_parser = parse_light_format, conflicts_with = "group_by")]
+        format: Option<Template>,
+        /// List the lights under a heading for each room.
+        #[arg(long, value_enum)]
+        group_by: Option<GroupBy>,
+        /// Print the lights as JSON, with the same fields as `--format`.
+        #[arg(long, conflicts_with_all = ["format", "group_by"])]
+        json: bool,
+        /// Also include the bridge's own JSON of each light under a `raw` key, with fields
+        /// blilys doesn't know.
+        #[arg(long, requires = "json")]
+        full: bool,
+    },
     /// Control a light.
     Light {
-        light: usize,
-        #[structopt(subcommand)]
+        /// Light ID or name.
+        light: String,
+        #[command(subcommand)]
+        op: LightOperation,
+    },
+    /// Turn a target on.
+    On {
+        #[command(flatten)]
+        args: OnArgs,
+        #[arg(help = TARGET_HELP)]
+        target: Option<String>,
+    },
+    /// Turn a target off.
+    Off {
+        #[command(flatten)]
+        args: OffArgs,
+        #[arg(help = TARGET_HELP)]
+        target: Option<String>,
+    },
+    /// Turn a target off if any of its lights are on, or on otherwise.
+    Toggle {
+        #[command(flatten)]
+        args: OffArgs,
+        #[arg(help = TARGET_HELP)]
+        target: Option<String>,
+    },
+    /// Turn a target on at the given brightness.
+    #[command(after_help = "Examples:
+  blilys dim 30%
+  blilys dim 80% kitchen
+  blilys dim 10 light:desk")]
+    Dim {
+        #[command(flatten)]
+        args: DimArgs,
+        #[arg(help = TARGET_HELP)]
+        target: Option<String>,
+    },
+    /// Find a light's ID by blinking the lights one by one.
+    Which {
+        /// Only try the lights in this group, given by ID or name.
+        group: Option<String>,
+    },
+    /// Label lights, to control them together like `blilys on tag:reading`.
+    Tag {
+        #[command(subcommand)]
+        op: TagOperation,
+    },
+    /// Wait until a light, group, or alias is in the given state.
+    Wait {
+        #[arg(help = TARGET_HELP)]
+        target: String,
+        /// State to wait for.
+        #[arg(long, value_enum)]
+        until: Power,
+        /// Give up and fail after this long, like 5m. Waits forever by default.
+        #[arg(long, value_parser = parse_duration)]
+        timeout: Option<Duration>,
+        /// Time between polls of the bridge.
+        #[arg(short, long, default_value = "1s", value_parser = parse_duration)]
+        interval: Duration,
+    },
+    /// Run a blilys command only if a light, group, or alias is in the given state.
+    #[command(after_help = "Example:\n  blilys if hall --is off -- light porch off")]
+    If {
+        #[arg(help = TARGET_HELP)]
+        target: String,
+        /// State the target must be in.
+        #[arg(long, value_enum)]
+        is: Power,
+        /// The command to run, after `--`.
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
+    /// List dimmer switches, smart buttons, and tap dials, with the rules and resourcelinks using
+    /// them.
+    Accessories,
+    /// Program a dimmer switch, smart button, or tap dial.
+    Accessory {
+        /// Sensor ID or name of the switch.
+        switch: String,
+        #[command(subcommand)]
+        op: AccessoryOperation,
+    },
+    /// Configure a sensor.
+    Sensor {
+        /// Sensor ID or name. For motion sensors, any of the device's sensors will do.
+        sensor: String,
+        #[command(subcommand)]
+        op: SensorOperation,
+    },
+    /// Create, list, or remove automations that run on the bridge.
+    Automation {
+        #[command(subcommand)]
+        op: AutomationOperation,
+    },
+    /// Recall, schedule, export, or import a scene, or recall a random one.
+    Scene {
+        /// Scene ID or name, or the name of the scene to import. Not given to `random`.
+        scene: Option<String>,
+        #[command(subcommand)]
+        op: SceneOperation,
+    },
+    /// Control all lights.
+    All {
+        #[command(subcommand)]
         op: LightOperation,
     },
+    /// Measure bridge round-trip times.
+    Bench {
+        /// Number of requests of each kind.
+        #[arg(short, long, default_value = "50")]
+        count: usize,
+        /// Light to send PUT requests to. Defaults to the first light.
+        #[arg(short, long)]
+        light: Option<usize>,
+    },
+    /// Manage the local cache of light, group, and scene names.
+    Cache {
+        #[command(subcommand)]
+        op: CacheOperation,
+    },
+    /// Save, list, or delete snapshots of the state of all lights.
+    Snapshot {
+        #[command(subcommand)]
+        op: SnapshotOperation,
+    },
+    /// Show how the lights changed between a snapshot and now, or between two snapshots.
+    Diff {
+        /// Name of the earlier snapshot.
+        from: String,
+        /// Name of the later snapshot. Defaults to the current state of the lights.
+        to: Option<String>,
+    },
+    /// Turn every light on at full brightness at once, ignoring caps, for emergencies. The lights
+    /// are saved first, for `allclear`.
+    Panic,
+    /// Set the lights back to how they were before `panic`.
+    Allclear,
+    /// Set the lights to a saved snapshot, or crossfade from one snapshot to another.
+    #[command(after_help = "Examples:
+  preset evening
+  preset day --crossfade-to evening --duration 30m")]
+    Preset {
+        /// Name of the snapshot to set the lights to.
+        name: String,
+        /// Name of a snapshot to fade to afterwards, light by light through the colors between.
+        #[arg(long)]
+        crossfade_to: Option<String>,
+        /// How long the crossfade takes.
+        #[arg(long, default_value = "30s", value_parser = parse_duration, requires = "crossfade_to")]
+        duration: Duration,
+    },
+    /// Dump the lights, groups, scenes, and rules in a stable order, for keeping in version
+    /// control.
+    Export {
+        /// Indent JSON output.
+        #[arg(long)]
+        pretty: bool,
+        #[arg(short, long, value_enum, default_value = "json")]
+        format: ExportFormat,
+        /// File to write to, instead of stdout.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Keep running, exporting again whenever anything changes.
+        #[arg(long, requires = "output")]
+        watch: bool,
+        /// Time between polls of the bridge when watching without its event stream.
+        #[arg(short, long, default_value = "10s", value_parser = parse_duration)]
+        interval: Duration,
+    },
+    /// Bring the bridge's groups, scenes, and schedules, and the config's aliases, to the state
+    /// described in a file, after showing the changes.
+    #[command(after_help = "Example file:\n  \
+  [aliases]\n  \
+  desk = \"light:1\"\n\n  \
+  [groups.Office]\n  \
+  lights = [\"1\", \"2\"]\n  \
+  type = \"Room\"")]
+    Apply {
+        /// TOML, YAML, or JSON file describing the desired state.
+        file: PathBuf,
+        /// Delete groups, scenes, schedules, and aliases not in the file. Only sections present
+        /// in the file are pruned.
+        #[arg(long)]
+        prune: bool,
+        /// Only show the changes.
+        #[arg(long)]
+        dry_run: bool,
+        /// Make the changes without asking.
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Show recorded history for a light or sensor.
+    History {
+        /// Light or sensor ID or name.
+        target: Option<String>,
+        /// How far back to look, e.g. "24h" or "7d".
+        #[arg(short, long, default_value = "24h", value_parser = parse_duration)]
+        since: Duration,
+        #[command(subcommand)]
+        op: Option<HistoryOperation>,
+    },
+    /// Estimate energy usage from recorded history.
+    Energy {
+        /// How far back to look, e.g. "24h" or "7d".
+        #[arg(short, long, default_value = "7d", value_parser = parse_duration)]
+        since: Duration,
+    },
+    /// Set up access through the Hue Remote API.
+    Remote {
+        #[command(subcommand)]
+        op: RemoteOperation,
+    },
+    /// Inspect the log of state-changing commands.
+    Log {
+        #[command(subcommand)]
+        op: LogOperation,
+    },
+    /// Keep running, acting on presence detection and calendar events as set up in the config's
+    /// `[presence]` and `[calendar]` sections, and on commands from `blilys ctl` and signals.
+    #[command(after_help = "Example config:\n  \
+  [presence]\n  \
+  arrive = \"group:Hallway on\"\n  \
+  arrive_only_when_dark = true\n  \
+  leave = \"all off\"\n\n  \
+  [presence.devices.phone]\n  \
+  ip = \"192.168.1.23\"\n  \
+  mac = \"aa:bb:cc:dd:ee:ff\"\n\n  \
+  [calendar]\n  \
+  url = \"https://example.com/basic.ics\"\n\n  \
+  [[calendar.rules]]\n  \
+  summary = \"meeting\"\n  \
+  before = \"5m\"\n  \
+  start = \"busy-light on --color red\"\n  \
+  end = \"busy-light off\"\n\n  \
+  [daemon]\n  \
+  sigusr1 = \"toggle\"\n  \
+  sigusr2 = \"all off\"\n  \
+  # Send commands like `blilys toggle` through the daemon, starting it if needed.\n  \
+  forward = true")]
+    Daemon,
+    /// Check the config, directories, discovery, and the connection to the bridge, with hints on
+    /// how to fix any problems.
+    Doctor,
+    /// Send a command controlling lights to the running daemon, which runs it without connecting
+    /// to the bridge again. Only supported on Unix.
+    #[command(after_help = "Examples:
+  blilys ctl toggle
+  blilys ctl light desk on --bri 50%")]
+    Ctl {
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// Keep a light colored by the weather forecast: yellow for sun, white for clouds, blue for
+    /// rain, cyan for snow, and pulsing white for warnings.
+    WeatherSync {
+        #[arg(long, value_enum, default_value = "met.no")]
+        provider: WeatherProvider,
+        /// Latitude and longitude, like "59.9,10.7".
+        #[arg(long, value_parser = parse_location)]
+        location: (f64, f64),
+        /// Light, group, or alias to color.
+        #[arg(long)]
+        light: String,
+        /// Time between forecast updates.
+        #[arg(short, long, default_value = "30m", value_parser = parse_duration)]
+        interval: Duration,
+    },
+    /// Keep a light colored by the status of a GitHub repo's latest workflow run: green for
+    /// passing, red for failing, and yellow while running. Set GITHUB_TOKEN for private repos.
+    CiLight {
+        /// Repo like "jodal/blilys".
+        #[arg(long, value_parser = parse_repo)]
+        github: String,
+        /// Only look at runs on this branch.
+        #[arg(long)]
+        branch: Option<String>,
+        /// Light, group, or alias to color.
+        #[arg(long)]
+        light: String,
+        /// Time between polls of GitHub.
+        #[arg(short, long, default_value = "1m", value_parser = parse_duration)]
+        interval: Duration,
+    },
+    /// Set up systemd user units running blilys in the background.
+    Systemd {
+        #[command(subcommand)]
+        op: SystemdOperation,
+    },
+    /// Set up macOS launch agents running blilys in the background.
+    Launchd {
+        #[command(subcommand)]
+        op: LaunchdOperation,
+    },
+    /// Update blilys to the latest release.
+    SelfUpdate {
+        /// Only check if a newer release is available.
+        #[arg(long)]
+        check: bool,
+    },
+    /// Print the man page, or write man pages for all subcommands to a directory.
+    Man {
+        /// Directory to write blilys.1 and a page per subcommand to.
+        #[arg(short, long)]
+        out_dir: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum RemoteOperation {
+    /// Authorize blilys with the Hue Remote API.
+    Login,
+    /// Forget the Hue Remote API tokens.
+    Logout,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum LogOperation {
+    /// Show the most recent commands.
+    Tail {
+        /// Number of entries to show.
+        #[arg(short = 'n', long, default_value = "20")]
+        lines: usize,
+        /// Keep printing new entries as they are logged.
+        #[arg(short, long)]
+        follow: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum HistoryOperation {
+    /// Record light state transitions and sensor readings until stopped.
+    Record {
+        /// Time between polls of the bridge.
+        #[arg(short, long, default_value = "10s", value_parser = parse_duration)]
+        interval: Duration,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigOperation {
+    /// Show config. This is the default.
+    Show,
+    /// Open the config file in $VISUAL or $EDITOR, and validate it afterwards.
+    Edit,
+    /// Check that the config file parses.
+    Validate,
+    /// Print the path to the config file.
+    Path,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TagOperation {
+    /// Tag the target's lights.
+    #[command(after_help = "Examples:
+  blilys tag add light:3 reading ambient
+  blilys tag add office ambient")]
+    Add {
+        #[arg(help = TARGET_HELP)]
+        target: String,
+        #[arg(required = true, value_parser = parse_tag)]
+        tags: Vec<String>,
+    },
+    /// Remove tags from the target's lights.
+    Remove {
+        #[arg(help = TARGET_HELP)]
+        target: String,
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+    /// List the tags and their lights.
+    List,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CacheOperation {
+    /// Fetch names from the bridge and update the cache.
+    Refresh,
+    /// Remove the cache.
+    Clear,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AccessoryOperation {
+    /// Create a rule on the bridge running an action when a button is pressed.
+    #[command(after_help = "Examples:
+  blilys accessory \"Hall dimmer\" bind --button 1 --action \"group:hall on\"
+  blilys accessory 2 bind --button 2 --hold --action \"group:kitchen brighter 20%\"")]
+    Bind {
+        /// Number of the button, counting from 1.
+        #[arg(long)]
+        button: u8,
+        /// Run the action repeatedly while the button is held, instead of on release.
+        #[arg(long)]
+        hold: bool,
+        /// A target and what to do with it: on, off, dim, brighter, or darker, with a brightness
+        /// for the last three.
+        #[arg(long)]
+        action: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SensorOperation {
+    /// Change the sensitivity, enabled state, or daylight threshold of a motion sensor.
+    Config {
+        /// How little motion triggers the sensor.
+        #[arg(long, value_enum)]
+        sensitivity: Option<Sensitivity>,
+        /// Enable the sensor.
+        #[arg(long, conflicts_with = "off")]
+        on: bool,
+        /// Disable the sensor.
+        #[arg(long)]
+        off: bool,
+        /// Light level in lux below which the sensor considers it dark.
+        #[arg(long)]
+        daylight_threshold: Option<f64>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Sensitivity {
+    Low,
+    Medium,
+    High,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AutomationOperation {
+    /// Dimly light the way when there is motion at night.
+    #[command(after_help = "Example:
+  blilys automation nightlight --sensor \"Hall motion\" --light hall --bri 10% \\
+    --between 23:00-06:00")]
+    Nightlight {
+        /// ID or name of the motion sensor.
+        #[arg(long)]
+        sensor: String,
+        #[arg(long, help = TARGET_HELP)]
+        light: String,
+        /// Brightness, as a percentage like 10% or a value from 1 to 254.
+        #[arg(short, long, default_value = "10%", value_parser = parse_brightness)]
+        bri: u8,
+        /// Times of night to react to motion, like 23:00-06:00.
+        #[arg(long, value_parser = parse_time_range)]
+        between: (TimeOfDay, TimeOfDay),
+        /// Turn the light off after this long without motion.
+        #[arg(long, default_value = "1m", value_parser = parse_duration)]
+        off_after: Duration,
+    },
+    /// Turn a light on when there is motion, and off when there has been none for a while.
+    MotionLight {
+        /// ID or name of the motion sensor.
+        #[arg(long)]
+        sensor: String,
+        #[arg(long, help = TARGET_HELP)]
+        light: String,
+        /// Brightness, as a percentage like 80% or a value from 1 to 254.
+        #[arg(short, long, default_value = "100%", value_parser = parse_brightness)]
+        bri: u8,
+        /// Only react to motion between these times, like 07:00-23:00.
+        #[arg(long, value_parser = parse_time_range)]
+        between: Option<(TimeOfDay, TimeOfDay)>,
+        /// Turn the light off after this long without motion.
+        #[arg(long, default_value = "5m", value_parser = parse_duration)]
+        off_after: Duration,
+    },
+    /// Turn a light off when there has been no motion for a while.
+    OffWhenNoMotion {
+        /// ID or name of the motion sensor.
+        #[arg(long)]
+        sensor: String,
+        #[arg(long, help = TARGET_HELP)]
+        light: String,
+        /// Time without motion before turning the light off, like 15m.
+        #[arg(long, default_value = "15m", value_parser = parse_duration)]
+        after: Duration,
+    },
+    /// Recall a scene on motion when all lights are off, as when coming home.
+    ArrivalScene {
+        /// ID or name of the motion sensor.
+        #[arg(long)]
+        sensor: String,
+        /// Scene ID or name.
+        #[arg(long)]
+        scene: String,
+        /// Only react to motion between these times, like 17:00-23:00.
+        #[arg(long, value_parser = parse_time_range)]
+        between: Option<(TimeOfDay, TimeOfDay)>,
+    },
+    /// List the automations created by blilys.
+    List,
+    /// Remove an automation created by blilys, with its rules.
+    Remove {
+        /// ID of the automation, as listed.
+        id: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SceneOperation {
+    /// Set the lights to a random scene.
+    #[command(after_help = "Example:\n  blilys scene random --group bedroom --match 'relax*'")]
+    Random {
+        /// Only pick scenes of lights in this group, given by ID or name.
+        #[arg(long)]
+        group: Option<String>,
+        /// Only pick scenes with names matching this pattern, where `*` matches anything.
+        #[arg(long = "match")]
+        pattern: Option<String>,
+    },
+    /// Set the lights to the scene.
+    Recall,
+    /// List the color and brightness of each light in the scene, without recalling it.
+    Show,
+    /// Create a schedule on the bridge that recalls the scene, even when blilys isn't running.
+    #[command(after_help = "Example:\n  blilys scene Energize schedule --at 07:00 --days mon-fri")]
+    Schedule {
+        /// Time of day, like 07:00.
+        #[arg(long, value_parser = parse_time_of_day)]
+        at: TimeOfDay,
+        /// Days of the week, like mon-fri or sat,sun.
+        #[arg(long, default_value = "daily", value_parser = parse_weekdays)]
+        days: u8,
+    },
+    /// Print the scene as JSON with the lights by name, for importing on another bridge.
+    #[command(after_help = "Example:\n  blilys scene Relax export > relax.json")]
+    Export,
+    /// Create the scene from a file made by `export`, matching the lights by name.
+    #[command(after_help = "Example:\n  blilys scene Relax import relax.json --remap")]
+    Import {
+        file: PathBuf,
+        /// Ask which light to use for each light in the file, instead of only matching by name.
+        #[arg(long)]
+        remap: bool,
+    },
 }
 
-#[derive(Debug, StructOpt)]
+#[derive(Debug, Subcommand)]
+pub enum SnapshotOperation {
+    /// Save the current state of all lights under a name.
+    Save { name: String },
+    /// List saved snapshots.
+    List,
+    /// Delete a saved snapshot.
+    Delete { name: String },
+}
+
+#[derive(Debug, Subcommand)]
 pub enum LightOperation {
     /// Turn light on.
-    On {
-        #[structopt(short, long, help = "Brightness")]
-        bri: Option<u8>,
-    },
+    #[command(after_help = "Examples:
+  on --bri 50%
+  on --ct 2700K --transition 2s
+  on --color orange")]
+    On(OnArgs),
     /// Turn light off.
-    Off,
+    Off(OffArgs),
+    /// Turn light off if it is on, or on if it is off.
+    Toggle(OffArgs),
+    /// Turn light on at the given brightness.
+    Dim(DimArgs),
+    /// Fade smoothly to a brightness, color, or off, using the bulbs' own transitions.
+    #[command(after_help = "Examples:
+  fade 30m --bri 100% --ct 4000K
+  fade 3h --off")]
+    Fade(FadeArgs),
+    /// Fade on from a dim, warm glow, like a sunrise.
+    Wake(WakeArgs),
+    /// Fade down to a dim, warm glow, then off.
+    Sleep(SleepArgs),
     /// Enable special mode.
     Mode {
-        #[structopt(subcommand)]
+        /// Stop the mode after this long, like 30s or 1h. Runs until stopped by default.
+        #[arg(short, long, value_parser = parse_duration)]
+        duration: Option<Duration>,
+        /// Run a cyclic mode on each light of the group in turn, like a wave.
+        #[arg(long)]
+        stagger: bool,
+        /// Colors to use, from a palette in the config like `[palette.halloween]`.
+        #[arg(long)]
+        palette: Option<String>,
+        /// The colors of the palette, looked up in the config.
+        #[arg(skip)]
+        colors: Vec<(f32, f32)>,
+        #[command(subcommand)]
         mode: LightMode,
     },
+    /// Set the light to one of Philips' light recipes, without needing a scene on the bridge.
+    #[command(after_help = "Example:\n  recipe relax --transition 2s")]
+    Recipe {
+        #[arg(value_enum)]
+        recipe: Recipe,
+        /// Time to fade to the recipe, like 400ms or 2s.
+        #[arg(short, long, value_parser = parse_duration)]
+        transition: Option<Duration>,
+    },
+}
+
+/// Philips' standard light recipes, as in the Hue app.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Recipe {
+    /// 2700K at 100%.
+    Read,
+    /// 4300K at 100%.
+    Concentrate,
+    /// 2200K at 56%.
+    Relax,
+    /// 6400K at 100%.
+    Energize,
+}
+
+impl Recipe {
+    /// Returns the color temperature in Kelvin and the brightness of the recipe.
+    fn kelvin_and_bri(self) -> (u32, u8) {
+        match self {
+            Recipe::Read => (2700, 254),
+            Recipe::Concentrate => (4300, 254),
+            // 56%.
+            Recipe::Relax => (2200, 142),
+            Recipe::Energize => (6400, 254),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ExportFormat {
+    Json,
+    Toml,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SystemdOperation {
+    /// Write units running the daemon or commands on a schedule, and enable them.
+    #[command(group(
+        ArgGroup::new("units").required(true).multiple(true).args(["daemon", "timer"])
+    ))]
+    Install {
+        /// Run `blilys daemon` at login, restarting it if it fails.
+        #[arg(long)]
+        daemon: bool,
+        /// Run a command on a crontab schedule, like "0 22 * * * all off". Can be repeated.
+        #[arg(long, value_parser = parse_timer)]
+        timer: Vec<Timer>,
+        /// Only write the units, without enabling them.
+        #[arg(long)]
+        no_enable: bool,
+    },
+    /// Disable and remove the units written by `install`.
+    Uninstall {
+        /// Only remove the units, without disabling them first.
+        #[arg(long)]
+        no_disable: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum LaunchdOperation {
+    /// Write agents running the daemon or exporter at login, and load them.
+    #[command(group(
+        ArgGroup::new("agents").required(true).multiple(true).args(["daemon", "export"])
+    ))]
+    Install {
+        /// Run `blilys daemon`.
+        #[arg(long)]
+        daemon: bool,
+        /// Run `blilys export --watch`, writing to this file.
+        #[arg(long)]
+        export: Option<PathBuf>,
+        /// Only write the agents, without loading them.
+        #[arg(long)]
+        no_load: bool,
+    },
+    /// Unload and remove the agents written by `install`.
+    Uninstall {
+        /// Only remove the agents, without unloading them first.
+        #[arg(long)]
+        no_unload: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum WeatherProvider {
+    /// The Norwegian Meteorological Institute, with forecasts for the whole world.
+    #[value(name = "met.no")]
+    MetNo,
+}
+
+/// Whether a target's lights are on, where a group is on if any of its lights are.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Power {
+    On,
+    Off,
+}
+
+#[derive(Debug, Args)]
+#[command(group(ArgGroup::new("color-mode").args(["ct", "color"])))]
+pub struct OnArgs {
+    /// Brightness, as a percentage like 50% or a value from 1 to 254.
+    #[arg(short, long, value_parser = parse_brightness)]
+    pub bri: Option<u8>,
+    /// Color temperature in Kelvin, like 2700K.
+    #[arg(long, value_parser = parse_kelvin)]
+    pub ct: Option<u16>,
+    /// Color, as a hex code like #ff8000 or a name like orange.
+    #[arg(long, value_parser = parse_color)]
+    pub color: Option<(f32, f32)>,
+    /// Time to fade to the new state, like 400ms or 2s.
+    #[arg(short, long, value_parser = parse_duration)]
+    pub transition: Option<Duration>,
+}
+
+#[derive(Debug, Args)]
+pub struct OffArgs {
+    /// Time to fade to the new state, like 400ms or 2s.
+    #[arg(short, long, value_parser = parse_duration)]
+    pub transition: Option<Duration>,
+}
+
+#[derive(Debug, Args)]
+pub struct DimArgs {
+    /// Brightness, as a percentage like 30% or a value from 1 to 254.
+    #[arg(value_parser = parse_brightness)]
+    pub bri: u8,
+    /// Time to fade to the new brightness, like 400ms or 2s.
+    #[arg(short, long, value_parser = parse_duration)]
+    pub transition: Option<Duration>,
 }
 
-#[derive(Debug, StructOpt)]
+#[derive(Debug, Args)]
+#[command(group(ArgGroup::new("color-mode").args(["ct", "color"])))]
+#[command(group(
+    ArgGroup::new("end-state")
+        .args(["bri", "ct", "color", "off"])
+        .multiple(true)
+        .required(true)
+))]
+pub struct FadeArgs {
+    /// How long the fade takes, like 30m. Fades longer than the bridge's longest transition of
+    /// about 1h49m chain several, and keep running until they end.
+    #[arg(value_parser = parse_duration)]
+    pub over: Duration,
+    /// Brightness to end at, as a percentage like 50% or a value from 1 to 254.
+    #[arg(short, long, value_parser = parse_brightness)]
+    pub bri: Option<u8>,
+    /// Color temperature to end at, in Kelvin like 2700K.
+    #[arg(long, value_parser = parse_kelvin)]
+    pub ct: Option<u16>,
+    /// Color to end at, as a hex code like #ff8000 or a name like orange.
+    #[arg(long, value_parser = parse_color)]
+    pub color: Option<(f32, f32)>,
+    /// Fade out and turn off.
+    #[arg(long, conflicts_with_all = ["bri", "ct", "color"])]
+    pub off: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct WakeArgs {
+    /// How long the sunrise takes, like 30m.
+    #[arg(default_value = "30m", value_parser = parse_duration)]
+    pub over: Duration,
+    /// Brightness to end at, as a percentage like 50% or a value from 1 to 254.
+    #[arg(short, long, default_value = "100%", value_parser = parse_brightness)]
+    pub bri: u8,
+    /// Color temperature to end at, in Kelvin like 4000K.
+    #[arg(long, default_value = "4000K", value_parser = parse_kelvin)]
+    pub ct: u16,
+}
+
+#[derive(Debug, Args)]
+pub struct SleepArgs {
+    /// How long the fade takes before turning off, like 30m.
+    #[arg(default_value = "30m", value_parser = parse_duration)]
+    pub over: Duration,
+    /// Color temperature to fade to, in Kelvin like 2200K.
+    #[arg(long, default_value = "2200K", value_parser = parse_kelvin)]
+    pub ct: u16,
+}
+
+/// Color temperature a wake up starts at, as warm as most bulbs go.
+const SUNRISE_MIRED: u16 = 500;
+
+/// Parses a tag, which can't contain spaces, as selectors like `tag:a + tag:b` would break.
+fn parse_tag(s: &str) -> Result<String, String> {
+    if s.is_empty() || s.contains(char::is_whitespace) {
+        return Err(format!("Invalid tag {:?}, tags can't contain spaces", s));
+    }
+    Ok(s.to_owned())
+}
+
+fn parse_group_format(s: &str) -> Result<Template, String> {
+    Template::parse(s, GROUP_FIELDS)
+}
+
+fn parse_light_format(s: &str) -> Result<Template, String> {
+    Template::parse(s, LIGHT_FIELDS)
+}
+
+/// Parses a GitHub repo like `owner/repo`.
+fn parse_repo(s: &str) -> Result<String, String> {
+    match s.split_once('/') {
+        Some((owner, repo)) if !owner.is_empty() && !repo.is_empty() && !repo.contains('/') => {
+            Ok(s.to_owned())
+        }
+        _ => Err(format!("Invalid repo {:?}, expected owner/repo", s)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Subcommand)]
 pub enum LightMode {
     /// Halloween mode with scary blinking lights.
     Halloween,
+    /// Slowly pulse the brightness up and down.
+    Breathe {
+        /// Time of one pulse, like 4s.
+        #[arg(short, long, default_value = "4s", value_parser = parse_duration)]
+        period: Duration,
+        /// Lowest brightness, as a percentage like 10% or a value from 1 to 254.
+        #[arg(long, default_value = "10%", value_parser = parse_brightness)]
+        min: u8,
+        /// Highest brightness, as a percentage like 100% or a value from 1 to 254.
+        #[arg(long, default_value = "100%", value_parser = parse_brightness)]
+        max: u8,
+    },
+}
+
+impl LightMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            LightMode::Halloween => "halloween",
+            LightMode::Breathe { .. } => "breathe",
+        }
+    }
+
+    /// Whether the mode repeats a pattern, so that it can be staggered across lights.
+    pub fn is_cyclic(self) -> bool {
+        matches!(self, LightMode::Breathe { .. })
+    }
+}
+
+/// What a light operation does to the lights.
+#[derive(Debug)]
+pub enum Action {
+    /// Set the state once.
+    Set(CommandLight),
+    /// Turn off if on, or on if off.
+    Toggle { transitiontime: Option<u16> },
+    /// Fade from the `start` state if given, or the current one, to the `end` state. Fades ending
+    /// off with a color fade to it at the lowest brightness before turning off.
+    Fade {
+        start: Option<CommandLight>,
+        end: CommandLight,
+        over: Duration,
+    },
+    /// Keep changing the state until stopped, or for the given duration.
+    Effect {
+        mode: LightMode,
+        duration: Option<Duration>,
+        /// Run the effect on each light with a phase offset.
+        stagger: bool,
+        /// Colors to use. Effects keep the current color if empty.
+        colors: Vec<(f32, f32)>,
+    },
 }
 
 impl LightOperation {
-    pub fn to_hue_command(&self) -> CommandLight {
+    pub fn to_action(&self) -> Action {
         match self {
-            LightOperation::On { bri } => {
-                let mut command = CommandLight::default().on();
-                if let Some(bri) = bri {
-                    command = command.with_bri(*bri);
-                }
-                command
+            LightOperation::On(args) => Action::Set(CommandLight {
+                bri: args.bri,
+                ct: args.ct,
+                xy: args.color,
+                transitiontime: args.transition.map(deciseconds),
+                ..CommandLight::default().on()
+            }),
+            LightOperation::Off(args) => Action::Set(CommandLight {
+                transitiontime: args.transition.map(deciseconds),
+                ..CommandLight::default().off()
+            }),
+            LightOperation::Toggle(args) => Action::Toggle {
+                transitiontime: args.transition.map(deciseconds),
+            },
+            LightOperation::Dim(args) => Action::Set(CommandLight {
+                bri: Some(args.bri),
+                transitiontime: args.transition.map(deciseconds),
+                ..CommandLight::default().on()
+            }),
+            LightOperation::Fade(args) if args.off => Action::Fade {
+                start: None,
+                end: CommandLight::default().off(),
+                over: args.over,
+            },
+            LightOperation::Fade(args) => Action::Fade {
+                start: None,
+                end: CommandLight {
+                    bri: args.bri,
+                    ct: args.ct,
+                    xy: args.color,
+                    ..CommandLight::default().on()
+                },
+                over: args.over,
+            },
+            LightOperation::Wake(args) => Action::Fade {
+                start: Some(
+                    CommandLight::default()
+                        .on()
+                        .with_bri(1)
+                        .with_ct(SUNRISE_MIRED),
+                ),
+                end: CommandLight::default()
+                    .on()
+                    .with_bri(args.bri)
+                    .with_ct(args.ct),
+                over: args.over,
+            },
+            LightOperation::Sleep(args) => Action::Fade {
+                start: None,
+                end: CommandLight::default().off().with_ct(args.ct),
+                over: args.over,
+            },
+            LightOperation::Mode {
+                mode,
+                duration,
+                stagger,
+                colors,
+                ..
+            } => Action::Effect {
+                mode: *mode,
+                duration: *duration,
+                stagger: *stagger,
+                colors: colors.clone(),
+            },
+            LightOperation::Recipe { recipe, transition } => {
+                let (kelvin, bri) = recipe.kelvin_and_bri();
+                Action::Set(CommandLight {
+                    bri: Some(bri),
+                    ct: Some(kelvin_to_mired(kelvin)),
+                    transitiontime: transition.map(deciseconds),
+                    ..CommandLight::default().on()
+                })
             }
-            LightOperation::Off => CommandLight::default().off(),
-            LightOperation::Mode { mode: _ } => CommandLight::default(),
         }
     }
 }
+
+/// Converts a duration to the bridge's transition time unit of 100 ms.
+fn deciseconds(duration: Duration) -> u16 {
+    (duration.as_millis() / 100).min(u16::MAX as u128) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Action, Command, LightMode, LightOperation, Opt};
+    use clap::Parser;
+    use serde_json::{json, Value};
+
+    fn parse(args: &[&str]) -> Result<LightOperation, clap::Error> {
+        let args = ["blilys", "light", "1"].iter().chain(args);
+        match Opt::try_parse_from(args)?.cmd {
+            Command::Light { op, .. } => Ok(op),
+            cmd => panic!("Expected a light command, got {:?}", cmd),
+        }
+    }
+
+    fn command(args: &[&str]) -> Value {
+        match parse(args).unwrap().to_action() {
+            Action::Set(command) => serde_json::to_value(command).unwrap(),
+            action => panic!("Expected a command, got {:?}", action),
+        }
+    }
+
+    #[test]
+    fn on_turns_light_on() {
+        assert_eq!(command(&["on"]), json!({"on": true}));
+    }
+
+    #[test]
+    fn on_with_brightness_sets_brightness() {
+        assert_eq!(
+            command(&["on", "--bri", "42"]),
+            json!({"on": true, "bri": 42})
+        );
+        assert_eq!(
+            command(&["on", "-b", "50%"]),
+            json!({"on": true, "bri": 127})
+        );
+    }
+
+    #[test]
+    fn on_with_color_temperature_sets_mired() {
+        assert_eq!(
+            command(&["on", "--ct", "2700K"]),
+            json!({"on": true, "ct": 370})
+        );
+    }
+
+    #[test]
+    fn on_with_transition_sets_deciseconds() {
+        assert_eq!(
+            command(&["on", "--transition", "1500ms"]),
+            json!({"on": true, "transitiontime": 15})
+        );
+    }
+
+    #[test]
+    fn on_rejects_both_color_temperature_and_color() {
+        assert!(parse(&["on", "--ct", "2700K", "--color", "red"]).is_err());
+    }
+
+    #[test]
+    fn dim_sets_brightness() {
+        assert_eq!(command(&["dim", "30%"]), json!({"on": true, "bri": 76}));
+    }
+
+    #[test]
+    fn off_turns_light_off() {
+        assert_eq!(command(&["off"]), json!({"on": false}));
+    }
+
+    #[test]
+    fn off_with_transition_sets_deciseconds() {
+        assert_eq!(
+            command(&["off", "-t", "1m"]),
+            json!({"on": false, "transitiontime": 600})
+        );
+    }
+
+    fn fade(args: &[&str]) -> (Option<Value>, Value, u64) {
+        match parse(args).unwrap().to_action() {
+            Action::Fade { start, end, over } => (
+                start.map(|start| serde_json::to_value(start).unwrap()),
+                serde_json::to_value(end).unwrap(),
+                over.as_secs(),
+            ),
+            action => panic!("Expected a fade, got {:?}", action),
+        }
+    }
+
+    #[test]
+    fn fade_ends_at_the_state_over_the_duration() {
+        assert_eq!(
+            fade(&["fade", "30m", "--bri", "100%"]),
+            (None, json!({"on": true, "bri": 254}), 30 * 60)
+        );
+        assert_eq!(
+            fade(&["fade", "3h", "--off"]),
+            (None, json!({"on": false}), 3 * 60 * 60)
+        );
+        assert!(parse(&["fade", "10m"]).is_err());
+    }
+
+    #[test]
+    fn wake_and_sleep_fade_through_a_warm_glow() {
+        assert_eq!(
+            fade(&["wake"]),
+            (
+                Some(json!({"on": true, "bri": 1, "ct": 500})),
+                json!({"on": true, "bri": 254, "ct": 250}),
+                30 * 60
+            )
+        );
+        assert_eq!(
+            fade(&["sleep", "2h"]),
+            (None, json!({"on": false, "ct": 455}), 2 * 60 * 60)
+        );
+    }
+
+    #[test]
+    fn recipes_set_color_temperature_and_brightness() {
+        assert_eq!(
+            command(&["recipe", "read"]),
+            json!({"on": true, "bri": 254, "ct": 370})
+        );
+        assert_eq!(
+            command(&["recipe", "concentrate"]),
+            json!({"on": true, "bri": 254, "ct": 233})
+        );
+        assert_eq!(
+            command(&["recipe", "relax", "--transition", "2s"]),
+            json!({"on": true, "bri": 142, "ct": 455, "transitiontime": 20})
+        );
+        assert_eq!(
+            command(&["recipe", "energize"]),
+            json!({"on": true, "bri": 254, "ct": 156})
+        );
+        assert!(parse(&["recipe", "nap"]).is_err());
+    }
+
+    #[test]
+    fn mode_is_an_effect() {
+        assert!(matches!(
+            parse(&["mode", "halloween"]).unwrap().to_action(),
+            Action::Effect {
+                mode: LightMode::Halloween,
+                duration: None,
+                stagger: false,
+                ..
+            }
+        ));
+        assert!(matches!(
+            parse(&["mode", "--duration", "1h30m", "halloween"]).unwrap().to_action(),
+            Action::Effect {
+                mode: LightMode::Halloween,
+                duration: Some(d),
+                stagger: false,
+                ..
+            } if d.as_secs() == 90 * 60
+        ));
+    }
+}
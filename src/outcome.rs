@@ -0,0 +1,84 @@
+use crate::output::{paint, Style};
+use eyre::{eyre, Report, Result};
+use std::fmt::Display;
+
+/// Results of an operation on several targets, carrying on past failures unless strict.
+pub struct Outcomes {
+    strict: bool,
+    results: Vec<(String, Option<Report>)>,
+}
+
+impl Outcomes {
+    pub fn new(strict: bool) -> Outcomes {
+        Outcomes {
+            strict,
+            results: vec![],
+        }
+    }
+
+    /// Records the result for a target. When strict, a failure is returned right away instead.
+    pub fn record(&mut self, target: impl Display, result: Result<()>) -> Result<()> {
+        match result {
+            Err(err) if self.strict => Err(err.wrap_err(format!("Failed on {}", target))),
+            result => {
+                self.results.push((target.to_string(), result.err()));
+                Ok(())
+            }
+        }
+    }
+
+    /// Prints a table of the results if anything failed, failing only if everything did.
+    /// Returns how many succeeded.
+    pub fn finish(self) -> Result<usize> {
+        let failed = self.results.iter().filter(|(_, err)| err.is_some()).count();
+        if failed == 0 {
+            return Ok(self.results.len());
+        }
+        let width = self.results.iter().map(|(t, _)| t.len()).max().unwrap_or(0);
+        for (target, err) in &self.results {
+            match err {
+                Some(err) => {
+                    eprintln!("{:width$}  {} {}", target, paint("failed", Style::Red), err)
+                }
+                None => eprintln!("{:width$}  {}", target, paint("ok", Style::Green)),
+            }
+        }
+        if failed == self.results.len() {
+            return Err(eyre!("All {} failed", failed));
+        }
+        eprintln!(
+            "{} of {} failed. Use --strict to fail when anything does.",
+            failed,
+            self.results.len()
+        );
+        Ok(self.results.len() - failed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Outcomes;
+    use eyre::eyre;
+
+    #[test]
+    fn only_fails_when_everything_failed_unless_strict() {
+        let mut outcomes = Outcomes::new(false);
+        outcomes.record("light/1", Ok(())).unwrap();
+        outcomes
+            .record("light/2", Err(eyre!("unreachable")))
+            .unwrap();
+        assert_eq!(outcomes.finish().unwrap(), 1);
+
+        let mut outcomes = Outcomes::new(false);
+        outcomes
+            .record("light/1", Err(eyre!("unreachable")))
+            .unwrap();
+        assert!(outcomes.finish().is_err());
+
+        let mut outcomes = Outcomes::new(true);
+        let err = outcomes
+            .record("light/2", Err(eyre!("unreachable")))
+            .unwrap_err();
+        assert_eq!(format!("{:#}", err), "Failed on light/2: unreachable");
+    }
+}
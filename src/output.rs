@@ -0,0 +1,90 @@
+use std::env;
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+static COLOR: AtomicBool = AtomicBool::new(false);
+static WIDE: AtomicBool = AtomicBool::new(false);
+
+/// Prints an informational message to stderr, unless `--quiet` is given.
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if !$crate::output::is_quiet() {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// Sets up output from the command line flags and the environment.
+///
+/// Color is used only when writing to a terminal, and never if `NO_COLOR` is set to a non-empty
+/// value, as described at <https://no-color.org/>.
+pub fn init(quiet: bool, no_color: bool, wide: bool) {
+    let no_color = no_color || env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    QUIET.store(quiet, Ordering::Relaxed);
+    WIDE.store(wide, Ordering::Relaxed);
+    COLOR.store(!no_color && io::stdout().is_terminal(), Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Returns how many columns tables may use: the terminal's width, unless `--wide` is given or
+/// stdout isn't a terminal, in which case there is no limit.
+pub fn table_width() -> Option<usize> {
+    if WIDE.load(Ordering::Relaxed) || !io::stdout().is_terminal() {
+        return None;
+    }
+    env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .or_else(terminal_width)
+}
+
+#[cfg(unix)]
+fn terminal_width() -> Option<usize> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    // SAFETY: TIOCGWINSZ only writes to the winsize struct it is given.
+    match unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } {
+        0 if size.ws_col > 0 => Some(size.ws_col.into()),
+        _ => None,
+    }
+}
+
+#[cfg(not(unix))]
+fn terminal_width() -> Option<usize> {
+    None
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Style {
+    Green,
+    Red,
+    Yellow,
+    Dim,
+}
+
+/// Shows a color as a block of that color followed by its hex code, or only the hex code if
+/// color is disabled.
+pub fn swatch((r, g, b): (u8, u8, u8)) -> String {
+    let hex = format!("#{:02x}{:02x}{:02x}", r, g, b);
+    if !COLOR.load(Ordering::Relaxed) {
+        return hex;
+    }
+    format!("\x1b[48;2;{};{};{}m    \x1b[0m {}", r, g, b, hex)
+}
+
+/// Wraps text in the escape codes for the style, if color is enabled.
+pub fn paint(text: &str, style: Style) -> String {
+    if !COLOR.load(Ordering::Relaxed) {
+        return text.to_owned();
+    }
+    let code = match style {
+        Style::Green => "32",
+        Style::Red => "31",
+        Style::Yellow => "33",
+        Style::Dim => "2",
+    };
+    format!("\x1b[{}m{}\x1b[0m", code, text)
+}
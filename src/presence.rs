@@ -0,0 +1,214 @@
+use crate::api::Bridge;
+use crate::config::{Device, Presence};
+use eyre::{eyre, Result};
+use serde_json::{Map, Value};
+use std::fs;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Table of the kernel's neighbor cache, listing the MAC addresses seen on the local network.
+const ARP_TABLE: &str = "/proc/net/arp";
+
+/// Whether anyone came home or everyone left.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    Arrive,
+    Leave,
+}
+
+/// Tracks whether anyone is home from scans of their devices.
+///
+/// Phones put Wi-Fi to sleep and miss scans, so everyone counts as gone only after no device has
+/// been seen for `away_after`.
+#[derive(Debug)]
+pub struct Tracker {
+    away_after: Duration,
+    last_seen: Option<Instant>,
+    home: Option<bool>,
+}
+
+impl Tracker {
+    pub fn new(away_after: Duration) -> Tracker {
+        Tracker {
+            away_after,
+            last_seen: None,
+            home: None,
+        }
+    }
+
+    /// Records a scan, returning the event if anyone came home or everyone left. The first scan
+    /// only records whether anyone is home, as nobody arrived or left when the daemon started.
+    pub fn update(&mut self, anyone_seen: bool, now: Instant) -> Option<Event> {
+        if anyone_seen {
+            self.last_seen = Some(now);
+        }
+        let home = self
+            .last_seen
+            .is_some_and(|seen| now.duration_since(seen) < self.away_after);
+        let event = match self.home {
+            Some(false) if home => Some(Event::Arrive),
+            Some(true) if !home => Some(Event::Leave),
+            _ => None,
+        };
+        self.home = Some(home);
+        event
+    }
+}
+
+/// Checks that each device can be probed. Devices matched by MAC address need an IP address to
+/// ping too, as nothing else keeps their entries in the ARP table fresh, and the ARP table is only
+/// read on Linux.
+pub fn check(presence: &Presence) -> Result<()> {
+    for (name, device) in &presence.devices {
+        match (&device.ip, &device.mac) {
+            (None, None) => return Err(eyre!("Device {:?} needs an ip to ping", name)),
+            (None, Some(_)) => {
+                return Err(eyre!(
+                    "Device {:?} needs an ip to ping, as well as its mac",
+                    name
+                ))
+            }
+            (Some(_), Some(_)) if !cfg!(target_os = "linux") => {
+                return Err(eyre!(
+                    "Device {:?} can't be matched by mac, as the ARP table is only read on \
+                     Linux; give its ip alone",
+                    name
+                ))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Returns whether any of the devices answer on the local network.
+pub fn scan(presence: &Presence) -> Result<bool> {
+    check(presence)?;
+    for device in presence.devices.values() {
+        if is_present(device)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Pings the device's IP address, and if it has a MAC address, looks for it at that IP address in
+/// the ARP table.
+///
+/// Phones often ignore pings while asleep, but still answer the ARP requests the ping triggers.
+fn is_present(device: &Device) -> Result<bool> {
+    let ip = match &device.ip {
+        Some(ip) => ip,
+        None => return Ok(false),
+    };
+    let answered = ping(ip);
+    match &device.mac {
+        Some(mac) => {
+            let table = fs::read_to_string(ARP_TABLE)
+                .map_err(|err| eyre!("Failed to read {}: {}", ARP_TABLE, err))?;
+            Ok(in_arp_table(&table, ip, mac))
+        }
+        None => Ok(answered),
+    }
+}
+
+fn ping(ip: &str) -> bool {
+    Command::new("ping")
+        .args(["-c", "1", "-W", "1", ip])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Returns whether the ARP table has a complete entry for the MAC address at the IP address.
+fn in_arp_table(table: &str, ip: &str, mac: &str) -> bool {
+    // Lines are like `192.168.1.23  0x1  0x2  aa:bb:cc:dd:ee:ff  *  wlan0`, after a header.
+    table.lines().skip(1).any(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields[..] {
+            [at, _, flags, address, ..] => {
+                at == ip && flags == "0x2" && address.eq_ignore_ascii_case(mac)
+            }
+            _ => false,
+        }
+    })
+}
+
+/// Returns whether the bridge's daylight sensor says it is after sunset, or `None` if the bridge
+/// has no configured daylight sensor.
+pub fn is_dark(bridge: &Bridge) -> Result<Option<bool>> {
+    let sensors: Map<String, Value> = bridge.get("sensors")?;
+    Ok(sensors
+        .values()
+        .filter(|sensor| sensor["type"] == "Daylight")
+        .find_map(|sensor| sensor["state"]["daylight"].as_bool())
+        .map(|daylight| !daylight))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracker_waits_before_leaving() {
+        let start = Instant::now();
+        let mut tracker = Tracker::new(Duration::from_secs(600));
+        assert_eq!(tracker.update(true, start), None);
+        assert_eq!(
+            tracker.update(false, start + Duration::from_secs(300)),
+            None
+        );
+        assert_eq!(
+            tracker.update(false, start + Duration::from_secs(600)),
+            Some(Event::Leave)
+        );
+        assert_eq!(
+            tracker.update(false, start + Duration::from_secs(900)),
+            None
+        );
+        assert_eq!(
+            tracker.update(true, start + Duration::from_secs(1200)),
+            Some(Event::Arrive)
+        );
+    }
+
+    #[test]
+    fn tracker_starts_without_event() {
+        let mut tracker = Tracker::new(Duration::from_secs(600));
+        assert_eq!(tracker.update(false, Instant::now()), None);
+    }
+
+    #[test]
+    fn arp_table_needs_complete_entry() {
+        let table = "\
+IP address       HW type     Flags       HW address            Mask     Device
+192.168.1.23     0x1         0x2         aa:bb:cc:dd:ee:ff     *        wlan0
+192.168.1.24     0x1         0x0         00:00:00:00:00:00     *        wlan0
+";
+        assert!(in_arp_table(table, "192.168.1.23", "AA:BB:CC:DD:EE:FF"));
+        assert!(!in_arp_table(table, "192.168.1.24", "aa:bb:cc:dd:ee:ff"));
+        assert!(!in_arp_table(table, "192.168.1.24", "00:00:00:00:00:00"));
+        assert!(!in_arp_table(table, "192.168.1.23", "11:22:33:44:55:66"));
+    }
+
+    #[test]
+    fn devices_matched_by_mac_need_an_ip() {
+        let device = |ip: Option<&str>, mac: Option<&str>| Device {
+            ip: ip.map(str::to_owned),
+            mac: mac.map(str::to_owned),
+        };
+        let presence = |device| Presence {
+            devices: std::iter::once(("phone".to_owned(), device)).collect(),
+            ..Presence::default()
+        };
+        assert!(check(&presence(device(Some("192.168.1.23"), None))).is_ok());
+        assert!(check(&presence(device(None, Some("aa:bb:cc:dd:ee:ff")))).is_err());
+        assert!(check(&presence(device(None, None))).is_err());
+        let both = check(&presence(device(
+            Some("192.168.1.23"),
+            Some("aa:bb:cc:dd:ee:ff"),
+        )));
+        assert_eq!(both.is_ok(), cfg!(target_os = "linux"));
+    }
+}
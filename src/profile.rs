@@ -0,0 +1,164 @@
+use crate::backend::{Datastore, LightBackend, Members};
+use crate::config::{BriCurve, Config, ModelProfile};
+use crate::dial::GAMMA;
+use crate::values::parse_brightness;
+use eyre::{eyre, Result};
+use hueclient::{CommandLight, IdentifiedGroup, IdentifiedLight, IdentifiedScene};
+use std::collections::HashMap;
+
+/// A backend that tunes commands to the models of the lights, with the profiles in `[model]`.
+///
+/// Commands to groups with tuned lights that change the brightness or color temperature are sent
+/// to each light instead, as the lights need different values.
+pub struct Tuned<'a> {
+    inner: &'a dyn LightBackend,
+    /// Profiles of the lights that have one.
+    lights: HashMap<usize, Profile>,
+    members: &'a Members,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Profile {
+    curve: BriCurve,
+    min_bri: u8,
+    ct: (u16, u16),
+}
+
+impl<'a> Tuned<'a> {
+    pub fn new(
+        inner: &'a dyn LightBackend,
+        config: &Config,
+        datastore: &Datastore,
+        members: &'a Members,
+    ) -> Result<Tuned<'a>> {
+        let mut tuned = Tuned {
+            inner,
+            lights: HashMap::new(),
+            members,
+        };
+        let profiles = config
+            .model
+            .iter()
+            .map(|(model, profile)| {
+                Profile::new(profile)
+                    .map(|profile| (model.as_str(), profile))
+                    .map_err(|err| eyre!("Invalid profile for model {:?}: {}", model, err))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+        for il in &datastore.lights {
+            if let Some(profile) = profiles.get(il.light.modelid.as_str()) {
+                tuned.lights.insert(il.id, *profile);
+            }
+        }
+        Ok(tuned)
+    }
+}
+
+impl Profile {
+    fn new(profile: &ModelProfile) -> Result<Profile> {
+        let min_bri = match &profile.min_bri {
+            Some(bri) => parse_brightness(bri).map_err(|err| eyre!(err))?,
+            None => 1,
+        };
+        let ct = (
+            profile.min_ct.unwrap_or(0),
+            profile.max_ct.unwrap_or(u16::MAX),
+        );
+        if ct.0 > ct.1 {
+            return Err(eyre!("min_ct is above max_ct"));
+        }
+        Ok(Profile {
+            curve: profile.bri_curve,
+            min_bri,
+            ct,
+        })
+    }
+
+    fn tune(&self, command: &CommandLight) -> CommandLight {
+        let mut command = command.clone();
+        if let Some(bri) = command.bri {
+            command.bri = Some(curve(bri, self.curve).max(self.min_bri));
+        }
+        if let Some(ct) = command.ct {
+            command.ct = Some(ct.clamp(self.ct.0, self.ct.1));
+        }
+        command
+    }
+}
+
+/// Turns a brightness value into the one giving the same light on a model with the curve.
+fn curve(bri: u8, curve: BriCurve) -> u8 {
+    let exponent = match curve {
+        BriCurve::Linear => return bri,
+        BriCurve::Ies => 2.0,
+        BriCurve::Gamma => GAMMA,
+    };
+    ((bri as f64 / 254.0).powf(exponent) * 254.0)
+        .round()
+        .clamp(1.0, 254.0) as u8
+}
+
+impl LightBackend for Tuned<'_> {
+    fn describe(&self) -> String {
+        self.inner.describe()
+    }
+
+    fn get_all_lights(&self) -> Result<Vec<IdentifiedLight>> {
+        self.inner.get_all_lights()
+    }
+
+    fn get_all_groups(&self) -> Result<Vec<IdentifiedGroup>> {
+        self.inner.get_all_groups()
+    }
+
+    fn get_all_scenes(&self) -> Result<Vec<IdentifiedScene>> {
+        self.inner.get_all_scenes()
+    }
+
+    fn set_light_state(&self, light: usize, command: &CommandLight) -> Result<()> {
+        match self.lights.get(&light) {
+            Some(profile) => self.inner.set_light_state(light, &profile.tune(command)),
+            None => self.inner.set_light_state(light, command),
+        }
+    }
+
+    fn set_group_state(&self, group: usize, command: &CommandLight) -> Result<()> {
+        let members = self.members.of(group);
+        let tuned = members.iter().any(|id| self.lights.contains_key(id));
+        if !tuned || (command.bri.is_none() && command.ct.is_none()) {
+            return self.inner.set_group_state(group, command);
+        }
+        for &light in members {
+            self.set_light_state(light, command)?;
+        }
+        Ok(())
+    }
+
+    fn get_datastore(&self) -> Result<Datastore> {
+        self.inner.get_datastore()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{curve, Profile};
+    use crate::config::BriCurve;
+    use hueclient::CommandLight;
+
+    #[test]
+    fn commands_are_tuned_to_the_model() {
+        assert_eq!(curve(127, BriCurve::Linear), 127);
+        assert_eq!(curve(127, BriCurve::Ies), 64);
+        assert_eq!(curve(127, BriCurve::Gamma), 55);
+        assert_eq!(curve(254, BriCurve::Ies), 254);
+
+        let profile = Profile {
+            curve: BriCurve::Ies,
+            min_bri: 13,
+            ct: (153, 454),
+        };
+        let command = profile.tune(&CommandLight::default().with_bri(20).with_ct(500));
+        assert_eq!(command.bri, Some(13));
+        assert_eq!(command.ct, Some(454));
+    }
+}
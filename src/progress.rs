@@ -0,0 +1,127 @@
+use crate::output;
+use crate::time::format_duration;
+use std::cell::Cell;
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+
+/// Width of the bar in characters.
+const BAR_WIDTH: usize = 20;
+
+/// Stops progress from being shown, for commands that run several operations at once, like
+/// `blilys daemon`.
+pub fn disable() {
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+/// Progress of a long operation on stderr, as a bar with the time left when the length is known,
+/// and as a spinner otherwise. Only shown when stderr is a terminal and `--quiet` isn't given, and
+/// cleared when dropped.
+pub struct Progress {
+    message: String,
+    length: Option<Duration>,
+    start: Instant,
+    shown: bool,
+    frame: Cell<usize>,
+}
+
+impl Progress {
+    pub fn new(message: impl Into<String>, length: Option<Duration>) -> Progress {
+        Progress {
+            message: message.into(),
+            length,
+            start: Instant::now(),
+            shown: ENABLED.load(Ordering::Relaxed)
+                && !output::is_quiet()
+                && io::stderr().is_terminal(),
+            frame: Cell::new(0),
+        }
+    }
+
+    /// Redraws the progress with the current value, like "bri 120".
+    pub fn update(&self, value: &str) {
+        if !self.shown {
+            return;
+        }
+        let frame = self.frame.get();
+        self.frame.set(frame + 1);
+        let line = render(
+            &self.message,
+            self.length,
+            self.start.elapsed(),
+            frame,
+            value,
+        );
+        let mut stderr = io::stderr().lock();
+        let _ = write!(stderr, "\r\x1b[2K{}", line);
+        let _ = stderr.flush();
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if self.shown && self.frame.get() > 0 {
+            eprint!("\r\x1b[2K");
+        }
+    }
+}
+
+fn render(
+    message: &str,
+    length: Option<Duration>,
+    elapsed: Duration,
+    frame: usize,
+    value: &str,
+) -> String {
+    let whole_seconds =
+        |duration: Duration| format_duration(Duration::from_secs(duration.as_secs()));
+    let line = match length {
+        Some(length) => {
+            let done = (elapsed.as_secs_f64() / length.as_secs_f64().max(0.001)).min(1.0);
+            let filled = (done * BAR_WIDTH as f64).round() as usize;
+            format!(
+                "{} [{}{}] {:3.0}% {} left",
+                message,
+                "█".repeat(filled),
+                "░".repeat(BAR_WIDTH - filled),
+                done * 100.0,
+                whole_seconds(length.saturating_sub(elapsed)),
+            )
+        }
+        None => format!(
+            "{} {} {}",
+            SPINNER[frame % SPINNER.len()],
+            message,
+            whole_seconds(elapsed)
+        ),
+    };
+    if value.is_empty() {
+        line
+    } else {
+        format!("{}  {}", line, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render;
+    use std::time::Duration;
+
+    #[test]
+    fn progress_shows_time_left_or_spins() {
+        let secs = Duration::from_secs;
+        assert_eq!(
+            render("breathe", Some(secs(60)), secs(15), 0, "bri 120"),
+            "breathe [█████░░░░░░░░░░░░░░░]  25% 45s left  bri 120"
+        );
+        assert_eq!(
+            render("breathe", Some(secs(60)), secs(90), 3, ""),
+            "breathe [████████████████████] 100% 0s left"
+        );
+        assert_eq!(render("waiting", None, secs(61), 1, ""), "/ waiting 1m1s");
+    }
+}
@@ -0,0 +1,131 @@
+use crate::metrics;
+use std::cell::Cell;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::{Condvar, Mutex};
+
+/// How urgent requests to the bridge are, for the queue to let the most urgent go first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Polling for changes, like `export --watch` and `history record`.
+    Background,
+    /// Steps of a running mode, which keeps sending requests until stopped.
+    Effect,
+    /// Commands from the user, who is waiting for the lights to change.
+    Interactive,
+}
+
+thread_local! {
+    static PRIORITY: Cell<Priority> = const { Cell::new(Priority::Interactive) };
+}
+
+/// Runs `f` with the requests it makes on this thread queued at `priority`.
+pub fn with_priority<T>(priority: Priority, f: impl FnOnce() -> T) -> T {
+    let previous = PRIORITY.with(|current| current.replace(priority));
+    let result = f();
+    PRIORITY.with(|current| current.set(previous));
+    result
+}
+
+/// Lets a limited number of requests to the bridge run at once, in order of priority and then
+/// arrival, shared by every thread using the bridge.
+pub struct Queue {
+    limit: usize,
+    state: Mutex<State>,
+    turn: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    running: usize,
+    /// Requests waiting for their turn, the most urgent and then the earliest on top.
+    waiting: BinaryHeap<(Priority, Reverse<u64>)>,
+    next_ticket: u64,
+}
+
+/// A turn to send a request, given back to the queue when dropped.
+pub struct Turn<'a> {
+    queue: &'a Queue,
+}
+
+impl Queue {
+    /// Creates a queue letting `limit` requests run at once, but at least one.
+    pub fn new(limit: usize) -> Queue {
+        Queue {
+            limit: limit.max(1),
+            state: Mutex::new(State::default()),
+            turn: Condvar::new(),
+        }
+    }
+
+    /// Waits until it is this thread's turn to send a request, at the priority set with
+    /// `with_priority`.
+    pub fn wait(&self) -> Turn<'_> {
+        let priority = PRIORITY.with(Cell::get);
+        let mut state = self.state.lock().expect("Request queue lock poisoned");
+        let ticket = (priority, Reverse(state.next_ticket));
+        state.next_ticket += 1;
+        state.waiting.push(ticket);
+        metrics::queued(true);
+        while state.running >= self.limit || state.waiting.peek() != Some(&ticket) {
+            state = self.turn.wait(state).expect("Request queue lock poisoned");
+        }
+        state.waiting.pop();
+        metrics::queued(false);
+        state.running += 1;
+        // The next in line may have a turn too if more than one request may run.
+        self.turn.notify_all();
+        Turn { queue: self }
+    }
+}
+
+impl Drop for Turn<'_> {
+    fn drop(&mut self) {
+        let mut state = self
+            .queue
+            .state
+            .lock()
+            .expect("Request queue lock poisoned");
+        state.running -= 1;
+        self.queue.turn.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{with_priority, Priority, Queue};
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn urgent_requests_go_first() {
+        let queue = Queue::new(1);
+        let order = Mutex::new(vec![]);
+        thread::scope(|scope| {
+            let first = queue.wait();
+            for (i, priority) in [
+                Priority::Background,
+                Priority::Effect,
+                Priority::Background,
+                Priority::Interactive,
+            ]
+            .iter()
+            .copied()
+            .enumerate()
+            {
+                let (queue, order) = (&queue, &order);
+                scope.spawn(move || {
+                    with_priority(priority, || {
+                        let _turn = queue.wait();
+                        order.lock().unwrap().push(i);
+                    })
+                });
+                // Let each thread get in line before the next.
+                thread::sleep(Duration::from_millis(50));
+            }
+            drop(first);
+        });
+        assert_eq!(order.into_inner().unwrap(), vec![3, 1, 0, 2]);
+    }
+}
@@ -0,0 +1,166 @@
+use crate::config::Config;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+
+/// Parts of the config used by threads the daemon starts once, which only see changes when it
+/// restarts.
+const ON_RESTART: [&str; 7] = [
+    "daemon.effects",
+    "dials",
+    "buttons",
+    "adaptive",
+    "sinks",
+    "history",
+    "presence.away_after",
+];
+
+/// The config of a long-running command, read again from its file when the file changes.
+pub struct Live {
+    path: Option<PathBuf>,
+    modified: Mutex<Option<SystemTime>>,
+    config: RwLock<Arc<Config>>,
+}
+
+impl Live {
+    pub fn new(config: Config) -> Live {
+        let path = config.path.clone();
+        Live {
+            modified: Mutex::new(path.as_deref().and_then(modified)),
+            path,
+            config: RwLock::new(Arc::new(config)),
+        }
+    }
+
+    /// Returns the current config, which stays the same for as long as it is held.
+    pub fn get(&self) -> Arc<Config> {
+        self.config.read().expect("Config lock poisoned").clone()
+    }
+
+    /// Reads the config again if its file changed since last time, logging what changed and
+    /// returning whether anything did. An invalid config is reported and ignored, keeping the
+    /// current one until the file is fixed.
+    pub fn check(&self) -> bool {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return false,
+        };
+        let modified = modified(path);
+        {
+            let mut last = self.modified.lock().expect("Config lock poisoned");
+            if modified == *last {
+                return false;
+            }
+            *last = modified;
+        }
+        let mut config = match Config::read_file(path) {
+            Ok(config) => config,
+            Err(err) => {
+                eprintln!(
+                    "Failed to reload the config, keeping the old one: {:#}",
+                    err
+                );
+                return false;
+            }
+        };
+        let old = self.get();
+        // The connection to the bridge is set up once, so keep what it was set up with.
+        config.bridge = old.bridge.clone();
+
+        let changes = diff(
+            &serde_json::to_value(&*old).unwrap_or_default(),
+            &serde_json::to_value(&config).unwrap_or_default(),
+        );
+        if changes.is_empty() {
+            return false;
+        }
+        info!("Reloaded the config: {}.", changes.join(", "));
+        let sections = ON_RESTART.iter().filter(|section| {
+            changes.iter().any(|change| {
+                change
+                    .strip_prefix(*section)
+                    .is_some_and(|rest| rest.starts_with(['.', ' ']))
+            })
+        });
+        for section in sections {
+            info!(
+                "Changes to {} take effect when the daemon restarts.",
+                section
+            );
+        }
+        *self.config.write().expect("Config lock poisoned") = Arc::new(config);
+        true
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Describes the differences between two configs, like "aliases.desk added", down to the
+/// entries of each section.
+fn diff(old: &Value, new: &Value) -> Vec<String> {
+    let mut changes = vec![];
+    diff_into(&mut changes, "", old, new, 2);
+    changes
+}
+
+fn diff_into(changes: &mut Vec<String>, path: &str, old: &Value, new: &Value, depth: usize) {
+    let (old, new) = match (old, new) {
+        (Value::Object(old), Value::Object(new)) if depth > 0 => (old, new),
+        (old, new) => {
+            if old != new {
+                changes.push(format!("{} changed", path));
+            }
+            return;
+        }
+    };
+    let keys: BTreeSet<_> = old.keys().chain(new.keys()).collect();
+    for key in keys {
+        let path = match path {
+            "" => key.to_owned(),
+            path => format!("{}.{}", path, key),
+        };
+        match (old.get(key), new.get(key)) {
+            (Some(old), Some(new)) => diff_into(changes, &path, old, new, depth - 1),
+            (None, Some(_)) => changes.push(format!("{} added", path)),
+            (Some(_), None) => changes.push(format!("{} removed", path)),
+            (None, None) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::diff;
+    use serde_json::json;
+
+    #[test]
+    fn config_changes_are_described_by_entry() {
+        let old = json!({
+            "aliases": {"desk": "light:1", "hall": "light:3"},
+            "calendar": {"rules": [1], "interval": 60},
+            "language": "nb",
+        });
+        let new = json!({
+            "aliases": {"desk": "light:2", "kitchen": "light:2"},
+            "calendar": {"rules": [1, 2], "interval": 60},
+            "caps": {"all": "50%"},
+        });
+        assert_eq!(
+            diff(&old, &new),
+            [
+                "aliases.desk changed",
+                "aliases.hall removed",
+                "aliases.kitchen added",
+                "calendar.rules changed",
+                "caps added",
+                "language removed",
+            ]
+        );
+        assert!(diff(&old, &old).is_empty());
+    }
+}
@@ -0,0 +1,164 @@
+use crate::api::Bridge;
+use crate::config::{self, create_private};
+use crate::time::now;
+use directories::ProjectDirs;
+use eyre::{eyre, Result};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+const BASE_URL: &str = "https://api.meethue.com";
+
+/// Tokens from the Hue Remote API's OAuth2 flow, and the username whitelisted on the bridge
+/// through it.
+#[derive(Debug, Serialize, Deserialize)]
+struct Tokens {
+    access_token: String,
+    refresh_token: String,
+    /// Seconds since the Unix epoch when the access token expires.
+    expires_at: u64,
+    username: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: String,
+    expires_in: u64,
+}
+
+impl Tokens {
+    fn load() -> Result<Option<Tokens>> {
+        let path = Tokens::get_path()?;
+        if !path.is_file() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&fs::read_to_string(path)?)?))
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = Tokens::get_path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        create_private(&path)?.write_all(serde_json::to_string(self)?.as_bytes())?;
+        Ok(())
+    }
+
+    fn get_path() -> Result<PathBuf> {
+        let project_dirs = ProjectDirs::from("", "", "blilys").expect("Data dir not readable");
+        Ok(project_dirs.data_dir().join("remote-tokens.json"))
+    }
+}
+
+fn credentials(settings: &config::Remote) -> Result<(&str, &str)> {
+    match (&settings.client_id, &settings.client_secret) {
+        (Some(id), Some(secret)) => Ok((id, secret)),
+        _ => Err(eyre!(
+            "Set remote.client_id and remote.client_secret in the config to the credentials of \
+             an app registered at https://developers.meethue.com/"
+        )),
+    }
+}
+
+fn request_token(settings: &config::Remote, form: &[(&str, &str)]) -> Result<TokenResponse> {
+    let (client_id, client_secret) = credentials(settings)?;
+    Ok(reqwest::blocking::Client::new()
+        .post(&format!("{}/v2/oauth2/token", BASE_URL))
+        .basic_auth(client_id, Some(client_secret))
+        .form(form)
+        .send()?
+        .error_for_status()?
+        .json()?)
+}
+
+/// Authorizes blilys with the Hue Remote API and whitelists a username on the bridge.
+pub fn login(settings: &config::Remote, devicetype: &str) -> Result<()> {
+    let (client_id, _) = credentials(settings)?;
+    let state: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect();
+
+    eprintln!("Open this URL in a browser and grant blilys access to your bridge:");
+    println!(
+        "{}/v2/oauth2/authorize?client_id={}&response_type=code&state={}",
+        BASE_URL, client_id, state
+    );
+    eprintln!("Then paste the URL you were redirected to, or just its code parameter:");
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    let input = input.trim();
+    let code = match input.split_once('?') {
+        Some((_, query)) => {
+            let param = |name: &str| {
+                query
+                    .split('&')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| value.to_owned())
+            };
+            if param("state").as_deref() != Some(state.as_str()) {
+                return Err(eyre!("The redirect URL's state doesn't match the request"));
+            }
+            param("code").ok_or_else(|| eyre!("The redirect URL has no code parameter"))?
+        }
+        None => input.to_owned(),
+    };
+
+    let token = request_token(
+        settings,
+        &[("grant_type", "authorization_code"), ("code", &code)],
+    )?;
+
+    info!("Registering user on the bridge ...");
+    let bridge = Bridge::remote(BASE_URL, &token.access_token);
+    let bridge = bridge.with_user("0");
+    let _: serde_json::Value = bridge.put("config", &serde_json::json!({ "linkbutton": true }))?;
+    let username = bridge.register_user(devicetype)?.username;
+
+    Tokens {
+        access_token: token.access_token,
+        refresh_token: token.refresh_token,
+        expires_at: now() + token.expires_in,
+        username,
+    }
+    .save()?;
+    info!("Remote access is set up. Use --remote to control lights through the cloud.");
+    Ok(())
+}
+
+pub fn logout() -> Result<()> {
+    let path = Tokens::get_path()?;
+    if path.is_file() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Connects to the bridge through the Hue Remote API, refreshing the access token if needed.
+pub fn connect(settings: &config::Remote) -> Result<Bridge> {
+    let mut tokens = Tokens::load()?
+        .ok_or_else(|| eyre!("Remote access is not set up. Run `blilys remote login` first."))?;
+
+    // Refresh a minute early, so the token doesn't expire mid-command.
+    if tokens.expires_at <= now() + 60 {
+        let token = request_token(
+            settings,
+            &[
+                ("grant_type", "refresh_token"),
+                ("refresh_token", &tokens.refresh_token),
+            ],
+        )?;
+        tokens.access_token = token.access_token;
+        tokens.refresh_token = token.refresh_token;
+        tokens.expires_at = now() + token.expires_in;
+        tokens.save()?;
+    }
+
+    Ok(Bridge::remote(BASE_URL, &tokens.access_token).with_user(tokens.username))
+}
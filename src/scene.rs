@@ -0,0 +1,297 @@
+use crate::api::Bridge;
+use crate::backend::LightBackend;
+use crate::color::{self, Gamut};
+use crate::commands;
+use crate::output::{self, Style};
+use crate::table::{Align, Cell, Table};
+use crate::targets;
+use crate::time::TimeOfDay;
+use eyre::{eyre, Result, WrapErr};
+use hueclient::{CommandLight, IdentifiedLight};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// A scene in a file, with its lights by name so that it can be imported on another bridge.
+#[derive(Debug, Serialize, Deserialize)]
+struct PortableScene {
+    name: String,
+    lights: Vec<PortableLight>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PortableLight {
+    name: String,
+    model: String,
+    /// The state the scene sets the light to, as the bridge has it.
+    state: Value,
+}
+
+/// Sets the lights to the scene with the given ID.
+pub fn recall(backend: &dyn LightBackend, scene: &str) -> Result<()> {
+    let command = CommandLight {
+        scene: Some(scene.to_owned()),
+        ..CommandLight::default()
+    };
+    // Group 0 lets the bridge recall scenes of any lights.
+    backend.set_group_state(0, &command)
+}
+
+/// Sets the lights to a random scene, picking among those of lights in `group` and with names
+/// matching `pattern` if given, and returns the name of the scene. Scenes the bridge may remove
+/// on its own, like those made by apps while editing, are left out.
+pub fn random(
+    backend: &dyn LightBackend,
+    group: Option<usize>,
+    pattern: Option<&str>,
+) -> Result<String> {
+    let datastore = backend.get_datastore()?;
+    let members = match group {
+        Some(id) => {
+            let ig = datastore.groups.iter().find(|ig| ig.id == id);
+            Some(
+                ig.ok_or_else(|| eyre!("No group {}", id))?
+                    .group
+                    .lights
+                    .clone(),
+            )
+        }
+        None => None,
+    };
+    let scenes: Vec<_> = datastore
+        .scenes
+        .iter()
+        .filter(|is| !is.scene.recycle && !is.scene.lights.is_empty())
+        .filter(|is| {
+            members
+                .as_ref()
+                .is_none_or(|members| is.scene.lights.iter().all(|light| members.contains(light)))
+        })
+        .filter(|is| pattern.is_none_or(|pattern| targets::matches(pattern, &is.scene.name)))
+        .collect();
+    let picked = scenes
+        .choose(&mut rand::thread_rng())
+        .ok_or_else(|| eyre!("No scenes to pick from"))?;
+    recall(backend, &picked.id)?;
+    Ok(picked.scene.name.clone())
+}
+
+/// Bar of the brightness in 10 steps, like `██████░░░░  60%`.
+fn brightness_bar(bri: u8) -> String {
+    let percent = (u32::from(bri) * 100 + 127) / 254;
+    let filled = ((percent + 5) / 10) as usize;
+    format!(
+        "{}{} {:>3}%",
+        "█".repeat(filled),
+        "░".repeat(10 - filled),
+        percent
+    )
+}
+
+/// The color a light in a scene is set to, preferring the color mode the bridge would: xy, then
+/// color temperature, then hue and saturation. xy colors are shown as the light's gamut allows.
+fn color(state: &Value, gamut: Gamut) -> Option<(u8, u8, u8)> {
+    let number = |key: &str| state[key].as_f64();
+    if let (Some(x), Some(y)) = (state["xy"][0].as_f64(), state["xy"][1].as_f64()) {
+        let (x, y) = gamut.clamp((x as f32, y as f32));
+        return Some(color::xy_to_rgb(x, y));
+    }
+    if let Some(ct) = number("ct") {
+        return Some(color::mired_to_rgb(ct as u16));
+    }
+    match (number("hue"), number("sat")) {
+        (Some(hue), Some(sat)) => Some(color::hue_sat_to_rgb(hue as u16, sat as u8)),
+        (Some(hue), None) => Some(color::hue_sat_to_rgb(hue as u16, 254)),
+        _ => None,
+    }
+}
+
+/// Lists each light in the scene with the color and brightness it is set to, without recalling
+/// the scene.
+pub fn show(bridge: &Bridge, scene: &str) -> Result<()> {
+    let scene: Value = bridge.get(&format!("scenes/{}", scene))?;
+    let lights: HashMap<String, (String, Gamut)> = bridge
+        .get_all_lights()?
+        .into_iter()
+        .map(|il| {
+            let gamut = Gamut::of_model(&il.light.modelid);
+            (il.id.to_string(), (il.light.name, gamut))
+        })
+        .collect();
+    let lightstates = scene["lightstates"]
+        .as_object()
+        .ok_or_else(|| eyre!("The bridge has no light states for the scene"))?;
+    let mut states: Vec<(&String, &Value)> = lightstates.iter().collect();
+    states.sort_by_key(|(id, _)| id.parse::<usize>().unwrap_or(usize::MAX));
+
+    let mut table = Table::new(&[Align::Right, Align::Left, Align::Left, Align::Left]).shrinking(1);
+    for (id, state) in states {
+        let (name, gamut) = match lights.get(id) {
+            Some((name, gamut)) => (name.as_str(), *gamut),
+            None => ("", color::GAMUT_C),
+        };
+        let mut row: Vec<Cell> = vec![format!("{}:", id).into(), name.into()];
+        if state["on"] == json!(false) {
+            row.push(Cell::styled(t!("list-off"), Style::Dim));
+        } else {
+            let bri = state["bri"].as_u64().unwrap_or(254).min(254) as u8;
+            row.push(brightness_bar(bri).into());
+            // Swatches have escape codes, so they go last, where they aren't padded.
+            row.push(
+                color(state, gamut)
+                    .map(output::swatch)
+                    .unwrap_or_default()
+                    .into(),
+            );
+        }
+        table.row(row);
+    }
+    table.print();
+    Ok(())
+}
+
+/// Prints the scene with the given ID as JSON, with its lights by name.
+pub fn export(bridge: &Bridge, scene: &str) -> Result<()> {
+    let scene: Value = bridge.get(&format!("scenes/{}", scene))?;
+    let lightstates = scene["lightstates"]
+        .as_object()
+        .ok_or_else(|| eyre!("The bridge has no light states for the scene"))?;
+    let lights: HashMap<String, IdentifiedLight> = bridge
+        .get_all_lights()?
+        .into_iter()
+        .map(|il| (il.id.to_string(), il))
+        .collect();
+    let mut ids: Vec<&String> = lightstates.keys().collect();
+    ids.sort_by_key(|id| id.parse::<usize>().unwrap_or(usize::MAX));
+    let portable = PortableScene {
+        name: scene["name"].as_str().unwrap_or_default().to_owned(),
+        lights: ids
+            .into_iter()
+            .filter_map(|id| {
+                let il = lights.get(id)?;
+                Some(PortableLight {
+                    name: il.light.name.to_owned(),
+                    model: il.light.modelid.to_owned(),
+                    state: lightstates[id].clone(),
+                })
+            })
+            .collect(),
+    };
+    println!("{}", serde_json::to_string_pretty(&portable)?);
+    Ok(())
+}
+
+/// Creates a scene named `name` from a file made by `export`, matching its lights to the
+/// bridge's by name, or by asking for each light if `remap`. Returns the ID of the scene.
+pub fn import(bridge: &Bridge, name: &str, file: &Path, remap: bool) -> Result<String> {
+    let portable: PortableScene = serde_json::from_str(
+        &fs::read_to_string(file).wrap_err_with(|| format!("Failed to read {}", file.display()))?,
+    )
+    .wrap_err_with(|| format!("Invalid scene file {}", file.display()))?;
+    let lights = bridge.get_all_lights()?;
+    if remap {
+        eprintln!("Lights on the bridge:");
+        for il in &lights {
+            eprintln!("{:>3}: {}", il.id, il.light.name);
+        }
+    }
+
+    let mut lightstates = Map::new();
+    for light in &portable.lights {
+        let matched = find_light(&lights, &light.name);
+        let id = if remap {
+            ask_for_light(&lights, light, matched)?
+        } else {
+            Some(matched.ok_or_else(|| {
+                eyre!(
+                    "No light named {:?} on the bridge. Use --remap to pick the lights.",
+                    light.name
+                )
+            })?)
+        };
+        if let Some(id) = id {
+            lightstates.insert(id.to_string(), light.state.clone());
+        }
+    }
+    if lightstates.is_empty() {
+        return Err(eyre!("The scene has no lights on this bridge"));
+    }
+
+    let scene = json!({
+        // The bridge allows at most 32 characters.
+        "name": name.chars().take(32).collect::<String>(),
+        "type": "LightScene",
+        "lights": lightstates.keys().collect::<Vec<_>>(),
+        "recycle": false,
+        "lightstates": lightstates,
+    });
+    let response: Value = bridge.post("scenes", &scene)?;
+    Ok(response[0]["success"]["id"]
+        .as_str()
+        .unwrap_or_default()
+        .to_owned())
+}
+
+fn find_light(lights: &[IdentifiedLight], name: &str) -> Option<usize> {
+    lights
+        .iter()
+        .find(|il| il.id.to_string() == name || il.light.name.eq_ignore_ascii_case(name))
+        .map(|il| il.id)
+}
+
+/// Asks which light on the bridge to use for a light in the file, defaulting to the one with the
+/// same name. Returns `None` to leave the light out of the scene.
+fn ask_for_light(
+    lights: &[IdentifiedLight],
+    light: &PortableLight,
+    matched: Option<usize>,
+) -> Result<Option<usize>> {
+    let default = match matched {
+        Some(id) => format!("press Enter for {}", id),
+        None => "press Enter to leave it out".to_owned(),
+    };
+    loop {
+        let question = format!(
+            "Light for {:?} ({}), by ID or name, - to leave it out, or {}: ",
+            light.name, light.model, default
+        );
+        match commands::prompt(&question)?.as_deref() {
+            None => return Ok(matched),
+            Some("-") => return Ok(None),
+            Some(answer) => match find_light(lights, answer) {
+                Some(id) => return Ok(Some(id)),
+                None => eprintln!("No light {:?} on the bridge.", answer),
+            },
+        }
+    }
+}
+
+/// Creates a schedule on the bridge recalling the scene at a time on the given days, as a bitmask
+/// with Monday as 64 and Sunday as 1. Returns the ID of the schedule.
+pub fn schedule(
+    bridge: &Bridge,
+    scene: &str,
+    name: &str,
+    at: TimeOfDay,
+    days: u8,
+) -> Result<String> {
+    let schedule = json!({
+        // The bridge allows at most 32 characters.
+        "name": format!("{} at {}", name, at).chars().take(32).collect::<String>(),
+        "command": {
+            "address": format!("/api/{}/groups/0/action", bridge.username),
+            "method": "PUT",
+            "body": {"scene": scene},
+        },
+        "localtime": format!("W{}/T{}", days, at),
+        "status": "enabled",
+    });
+    let response: Value = bridge.post("schedules", &schedule)?;
+    Ok(response[0]["success"]["id"]
+        .as_str()
+        .unwrap_or_default()
+        .to_owned())
+}
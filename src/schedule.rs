@@ -0,0 +1,371 @@
+use crate::config::Schedule;
+use crate::holidays::Holidays;
+use crate::time::{
+    epoch_from_local, format_duration, local_date, next_day, parse_duration, parse_time_of_day,
+    parse_weekdays, weekday_bit, Date, TimeOfDay,
+};
+use crate::zone::Zone;
+use directories::ProjectDirs;
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// How late a job may run and still be on time, as the daemon only checks every few seconds.
+const ON_TIME: Duration = Duration::from_secs(60);
+
+/// How far back to look for the last time a job was due, enough for it to have been due on any
+/// day of the week.
+const LOOK_BACK: Duration = Duration::from_secs(8 * 24 * 60 * 60);
+
+/// A schedule from `[[schedules]]`, ready to run.
+#[derive(Debug)]
+pub struct Job {
+    pub command: String,
+    at: TimeOfDay,
+    /// Time zone of `at`, or `None` for the system's.
+    zone: Option<Zone>,
+    /// Days of the week in the bitmask of `parse_weekdays`.
+    days: u8,
+    jitter: Duration,
+    skip_holidays: bool,
+    missed: Missed,
+}
+
+/// What to do with a job that was due while the daemon wasn't running or the computer slept.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Missed {
+    Skip,
+    RunOnceLate,
+    RunIfWithin(Duration),
+}
+
+/// When the daemon last checked for due jobs, saved so that jobs due while it wasn't running
+/// count as missed.
+#[derive(Debug, Serialize, Deserialize)]
+struct State {
+    /// Seconds since the Unix epoch.
+    last_check: u64,
+}
+
+/// Parses the schedules in the config, with `holidays` from the config.
+pub fn jobs(schedules: &[Schedule], holidays: Option<&str>) -> Result<Vec<Job>> {
+    schedules
+        .iter()
+        .map(|schedule| {
+            let job = if schedule.skip_holidays && holidays.is_none() {
+                Err("skip_holidays needs holidays set in the config".to_owned())
+            } else {
+                Job::new(schedule)
+            };
+            job.map_err(|err| eyre!("Invalid schedule for {:?}: {}", schedule.command, err))
+        })
+        .collect()
+}
+
+/// Returns whether any of the jobs skip holidays, for the holidays to be fetched.
+pub fn need_holidays(jobs: &[Job]) -> bool {
+    jobs.iter().any(|job| job.skip_holidays)
+}
+
+impl Job {
+    fn new(schedule: &Schedule) -> Result<Job, String> {
+        Ok(Job {
+            command: schedule.command.to_owned(),
+            at: parse_time_of_day(&schedule.at)?,
+            zone: match &schedule.timezone {
+                Some(name) => Some(Zone::named(name).map_err(|err| err.to_string())?),
+                None => None,
+            },
+            days: match &schedule.days {
+                Some(days) => parse_weekdays(days)?,
+                None => 0b111_1111,
+            },
+            jitter: match &schedule.jitter {
+                Some(jitter) => parse_duration(jitter)?,
+                None => Duration::ZERO,
+            },
+            skip_holidays: schedule.skip_holidays,
+            missed: match &schedule.missed {
+                Some(missed) => parse_missed(missed)?,
+                None => Missed::Skip,
+            },
+        })
+    }
+
+    /// Returns the last time the job was due after `after` and up to `until`, if it was.
+    ///
+    /// Each date has one time the job is due, so days when the clocks change don't skip it or
+    /// run it twice.
+    fn last_due(&self, holidays: &Holidays, after: u64, until: u64) -> Option<u64> {
+        let date = |secs| match &self.zone {
+            Some(zone) => zone.date(secs),
+            None => local_date(secs),
+        };
+        let mut day = date(after.max(until.saturating_sub(LOOK_BACK.as_secs())));
+        let last = date(until);
+        let mut due = None;
+        loop {
+            let holiday = self.skip_holidays && holidays.contains(&day);
+            if self.days & weekday_bit(day) != 0 && !holiday {
+                let time = match &self.zone {
+                    Some(zone) => zone.epoch(day, self.at),
+                    None => epoch_from_local(day, self.at),
+                } + self.jitter(day);
+                if after < time && time <= until {
+                    due = Some(time);
+                }
+            }
+            if day >= last {
+                return due;
+            }
+            day = next_day(day);
+        }
+    }
+
+    /// Returns how long after `at` the job runs on the date, which is random but the same every
+    /// time it is asked, so that the job doesn't move once it is due.
+    fn jitter(&self, date: Date) -> u64 {
+        let secs = self.jitter.as_secs();
+        if secs == 0 {
+            return 0;
+        }
+        let hash = Sha256::digest(format!("{} {:?}", self.command, date));
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&hash[..8]);
+        u64::from_le_bytes(bytes) % secs
+    }
+}
+
+fn parse_missed(s: &str) -> Result<Missed, String> {
+    match s.trim() {
+        "skip" => Ok(Missed::Skip),
+        "run_once_late" => Ok(Missed::RunOnceLate),
+        s => match s.strip_prefix("run_if_within ") {
+            Some(within) => Ok(Missed::RunIfWithin(parse_duration(within)?)),
+            None => Err(format!(
+                "Unknown policy for missed runs {:?}, expected skip, run_once_late, or \
+                 run_if_within and a duration like 30m",
+                s
+            )),
+        },
+    }
+}
+
+/// Returns the jobs to run for being due after `after` and up to `until`, leaving out those that
+/// skip the `holidays`.
+///
+/// Jobs due more than a minute before `until` were missed, and are run or skipped by their
+/// policy for missed runs. A job that was missed several times runs at most once.
+pub fn due<'a>(jobs: &'a [Job], holidays: &Holidays, after: u64, until: u64) -> Vec<&'a Job> {
+    jobs.iter()
+        .filter(|job| {
+            let due = match job.last_due(holidays, after, until) {
+                Some(due) => due,
+                None => return false,
+            };
+            let late = Duration::from_secs(until - due);
+            let run = late <= ON_TIME
+                || match job.missed {
+                    Missed::Skip => false,
+                    Missed::RunOnceLate => true,
+                    Missed::RunIfWithin(within) => late <= within,
+                };
+            if !run {
+                info!(
+                    "Skipping {:?}, which was due {} ago.",
+                    job.command,
+                    format_duration(late)
+                );
+            }
+            run
+        })
+        .collect()
+}
+
+/// Returns when the daemon last checked for due jobs, if it has before.
+pub fn last_check() -> Option<u64> {
+    let contents = fs::read_to_string(get_path()).ok()?;
+    let state: State = serde_json::from_str(&contents).ok()?;
+    Some(state.last_check)
+}
+
+/// Saves when the daemon last checked for due jobs, through a temporary file so that a crash
+/// never leaves half a file.
+pub fn save_last_check(last_check: u64) -> Result<()> {
+    let path = get_path();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let temporary = path.with_extension("json.tmp");
+    fs::write(&temporary, serde_json::to_string(&State { last_check })?)?;
+    fs::rename(temporary, path)?;
+    Ok(())
+}
+
+fn get_path() -> PathBuf {
+    let project_dirs = ProjectDirs::from("", "", "blilys").expect("Data dir not readable");
+    project_dirs.data_dir().join("schedules.json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{due, jobs};
+    use crate::config::Schedule;
+    use crate::holidays::Holidays;
+    use crate::time::{epoch_from_local, epoch_from_utc, parse_time_of_day};
+    use crate::zone::Zone;
+
+    fn schedule(at: &str, days: Option<&str>, missed: Option<&str>) -> Schedule {
+        Schedule {
+            at: at.to_owned(),
+            timezone: None,
+            days: days.map(str::to_owned),
+            command: format!("{} {:?}", at, missed),
+            jitter: None,
+            skip_holidays: false,
+            missed: missed.map(str::to_owned),
+        }
+    }
+
+    fn at(date: (i64, u32, u32), time: &str) -> u64 {
+        epoch_from_local(date, parse_time_of_day(time).unwrap())
+    }
+
+    #[test]
+    fn jobs_run_on_their_days() {
+        let jobs = jobs(&[schedule("07:00", Some("mon-fri"), None)], None).unwrap();
+        // 2026-01-09 is a Friday.
+        let friday = at((2026, 1, 9), "07:00");
+        assert_eq!(
+            due(&jobs, &Holidays::new(), friday - 5, friday + 5).len(),
+            1
+        );
+        assert_eq!(
+            due(&jobs, &Holidays::new(), friday + 5, friday + 10).len(),
+            0
+        );
+        let saturday = at((2026, 1, 10), "07:00");
+        assert_eq!(
+            due(&jobs, &Holidays::new(), saturday - 5, saturday + 5).len(),
+            0
+        );
+    }
+
+    #[test]
+    fn missed_jobs_follow_their_policy() {
+        let jobs = jobs(
+            &[
+                schedule("07:00", None, None),
+                schedule("07:00", None, Some("run_once_late")),
+                schedule("07:00", None, Some("run_if_within 30m")),
+            ],
+            None,
+        )
+        .unwrap();
+        let slept = at((2026, 1, 6), "23:00");
+        let commands = |woke| -> Vec<_> {
+            due(&jobs, &Holidays::new(), slept, woke)
+                .into_iter()
+                .map(|job| job.command.as_str())
+                .collect()
+        };
+
+        // Waking up at 07:00 is on time for every job.
+        assert_eq!(commands(at((2026, 1, 7), "07:00:30")).len(), 3);
+        assert_eq!(
+            commands(at((2026, 1, 7), "07:20")),
+            [
+                "07:00 Some(\"run_once_late\")",
+                "07:00 Some(\"run_if_within 30m\")"
+            ]
+        );
+        // Missing several days of runs only runs once.
+        assert_eq!(
+            commands(at((2026, 1, 9), "09:00")),
+            ["07:00 Some(\"run_once_late\")"]
+        );
+    }
+
+    #[test]
+    fn jobs_run_once_on_days_the_clocks_change() {
+        let mut jobs = jobs(
+            &[schedule("07:00", None, None), schedule("02:30", None, None)],
+            None,
+        )
+        .unwrap();
+        for job in &mut jobs {
+            job.zone = Some(Zone::posix("CET-1CEST,M3.5.0,M10.5.0/3").unwrap());
+        }
+        // Check every five minutes through the nights the clocks go forward and back.
+        for date in [(2026, 3, 28), (2026, 10, 24)] {
+            let evening = epoch_from_utc(date, parse_time_of_day("20:00").unwrap());
+            let mut runs = vec![];
+            for tick in 0..12 * 16 {
+                let now = evening + tick * 300;
+                for job in due(&jobs, &Holidays::new(), now, now + 300) {
+                    runs.push((job.command.clone(), now + 300));
+                }
+            }
+            assert_eq!(runs.len(), 2, "{:?}", runs);
+        }
+        // 07:00 is at 05:00 UTC in summer time.
+        let morning = epoch_from_utc((2026, 3, 29), parse_time_of_day("05:00").unwrap());
+        assert_eq!(
+            due(&jobs, &Holidays::new(), morning - 1, morning)[0].command,
+            "07:00 None"
+        );
+    }
+
+    #[test]
+    fn jitter_moves_jobs_from_day_to_day() {
+        let mut with_jitter = schedule("07:00", None, None);
+        with_jitter.jitter = Some("10m".to_owned());
+        let jobs = jobs(&[with_jitter], None).unwrap();
+        let mut offsets = vec![];
+        for day in 1..=10 {
+            let seven = at((2026, 1, day), "07:00");
+            let due = jobs[0]
+                .last_due(&Holidays::new(), seven - 1, seven + 600)
+                .unwrap();
+            assert!((seven..seven + 600).contains(&due));
+            // The time stays the same however the checks fall.
+            assert_eq!(jobs[0].last_due(&Holidays::new(), due - 1, due), Some(due));
+            offsets.push(due - seven);
+        }
+        offsets.dedup();
+        assert!(offsets.len() > 1, "{:?}", offsets);
+    }
+
+    #[test]
+    fn jobs_can_skip_holidays() {
+        let mut workday = schedule("07:00", Some("mon-fri"), None);
+        workday.skip_holidays = true;
+        let jobs = jobs(&[workday, schedule("08:00", None, None)], Some("NO")).unwrap();
+        // 2026-05-14 is Ascension Day, a Thursday.
+        let holidays = [(2026, 5, 14)].iter().copied().collect();
+        let day = |date| {
+            let after = at(date, "00:00");
+            let until = after + 24 * 60 * 60;
+            jobs.iter()
+                .filter(|job| job.last_due(&holidays, after, until).is_some())
+                .count()
+        };
+        assert_eq!(day((2026, 5, 13)), 2);
+        assert_eq!(day((2026, 5, 14)), 1);
+
+        let mut workday = schedule("07:00", None, None);
+        workday.skip_holidays = true;
+        let err = super::jobs(&[workday], None).unwrap_err();
+        assert!(err.to_string().contains("needs holidays"), "{}", err);
+    }
+
+    #[test]
+    fn invalid_schedules_are_reported() {
+        let err = jobs(&[schedule("07:00", None, Some("sometimes"))], None).unwrap_err();
+        assert!(err.to_string().contains("Unknown policy"), "{}", err);
+        assert!(jobs(&[schedule("25:00", None, None)], None).is_err());
+    }
+}
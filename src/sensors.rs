@@ -0,0 +1,134 @@
+use crate::api::Bridge;
+use crate::options::Sensitivity;
+use eyre::{eyre, Result};
+use serde_json::{json, Map, Value};
+
+/// Changes to a motion sensor's config. Settings left as `None` are not changed.
+#[derive(Debug, Default)]
+pub struct SensorConfig {
+    pub enabled: Option<bool>,
+    pub sensitivity: Option<Sensitivity>,
+    /// Light level in lux below which it counts as dark.
+    pub dark_below_lux: Option<f64>,
+}
+
+/// Finds a sensor given by ID or name, returning its ID and attributes.
+fn resolve(sensors: &Map<String, Value>, name: &str) -> Result<(String, Value)> {
+    sensors
+        .iter()
+        .find(|(id, sensor)| {
+            *id == name
+                || sensor["name"]
+                    .as_str()
+                    .is_some_and(|sensor_name| sensor_name.eq_ignore_ascii_case(name))
+        })
+        .map(|(id, sensor)| (id.to_owned(), sensor.clone()))
+        .ok_or_else(|| eyre!("No sensor named {:?}", name))
+}
+
+/// Returns the ID of the sensor of the given type on the same device, as a motion sensor shows up
+/// as separate presence, light level, and temperature sensors.
+fn sibling(sensors: &Map<String, Value>, sensor: &Value, kind: &str) -> Option<String> {
+    let device_of = |sensor: &Value| {
+        let uniqueid = sensor["uniqueid"].as_str()?;
+        // Like `00:17:88:01:02:03:04:05-02-0406`, where the last part is the sensor type.
+        Some(uniqueid.rsplit_once('-')?.0.to_owned())
+    };
+    let device = device_of(sensor)?;
+    sensors
+        .iter()
+        .find(|(_, other)| other["type"] == kind && device_of(other) == Some(device.clone()))
+        .map(|(id, _)| id.to_owned())
+}
+
+/// Finds the motion sensor of a device given by the ID or name of any of its sensors.
+pub fn resolve_presence(bridge: &Bridge, name: &str) -> Result<String> {
+    let sensors: Map<String, Value> = bridge.get("sensors")?;
+    let (id, sensor) = resolve(&sensors, name)?;
+    if sensor["type"] == "ZLLPresence" {
+        return Ok(id);
+    }
+    sibling(&sensors, &sensor, "ZLLPresence")
+        .ok_or_else(|| eyre!("Sensor {:?} is not a motion sensor", name))
+}
+
+/// Finds the light level sensor of a device given by the ID or name of any of its sensors.
+pub fn resolve_light_level(bridge: &Bridge, name: &str) -> Result<String> {
+    let sensors: Map<String, Value> = bridge.get("sensors")?;
+    let (id, sensor) = resolve(&sensors, name)?;
+    if sensor["type"] == "ZLLLightLevel" {
+        return Ok(id);
+    }
+    sibling(&sensors, &sensor, "ZLLLightLevel")
+        .ok_or_else(|| eyre!("Sensor {:?} has no light level sensor", name))
+}
+
+/// Reads the light level in lux from a light level sensor, or `None` if it has no reading, like
+/// when it is unreachable.
+pub fn read_lux(bridge: &Bridge, id: &str) -> Result<Option<f64>> {
+    let sensor: Value = bridge.get(&format!("sensors/{}", id))?;
+    if sensor["config"]["reachable"] == false {
+        return Ok(None);
+    }
+    Ok(sensor["state"]["lightlevel"].as_u64().map(lux))
+}
+
+/// Converts lux to the bridge's light level unit of `10000 * log10(lux) + 1`.
+fn light_level(lux: f64) -> u64 {
+    (10000.0 * lux.max(1.0).log10() + 1.0).round() as u64
+}
+
+/// Converts the bridge's light level unit back to lux.
+fn lux(light_level: u64) -> f64 {
+    10f64.powf((light_level as f64 - 1.0) / 10000.0)
+}
+
+/// Updates the config of a motion sensor, given by the ID or name of any of its sensors.
+pub fn configure(bridge: &Bridge, name: &str, config: &SensorConfig) -> Result<()> {
+    let sensors: Map<String, Value> = bridge.get("sensors")?;
+    let (id, sensor) = resolve(&sensors, name)?;
+    let presence = if sensor["type"] == "ZLLPresence" {
+        Some(id.clone())
+    } else {
+        sibling(&sensors, &sensor, "ZLLPresence")
+    };
+    let light_level_sensor = if sensor["type"] == "ZLLLightLevel" {
+        Some(id.clone())
+    } else {
+        sibling(&sensors, &sensor, "ZLLLightLevel")
+    };
+
+    let mut presence_config = Map::new();
+    if let Some(enabled) = config.enabled {
+        presence_config.insert("on".to_owned(), enabled.into());
+    }
+    if let Some(sensitivity) = config.sensitivity {
+        let presence = presence
+            .as_ref()
+            .ok_or_else(|| eyre!("Sensor {:?} has no motion sensitivity", name))?;
+        let max = sensors[presence]["config"]["sensitivitymax"]
+            .as_u64()
+            .unwrap_or(2);
+        let value = match sensitivity {
+            Sensitivity::Low => 0,
+            Sensitivity::Medium => max / 2,
+            Sensitivity::High => max,
+        };
+        presence_config.insert("sensitivity".to_owned(), value.into());
+    }
+    if !presence_config.is_empty() {
+        // Sensors without motion, like switches, can also be turned on and off.
+        let target = presence.as_ref().unwrap_or(&id);
+        bridge.put::<Value>(&format!("sensors/{}/config", target), &presence_config)?;
+    }
+
+    if let Some(lux) = config.dark_below_lux {
+        let light_level_sensor = light_level_sensor
+            .ok_or_else(|| eyre!("Sensor {:?} has no light level sensor", name))?;
+        bridge.put::<Value>(
+            &format!("sensors/{}/config", light_level_sensor),
+            &json!({"tholddark": light_level(lux)}),
+        )?;
+    }
+    Ok(())
+}
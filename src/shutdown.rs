@@ -0,0 +1,48 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+/// Commands and effects currently running.
+static BUSY: AtomicUsize = AtomicUsize::new(0);
+
+/// A command or effect running, holding up a graceful stop until dropped.
+pub struct Busy(());
+
+impl Busy {
+    /// Marks the start of some work, or returns `None` if stopping, when no new work is taken.
+    pub fn start() -> Option<Busy> {
+        BUSY.fetch_add(1, Ordering::SeqCst);
+        if is_stopping() {
+            BUSY.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(Busy(()))
+    }
+}
+
+impl Drop for Busy {
+    fn drop(&mut self) {
+        BUSY.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Returns whether a stop was requested, for effects to end at their next step.
+pub fn is_stopping() -> bool {
+    STOPPING.load(Ordering::SeqCst)
+}
+
+/// Stops taking new work and waits up to `timeout` for the running work to end, returning
+/// whether it did.
+pub fn stop(timeout: Duration) -> bool {
+    STOPPING.store(true, Ordering::SeqCst);
+    let deadline = Instant::now() + timeout;
+    while BUSY.load(Ordering::SeqCst) > 0 {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    true
+}
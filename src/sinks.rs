@@ -0,0 +1,262 @@
+use crate::api::Bridge;
+use crate::bridge;
+use crate::config::{Sink, SinkOutput};
+use crate::eventstream;
+use eyre::{eyre, Report, Result, WrapErr};
+use serde_json::Value;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Longest time to wait for the MQTT broker to accept a connection or take a packet.
+const MQTT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An open destination of a sink.
+pub enum Output {
+    File(File),
+    Webhook(reqwest::blocking::Client, String),
+    Mqtt(Mqtt),
+    #[cfg(unix)]
+    Syslog(std::os::unix::net::UnixDatagram),
+}
+
+/// A connection to an MQTT broker, made again on the next event after it fails.
+pub struct Mqtt {
+    address: (String, u16),
+    topic: String,
+    client_id: String,
+    stream: Option<TcpStream>,
+}
+
+/// Opens the sinks in the config.
+pub fn open(sinks: &[Sink]) -> Result<Vec<(&Sink, Output)>> {
+    sinks
+        .iter()
+        .map(|sink| {
+            let output = match &sink.output {
+                SinkOutput::File { path } => Output::File(
+                    OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(path)
+                        .wrap_err_with(|| format!("Failed to open sink {}", path.display()))?,
+                ),
+                SinkOutput::Webhook { url } => Output::Webhook(
+                    reqwest::blocking::Client::builder()
+                        .timeout(Duration::from_secs(10))
+                        .build()?,
+                    url.to_owned(),
+                ),
+                SinkOutput::Mqtt {
+                    host,
+                    port,
+                    topic,
+                    client_id,
+                } => Output::Mqtt(Mqtt {
+                    address: (host.to_owned(), *port),
+                    topic: topic.to_owned(),
+                    client_id: client_id.clone().unwrap_or_else(default_client_id),
+                    stream: None,
+                }),
+                #[cfg(unix)]
+                SinkOutput::Syslog => Output::Syslog(std::os::unix::net::UnixDatagram::unbound()?),
+                #[cfg(not(unix))]
+                SinkOutput::Syslog => return Err(eyre!("Syslog sinks are only supported on Unix")),
+            };
+            Ok((sink, output))
+        })
+        .collect()
+}
+
+/// Forwards the bridge's events to the sinks, forever, reconnecting to the bridge's event stream
+/// whenever it is lost. A failing sink doesn't hold up the others.
+pub fn forward(bridge: &Bridge, mut outputs: Vec<(&Sink, Output)>) {
+    eventstream::follow_forever(bridge, |stream| follow(&mut outputs, stream));
+}
+
+fn follow(
+    outputs: &mut [(&Sink, Output)],
+    stream: impl Iterator<Item = Result<Value>>,
+) -> Result<()> {
+    for resource in stream {
+        let resource = resource?;
+        for (sink, output) in outputs.iter_mut() {
+            if !accepts(sink, &resource) {
+                continue;
+            }
+            if let Err(err) = output.send(&resource) {
+                eprintln!("Failed to forward an event to {:?}: {:#}", sink.output, err);
+            }
+        }
+    }
+    Err(eyre!("The event stream ended"))
+}
+
+fn accepts(sink: &Sink, resource: &Value) -> bool {
+    sink.types.is_empty()
+        || sink
+            .types
+            .iter()
+            .any(|kind| resource["type"] == kind.as_str())
+}
+
+impl Output {
+    fn send(&mut self, resource: &Value) -> Result<()> {
+        match self {
+            Output::File(file) => writeln!(file, "{}", resource)?,
+            Output::Webhook(client, url) => {
+                client
+                    .post(url.as_str())
+                    .json(resource)
+                    .send()?
+                    .error_for_status()?;
+            }
+            Output::Mqtt(mqtt) => {
+                let topic = format!(
+                    "{}/{}",
+                    mqtt.topic,
+                    resource["type"].as_str().unwrap_or("unknown")
+                );
+                let packet = publish_packet(&topic, resource.to_string().as_bytes());
+                let result = mqtt
+                    .connect()
+                    .and_then(|stream| Ok(stream.write_all(&packet)?));
+                if result.is_err() {
+                    mqtt.stream = None;
+                }
+                result?;
+            }
+            #[cfg(unix)]
+            Output::Syslog(socket) => {
+                socket.send_to(syslog_message(resource).as_bytes(), "/dev/log")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns a client ID from the hostname, so that daemons on different machines don't take over
+/// each other's connection to the broker. Brokers need only take IDs of up to 23 characters.
+fn default_client_id() -> String {
+    let id = match bridge::hostname() {
+        Some(host) if !host.is_empty() => format!("blilys-{}", host),
+        _ => "blilys".to_owned(),
+    };
+    id.chars().take(23).collect()
+}
+
+impl Mqtt {
+    fn connect(&mut self) -> Result<&mut TcpStream> {
+        if self.stream.is_none() {
+            let mut stream = self
+                .address
+                .to_socket_addrs()
+                .map_err(Report::from)
+                .and_then(|mut addrs| {
+                    addrs
+                        .next()
+                        .ok_or_else(|| eyre!("{:?} did not resolve to any address", self.address.0))
+                })
+                .and_then(|addr| Ok(TcpStream::connect_timeout(&addr, MQTT_TIMEOUT)?))
+                .wrap_err("Failed to connect to the MQTT broker")?;
+            // A broker that stops reading would otherwise hold up the other sinks forever.
+            stream.set_read_timeout(Some(MQTT_TIMEOUT))?;
+            stream.set_write_timeout(Some(MQTT_TIMEOUT))?;
+            stream.write_all(&connect_packet(&self.client_id))?;
+            let mut connack = [0; 4];
+            stream.read_exact(&mut connack)?;
+            if connack[0] != 0x20 || connack[3] != 0 {
+                return Err(eyre!(
+                    "The MQTT broker refused the connection with code {}",
+                    connack[3]
+                ));
+            }
+            self.stream = Some(stream);
+        }
+        Ok(self.stream.as_mut().expect("stream to be connected"))
+    }
+}
+
+/// Builds an MQTT 3.1.1 CONNECT packet with a clean session and no keep alive, so the broker
+/// doesn't drop the connection between events.
+fn connect_packet(client_id: &str) -> Vec<u8> {
+    let mut body = vec![];
+    push_string(&mut body, b"MQTT");
+    // Protocol level 4, clean session, and a keep alive of 0.
+    body.extend_from_slice(&[4, 0x02, 0, 0]);
+    push_string(&mut body, client_id.as_bytes());
+    packet(0x10, &body)
+}
+
+/// Builds an MQTT PUBLISH packet at QoS 0, which the broker doesn't acknowledge.
+fn publish_packet(topic: &str, payload: &[u8]) -> Vec<u8> {
+    let mut body = vec![];
+    push_string(&mut body, topic.as_bytes());
+    body.extend_from_slice(payload);
+    packet(0x30, &body)
+}
+
+fn push_string(buf: &mut Vec<u8>, s: &[u8]) {
+    buf.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    buf.extend_from_slice(s);
+}
+
+/// Prefixes the body with the fixed header: the packet type and the remaining length, in seven
+/// bits per byte.
+fn packet(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![kind];
+    let mut length = body.len();
+    loop {
+        let byte = (length % 128) as u8;
+        length /= 128;
+        if length == 0 {
+            packet.push(byte);
+            break;
+        }
+        packet.push(byte | 0x80);
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+/// Formats an event for the local syslog, at the user facility and info severity.
+#[cfg(unix)]
+fn syslog_message(resource: &Value) -> String {
+    format!("<14>blilys: {}", resource)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{accepts, connect_packet, publish_packet};
+    use crate::config::{Sink, SinkOutput};
+    use serde_json::json;
+
+    #[test]
+    fn sinks_filter_by_resource_type() {
+        let mut sink = Sink {
+            types: vec![],
+            output: SinkOutput::Syslog,
+        };
+        assert!(accepts(&sink, &json!({"type": "light"})));
+        sink.types = vec!["button".to_owned(), "motion".to_owned()];
+        assert!(!accepts(&sink, &json!({"type": "light"})));
+        assert!(accepts(&sink, &json!({"type": "motion"})));
+    }
+
+    #[test]
+    fn mqtt_packets_are_encoded() {
+        assert_eq!(
+            connect_packet("b"),
+            [0x10, 13, 0, 4, b'M', b'Q', b'T', b'T', 4, 2, 0, 0, 0, 1, b'b']
+        );
+        assert_eq!(
+            publish_packet("t", b"{}"),
+            [0x30, 5, 0, 1, b't', b'{', b'}']
+        );
+        // Lengths above 127 take more than one byte.
+        let long = publish_packet("t", &[0; 200]);
+        assert_eq!(&long[..3], [0x30, 0xcb, 0x01]);
+        assert_eq!(long.len(), 3 + 203);
+    }
+}
@@ -0,0 +1,250 @@
+use crate::backend::LightBackend;
+use crate::output::{paint, Style};
+use crate::time::now;
+use directories::ProjectDirs;
+use eyre::{eyre, Result};
+use hueclient::LightState;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs;
+use std::path::PathBuf;
+
+/// The state of all lights at one point in time.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Seconds since the Unix epoch when the snapshot was taken.
+    pub time: u64,
+    pub lights: BTreeMap<usize, SavedLight>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedLight {
+    pub name: String,
+    pub state: LightState,
+}
+
+impl Snapshot {
+    pub fn take(backend: &dyn LightBackend) -> Result<Snapshot> {
+        Ok(Snapshot {
+            time: now(),
+            lights: backend
+                .get_all_lights()?
+                .into_iter()
+                .map(|il| {
+                    let light = SavedLight {
+                        name: il.light.name,
+                        state: il.light.state,
+                    };
+                    (il.id, light)
+                })
+                .collect(),
+        })
+    }
+
+    pub fn load(name: &str) -> Result<Snapshot> {
+        let path = Snapshot::get_path(name)?;
+        if !path.is_file() {
+            return Err(eyre!("No snapshot named {:?}", name));
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, name: &str) -> Result<()> {
+        let path = Snapshot::get_path(name)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn delete(name: &str) -> Result<()> {
+        let path = Snapshot::get_path(name)?;
+        if !path.is_file() {
+            return Err(eyre!("No snapshot named {:?}", name));
+        }
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    /// Returns the names of the saved snapshots, sorted.
+    pub fn list() -> Result<Vec<String>> {
+        let dir = Snapshot::get_dir()?;
+        if !dir.is_dir() {
+            return Ok(vec![]);
+        }
+        let mut names = vec![];
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    names.push(name.to_owned());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn get_dir() -> Result<PathBuf> {
+        let project_dirs = ProjectDirs::from("", "", "blilys").expect("Data dir not readable");
+        Ok(project_dirs.data_dir().join("snapshots"))
+    }
+
+    fn get_path(name: &str) -> Result<PathBuf> {
+        if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+            return Err(eyre!("Invalid snapshot name {:?}", name));
+        }
+        Ok(Snapshot::get_dir()?.join(format!("{}.json", name)))
+    }
+}
+
+/// A difference in a light between two snapshots.
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    Added,
+    Removed,
+    /// Changed attributes, with their old and new values.
+    Changed(Vec<(&'static str, String, String)>),
+}
+
+/// Compares the lights of two snapshots, returning the lights that differ by ID.
+pub fn diff(from: &Snapshot, to: &Snapshot) -> BTreeMap<usize, (String, Change)> {
+    let mut changes = BTreeMap::new();
+    for (id, old) in &from.lights {
+        match to.lights.get(id) {
+            None => {
+                changes.insert(*id, (old.name.clone(), Change::Removed));
+            }
+            Some(new) => {
+                let attributes = changed_attributes(&old.state, &new.state);
+                if !attributes.is_empty() {
+                    changes.insert(*id, (new.name.clone(), Change::Changed(attributes)));
+                }
+            }
+        }
+    }
+    for (id, new) in &to.lights {
+        if !from.lights.contains_key(id) {
+            changes.insert(*id, (new.name.clone(), Change::Added));
+        }
+    }
+    changes
+}
+
+fn changed_attributes(old: &LightState, new: &LightState) -> Vec<(&'static str, String, String)> {
+    let mut attributes = vec![];
+    let mut compare = |name, old: String, new: String| {
+        if old != new {
+            attributes.push((name, old, new));
+        }
+    };
+    compare("on", on_off(old.on), on_off(new.on));
+    compare("bri", optional(old.bri), optional(new.bri));
+    compare("ct", optional(old.ct), optional(new.ct));
+    compare("hue", optional(old.hue), optional(new.hue));
+    compare("sat", optional(old.sat), optional(new.sat));
+    compare(
+        "xy",
+        optional(old.xy.map(|(x, y)| format!("{:.4},{:.4}", x, y))),
+        optional(new.xy.map(|(x, y)| format!("{:.4},{:.4}", x, y))),
+    );
+    attributes
+}
+
+fn on_off(on: bool) -> String {
+    if on { "on" } else { "off" }.to_owned()
+}
+
+fn optional<T: Display>(value: Option<T>) -> String {
+    value.map_or_else(|| "-".to_owned(), |value| value.to_string())
+}
+
+/// Prints the differences between two snapshots, one light per line.
+pub fn print_diff(from: &Snapshot, to: &Snapshot) {
+    let changes = diff(from, to);
+    if changes.is_empty() {
+        info!("No lights changed.");
+    }
+    for (id, (name, change)) in changes {
+        let description = match change {
+            Change::Added => paint("added", Style::Green),
+            Change::Removed => paint("removed", Style::Dim),
+            Change::Changed(attributes) => attributes
+                .iter()
+                .map(|(attribute, old, new)| {
+                    format!(
+                        "{} {} -> {}",
+                        attribute,
+                        paint(old, Style::Dim),
+                        paint(new, Style::Green)
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(", "),
+        };
+        println!("{:2}: {:30} {}", id, name, description);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(lights: &[(usize, &str, bool, u8)]) -> Snapshot {
+        Snapshot {
+            time: 0,
+            lights: lights
+                .iter()
+                .map(|&(id, name, on, bri)| {
+                    let state = LightState {
+                        on,
+                        bri: Some(bri),
+                        hue: None,
+                        sat: None,
+                        ct: None,
+                        xy: None,
+                    };
+                    let light = SavedLight {
+                        name: name.to_owned(),
+                        state,
+                    };
+                    (id, light)
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn diff_reports_changed_added_and_removed_lights() {
+        let from = snapshot(&[(1, "Desk", true, 200), (2, "Kitchen", false, 10)]);
+        let to = snapshot(&[(1, "Desk", false, 100), (3, "Hall", true, 254)]);
+
+        let changes = diff(&from, &to);
+
+        assert_eq!(
+            changes.get(&1),
+            Some(&(
+                "Desk".to_owned(),
+                Change::Changed(vec![
+                    ("on", "on".to_owned(), "off".to_owned()),
+                    ("bri", "200".to_owned(), "100".to_owned()),
+                ])
+            ))
+        );
+        assert_eq!(
+            changes.get(&2),
+            Some(&("Kitchen".to_owned(), Change::Removed))
+        );
+        assert_eq!(changes.get(&3), Some(&("Hall".to_owned(), Change::Added)));
+    }
+
+    #[test]
+    fn diff_ignores_unchanged_lights() {
+        let from = snapshot(&[(1, "Desk", true, 200)]);
+        let to = snapshot(&[(1, "Desk", true, 200)]);
+
+        assert!(diff(&from, &to).is_empty());
+    }
+}
@@ -0,0 +1,274 @@
+use crate::api::Bridge;
+use crate::config::Config;
+use crate::metrics;
+use crate::shutdown;
+use crate::time::{self, format_duration};
+use directories::ProjectDirs;
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::thread::{self, Scope};
+use std::time::{Duration, Instant};
+
+/// Time to wait before restarting an effect that failed, doubled each time it fails again.
+const MIN_BACKOFF: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Held while effects.json is read and written, as effects ending on their threads update it.
+static SAVING: Mutex<()> = Mutex::new(());
+
+/// The effects from `daemon.effects` as they were started, saved so that the daemon resumes
+/// them after a crash or reboot instead of starting them over.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Effects {
+    /// The state of each effect by its command line.
+    effects: BTreeMap<String, EffectState>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct EffectState {
+    /// Seconds since the Unix epoch when the effect first started.
+    started: u64,
+    /// Seconds since the Unix epoch when the effect ends, if it has a duration.
+    until: Option<u64>,
+}
+
+impl Effects {
+    fn load() -> Result<Effects> {
+        let path = Effects::get_path()?;
+        if !path.is_file() {
+            return Ok(Effects::default());
+        }
+        // A file that can't be read is no reason to keep the lights still.
+        Ok(serde_json::from_str(&fs::read_to_string(path)?).unwrap_or_default())
+    }
+
+    /// Saves the effects that haven't run their time, so that they're started afresh the next
+    /// time, through a temporary file, so that a crash never leaves half a file.
+    fn save(&self, now: u64) -> Result<()> {
+        let path = Effects::get_path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let temporary = path.with_extension("json.tmp");
+        fs::write(&temporary, serde_json::to_string(&self.running(now))?)?;
+        fs::rename(temporary, path)?;
+        Ok(())
+    }
+
+    fn get_path() -> Result<PathBuf> {
+        let project_dirs = ProjectDirs::from("", "", "blilys").expect("Data dir not readable");
+        Ok(project_dirs.data_dir().join("effects.json"))
+    }
+
+    /// Returns the effects to run for the configured command lines with their durations, keeping
+    /// the state of effects that were started before and forgetting those no longer configured.
+    fn resume(&self, configured: &[(&str, Option<Duration>)], now: u64) -> Effects {
+        let effects = configured
+            .iter()
+            .map(|&(line, duration)| {
+                let state = self.effects.get(line).copied().unwrap_or(EffectState {
+                    started: now,
+                    until: duration.map(|duration| now + duration.as_secs()),
+                });
+                (line.to_owned(), state)
+            })
+            .collect();
+        Effects { effects }
+    }
+
+    /// Returns the effects that haven't run their time.
+    fn running(&self, now: u64) -> Effects {
+        let effects = self
+            .effects
+            .iter()
+            .filter(|(_, state)| state.remaining(now).is_some())
+            .map(|(line, state)| (line.clone(), *state))
+            .collect();
+        Effects { effects }
+    }
+
+    /// Forgets an effect that has run its time, so that the daemon starts it afresh next time.
+    fn end(line: &str) -> Result<()> {
+        let _saving = SAVING.lock().expect("Effects lock poisoned");
+        let mut effects = Effects::load()?;
+        effects.effects.remove(line);
+        effects.save(time::now())
+    }
+}
+
+impl EffectState {
+    /// Returns how long the effect has left to run, `Some(None)` if it runs until stopped, or
+    /// `None` if it has ended.
+    fn remaining(&self, now: u64) -> Option<Option<Duration>> {
+        match self.until {
+            Some(until) if until <= now => None,
+            Some(until) => Some(Some(Duration::from_secs(until - now))),
+            None => Some(None),
+        }
+    }
+}
+
+/// Starts the effects from `daemon.effects` on threads of the scope, each restarted if it fails
+/// and resumed where it left off if the daemon was stopped while it ran.
+pub fn start<'scope>(
+    scope: &'scope Scope<'scope, '_>,
+    bridge: &'scope Bridge,
+    config: &'scope Config,
+) -> Result<()> {
+    let configured = config
+        .daemon
+        .effects
+        .iter()
+        .map(|line| match crate::effect_duration(line) {
+            Ok(duration) => Ok((line.as_str(), duration)),
+            Err(err) => Err(eyre!(
+                "Invalid effect {:?} in daemon.effects: {}",
+                line,
+                err
+            )),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let effects = {
+        let _saving = SAVING.lock().expect("Effects lock poisoned");
+        let effects = Effects::load()?.resume(&configured, time::now());
+        effects.save(time::now())?;
+        effects
+    };
+    for (line, state) in effects.effects {
+        if state.remaining(time::now()).is_none() {
+            info!(
+                "Effect {:?} ran its time while the daemon was stopped.",
+                line
+            );
+            continue;
+        }
+        scope.spawn(move || supervise(bridge, config, &line, state));
+    }
+    Ok(())
+}
+
+/// Runs the effect until it ends, restarting it with a growing wait each time it fails.
+fn supervise(bridge: &Bridge, config: &Config, line: &str, state: EffectState) {
+    let _span = tracing::info_span!("effect", line).entered();
+    let mut backoff = MIN_BACKOFF;
+    while let Some(remaining) = state.remaining(time::now()) {
+        let started = Instant::now();
+        let busy = match shutdown::Busy::start() {
+            Some(busy) => busy,
+            None => break,
+        };
+        tracing::info!("starting");
+        let result = crate::run_effect_command(bridge, config, line, remaining);
+        drop(busy);
+        match result {
+            // Effects end early when the daemon stops, and are resumed when it starts again.
+            Ok(()) => break,
+            Err(err) => {
+                // An effect that ran fine for a while gets restarted quickly again.
+                if started.elapsed() > MAX_BACKOFF {
+                    backoff = MIN_BACKOFF;
+                }
+                tracing::error!(error = %err, "failed");
+                metrics::effect_restart();
+                eprintln!(
+                    "Effect {:?} failed, restarting in {}: {:#}",
+                    line,
+                    format_duration(backoff),
+                    err
+                );
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+    if state.remaining(time::now()).is_none() {
+        if let Err(err) = Effects::end(line) {
+            eprintln!("Failed to forget ended effect {:?}: {:#}", line, err);
+        }
+    }
+    tracing::info!("ended");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EffectState, Effects};
+    use std::time::Duration;
+
+    #[test]
+    fn started_effects_are_resumed_with_their_remaining_time() {
+        let mut saved = Effects::default();
+        saved.effects.insert(
+            "light Strip mode breathe -d 1h".to_owned(),
+            EffectState {
+                started: 1000,
+                until: Some(4600),
+            },
+        );
+        saved.effects.insert(
+            "light Desk mode halloween".to_owned(),
+            EffectState {
+                started: 1000,
+                until: None,
+            },
+        );
+
+        let effects = saved.resume(
+            &[
+                (
+                    "light Strip mode breathe -d 1h",
+                    Some(Duration::from_secs(3600)),
+                ),
+                ("all mode breathe -d 1m", Some(Duration::from_secs(60))),
+            ],
+            2000,
+        );
+
+        let strip = effects.effects["light Strip mode breathe -d 1h"];
+        assert_eq!(strip.remaining(2000), Some(Some(Duration::from_secs(2600))));
+        assert_eq!(strip.remaining(5000), None);
+        let all = effects.effects["all mode breathe -d 1m"];
+        assert_eq!(all.remaining(2000), Some(Some(Duration::from_secs(60))));
+        // Effects no longer in the config are forgotten.
+        assert_eq!(effects.effects.len(), 2);
+    }
+
+    #[test]
+    fn effects_that_have_run_their_time_are_not_saved() {
+        let mut effects = Effects::default();
+        effects.effects.insert(
+            "light Strip mode breathe -d 1h".to_owned(),
+            EffectState {
+                started: 1000,
+                until: Some(4600),
+            },
+        );
+        effects.effects.insert(
+            "light Desk mode halloween".to_owned(),
+            EffectState {
+                started: 1000,
+                until: None,
+            },
+        );
+
+        assert_eq!(effects.running(2000).effects.len(), 2);
+        let running = effects.running(5000);
+        assert_eq!(
+            running.effects.keys().collect::<Vec<_>>(),
+            ["light Desk mode halloween"]
+        );
+        // Started afresh, the effect runs its whole time again.
+        let again = running.resume(
+            &[(
+                "light Strip mode breathe -d 1h",
+                Some(Duration::from_secs(3600)),
+            )],
+            5000,
+        );
+        let strip = again.effects["light Strip mode breathe -d 1h"];
+        assert_eq!(strip.remaining(5000), Some(Some(Duration::from_secs(3600))));
+    }
+}
@@ -0,0 +1,263 @@
+use directories::BaseDirs;
+use eyre::{eyre, Result};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// First line of the unit files blilys writes, to find them again when uninstalling.
+const MARKER: &str = "# Written by blilys. Remove with `blilys systemd uninstall`.";
+
+const DAEMON_UNIT: &str = "blilys-daemon.service";
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+/// A command line to run on a schedule, given like `0 22 * * * all off`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timer {
+    /// The schedule as a crontab line's first five fields, like `0 22 * * *`.
+    pub cron: String,
+    /// The schedule in systemd's calendar event format, like `*-*-* 22:00:00`.
+    pub on_calendar: String,
+    pub command: String,
+}
+
+/// Parses a timer given as a crontab schedule followed by a blilys command line.
+///
+/// Unlike cron, systemd only runs when both the day of the month and the day of the week match,
+/// if both are restricted.
+pub fn parse_timer(s: &str) -> Result<Timer, String> {
+    let words: Vec<&str> = s.split_whitespace().collect();
+    if words.len() < 6 {
+        return Err(format!(
+            "Invalid timer {:?}, expected a crontab schedule and a command, \
+             like \"0 22 * * * all off\"",
+            s
+        ));
+    }
+    let (cron, command) = words.split_at(5);
+    let field = |index: usize, min: u32, max: u32, step_start: u32| {
+        convert_field(cron[index], min, max, step_start)
+            .map_err(|err| format!("{} in timer {:?}", err, s))
+    };
+    let (minute, hour) = (field(0, 0, 59, 0)?, field(1, 0, 23, 0)?);
+    let (day, month) = (field(2, 1, 31, 1)?, field(3, 1, 12, 1)?);
+    let weekdays = convert_weekdays(cron[4]).map_err(|err| format!("{} in timer {:?}", err, s))?;
+    Ok(Timer {
+        cron: cron.join(" "),
+        on_calendar: format!(
+            "{}*-{}-{} {}:{}:00",
+            weekdays.map(|days| days + " ").unwrap_or_default(),
+            month,
+            day,
+            hour,
+            minute
+        ),
+        command: command.join(" "),
+    })
+}
+
+/// Converts a crontab field like `*`, `*/15`, `1-5`, or `0,30` to systemd's format.
+fn convert_field(field: &str, min: u32, max: u32, step_start: u32) -> Result<String, String> {
+    let number = |s: &str| {
+        s.parse::<u32>()
+            .ok()
+            .filter(|n| (min..=max).contains(n))
+            .map(|n| format!("{:02}", n))
+            .ok_or_else(|| format!("Invalid value {:?}", s))
+    };
+    field
+        .split(',')
+        .map(|part| match part {
+            "*" => Ok("*".to_owned()),
+            _ => match (part.strip_prefix("*/"), part.split_once('-')) {
+                (Some(step), _) => Ok(format!("{:02}/{}", step_start, number(step)?)),
+                (None, Some((first, last))) => Ok(format!("{}..{}", number(first)?, number(last)?)),
+                (None, None) => number(part),
+            },
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(|parts| parts.join(","))
+}
+
+/// Converts a crontab day of week field like `1-5` or `sat,sun` to systemd's format, or `None`
+/// for every day.
+fn convert_weekdays(field: &str) -> Result<Option<String>, String> {
+    if field == "*" {
+        return Ok(None);
+    }
+    let day = |s: &str| match s.parse::<usize>() {
+        // Both 0 and 7 are Sunday.
+        Ok(n) if n <= 7 => Ok(WEEKDAYS[n % 7].to_owned()),
+        _ => WEEKDAYS
+            .iter()
+            .find(|day| day.eq_ignore_ascii_case(s))
+            .map(|day| (*day).to_owned())
+            .ok_or_else(|| format!("Invalid day of week {:?}", s)),
+    };
+    field
+        .split(',')
+        .map(|part| match part.split_once('-') {
+            Some((first, last)) => Ok(format!("{}..{}", day(first)?, day(last)?)),
+            None => day(part),
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(|days| Some(days.join(",")))
+}
+
+/// Directory of the user's systemd units.
+fn units_dir() -> Result<PathBuf> {
+    let base_dirs = BaseDirs::new().ok_or_else(|| eyre!("Home directory not found"))?;
+    Ok(base_dirs.config_dir().join("systemd/user"))
+}
+
+/// Names a timer's units after its command, like `blilys-all-off`.
+fn timer_name(timer: &Timer) -> String {
+    let slug: Vec<String> = timer
+        .command
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_ascii_lowercase)
+        .collect();
+    format!("blilys-{}", slug.join("-"))
+}
+
+/// Quotes a command line for `ExecStart`, running the current blilys executable.
+fn exec_start(args: &[String]) -> Result<String> {
+    let exe = env::current_exe()?.to_string_lossy().into_owned();
+    let line = shell_words::join(std::iter::once(exe).chain(args.iter().cloned()));
+    // Percent signs start specifiers in unit files.
+    Ok(line.replace('%', "%%"))
+}
+
+/// Writes user units running the daemon, and each timer's command on its schedule, and enables
+/// them unless `enable` is false. Timers with the same command replace each other.
+pub fn install(daemon: bool, timers: &[Timer], enable: bool) -> Result<()> {
+    let dir = units_dir()?;
+    fs::create_dir_all(&dir)?;
+    let mut units = vec![];
+
+    if daemon {
+        let service = format!(
+            "{}\n[Unit]\nDescription=blilys daemon\nWants=network-online.target\n\
+             After=network-online.target\n\n[Service]\nExecStart={}\nRestart=on-failure\n\
+             RestartSec=30\n\n[Install]\nWantedBy=default.target\n",
+            MARKER,
+            exec_start(&["daemon".to_owned()])?
+        );
+        fs::write(dir.join(DAEMON_UNIT), service)?;
+        units.push(DAEMON_UNIT.to_owned());
+    }
+
+    for timer in timers {
+        let name = timer_name(timer);
+        let args = shell_words::split(&timer.command)
+            .map_err(|err| eyre!("Invalid command {:?}: {}", timer.command, err))?;
+        let service = format!(
+            "{}\n[Unit]\nDescription=blilys {}\n\n[Service]\nType=oneshot\nExecStart={}\n",
+            MARKER,
+            timer.command.replace('%', "%%"),
+            exec_start(&args)?
+        );
+        let timer_unit = format!(
+            "{}\n[Unit]\nDescription=Run blilys {} at {}\n\n[Timer]\nOnCalendar={}\n\n\
+             [Install]\nWantedBy=timers.target\n",
+            MARKER,
+            timer.command.replace('%', "%%"),
+            timer.cron,
+            timer.on_calendar
+        );
+        fs::write(dir.join(format!("{}.service", name)), service)?;
+        fs::write(dir.join(format!("{}.timer", name)), timer_unit)?;
+        units.push(format!("{}.timer", name));
+    }
+
+    for unit in &units {
+        info!("Wrote {}.", dir.join(unit).display());
+    }
+    if enable {
+        systemctl(&["daemon-reload"]);
+        for unit in &units {
+            systemctl(&["enable", "--now", unit]);
+        }
+    } else {
+        info!(
+            "Enable with: systemctl --user daemon-reload && systemctl --user enable --now {}",
+            units.join(" ")
+        );
+    }
+    Ok(())
+}
+
+/// Removes the units written by `install`, disabling them first unless `disable` is false.
+pub fn uninstall(disable: bool) -> Result<()> {
+    let dir = units_dir()?;
+    let mut files: Vec<PathBuf> = match fs::read_dir(&dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| {
+                fs::read_to_string(path).is_ok_and(|contents| contents.starts_with(MARKER))
+            })
+            .collect(),
+        Err(_) => vec![],
+    };
+    if files.is_empty() {
+        info!("No units to remove.");
+        return Ok(());
+    }
+    files.sort();
+
+    if disable {
+        for path in &files {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            // Services run by timers aren't enabled themselves.
+            if name.ends_with(".timer") || name == DAEMON_UNIT {
+                systemctl(&["disable", "--now", &name]);
+            }
+        }
+    }
+    for path in &files {
+        fs::remove_file(path)?;
+        info!("Removed {}.", path.display());
+    }
+    if disable {
+        systemctl(&["daemon-reload"]);
+    }
+    Ok(())
+}
+
+/// Runs `systemctl --user`, only warning on failure, as the unit files are written either way.
+fn systemctl(args: &[&str]) {
+    let result = Command::new("systemctl").arg("--user").args(args).status();
+    if !result.is_ok_and(|status| status.success()) {
+        eprintln!(
+            "Failed to run `systemctl --user {}`, run it yourself to finish.",
+            args.join(" ")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_timer;
+
+    #[test]
+    fn timers_convert_cron_to_calendar_events() {
+        let timer = parse_timer("0 22 * * * all off").unwrap();
+        assert_eq!(timer.on_calendar, "*-*-* 22:00:00");
+        assert_eq!(timer.command, "all off");
+
+        let timer = parse_timer("*/15 7-9 * * 1-5 group:Office on").unwrap();
+        assert_eq!(timer.on_calendar, "Mon..Fri *-*-* 07..09:00/15:00");
+
+        let timer = parse_timer("30 6 1,15 * sat,0 desk on").unwrap();
+        assert_eq!(timer.on_calendar, "Sat,Sun *-*-01,15 06:30:00");
+    }
+
+    #[test]
+    fn timers_need_schedule_and_command() {
+        assert!(parse_timer("0 22 * * *").is_err());
+        assert!(parse_timer("60 22 * * * all off").is_err());
+        assert!(parse_timer("0 22 * * mon-funday all off").is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use crate::output::{self, paint, Style};
+
+/// Rows of cells printed in columns sized to their content, with one column shortened with an
+/// ellipsis if the table is wider than the terminal.
+pub struct Table {
+    aligns: Vec<Align>,
+    shrink: Option<usize>,
+    rows: Vec<Vec<Cell>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Align {
+    Left,
+    Right,
+}
+
+pub struct Cell {
+    text: String,
+    style: Option<Style>,
+}
+
+/// Columns are never shortened to less than this.
+const MIN_SHRUNK_WIDTH: usize = 8;
+
+impl Cell {
+    pub fn styled(text: impl Into<String>, style: Style) -> Cell {
+        Cell {
+            text: text.into(),
+            style: Some(style),
+        }
+    }
+}
+
+impl From<String> for Cell {
+    fn from(text: String) -> Cell {
+        Cell { text, style: None }
+    }
+}
+
+impl From<&str> for Cell {
+    fn from(text: &str) -> Cell {
+        Cell::from(text.to_owned())
+    }
+}
+
+impl Table {
+    pub fn new(aligns: &[Align]) -> Table {
+        Table {
+            aligns: aligns.to_vec(),
+            shrink: None,
+            rows: vec![],
+        }
+    }
+
+    /// Shortens the column, like one with names, when the table doesn't fit.
+    pub fn shrinking(self, column: usize) -> Table {
+        Table {
+            shrink: Some(column),
+            ..self
+        }
+    }
+
+    pub fn row(&mut self, cells: Vec<Cell>) {
+        self.rows.push(cells);
+    }
+
+    /// Prints the table to fit the terminal, unless `--wide` is given.
+    pub fn print(&self) {
+        for line in self.render(output::table_width()) {
+            println!("{}", line);
+        }
+    }
+
+    fn render(&self, max_width: Option<usize>) -> Vec<String> {
+        let mut widths = vec![0; self.aligns.len()];
+        for row in &self.rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.text.chars().count());
+            }
+        }
+        let total = widths.iter().sum::<usize>() + widths.len().saturating_sub(1);
+        if let (Some(max_width), Some(column)) = (max_width, self.shrink) {
+            if total > max_width {
+                let shrunk = widths[column].saturating_sub(total - max_width);
+                widths[column] = shrunk.max(MIN_SHRUNK_WIDTH).min(widths[column]);
+            }
+        }
+        self.rows
+            .iter()
+            .map(|row| {
+                let last = row.len().saturating_sub(1);
+                row.iter()
+                    .zip(&widths)
+                    .zip(&self.aligns)
+                    .enumerate()
+                    .map(|(i, ((cell, &width), align))| {
+                        let text = truncate(&cell.text, width);
+                        let padding = " ".repeat(width - text.chars().count());
+                        let text = match cell.style {
+                            Some(style) => paint(&text, style),
+                            None => text,
+                        };
+                        match align {
+                            Align::Right => padding + &text,
+                            // Without trailing spaces at the end of the line.
+                            Align::Left if i == last => text,
+                            Align::Left => text + &padding,
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect()
+    }
+}
+
+/// Shortens the text to at most `width` characters, ending with an ellipsis if shortened.
+fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_owned();
+    }
+    let mut truncated: String = text.chars().take(width.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Align, Table};
+
+    #[test]
+    fn columns_are_sized_to_content_and_shrunk_to_fit() {
+        let mut table = Table::new(&[Align::Right, Align::Left, Align::Left]).shrinking(1);
+        table.row(vec!["1:".into(), "Desk".into(), "[on ]".into()]);
+        table.row(vec![
+            "12:".into(),
+            "Living room ceiling".into(),
+            "[off]".into(),
+        ]);
+
+        assert_eq!(
+            table.render(None),
+            vec![
+                " 1: Desk                [on ]",
+                "12: Living room ceiling [off]",
+            ]
+        );
+        assert_eq!(
+            table.render(Some(20)),
+            vec![" 1: Desk       [on ]", "12: Living ro… [off]"]
+        );
+    }
+}
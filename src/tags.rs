@@ -0,0 +1,48 @@
+use crate::config::Config;
+use eyre::{eyre, Result};
+
+/// Tags the lights, keeping each tag's lights sorted.
+pub fn add(config: &mut Config, lights: &[usize], tags: &[String]) {
+    for tag in tags {
+        let tagged = config.tags.entry(tag.to_owned()).or_default();
+        tagged.extend(lights);
+        tagged.sort_unstable();
+        tagged.dedup();
+    }
+}
+
+/// Removes the tags from the lights, dropping tags no light has anymore.
+pub fn remove(config: &mut Config, lights: &[usize], tags: &[String]) -> Result<()> {
+    for tag in tags {
+        let tagged = config
+            .tags
+            .get_mut(tag)
+            .ok_or_else(|| eyre!("No lights tagged {:?}", tag))?;
+        tagged.retain(|id| !lights.contains(id));
+        if tagged.is_empty() {
+            config.tags.remove(tag);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{add, remove};
+    use crate::config::Config;
+
+    #[test]
+    fn tags_are_added_and_removed() {
+        let mut config = Config::default();
+        let tags = ["reading".to_owned(), "ambient".to_owned()];
+        add(&mut config, &[5, 3], &tags);
+        add(&mut config, &[3, 4], &tags[1..]);
+        assert_eq!(config.tags["reading"], vec![3, 5]);
+        assert_eq!(config.tags["ambient"], vec![3, 4, 5]);
+
+        remove(&mut config, &[3, 5], &tags).unwrap();
+        assert!(!config.tags.contains_key("reading"));
+        assert_eq!(config.tags["ambient"], vec![4]);
+        assert!(remove(&mut config, &[4], &tags).is_err());
+    }
+}
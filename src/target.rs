@@ -0,0 +1,145 @@
+use crate::backend::LightBackend;
+use crate::cache;
+use eyre::{eyre, Result};
+use hueclient::CommandLight;
+use std::fmt;
+use std::time::Duration;
+
+/// The lights an operation applies to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    Light(usize),
+    Group(usize),
+    /// All lights, through the bridge's special group 0.
+    All,
+    /// Lights picked by a selector like `kitchen + light:desk`, controlled one by one.
+    Lights(Vec<usize>),
+}
+
+impl Target {
+    /// Returns whether any of the target's lights are on.
+    pub fn is_on(&self, backend: &dyn LightBackend) -> Result<bool> {
+        match *self {
+            Target::Light(id) => backend
+                .get_all_lights()?
+                .into_iter()
+                .find(|il| il.id == id)
+                .map(|il| il.light.state.on)
+                .ok_or_else(|| eyre!("No light with ID {}", id)),
+            Target::Group(id) => backend
+                .get_all_groups()?
+                .into_iter()
+                .find(|ig| ig.id == id)
+                .map(|ig| ig.group.state.any_on)
+                .ok_or_else(|| eyre!("No group with ID {}", id)),
+            Target::All => Ok(backend.get_all_lights()?.iter().any(|il| il.light.state.on)),
+            Target::Lights(ref ids) => Ok(backend
+                .get_all_lights()?
+                .iter()
+                .any(|il| ids.contains(&il.id) && il.light.state.on)),
+        }
+    }
+
+    /// Returns the average brightness of the target's lights that are on, or `None` if all are
+    /// off.
+    pub fn brightness(&self, backend: &dyn LightBackend) -> Result<Option<u8>> {
+        let ids = self.lights(backend)?;
+        let on: Vec<u32> = backend
+            .get_all_lights()?
+            .iter()
+            .filter(|il| ids.contains(&il.id) && il.light.state.on)
+            .map(|il| il.light.state.bri.unwrap_or(254) as u32)
+            .collect();
+        match on.len() {
+            0 => Ok(None),
+            len => Ok(Some((on.iter().sum::<u32>() / len as u32) as u8)),
+        }
+    }
+
+    /// Returns the IDs of the target's lights.
+    pub fn lights(&self, backend: &dyn LightBackend) -> Result<Vec<usize>> {
+        match *self {
+            Target::Light(id) => Ok(vec![id]),
+            Target::Group(id) => Ok(backend
+                .get_all_groups()?
+                .into_iter()
+                .find(|ig| ig.id == id)
+                .ok_or_else(|| eyre!("No group with ID {}", id))?
+                .group
+                .lights
+                .iter()
+                .filter_map(|light| light.parse().ok())
+                .collect()),
+            Target::All => Ok(backend.get_all_lights()?.iter().map(|il| il.id).collect()),
+            Target::Lights(ref ids) => Ok(ids.clone()),
+        }
+    }
+
+    /// Address of the target's state on the bridge, for actions in rules.
+    pub fn action_address(&self) -> Result<String> {
+        match self {
+            Target::Light(id) => Ok(format!("/lights/{}/state", id)),
+            Target::Group(id) => Ok(format!("/groups/{}/action", id)),
+            Target::All => Ok("/groups/0/action".to_owned()),
+            Target::Lights(_) => Err(eyre!(
+                "Rules on the bridge act on one light or group, not on {}",
+                self
+            )),
+        }
+    }
+
+    /// Sets the state of the target. Lights picked by a selector are all tried, failing with
+    /// the first error if any of them failed.
+    pub fn set_state(&self, backend: &dyn LightBackend, command: &CommandLight) -> Result<()> {
+        match *self {
+            Target::Light(id) => backend.set_light_state(id, command),
+            Target::Group(id) => backend.set_group_state(id, command),
+            Target::All => backend.set_group_state(0, command),
+            Target::Lights(ref ids) => ids
+                .iter()
+                .map(|&id| backend.set_light_state(id, command))
+                .fold(Ok(()), Result::and),
+        }
+    }
+}
+
+/// Formats the target like in the audit log, e.g. `light/3`.
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Target::Light(id) => write!(f, "light/{}", id),
+            Target::Group(id) => write!(f, "group/{}", id),
+            Target::All => write!(f, "all"),
+            Target::Lights(ids) => {
+                let ids: Vec<String> = ids.iter().map(usize::to_string).collect();
+                write!(f, "lights/{}", ids.join(","))
+            }
+        }
+    }
+}
+
+/// Resolves a target given like `light:3`, `group:Living room`, `all`, or just the name of a
+/// group or light. Lights and groups may be given by ID or name.
+pub fn resolve(spec: &str, backend: &dyn LightBackend, ttl: Duration) -> Result<Target> {
+    match spec.split_once(':') {
+        Some(("light", light)) => Ok(Target::Light(cache::resolve_light(backend, ttl, light)?)),
+        Some(("group", group)) => Ok(Target::Group(cache::resolve_group(backend, ttl, group)?)),
+        None if spec == "all" => Ok(Target::All),
+        None if spec.parse::<usize>().is_ok() => Err(eyre!(
+            "Ambiguous target {:?}, use light:{} or group:{}",
+            spec,
+            spec,
+            spec
+        )),
+        // A bare name may be a group or a light. Groups win, as rooms are the common case.
+        None => cache::resolve_group(backend, ttl, spec)
+            .map(Target::Group)
+            .or_else(|_| cache::resolve_light(backend, ttl, spec).map(Target::Light))
+            .map_err(|_| eyre!("No group or light named {:?}", spec)),
+        _ => Err(eyre!(
+            "Unknown target {:?}, expected light:<id or name>, group:<id or name>, \
+             room:<pattern>, tag:<name>, all, or an alias",
+            spec
+        )),
+    }
+}
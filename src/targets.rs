@@ -0,0 +1,234 @@
+use crate::backend::LightBackend;
+use crate::config::Config;
+use crate::target::{self, Target};
+use eyre::{eyre, Result};
+use std::collections::BTreeSet;
+
+/// A target selector like `group:kitchen + light:desk - light:4`, whose terms add lights to or
+/// remove them from the selection, from left to right.
+#[derive(Debug, PartialEq)]
+pub struct Selector {
+    terms: Vec<(Op, Term)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Add,
+    Remove,
+}
+
+#[derive(Debug, PartialEq)]
+enum Term {
+    /// A single target like `light:3`, `kitchen`, `all`, or an alias.
+    Spec(String),
+    /// The rooms with names matching a pattern like `*floor*`, ignoring case.
+    Room(String),
+    /// The lights tagged with a label in the config.
+    Tag(String),
+    /// The lights that are on, or off, right now.
+    Power(bool),
+}
+
+/// Parses a selector. The `+` and `-` between terms need spaces around them, as names may
+/// contain dashes.
+pub fn parse(s: &str) -> Result<Selector, String> {
+    let mut terms = vec![];
+    let mut op = Op::Add;
+    let mut rest = s;
+    loop {
+        let next = [(" + ", Op::Add), (" - ", Op::Remove)]
+            .iter()
+            .filter_map(|&(sep, op)| rest.find(sep).map(|i| (i, op)))
+            .min_by_key(|&(i, _)| i);
+        let term = match next {
+            Some((i, _)) => &rest[..i],
+            None => rest,
+        };
+        terms.push((op, parse_term(term.trim(), s)?));
+        match next {
+            Some((i, next_op)) => {
+                op = next_op;
+                rest = &rest[i + 3..];
+            }
+            None => return Ok(Selector { terms }),
+        }
+    }
+}
+
+fn parse_term(term: &str, selector: &str) -> Result<Term, String> {
+    match term {
+        "" => Err(format!("Missing a target in {:?}", selector)),
+        "@on" => Ok(Term::Power(true)),
+        "@off" => Ok(Term::Power(false)),
+        _ if term.starts_with('@') => {
+            Err(format!("Unknown target {:?}, expected @on or @off", term))
+        }
+        _ => match term.split_once(':') {
+            Some(("room", pattern)) => Ok(Term::Room(pattern.to_owned())),
+            Some(("tag", tag)) => Ok(Term::Tag(tag.to_owned())),
+            _ => Ok(Term::Spec(term.to_owned())),
+        },
+    }
+}
+
+/// Returns whether the name matches the pattern, where `*` matches anything, ignoring case.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let (pattern, name) = (pattern.to_lowercase(), name.to_lowercase());
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match name.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let parts: Vec<&str> = parts.collect();
+    match parts.split_last() {
+        None => rest.is_empty(),
+        Some((last, middle)) => {
+            for part in middle {
+                match rest.find(part) {
+                    Some(i) => rest = &rest[i + part.len()..],
+                    None => return false,
+                }
+            }
+            rest.len() >= last.len() && rest.ends_with(last)
+        }
+    }
+}
+
+/// Resolves a selector to a target. A single light, group, or all lights stays as it is, so
+/// that it is controlled with one request, while anything else selects lights one by one.
+pub fn resolve(selector: &str, backend: &dyn LightBackend, config: &Config) -> Result<Target> {
+    resolve_with(selector, backend, config, true)
+}
+
+/// Resolves a selector, expanding aliases only if `expand_aliases`, so that aliases can't refer
+/// to each other in circles.
+fn resolve_with(
+    selector: &str,
+    backend: &dyn LightBackend,
+    config: &Config,
+    expand_aliases: bool,
+) -> Result<Target> {
+    let parsed = parse(selector).map_err(|err| eyre!(err))?;
+    if let [(Op::Add, Term::Spec(spec))] = parsed.terms.as_slice() {
+        return resolve_spec(spec, backend, config, expand_aliases);
+    }
+    let mut lights = BTreeSet::new();
+    for (op, term) in parsed.terms {
+        let ids = match term {
+            Term::Spec(spec) => {
+                resolve_spec(&spec, backend, config, expand_aliases)?.lights(backend)?
+            }
+            Term::Tag(tag) => config
+                .tags
+                .get(&tag)
+                .cloned()
+                .ok_or_else(|| eyre!("No lights tagged {:?}", tag))?,
+            Term::Room(pattern) => {
+                let rooms: Vec<_> = backend
+                    .get_all_groups()?
+                    .into_iter()
+                    .filter(|ig| ig.group.r#type == "Room" && matches(&pattern, &ig.group.name))
+                    .collect();
+                if rooms.is_empty() {
+                    return Err(eyre!("No room matches {:?}", pattern));
+                }
+                rooms
+                    .iter()
+                    .flat_map(|ig| ig.group.lights.iter().filter_map(|id| id.parse().ok()))
+                    .collect()
+            }
+            Term::Power(on) => backend
+                .get_all_lights()?
+                .into_iter()
+                .filter(|il| il.light.state.on == on)
+                .map(|il| il.id)
+                .collect(),
+        };
+        match op {
+            Op::Add => lights.extend(ids),
+            Op::Remove => lights.retain(|id| !ids.contains(id)),
+        }
+    }
+    if lights.is_empty() {
+        return Err(eyre!("No lights selected by {:?}", selector));
+    }
+    Ok(Target::Lights(lights.into_iter().collect()))
+}
+
+fn resolve_spec(
+    spec: &str,
+    backend: &dyn LightBackend,
+    config: &Config,
+    expand_aliases: bool,
+) -> Result<Target> {
+    match config.aliases.get(spec).filter(|_| expand_aliases) {
+        Some(aliased) => resolve_with(aliased, backend, config, false)
+            .map_err(|err| eyre!("Alias {:?}: {}", spec, err)),
+        None => target::resolve(spec, backend, config.cache.ttl),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{matches, parse, resolve, Op, Term};
+    use crate::backend::mock::MockBackend;
+    use crate::config::Config;
+    use crate::target::Target;
+
+    #[test]
+    fn selectors_are_parsed() {
+        let selector = parse("group:kitchen + light:desk lamp - light:4").unwrap();
+        assert_eq!(
+            selector.terms,
+            vec![
+                (Op::Add, Term::Spec("group:kitchen".to_owned())),
+                (Op::Add, Term::Spec("light:desk lamp".to_owned())),
+                (Op::Remove, Term::Spec("light:4".to_owned())),
+            ]
+        );
+        let selector = parse("room:*floor* - @off + tag:reading").unwrap();
+        assert_eq!(
+            selector.terms,
+            vec![
+                (Op::Add, Term::Room("*floor*".to_owned())),
+                (Op::Remove, Term::Power(false)),
+                (Op::Add, Term::Tag("reading".to_owned())),
+            ]
+        );
+        assert_eq!(parse("light:x-1").unwrap().terms.len(), 1);
+        assert!(parse("kitchen + ").is_err());
+        assert!(parse("@dimmed").is_err());
+    }
+
+    #[test]
+    fn patterns_match_names() {
+        assert!(matches("*floor*", "First floor hall"));
+        assert!(matches("kitchen", "Kitchen"));
+        assert!(matches("bed*", "Bedroom"));
+        assert!(!matches("bed*", "Master bedroom"));
+        assert!(!matches("*room", "Roomy"));
+    }
+
+    #[test]
+    fn selectors_resolve_to_lights() {
+        let backend = MockBackend::default()
+            .with_light(1, "Desk", true, 100)
+            .with_light(2, "Shelf", false, 10)
+            .with_light(3, "Hall", true, 10)
+            .with_group(1, "Office", &[1, 2]);
+        let mut config = Config::default();
+        config.tags.insert("reading".to_owned(), vec![3]);
+        config
+            .aliases
+            .insert("desk".to_owned(), "light:1 + tag:reading".to_owned());
+        let resolve = |s| resolve(s, &backend, &config).unwrap();
+
+        assert_eq!(resolve("light:1"), Target::Light(1));
+        assert_eq!(resolve("group:1 + light:3"), Target::Lights(vec![1, 2, 3]));
+        assert_eq!(resolve("room:off* - light:1"), Target::Lights(vec![2]));
+        assert_eq!(resolve("@on"), Target::Lights(vec![1, 3]));
+        assert_eq!(resolve("desk - @off"), Target::Lights(vec![1, 3]));
+        assert_eq!(resolve("tag:reading"), Target::Lights(vec![3]));
+    }
+}
@@ -0,0 +1,108 @@
+/// A line of output shaped by the user, like `{id}\t{name}\t{bri}`, for listing commands.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Field(&'static str),
+}
+
+impl Template {
+    /// Parses a template with `{field}` for each of the given fields, `{{` and `}}` for literal
+    /// braces, and `\t`, `\n`, and `\\` for tabs, newlines, and backslashes.
+    pub fn parse(s: &str, fields: &[&'static str]) -> Result<Template, String> {
+        let mut parts = vec![];
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err("Unclosed { in format".to_owned()),
+                        }
+                    }
+                    let field = fields.iter().find(|&&field| field == name).ok_or_else(|| {
+                        format!(
+                            "Unknown field {{{}}}, expected one of {}",
+                            name,
+                            fields
+                                .iter()
+                                .map(|field| format!("{{{}}}", field))
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        )
+                    })?;
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Field(field));
+                }
+                '}' => return Err("Unmatched } in format, use }} for a literal brace".to_owned()),
+                '\\' => match chars.next() {
+                    Some('t') => text.push('\t'),
+                    Some('n') => text.push('\n'),
+                    Some('\\') => text.push('\\'),
+                    Some(other) => {
+                        text.push('\\');
+                        text.push(other);
+                    }
+                    None => text.push('\\'),
+                },
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Template { parts })
+    }
+
+    /// Renders the template with the value of each field.
+    pub fn render(&self, value: impl Fn(&str) -> String) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.to_owned(),
+                Part::Field(field) => value(field),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Template;
+
+    #[test]
+    fn fields_are_filled_in() {
+        let template = Template::parse(r"{id}\t{name} {{on}}", &["id", "name"]).unwrap();
+        let line = template.render(|field| match field {
+            "id" => "3".to_owned(),
+            _ => "Desk".to_owned(),
+        });
+        assert_eq!(line, "3\tDesk {on}");
+    }
+
+    #[test]
+    fn unknown_fields_and_braces_are_rejected() {
+        let err = Template::parse("{id} {nmae}", &["id", "name"]).unwrap_err();
+        assert_eq!(err, "Unknown field {nmae}, expected one of {id}, {name}");
+        assert!(Template::parse("{id}}", &["id"]).is_err());
+        assert!(Template::parse("{id", &["id"]).is_err());
+    }
+}
@@ -0,0 +1,412 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Returns the number of seconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Parses humane durations like `500ms`, `2s`, `10m`, `1h30m`, or `7d`.
+///
+/// A bare number is interpreted as seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    if let Ok(secs) = s.parse::<u64>() {
+        return Ok(Duration::from_secs(secs));
+    }
+
+    let mut total = Duration::from_secs(0);
+    let mut rest = s;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(|| format!("Missing unit in duration {:?}", s))?;
+        if digits == 0 {
+            return Err(format!("Invalid duration {:?}", s));
+        }
+        let value: u64 = rest[..digits]
+            .parse()
+            .map_err(|_| format!("Invalid duration {:?}", s))?;
+        rest = &rest[digits..];
+
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let unit = match &rest[..unit_len] {
+            "ms" => Duration::from_millis(1),
+            "s" => Duration::from_secs(1),
+            "m" => Duration::from_secs(60),
+            "h" => Duration::from_secs(60 * 60),
+            "d" => Duration::from_secs(24 * 60 * 60),
+            other => return Err(format!("Unknown duration unit {:?} in {:?}", other, s)),
+        };
+        rest = &rest[unit_len..];
+
+        let too_long = || format!("Duration {:?} is too long", s);
+        let part = u32::try_from(value)
+            .ok()
+            .and_then(|value| unit.checked_mul(value))
+            .ok_or_else(too_long)?;
+        total = total.checked_add(part).ok_or_else(too_long)?;
+    }
+
+    if s.is_empty() {
+        return Err("Empty duration".to_string());
+    }
+    Ok(total)
+}
+
+/// A time of day, like 07:00.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeOfDay {
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+/// Formats the time like `07:00:00`, as the bridge takes it.
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02}:{:02}:{:02}", self.hour, self.minute, self.second)
+    }
+}
+
+/// Parses a 24-hour time of day like `07:00` or `22:30:15`.
+pub fn parse_time_of_day(s: &str) -> Result<TimeOfDay, String> {
+    let invalid = || format!("Invalid time {:?}, expected a time like 07:00", s);
+    let parts = s
+        .trim()
+        .split(':')
+        .map(|part| part.parse::<u8>().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let time = match parts[..] {
+        [hour, minute] => TimeOfDay {
+            hour,
+            minute,
+            second: 0,
+        },
+        [hour, minute, second] => TimeOfDay {
+            hour,
+            minute,
+            second,
+        },
+        _ => return Err(invalid()),
+    };
+    if time.hour > 23 || time.minute > 59 || time.second > 59 {
+        return Err(invalid());
+    }
+    Ok(time)
+}
+
+/// Parses a range of times of day like `23:00-06:00`, which may wrap past midnight.
+pub fn parse_time_range(s: &str) -> Result<(TimeOfDay, TimeOfDay), String> {
+    let (start, end) = s.split_once('-').ok_or_else(|| {
+        format!(
+            "Invalid time range {:?}, expected a range like 23:00-06:00",
+            s
+        )
+    })?;
+    Ok((parse_time_of_day(start)?, parse_time_of_day(end)?))
+}
+
+const WEEKDAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// Parses days of the week like `mon-fri`, `sat,sun`, or `daily` into the bridge's bitmask for
+/// recurring schedules, where Monday is 64 and Sunday is 1.
+pub fn parse_weekdays(s: &str) -> Result<u8, String> {
+    let day = |name: &str| {
+        WEEKDAYS
+            .iter()
+            .position(|day| name.trim().eq_ignore_ascii_case(day))
+            .ok_or_else(|| {
+                format!(
+                    "Unknown day {:?}, expected one of {}",
+                    name,
+                    WEEKDAYS.join(", ")
+                )
+            })
+    };
+    if s.trim().eq_ignore_ascii_case("daily") {
+        return Ok(0b111_1111);
+    }
+    let mut mask = 0;
+    for part in s.split(',') {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (day(first)?, day(last)?),
+            None => (day(part)?, day(part)?),
+        };
+        if first > last {
+            return Err(format!("Days {:?} are not in order from Monday", part));
+        }
+        for day in first..=last {
+            mask |= 64 >> day;
+        }
+    }
+    Ok(mask)
+}
+
+/// Formats seconds since the Unix epoch as an RFC 3339 timestamp in UTC.
+pub fn format_utc(secs: u64) -> String {
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let rem = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// A calendar date as year, month, and day.
+pub type Date = (i64, u32, u32);
+
+/// Returns the date a number of days after the Unix epoch.
+pub fn civil_from_days(days: i64) -> Date {
+    // Civil-from-days, from Howard Hinnant's date algorithms.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month as u32, day as u32)
+}
+
+/// Returns the number of days from the Unix epoch to the date, the inverse of `civil_from_days`.
+pub fn days_from_civil((year, month, day): Date) -> i64 {
+    let (month, day) = (month as i64, day as i64);
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Returns the day after the date.
+pub fn next_day(date: Date) -> Date {
+    civil_from_days(days_from_civil(date) + 1)
+}
+
+/// Returns the day of the week of the date in the bitmask of `parse_weekdays`.
+pub fn weekday_bit(date: Date) -> u8 {
+    // The Unix epoch was a Thursday.
+    64 >> (days_from_civil(date) + 3).rem_euclid(7)
+}
+
+/// Converts a date and time in UTC to seconds since the Unix epoch.
+pub fn epoch_from_utc(date: Date, time: TimeOfDay) -> u64 {
+    let secs = days_from_civil(date) * 86400
+        + time.hour as i64 * 3600
+        + time.minute as i64 * 60
+        + time.second as i64;
+    secs.max(0) as u64
+}
+
+/// Returns the date in the system's time zone at seconds since the Unix epoch.
+#[cfg(unix)]
+pub fn local_date(secs: u64) -> Date {
+    let time = secs as libc::time_t;
+    // SAFETY: `tm` is plain data, and `localtime_r` only writes to it.
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return civil_from_days((secs / 86400) as i64);
+    }
+    (
+        tm.tm_year as i64 + 1900,
+        tm.tm_mon as u32 + 1,
+        tm.tm_mday as u32,
+    )
+}
+
+/// Returns the date at seconds since the Unix epoch, in UTC where the system's time zone isn't
+/// available.
+#[cfg(not(unix))]
+pub fn local_date(secs: u64) -> Date {
+    civil_from_days((secs / 86400) as i64)
+}
+
+/// Converts a date and time in the system's time zone to seconds since the Unix epoch.
+#[cfg(unix)]
+pub fn epoch_from_local((year, month, day): Date, time: TimeOfDay) -> u64 {
+    // SAFETY: `tm` is plain data, and `mktime` only reads and normalizes it.
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    tm.tm_year = (year - 1900) as libc::c_int;
+    tm.tm_mon = month as libc::c_int - 1;
+    tm.tm_mday = day as libc::c_int;
+    tm.tm_hour = time.hour.into();
+    tm.tm_min = time.minute.into();
+    tm.tm_sec = time.second.into();
+    // Let the C library figure out whether daylight saving time is in effect.
+    tm.tm_isdst = -1;
+    match unsafe { libc::mktime(&mut tm) } {
+        -1 => epoch_from_utc((year, month, day), time),
+        secs => secs.max(0) as u64,
+    }
+}
+
+/// Converts a date and time to seconds since the Unix epoch, taking it as UTC where the system's
+/// time zone isn't available.
+#[cfg(not(unix))]
+pub fn epoch_from_local(date: Date, time: TimeOfDay) -> u64 {
+    epoch_from_utc(date, time)
+}
+
+/// Formats a duration the way `parse_duration` reads it, like `1h30m` or `500ms`.
+pub fn format_duration(duration: Duration) -> String {
+    const UNITS: [(&str, u128); 5] = [
+        ("d", 24 * 60 * 60 * 1000),
+        ("h", 60 * 60 * 1000),
+        ("m", 60 * 1000),
+        ("s", 1000),
+        ("ms", 1),
+    ];
+    let mut rest = duration.as_millis();
+    if rest == 0 {
+        return "0s".to_owned();
+    }
+    let mut formatted = String::new();
+    for (unit, millis) in UNITS.iter() {
+        if rest >= *millis {
+            formatted.push_str(&format!("{}{}", rest / millis, unit));
+            rest %= millis;
+        }
+    }
+    formatted
+}
+
+/// Serializes durations in config files as humane strings like `1d`, and also accepts a number
+/// of seconds.
+pub mod humane {
+    use super::{format_duration, parse_duration};
+    use serde::de::{self, Deserializer, Visitor};
+    use serde::Serializer;
+    use std::convert::TryFrom;
+    use std::fmt;
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format_duration(*duration))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        struct DurationVisitor;
+
+        impl<'de> Visitor<'de> for DurationVisitor {
+            type Value = Duration;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a duration like \"10m\" or \"1d\", or a number of seconds")
+            }
+
+            fn visit_u64<E: de::Error>(self, secs: u64) -> Result<Duration, E> {
+                Ok(Duration::from_secs(secs))
+            }
+
+            fn visit_i64<E: de::Error>(self, secs: i64) -> Result<Duration, E> {
+                u64::try_from(secs)
+                    .map(Duration::from_secs)
+                    .map_err(|_| E::custom("duration must not be negative"))
+            }
+
+            fn visit_str<E: de::Error>(self, s: &str) -> Result<Duration, E> {
+                parse_duration(s).map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(DurationVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        epoch_from_utc, format_duration, format_utc, next_day, parse_duration, parse_time_of_day,
+        parse_time_range, parse_weekdays, weekday_bit, TimeOfDay,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn parses_compound_durations() {
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(90 * 60)));
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert!(parse_duration("5 parsecs").is_err());
+        assert!(parse_duration("").is_err());
+        assert_eq!(
+            parse_duration("4294967296s"),
+            Err("Duration \"4294967296s\" is too long".to_owned())
+        );
+    }
+
+    #[test]
+    fn formats_durations_parse_duration_reads() {
+        for s in &["1d", "1h30m", "2s500ms", "0s"] {
+            assert_eq!(format_duration(parse_duration(s).unwrap()), *s);
+        }
+    }
+
+    #[test]
+    fn parses_times_of_day() {
+        assert_eq!(parse_time_of_day("07:00").unwrap().to_string(), "07:00:00");
+        assert_eq!(
+            parse_time_of_day("22:30:15").unwrap().to_string(),
+            "22:30:15"
+        );
+        assert!(parse_time_of_day("24:00").is_err());
+        assert!(parse_time_of_day("7").is_err());
+    }
+
+    #[test]
+    fn parses_weekdays_into_bitmask() {
+        assert_eq!(parse_weekdays("mon-fri"), Ok(124));
+        assert_eq!(parse_weekdays("sat,sun"), Ok(3));
+        assert_eq!(parse_weekdays("Mon,wed-thu"), Ok(64 | 16 | 8));
+        assert_eq!(parse_weekdays("daily"), Ok(127));
+        assert!(parse_weekdays("fri-mon").is_err());
+        assert!(parse_weekdays("someday").is_err());
+    }
+
+    #[test]
+    fn parses_time_ranges() {
+        let (start, end) = parse_time_range("23:00-06:00").unwrap();
+        assert_eq!((start.hour, end.hour), (23, 6));
+        assert!(parse_time_range("23:00").is_err());
+    }
+
+    #[test]
+    fn epoch_from_utc_is_inverse_of_format_utc() {
+        let time = TimeOfDay {
+            hour: 9,
+            minute: 30,
+            second: 15,
+        };
+        let secs = epoch_from_utc((2024, 2, 29), time);
+        assert_eq!(format_utc(secs), "2024-02-29T09:30:15Z");
+        let midnight = TimeOfDay {
+            hour: 0,
+            minute: 0,
+            second: 0,
+        };
+        assert_eq!(epoch_from_utc((1970, 1, 1), midnight), 0);
+    }
+
+    #[test]
+    fn dates_step_through_months_and_weekdays() {
+        assert_eq!(next_day((2024, 2, 28)), (2024, 2, 29));
+        assert_eq!(next_day((2024, 12, 31)), (2025, 1, 1));
+        // 2026-10-15 is a Thursday, and 2026-10-18 a Sunday.
+        assert_eq!(weekday_bit((2026, 10, 15)), 8);
+        assert_eq!(weekday_bit((2026, 10, 18)), 1);
+    }
+}
@@ -0,0 +1,103 @@
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Stands in for the username in recorded paths, as the username grants full control of the
+/// lights.
+const USERNAME_PLACEHOLDER: &str = "<username>";
+
+/// An HTTP request to the bridge and its response.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Exchange {
+    pub method: String,
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request: Option<String>,
+    pub status: u16,
+    pub response: String,
+}
+
+/// Appends exchanges with the bridge to a JSON-lines file.
+pub struct Recorder {
+    file: Mutex<fs::File>,
+}
+
+impl Recorder {
+    pub fn open(path: &Path) -> Result<Recorder> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| eyre!("Failed to open {}: {}", path.display(), err))?;
+        Ok(Recorder {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn record(&self, username: &str, mut exchange: Exchange) -> Result<()> {
+        if !username.is_empty() {
+            exchange.path = exchange.path.replace(username, USERNAME_PLACEHOLDER);
+        }
+        let mut line = serde_json::to_string(&exchange)?;
+        line.push('\n');
+        self.file
+            .lock()
+            .expect("Trace file lock poisoned")
+            .write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Serves responses from a recorded trace instead of talking to a bridge.
+pub struct Replay {
+    exchanges: Mutex<Vec<Exchange>>,
+}
+
+impl Replay {
+    pub fn open(path: &Path) -> Result<Replay> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| eyre!("Failed to read {}: {}", path.display(), err))?;
+        let exchanges = contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .enumerate()
+            .map(|(i, line)| {
+                serde_json::from_str(line)
+                    .map_err(|err| eyre!("{}:{}: {}", path.display(), i + 1, err))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Replay {
+            exchanges: Mutex::new(exchanges),
+        })
+    }
+
+    /// Returns the status and body of the first unused recorded response to the same request.
+    ///
+    /// Responses are used once each and in recorded order, so a trace that fetches the same
+    /// resource before and after a change replays both states.
+    pub fn respond(&self, method: &str, path: &str) -> Result<(u16, String)> {
+        let mut exchanges = self.exchanges.lock().expect("Trace lock poisoned");
+        let path = without_username(path);
+        let index = exchanges
+            .iter()
+            .position(|e| e.method == method && without_username(&e.path) == path)
+            .ok_or_else(|| eyre!("The trace has no more responses to {} {}", method, path))?;
+        let exchange = exchanges.remove(index);
+        Ok((exchange.status, exchange.response))
+    }
+}
+
+/// Strips the username from v1 API paths like `/api/<username>/lights`, so traces replay with
+/// any username.
+fn without_username(path: &str) -> String {
+    let segments: Vec<&str> = path.split('/').collect();
+    match segments.as_slice() {
+        ["", "api", _, rest @ ..] if !rest.is_empty() => format!("/api/_/{}", rest.join("/")),
+        // The full state, at `/api/<username>` itself.
+        ["", "api", "" | USERNAME_PLACEHOLDER] => "/api/_".to_owned(),
+        _ => path.to_owned(),
+    }
+}
@@ -0,0 +1,191 @@
+use eyre::{eyre, Result, WrapErr};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const RELEASES_URL: &str = "https://api.github.com/repos/jodal/blilys/releases/latest";
+
+/// Name of the release asset listing the SHA-256 checksums of the other assets.
+const CHECKSUMS_ASSET: &str = "SHA256SUMS";
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+impl Release {
+    fn asset(&self, name: &str) -> Result<&Asset> {
+        self.assets
+            .iter()
+            .find(|a| a.name == name)
+            .ok_or_else(|| eyre!("Release {} has no asset named {}", self.tag_name, name))
+    }
+}
+
+/// Replaces the running executable with the latest release from GitHub, if it is newer.
+pub fn self_update(check_only: bool) -> Result<()> {
+    let client = reqwest::blocking::Client::builder()
+        .user_agent(concat!("blilys/", env!("CARGO_PKG_VERSION")))
+        .build()?;
+    let release: Release = client
+        .get(RELEASES_URL)
+        .send()
+        .and_then(|resp| resp.error_for_status())
+        .and_then(|resp| resp.json())
+        .wrap_err("Failed to fetch the latest release")?;
+
+    let current = env!("CARGO_PKG_VERSION");
+    let latest = release.tag_name.trim_start_matches('v');
+    if !is_newer(latest, current) {
+        info!("blilys {} is up to date.", current);
+        return Ok(());
+    }
+    if check_only {
+        println!("blilys {} is available, you have {}.", latest, current);
+        return Ok(());
+    }
+
+    let name = asset_name();
+    let asset = release.asset(&name)?;
+    let checksums = client
+        .get(&release.asset(CHECKSUMS_ASSET)?.browser_download_url)
+        .send()?
+        .error_for_status()?
+        .text()?;
+    let expected = checksum(&checksums, &name)
+        .ok_or_else(|| eyre!("{} has no checksum for {}", CHECKSUMS_ASSET, name))?;
+
+    info!("Downloading blilys {} ...", latest);
+    let binary = client
+        .get(&asset.browser_download_url)
+        .send()?
+        .error_for_status()?
+        .bytes()?;
+    let actual = format!("{:x}", Sha256::digest(&binary));
+    if actual != expected {
+        return Err(eyre!(
+            "Checksum mismatch for {}: expected {}, got {}. Not updating.",
+            name,
+            expected,
+            actual
+        ));
+    }
+
+    let exe = env::current_exe()?;
+    replace(&exe, &binary).wrap_err_with(|| format!("Failed to replace {}", exe.display()))?;
+    info!("Updated blilys from {} to {}.", current, latest);
+    Ok(())
+}
+
+/// The release asset with the binary for this platform, like `blilys-x86_64-linux`.
+fn asset_name() -> String {
+    format!(
+        "blilys-{}-{}{}",
+        env::consts::ARCH,
+        env::consts::OS,
+        env::consts::EXE_SUFFIX
+    )
+}
+
+/// Finds the checksum of the file in the output of `sha256sum`, where binary files are marked
+/// with a `*` before the name.
+fn checksum(checksums: &str, name: &str) -> Option<String> {
+    checksums
+        .lines()
+        .filter_map(|line| line.split_once(char::is_whitespace))
+        .find(|(_, file)| file.trim().trim_start_matches('*') == name)
+        .map(|(sum, _)| sum.to_ascii_lowercase())
+}
+
+/// Compares versions like `1.2.0` or tags like `v1.2.0` by their dotted numbers, where a
+/// release is newer than its pre-releases like `1.2.0-rc.1`, and build metadata is ignored.
+fn is_newer(latest: &str, current: &str) -> bool {
+    let parse = |version: &str| -> (Vec<u64>, bool) {
+        let version = version.trim_start_matches('v');
+        let version = version.split('+').next().unwrap_or_default();
+        let (release, pre) = match version.split_once('-') {
+            Some((release, pre)) => (release, Some(pre)),
+            None => (version, None),
+        };
+        let numbers = release
+            .split('.')
+            .map_while(|part| part.parse().ok())
+            .collect();
+        (numbers, pre.is_none())
+    };
+    parse(latest) > parse(current)
+}
+
+/// Writes the new binary next to the executable and renames it into place, so the executable is
+/// never left half-written.
+fn replace(exe: &Path, binary: &[u8]) -> Result<()> {
+    let new = with_extension(exe, "new");
+    let mut file = fs::File::create(&new)?;
+    file.write_all(binary)?;
+    file.sync_all()?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&new, fs::Permissions::from_mode(0o755))?;
+    }
+    // Windows can't overwrite a running executable, but it can rename it.
+    #[cfg(windows)]
+    fs::rename(exe, with_extension(exe, "old"))?;
+    fs::rename(&new, exe)?;
+    Ok(())
+}
+
+fn with_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{checksum, is_newer};
+
+    #[test]
+    fn newer_versions_are_found() {
+        assert!(is_newer("1.3.0", "1.2.9"));
+        assert!(is_newer("1.10.0", "1.9.0"));
+        assert!(is_newer("v1.3.0", "1.2.0"));
+        assert!(!is_newer("v1.2.0", "1.2.0"));
+        assert!(!is_newer("1.2.0", "1.3.0"));
+    }
+
+    #[test]
+    fn releases_are_newer_than_their_pre_releases() {
+        assert!(is_newer("1.2.0", "1.2.0-rc.1"));
+        assert!(!is_newer("1.2.0-rc.1", "1.2.0"));
+        assert!(is_newer("v1.3.0-rc.1", "1.2.0"));
+        assert!(!is_newer("1.2.0+build.5", "1.2.0"));
+    }
+
+    #[test]
+    fn checksums_are_found_by_name() {
+        let checksums = "\
+            0A1B  blilys-x86_64-linux\n\
+            2c3d *blilys-x86_64-windows.exe\n";
+        assert_eq!(
+            checksum(checksums, "blilys-x86_64-linux").as_deref(),
+            Some("0a1b")
+        );
+        assert_eq!(
+            checksum(checksums, "blilys-x86_64-windows.exe").as_deref(),
+            Some("2c3d")
+        );
+        assert_eq!(checksum(checksums, "blilys-aarch64-linux"), None);
+    }
+}
@@ -0,0 +1,137 @@
+//! Parsers for command line values, with error messages suggesting the accepted formats.
+
+use crate::color;
+
+/// The range of color temperatures Hue lights support, in Kelvin.
+const KELVIN_RANGE: (u32, u32) = (2000, 6500);
+
+/// Parses a brightness given as a percentage like `50%` or a bridge value from 1 to 254.
+pub fn parse_brightness(s: &str) -> Result<u8, String> {
+    let s = s.trim();
+    if let Some(percent) = s.strip_suffix('%') {
+        let percent: f64 = percent
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid percentage {:?}", s))?;
+        if !(0.0..=100.0).contains(&percent) {
+            return Err(format!("Brightness {} is not between 0% and 100%", s));
+        }
+        // 0% is the lowest brightness a light can have while on.
+        return Ok((percent / 100.0 * 254.0).round().max(1.0) as u8);
+    }
+    match s.parse::<u8>() {
+        Ok(bri @ 1..=254) => Ok(bri),
+        _ => Err(format!(
+            "Invalid brightness {:?}, expected a percentage like 50% or a value from 1 to 254",
+            s
+        )),
+    }
+}
+
+/// Parses a color temperature in Kelvin, like `2700K`, into the bridge's unit of mired.
+pub fn parse_kelvin(s: &str) -> Result<u16, String> {
+    let s = s.trim();
+    let kelvin: u32 = s
+        .strip_suffix(['K', 'k'])
+        .unwrap_or(s)
+        .parse()
+        .map_err(|_| {
+            format!(
+                "Invalid color temperature {:?}, expected Kelvin like 2700K",
+                s
+            )
+        })?;
+    let (min, max) = KELVIN_RANGE;
+    if !(min..=max).contains(&kelvin) {
+        return Err(format!(
+            "Color temperature {}K is not between {}K and {}K",
+            kelvin, min, max
+        ));
+    }
+    Ok(color::kelvin_to_mired(kelvin))
+}
+
+/// Parses a color given as a hex code like `#ff8000` or a name like `orange` into CIE xy
+/// coordinates.
+pub fn parse_color(s: &str) -> Result<(f32, f32), String> {
+    let name = s.trim().to_ascii_lowercase();
+    let hex = match name.as_str() {
+        "red" => "ff0000",
+        "orange" => "ff8000",
+        "yellow" => "ffff00",
+        "green" => "00ff00",
+        "cyan" => "00ffff",
+        "blue" => "0000ff",
+        "purple" => "8000ff",
+        "pink" => "ff60c0",
+        "white" => "ffffff",
+        other => other.trim_start_matches('#'),
+    };
+    let invalid = || {
+        format!(
+            "Invalid color {:?}, expected a hex code like #ff8000 or one of red, orange, \\
+             yellow, green, cyan, blue, purple, pink, or white",
+            s
+        )
+    };
+    if hex.len() != 6 {
+        return Err(invalid());
+    }
+    let rgb = u32::from_str_radix(hex, 16).map_err(|_| invalid())?;
+    Ok(color::rgb_to_xy(
+        (rgb >> 16) as u8,
+        (rgb >> 8 & 0xff) as u8,
+        (rgb & 0xff) as u8,
+    ))
+}
+
+/// Parses a location given as latitude and longitude in degrees, like `59.9,10.7`.
+pub fn parse_location(s: &str) -> Result<(f64, f64), String> {
+    let invalid = || format!("Invalid location {:?}, expected latitude,longitude", s);
+    let (lat, lon) = s.split_once(',').ok_or_else(invalid)?;
+    let lat: f64 = lat.trim().parse().map_err(|_| invalid())?;
+    let lon: f64 = lon.trim().parse().map_err(|_| invalid())?;
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+        return Err(format!("Location {:?} is out of range", s));
+    }
+    Ok((lat, lon))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_brightness, parse_color, parse_kelvin, parse_location};
+
+    #[test]
+    fn brightness_accepts_percentages_and_raw_values() {
+        assert_eq!(parse_brightness("100%"), Ok(254));
+        assert_eq!(parse_brightness("0%"), Ok(1));
+        assert_eq!(parse_brightness("1"), Ok(1));
+        assert!(parse_brightness("0").is_err());
+        assert!(parse_brightness("255").is_err());
+        assert!(parse_brightness("101%").is_err());
+    }
+
+    #[test]
+    fn kelvin_converts_to_mired() {
+        assert_eq!(parse_kelvin("2000K"), Ok(500));
+        assert_eq!(parse_kelvin("6500"), Ok(154));
+        assert!(parse_kelvin("1000K").is_err());
+        assert!(parse_kelvin("warm").is_err());
+    }
+
+    #[test]
+    fn color_accepts_hex_and_names() {
+        assert_eq!(parse_color("#ff0000"), Ok((0.7006, 0.2993)));
+        assert_eq!(parse_color("Red"), parse_color("ff0000"));
+        assert!(parse_color("#ff00").is_err());
+        assert!(parse_color("mauve").is_err());
+    }
+
+    #[test]
+    fn location_is_latitude_then_longitude() {
+        assert_eq!(parse_location("59.9,10.7"), Ok((59.9, 10.7)));
+        assert_eq!(parse_location("-33.9, 151.2"), Ok((-33.9, 151.2)));
+        assert!(parse_location("59.9").is_err());
+        assert!(parse_location("91,10").is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use crate::backend::{Datastore, LightBackend, Members};
+use crate::color::kelvin_to_mired;
+use eyre::Result;
+use hueclient::{CommandLight, IdentifiedGroup, IdentifiedLight, IdentifiedScene};
+use std::collections::HashSet;
+
+/// Color temperature at full brightness, in Kelvin.
+const FULL_KELVIN: f64 = 2700.0;
+
+/// Color temperature at the lowest brightness, in Kelvin, like a dimmed incandescent bulb.
+const DIM_KELVIN: f64 = 2200.0;
+
+/// A backend that gives commands setting only the brightness a color temperature that gets warmer
+/// as the light dims, like an incandescent bulb.
+///
+/// Only lights that take a color temperature get one, and a command to a group only does if any
+/// of its lights do.
+pub struct Warm<'a> {
+    inner: &'a dyn LightBackend,
+    /// Lights that take a color temperature, or `None` when commands are passed on as they are.
+    lights: Option<HashSet<usize>>,
+    members: &'a Members,
+}
+
+impl<'a> Warm<'a> {
+    pub fn new(
+        inner: &'a dyn LightBackend,
+        enabled: bool,
+        datastore: &Datastore,
+        members: &'a Members,
+    ) -> Warm<'a> {
+        let lights = datastore
+            .lights
+            .iter()
+            .filter(|il| il.light.state.ct.is_some());
+        Warm {
+            inner,
+            lights: Some(lights.map(|il| il.id).collect()).filter(|_| enabled),
+            members,
+        }
+    }
+
+    fn warm(
+        &self,
+        command: &CommandLight,
+        has_ct: impl Fn(&HashSet<usize>) -> bool,
+    ) -> CommandLight {
+        let mut command = command.clone();
+        let color = command.ct.is_some()
+            || command.xy.is_some()
+            || command.hue.is_some()
+            || command.sat.is_some();
+        if let (Some(bri), Some(lights), false) = (command.bri, &self.lights, color) {
+            if has_ct(lights) {
+                command.ct = Some(warm_ct(bri));
+            }
+        }
+        command
+    }
+}
+
+/// Returns the color temperature in mireds for a brightness, falling faster as the light gets
+/// dimmer, like the filament of an incandescent bulb cooling down.
+fn warm_ct(bri: u8) -> u16 {
+    let level = (bri as f64 / 254.0).clamp(0.0, 1.0);
+    let kelvin = DIM_KELVIN + (FULL_KELVIN - DIM_KELVIN) * level.sqrt();
+    kelvin_to_mired(kelvin.round() as u32)
+}
+
+impl LightBackend for Warm<'_> {
+    fn describe(&self) -> String {
+        self.inner.describe()
+    }
+
+    fn get_all_lights(&self) -> Result<Vec<IdentifiedLight>> {
+        self.inner.get_all_lights()
+    }
+
+    fn get_all_groups(&self) -> Result<Vec<IdentifiedGroup>> {
+        self.inner.get_all_groups()
+    }
+
+    fn get_all_scenes(&self) -> Result<Vec<IdentifiedScene>> {
+        self.inner.get_all_scenes()
+    }
+
+    fn set_light_state(&self, light: usize, command: &CommandLight) -> Result<()> {
+        let command = self.warm(command, |lights| lights.contains(&light));
+        self.inner.set_light_state(light, &command)
+    }
+
+    fn set_group_state(&self, group: usize, command: &CommandLight) -> Result<()> {
+        let members = self.members.of(group);
+        let command = self.warm(command, |lights| {
+            members.iter().any(|id| lights.contains(id))
+        });
+        self.inner.set_group_state(group, &command)
+    }
+
+    fn get_datastore(&self) -> Result<Datastore> {
+        self.inner.get_datastore()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::warm_ct;
+
+    #[test]
+    fn lights_get_warmer_as_they_dim() {
+        assert_eq!(warm_ct(254), 370);
+        assert_eq!(warm_ct(127), 392);
+        assert_eq!(warm_ct(25), 424);
+        assert_eq!(warm_ct(1), 448);
+    }
+}
@@ -0,0 +1,147 @@
+use crate::backend::LightBackend;
+use crate::target::Target;
+use crate::values::parse_color;
+use eyre::{Result, WrapErr};
+use hueclient::CommandLight;
+use reqwest::blocking::Client;
+use serde_json::Value;
+use std::thread;
+use std::time::Duration;
+
+const MET_NO_FORECAST_URL: &str = "https://api.met.no/weatherapi/locationforecast/2.0/compact";
+const MET_NO_ALERTS_URL: &str = "https://api.met.no/weatherapi/metalerts/2.0/current.json";
+
+/// The weather as shown by the light.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Weather {
+    Sun,
+    Clouds,
+    Rain,
+    Snow,
+    /// An official warning for the location, like for storms or floods.
+    Warning,
+}
+
+impl Weather {
+    /// Classifies a met.no symbol code like `lightrainshowers_day`.
+    fn from_symbol(symbol: &str) -> Weather {
+        // Symbols end with the time of day, like `_day`, `_night`, or `_polartwilight`.
+        let symbol = symbol.split('_').next().unwrap_or(symbol);
+        if symbol.contains("snow") {
+            Weather::Snow
+        } else if symbol.contains("rain") || symbol.contains("sleet") {
+            Weather::Rain
+        } else if symbol == "clearsky" || symbol == "fair" {
+            Weather::Sun
+        } else {
+            Weather::Clouds
+        }
+    }
+
+    fn command(self) -> CommandLight {
+        let color = |name| parse_color(name).ok();
+        let (xy, ct, alert) = match self {
+            Weather::Sun => (color("yellow"), None, None),
+            Weather::Clouds => (None, Some(250), None),
+            Weather::Rain => (color("blue"), None, None),
+            Weather::Snow => (color("cyan"), None, None),
+            // Breathes for 15 seconds at every update.
+            Weather::Warning => (None, Some(153), Some("lselect".to_owned())),
+        };
+        CommandLight {
+            on: Some(true),
+            xy,
+            ct,
+            alert,
+            ..CommandLight::default()
+        }
+    }
+}
+
+/// Fetches the weather for the next hour from the Norwegian Meteorological Institute, with any
+/// current warnings taking precedence. Its warnings only cover Norway and its waters.
+fn fetch(client: &Client, (lat, lon): (f64, f64)) -> Result<Weather> {
+    // met.no asks for coordinates with at most four decimals, to make use of its caches.
+    let query = [
+        ("lat", format!("{:.4}", lat)),
+        ("lon", format!("{:.4}", lon)),
+    ];
+    let alerts: Value = client
+        .get(MET_NO_ALERTS_URL)
+        .query(&query)
+        .send()
+        .and_then(|resp| resp.error_for_status())
+        .and_then(|resp| resp.json())
+        .wrap_err("Failed to fetch weather warnings")?;
+    if alerts["features"].as_array().is_some_and(|a| !a.is_empty()) {
+        return Ok(Weather::Warning);
+    }
+
+    let forecast: Value = client
+        .get(MET_NO_FORECAST_URL)
+        .query(&query)
+        .send()
+        .and_then(|resp| resp.error_for_status())
+        .and_then(|resp| resp.json())
+        .wrap_err("Failed to fetch the forecast")?;
+    let symbol = forecast["properties"]["timeseries"][0]["data"]["next_1_hours"]["summary"]
+        ["symbol_code"]
+        .as_str()
+        .unwrap_or_default();
+    Ok(Weather::from_symbol(symbol))
+}
+
+/// Sets the target's color by the weather every `interval`, until stopped.
+pub fn sync(
+    backend: &dyn LightBackend,
+    target: Target,
+    location: (f64, f64),
+    interval: Duration,
+) -> Result<()> {
+    // met.no blocks clients that don't identify themselves.
+    let client = Client::builder()
+        .user_agent(concat!(
+            "blilys/",
+            env!("CARGO_PKG_VERSION"),
+            " https://github.com/jodal/blilys"
+        ))
+        .timeout(Duration::from_secs(10))
+        .build()?;
+    info!("Syncing {} with the weather. Press Ctrl-C to stop.", target);
+    let mut last = None;
+    loop {
+        match fetch(&client, location) {
+            // Warnings are shown again at every update, as the pulse stops by itself.
+            Ok(weather) if last != Some(weather) || weather == Weather::Warning => {
+                info!("The weather is now {:?}.", weather);
+                if let Err(err) = target.set_state(backend, &weather.command()) {
+                    eprintln!("Failed to set {}: {}", target, err);
+                } else {
+                    last = Some(weather);
+                }
+            }
+            Ok(_) => {}
+            Err(err) => eprintln!("{:#}", err),
+        }
+        thread::sleep(interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Weather;
+
+    #[test]
+    fn symbols_are_classified() {
+        assert_eq!(Weather::from_symbol("clearsky_day"), Weather::Sun);
+        assert_eq!(Weather::from_symbol("fair_night"), Weather::Sun);
+        assert_eq!(Weather::from_symbol("partlycloudy_day"), Weather::Clouds);
+        assert_eq!(Weather::from_symbol("fog"), Weather::Clouds);
+        assert_eq!(Weather::from_symbol("lightrainshowers_day"), Weather::Rain);
+        assert_eq!(Weather::from_symbol("heavysleetandthunder"), Weather::Rain);
+        assert_eq!(
+            Weather::from_symbol("snowshowers_polartwilight"),
+            Weather::Snow
+        );
+    }
+}
@@ -0,0 +1,317 @@
+use crate::time::{civil_from_days, days_from_civil, Date, TimeOfDay};
+use eyre::{eyre, Result};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// A time zone, with its rule for daylight saving time as given by a POSIX TZ string like
+/// "CET-1CEST,M3.5.0,M10.5.0/3".
+///
+/// Zones by name, like "Europe/Oslo", are read from the system's time zone database. Only the
+/// rule for current times at the end of each zone's file is used, as schedules look no further
+/// back than a week.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Zone {
+    /// Seconds east of UTC in standard time.
+    std_offset: i64,
+    dst: Option<Dst>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Dst {
+    /// Seconds east of UTC in daylight saving time.
+    offset: i64,
+    start: Rule,
+    end: Rule,
+}
+
+/// A day of the year and a local time of day when daylight saving time starts or ends.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Rule {
+    day: Day,
+    /// Seconds after local midnight, which may be negative or past the end of the day.
+    time: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Day {
+    /// Day 1 to 365, never counting February 29, from `Jn`.
+    Julian(u32),
+    /// Day 0 to 365, counting February 29 in leap years, from `n`.
+    Zero(u32),
+    /// Month, week 1 to 5 where 5 is the last, and weekday from Sunday as 0, from `Mm.w.d`.
+    Month(u32, u32, u32),
+}
+
+impl Zone {
+    /// Reads a zone by name from the system's time zone database, in `TZDIR` or
+    /// /usr/share/zoneinfo.
+    pub fn named(name: &str) -> Result<Zone> {
+        if name.split('/').any(|part| part.is_empty() || part == "..") {
+            return Err(eyre!("Invalid time zone {:?}", name));
+        }
+        let dir = env::var_os("TZDIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("/usr/share/zoneinfo"));
+        let data = fs::read(dir.join(name))
+            .map_err(|err| eyre!("Unknown time zone {:?}: {}", name, err))?;
+        let footer = footer(&data)
+            .ok_or_else(|| eyre!("The time zone file for {:?} has no TZ rule", name))?;
+        Zone::posix(footer).map_err(|err| eyre!("Time zone {:?}: {}", name, err))
+    }
+
+    /// Parses a POSIX TZ string, like "CET-1CEST,M3.5.0,M10.5.0/3".
+    pub fn posix(s: &str) -> Result<Zone, String> {
+        let invalid = || format!("Invalid TZ rule {:?}", s);
+        let mut rest = skip_name(s).ok_or_else(invalid)?;
+        // POSIX offsets are west of UTC, the opposite of the usual.
+        let std_offset = -parse_offset(&mut rest).ok_or_else(invalid)?;
+        if rest.is_empty() {
+            return Ok(Zone {
+                std_offset,
+                dst: None,
+            });
+        }
+        rest = skip_name(rest).ok_or_else(invalid)?;
+        let offset = if rest.starts_with(',') {
+            std_offset + 3600
+        } else {
+            -parse_offset(&mut rest).ok_or_else(invalid)?
+        };
+        let rules = rest.strip_prefix(',').ok_or_else(invalid)?;
+        let (start, end) = rules.split_once(',').ok_or_else(invalid)?;
+        Ok(Zone {
+            std_offset,
+            dst: Some(Dst {
+                offset,
+                start: parse_rule(start).ok_or_else(invalid)?,
+                end: parse_rule(end).ok_or_else(invalid)?,
+            }),
+        })
+    }
+
+    /// Returns the offset from UTC in seconds at seconds since the Unix epoch.
+    fn offset_at(&self, secs: i64) -> i64 {
+        let dst = match &self.dst {
+            Some(dst) => dst,
+            None => return self.std_offset,
+        };
+        let year = civil_from_days((secs + self.std_offset).div_euclid(86400)).0;
+        let start = dst.start.local_secs(year) - self.std_offset;
+        let end = dst.end.local_secs(year) - dst.offset;
+        let in_dst = if start < end {
+            start <= secs && secs < end
+        } else {
+            // Daylight saving time spans the new year in the southern hemisphere.
+            secs >= start || secs < end
+        };
+        if in_dst {
+            dst.offset
+        } else {
+            self.std_offset
+        }
+    }
+
+    /// Returns the date in the zone at seconds since the Unix epoch.
+    pub fn date(&self, secs: u64) -> Date {
+        let secs = secs as i64;
+        civil_from_days((secs + self.offset_at(secs)).div_euclid(86400))
+    }
+
+    /// Converts a date and time in the zone to seconds since the Unix epoch.
+    ///
+    /// A time that occurs twice as the clocks go back is taken at its first occurrence, and a
+    /// time skipped as the clocks go forward at the moment they do.
+    pub fn epoch(&self, date: Date, time: TimeOfDay) -> u64 {
+        let local = days_from_civil(date) * 86400
+            + time.hour as i64 * 3600
+            + time.minute as i64 * 60
+            + time.second as i64;
+        let dst_offset = self.dst.as_ref().map_or(self.std_offset, |dst| dst.offset);
+        // Whichever offset is in effect at the time it gives is the right one.
+        let mut candidates = [local - dst_offset, local - self.std_offset];
+        candidates.sort_unstable();
+        let secs = candidates
+            .iter()
+            .copied()
+            .find(|&secs| local - self.offset_at(secs) == secs)
+            .unwrap_or_else(|| {
+                // The clocks skipped the time, going forward from the earlier offset's time to
+                // the later's.
+                let (before, after) = (candidates[0], candidates[1]);
+                let mut low = before;
+                let mut high = after;
+                while high - low > 1 {
+                    let mid = (low + high) / 2;
+                    if self.offset_at(mid) == self.offset_at(before) {
+                        low = mid;
+                    } else {
+                        high = mid;
+                    }
+                }
+                high
+            });
+        secs.max(0) as u64
+    }
+}
+
+impl Rule {
+    /// Returns the local time of the rule in the year, in seconds since the Unix epoch as if
+    /// local time were UTC.
+    fn local_secs(&self, year: i64) -> i64 {
+        let jan1 = days_from_civil((year, 1, 1));
+        let leap = days_from_civil((year, 3, 1)) - days_from_civil((year, 2, 28)) == 2;
+        let day = match self.day {
+            Day::Julian(n) => jan1 + n as i64 - 1 + (leap && n >= 60) as i64,
+            Day::Zero(n) => jan1 + n as i64,
+            Day::Month(month, week, weekday) => {
+                let first = days_from_civil((year, month, 1));
+                let next = match month {
+                    12 => days_from_civil((year + 1, 1, 1)),
+                    month => days_from_civil((year, month + 1, 1)),
+                };
+                // The Unix epoch was a Thursday, day 4 counting from Sunday.
+                let first_weekday = (first + 4).rem_euclid(7);
+                let mut day = first + (weekday as i64 - first_weekday).rem_euclid(7);
+                day += (week as i64 - 1) * 7;
+                while day >= next {
+                    day -= 7;
+                }
+                day
+            }
+        };
+        day * 86400 + self.time
+    }
+}
+
+/// Returns the TZ string at the end of a TZif file of version 2 or later, between its last two
+/// newlines.
+fn footer(data: &[u8]) -> Option<&str> {
+    if !data.starts_with(b"TZif") || data.get(4).is_none_or(|&version| version < b'2') {
+        return None;
+    }
+    let data = data.strip_suffix(b"\n")?;
+    let start = data.iter().rposition(|&byte| byte == b'\n')? + 1;
+    std::str::from_utf8(&data[start..])
+        .ok()
+        .filter(|s| !s.is_empty())
+}
+
+/// Skips a zone abbreviation, like "CET" or "<+03>".
+fn skip_name(s: &str) -> Option<&str> {
+    if let Some(rest) = s.strip_prefix('<') {
+        return rest.split_once('>').map(|(_, rest)| rest);
+    }
+    let len = s
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(s.len());
+    (len >= 3).then(|| &s[len..])
+}
+
+/// Parses an offset or time like "-1", "+5:30", or "3", in seconds, advancing past it.
+fn parse_offset(s: &mut &str) -> Option<i64> {
+    let (sign, rest) = match s.as_bytes().first()? {
+        b'-' => (-1, &s[1..]),
+        b'+' => (1, &s[1..]),
+        _ => (1, *s),
+    };
+    let len = rest
+        .find(|c: char| !c.is_ascii_digit() && c != ':')
+        .unwrap_or(rest.len());
+    let mut secs = 0;
+    let mut unit = 3600;
+    for part in rest[..len].split(':') {
+        if unit == 0 {
+            return None;
+        }
+        secs += part.parse::<i64>().ok()? * unit;
+        unit /= 60;
+    }
+    *s = &rest[len..];
+    Some(sign * secs)
+}
+
+/// Parses a rule like "M3.5.0", "J60", or "M10.5.0/3", where the time defaults to 02:00.
+fn parse_rule(s: &str) -> Option<Rule> {
+    let (day, time) = match s.split_once('/') {
+        Some((day, mut time)) => {
+            let secs = parse_offset(&mut time)?;
+            if !time.is_empty() {
+                return None;
+            }
+            (day, secs)
+        }
+        None => (s, 2 * 3600),
+    };
+    let day = if let Some(n) = day.strip_prefix('J') {
+        Day::Julian(n.parse().ok().filter(|n| (1..=365).contains(n))?)
+    } else if let Some(fields) = day.strip_prefix('M') {
+        let fields = fields
+            .split('.')
+            .map(|field| field.parse().ok())
+            .collect::<Option<Vec<u32>>>()?;
+        match fields[..] {
+            [month, week, weekday]
+                if (1..=12).contains(&month) && (1..=5).contains(&week) && weekday <= 6 =>
+            {
+                Day::Month(month, week, weekday)
+            }
+            _ => return None,
+        }
+    } else {
+        Day::Zero(day.parse().ok().filter(|n| *n <= 365)?)
+    };
+    Some(Rule { day, time })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{footer, Zone};
+    use crate::time::{format_utc, parse_time_of_day};
+
+    fn utc(zone: &Zone, date: (i64, u32, u32), time: &str) -> String {
+        format_utc(zone.epoch(date, parse_time_of_day(time).unwrap()))
+    }
+
+    #[test]
+    fn local_times_follow_daylight_saving_time() {
+        let oslo = Zone::posix("CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+        // In 2026, the clocks go forward on March 29 and back on October 25.
+        assert_eq!(utc(&oslo, (2026, 3, 28), "07:00"), "2026-03-28T06:00:00Z");
+        assert_eq!(utc(&oslo, (2026, 3, 29), "07:00"), "2026-03-29T05:00:00Z");
+        assert_eq!(utc(&oslo, (2026, 10, 25), "07:00"), "2026-10-25T06:00:00Z");
+        // 02:30 is skipped in spring, and happens twice in the fall.
+        assert_eq!(utc(&oslo, (2026, 3, 29), "02:30"), "2026-03-29T01:00:00Z");
+        assert_eq!(utc(&oslo, (2026, 10, 25), "02:30"), "2026-10-25T00:30:00Z");
+
+        let sydney = Zone::posix("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        assert_eq!(utc(&sydney, (2026, 1, 10), "07:00"), "2026-01-09T20:00:00Z");
+        assert_eq!(utc(&sydney, (2026, 7, 10), "07:00"), "2026-07-09T21:00:00Z");
+
+        let kolkata = Zone::posix("IST-5:30").unwrap();
+        assert_eq!(
+            utc(&kolkata, (2026, 1, 10), "07:00"),
+            "2026-01-10T01:30:00Z"
+        );
+    }
+
+    #[test]
+    fn dates_are_local_to_the_zone() {
+        let new_york = Zone::posix("EST5EDT,M3.2.0,M11.1.0").unwrap();
+        let evening = new_york.epoch((2026, 7, 4), parse_time_of_day("22:00").unwrap());
+        assert_eq!(new_york.date(evening), (2026, 7, 4));
+        assert_eq!(Zone::posix("UTC0").unwrap().date(evening), (2026, 7, 5));
+    }
+
+    #[test]
+    fn rules_are_read_from_the_end_of_zone_files() {
+        assert_eq!(
+            footer(b"TZif2\0\0binary\nCET-1CEST,M3.5.0,M10.5.0/3\n"),
+            Some("CET-1CEST,M3.5.0,M10.5.0/3")
+        );
+        assert_eq!(footer(b"TZif\0\0\0binary"), None);
+        assert!(Zone::posix("CET-1CEST").is_err());
+        assert!(Zone::posix("nonsense").is_err());
+        assert!(Zone::named("../../etc/passwd").is_err());
+    }
+}
@@ -0,0 +1,1870 @@
+//! End-to-end tests running the blilys binary against a mock bridge.
+
+mod mock_bridge;
+
+use mock_bridge::{MockBridge, BRIDGE_ID, USERNAME};
+use serde_json::json;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Child, Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tempfile::TempDir;
+
+/// A mock bridge and a home directory for blilys' config, cache, and data files.
+struct Env {
+    bridge: MockBridge,
+    home: TempDir,
+}
+
+impl Env {
+    /// Sets up a bridge that blilys is already paired with.
+    fn paired() -> Env {
+        Env::paired_with("")
+    }
+
+    /// Sets up a paired bridge, with extra top-level config settings.
+    fn paired_with(settings: &str) -> Env {
+        let env = Env::unpaired();
+        env.write_config(&format!(
+            "version = 3\n{}\n[bridge]\nhost = {:?}\nusername = {:?}\nid = {:?}\n",
+            settings,
+            env.bridge.host(),
+            USERNAME,
+            BRIDGE_ID
+        ));
+        env
+    }
+
+    fn unpaired() -> Env {
+        Env {
+            bridge: MockBridge::start(),
+            home: tempfile::tempdir().unwrap(),
+        }
+    }
+
+    fn config_path(&self) -> PathBuf {
+        self.home.path().join(".config/blilys/config.toml")
+    }
+
+    fn write_config(&self, contents: &str) {
+        let path = self.config_path();
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        self.run_with_input(args, "")
+    }
+
+    fn run_with_input(&self, args: &[&str], input: &str) -> Output {
+        let mut child = self
+            .command(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .unwrap();
+        child.wait_with_output().unwrap()
+    }
+
+    /// Starts a long-running command like `blilys daemon` in the background.
+    fn spawn(&self, args: &[&str]) -> Child {
+        self.command(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap()
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_blilys"));
+        command
+            .args(args)
+            .env("HOME", self.home.path())
+            .env("LANG", "C")
+            .env_remove("LC_ALL")
+            .env_remove("LC_MESSAGES")
+            .env_remove("XDG_CONFIG_HOME")
+            .env_remove("XDG_CACHE_HOME")
+            .env_remove("XDG_DATA_HOME");
+        command
+    }
+}
+
+/// Waits up to ten seconds for the condition to hold.
+fn wait_for(mut condition: impl FnMut() -> bool) {
+    for _ in 0..200 {
+        if condition() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("Timed out waiting");
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+fn assert_success(output: &Output) {
+    assert!(output.status.success(), "blilys failed: {}", stderr(output));
+}
+
+fn assert_failure(output: &Output, message: &str) {
+    assert!(!output.status.success(), "blilys unexpectedly succeeded");
+    assert!(
+        stderr(output).contains(message),
+        "expected {:?} in stderr: {}",
+        message,
+        stderr(output)
+    );
+}
+
+#[test]
+fn pair_saves_username_and_bridge_id() {
+    let env = Env::unpaired();
+    env.bridge.state().link_button = true;
+
+    let output = env.run_with_input(&["--bridge", &env.bridge.host(), "pair"], "\n");
+
+    assert_success(&output);
+    let config = fs::read_to_string(env.config_path()).unwrap();
+    assert!(config.contains(&format!("host = {:?}", env.bridge.host())));
+    assert!(config.contains("username = \"user1\""));
+    assert!(config.contains(&format!("id = {:?}", BRIDGE_ID)));
+}
+
+#[test]
+fn pair_identifies_device_and_stores_client_key() {
+    let env = Env::unpaired();
+    env.write_config("[bridge]\ndevice_name = \"kitchen-pi\"\n");
+    env.bridge.state().link_button = true;
+
+    let output = env.run_with_input(&["--bridge", &env.bridge.host(), "pair"], "\n");
+
+    assert_success(&output);
+    let posts = env.bridge.requests("POST");
+    assert_eq!(
+        posts[0].body,
+        Some(json!({"devicetype": "blilys#kitchen-pi", "generateclientkey": true}))
+    );
+    let config = fs::read_to_string(env.config_path()).unwrap();
+    assert!(config.contains("clientkey = \"0123456789ABCDEF\""));
+}
+
+#[test]
+fn quiet_pair_prints_nothing() {
+    let env = Env::unpaired();
+    env.bridge.state().link_button = true;
+
+    let output = env.run_with_input(&["-q", "--bridge", &env.bridge.host(), "pair"], "\n");
+
+    assert_success(&output);
+    assert_eq!(stdout(&output), "");
+    assert_eq!(stderr(&output), "");
+}
+
+#[test]
+fn pair_fails_without_link_button() {
+    let env = Env::unpaired();
+
+    let output = env.run_with_input(&["--bridge", &env.bridge.host(), "pair"], "\n");
+
+    assert_failure(&output, "link button not pressed");
+    assert!(!env.config_path().exists());
+}
+
+#[test]
+fn pair_waits_for_link_button_and_prints_json() {
+    let env = Env::unpaired();
+    env.bridge.state().press_link_button_after = Some(2);
+
+    // Nothing is written to stdin, so pairing must not wait for input.
+    let output = env.run(&[
+        "--bridge",
+        &env.bridge.host(),
+        "pair",
+        "--wait",
+        "10s",
+        "--json",
+    ]);
+
+    assert_success(&output);
+    let credentials: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        credentials,
+        json!({"host": env.bridge.host(), "id": BRIDGE_ID, "username": "user1"})
+    );
+    assert_eq!(env.bridge.requests("POST").len(), 3);
+}
+
+#[test]
+fn pair_gives_up_waiting_for_link_button() {
+    let env = Env::unpaired();
+
+    let output = env.run(&["--bridge", &env.bridge.host(), "pair", "--wait", "1s"]);
+
+    assert_failure(&output, "link button not pressed");
+    assert!(!env.config_path().exists());
+}
+
+#[test]
+fn commands_fail_when_not_paired() {
+    let env = Env::unpaired();
+    env.bridge.state().link_button = true;
+
+    let output = env.run(&["--bridge", &env.bridge.host(), "lights"]);
+
+    assert_failure(&output, "Run `blilys pair`");
+    assert!(env.bridge.requests("POST").is_empty());
+}
+
+#[test]
+fn messages_are_in_the_language_from_lang_or_config() {
+    let env = Env::unpaired();
+
+    let output = env
+        .command(&["--bridge", &env.bridge.host(), "lights"])
+        .env("LANG", "nb_NO.UTF-8")
+        .output()
+        .unwrap();
+    assert_failure(&output, "Ikke koblet til en bro");
+
+    let env = Env::paired_with("language = \"nb\"");
+    let output = env.run(&["lights"]);
+    assert_success(&output);
+    assert!(stdout(&output).contains("[på ]"), "{}", stdout(&output));
+}
+
+#[test]
+fn auto_pair_pairs_before_running_command() {
+    let env = Env::unpaired();
+    env.bridge.state().link_button = true;
+
+    let output = env.run_with_input(
+        &["--bridge", &env.bridge.host(), "--auto-pair", "lights"],
+        "\n",
+    );
+
+    assert_success(&output);
+    assert!(stdout(&output).contains("Desk"));
+    assert!(fs::read_to_string(env.config_path())
+        .unwrap()
+        .contains("username = \"user1\""));
+}
+
+#[test]
+fn username_flag_runs_without_any_files() {
+    let env = Env::unpaired();
+    let host = env.bridge.host();
+
+    let output = env.run(&[
+        "--bridge",
+        &host,
+        "--username",
+        USERNAME,
+        "off",
+        "light:Desk",
+    ]);
+
+    assert_success(&output);
+    assert_eq!(env.bridge.state().lights["1"]["state"]["on"], json!(false));
+    assert!(fs::read_dir(env.home.path()).unwrap().next().is_none());
+    assert_failure(&env.run(&["--username", USERNAME, "lights"]), "--bridge");
+}
+
+#[test]
+fn reading_an_old_config_leaves_the_file_alone() {
+    let env = Env::unpaired();
+    let contents = format!(
+        "# My lights\nversion = 1\n\n[bridge]\nip = {:?}  # The bridge\nusername = {:?}\nid = {:?}\n",
+        env.bridge.host(),
+        USERNAME,
+        BRIDGE_ID
+    );
+    env.write_config(&contents);
+    let dir = env.config_path().parent().unwrap().to_owned();
+    let files = || {
+        let mut files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        files.sort();
+        files
+    };
+    let before = files();
+
+    let output = env.run(&["config", "show"]);
+    assert_success(&output);
+    assert!(stdout(&output).contains("version = 3"));
+    assert_success(&env.run(&["lights"]));
+
+    assert_eq!(fs::read_to_string(env.config_path()).unwrap(), contents);
+    assert_eq!(files(), before);
+}
+
+#[test]
+fn cache_ttl_in_seconds_is_migrated_to_a_duration() {
+    let env = Env::unpaired();
+    env.write_config("version = 2\n[bridge]\n[cache]\nttl = 90\n");
+
+    let output = env.run(&["config", "show"]);
+
+    assert_success(&output);
+    assert!(stdout(&output).contains("ttl = \"1m30s\""));
+}
+
+#[test]
+fn configs_newer_than_supported_are_rejected() {
+    let env = Env::unpaired();
+    for version in &["4", "4294967297"] {
+        env.write_config(&format!("version = {}\n", version));
+        assert_failure(
+            &env.run(&["config", "show"]),
+            &format!("Config version {} is newer", version),
+        );
+    }
+}
+
+#[test]
+fn doctor_checks_setup_and_fails_on_clock_skew() {
+    let env = Env::unpaired();
+    env.write_config(&format!(
+        "version = 3\n[bridge]\nhost = {:?}\nusername = {:?}\nid = {:?}\ndiscovery = [\"manual\"]\n",
+        env.bridge.host(),
+        USERNAME,
+        BRIDGE_ID
+    ));
+    env.bridge.state().config["UTC"] = json!("2000-01-01T00:00:00");
+
+    let output = env.run(&["doctor"]);
+
+    assert_failure(&output, "1 check failed");
+    let stdout = stdout(&output);
+    assert!(stdout.contains("ok   Bridge: reachable at"), "{}", stdout);
+    assert!(stdout.contains("ok   API version: 1.50.0"), "{}", stdout);
+    assert!(stdout.contains("ok   Pairing:"), "{}", stdout);
+    assert!(stdout.contains("FAIL Clock:"), "{}", stdout);
+}
+
+#[test]
+fn log_file_has_every_request_without_username() {
+    let env = Env::paired();
+    let path = env.home.path().join("blilys.log");
+    let path = path.to_str().unwrap();
+
+    assert_success(&env.run(&["--log-file", path, "lights"]));
+    assert_success(&env.run(&["--log-file", path, "--log-format", "json", "on", "light:2"]));
+
+    let log = fs::read_to_string(path).unwrap();
+    assert!(!log.contains(USERNAME), "{}", log);
+    let lines: Vec<&str> = log.lines().collect();
+    assert!(
+        lines[0].contains("connect:request{method=GET path=/api/config}: blilys::api: response"),
+        "{}",
+        log
+    );
+    let last: serde_json::Value = serde_json::from_str(lines.last().unwrap()).unwrap();
+    assert_eq!(last["spans"][0]["name"], json!("request"));
+    assert_eq!(last["spans"][0]["method"], json!("PUT"));
+    assert_eq!(
+        last["spans"][0]["path"],
+        json!("/api/<username>/lights/2/state")
+    );
+    assert_eq!(last["fields"]["status"], json!(200));
+}
+
+#[test]
+fn lights_lists_all_lights() {
+    let env = Env::paired();
+
+    let output = env.run(&["lights"]);
+
+    assert_success(&output);
+    let lines: Vec<String> = stdout(&output).lines().map(str::to_owned).collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("1: Desk   "));
+    assert!(lines[0].contains("[on ] [bri 200]"));
+    assert!(lines[1].starts_with("2: Kitchen"));
+    assert!(lines[1].contains("[off] [bri  10]"));
+}
+
+#[test]
+fn listings_follow_format_templates() {
+    let env = Env::paired();
+
+    let output = env.run(&["lights", "--format", r"{id}\t{name}\t{bri}"]);
+    assert_success(&output);
+    assert_eq!(stdout(&output).lines().next(), Some("1\tDesk\t200"));
+
+    let output = env.run(&["groups", "--format", "{name}: {lights}"]);
+    assert_success(&output);
+    assert_eq!(stdout(&output), "Office: 1,2\n");
+
+    assert_failure(
+        &env.run(&["lights", "--format", "{brightness}"]),
+        "Unknown field",
+    );
+}
+
+#[test]
+fn lights_are_listed_with_their_rooms() {
+    let env = Env::paired();
+
+    let output = env.run(&["lights"]);
+    assert_success(&output);
+    assert!(stdout(&output).starts_with("1: Desk    Office [on ]"));
+
+    let output = env.run(&["lights", "--group-by", "room"]);
+    assert_success(&output);
+    let stdout = stdout(&output);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines[0], "Office:");
+    assert!(lines[1].starts_with("1: Desk    [on ]"), "{}", lines[1]);
+    assert!(lines[2].starts_with("2: Kitchen [off]"), "{}", lines[2]);
+    assert_eq!(&lines[3..5], ["", "No room:"]);
+    assert!(lines[5].starts_with("3: Hall [off]"), "{}", lines[5]);
+}
+
+#[test]
+fn groups_lists_all_groups() {
+    let env = Env::paired();
+
+    let output = env.run(&["groups"]);
+
+    assert_success(&output);
+    assert_eq!(stdout(&output), "1: Office [some on] [bri 200] [1, 2]\n");
+}
+
+#[test]
+fn listings_print_json_with_the_raw_state() {
+    let env = Env::paired();
+
+    let lights: serde_json::Value =
+        serde_json::from_str(&stdout(&env.run(&["lights", "--json"]))).unwrap();
+    let full: serde_json::Value =
+        serde_json::from_str(&stdout(&env.run(&["lights", "--json", "--full"]))).unwrap();
+    let groups: serde_json::Value =
+        serde_json::from_str(&stdout(&env.run(&["groups", "--json", "--full"]))).unwrap();
+
+    assert_eq!(
+        lights[0],
+        json!({
+            "id": 1, "name": "Desk", "room": "Office", "on": true, "bri": 200, "hue": 100,
+            "sat": 3, "ct": null, "model": "LCT007",
+        })
+    );
+    assert_eq!(lights[2]["room"], json!(null));
+    assert!(lights[0].get("raw").is_none());
+    assert_eq!(full[1]["raw"]["swversion"], "1.0");
+    assert_eq!(groups[0]["bri"], 200);
+    assert_eq!(groups[0]["lights"], json!(["1", "2"]));
+    assert_eq!(groups[0]["raw"]["recycle"], false);
+    assert_failure(&env.run(&["lights", "--full"]), "--json");
+}
+
+#[test]
+fn light_on_by_name_sets_state() {
+    let env = Env::paired();
+
+    let output = env.run(&["light", "kitchen", "on", "--bri", "50"]);
+
+    assert_success(&output);
+    let puts = env.bridge.requests("PUT");
+    assert_eq!(puts.len(), 1);
+    assert_eq!(puts[0].path, format!("/api/{}/lights/2/state", USERNAME));
+    assert_eq!(puts[0].body, Some(json!({"on": true, "bri": 50})));
+    assert_eq!(
+        env.bridge.state().lights["2"]["state"],
+        json!({"on": true, "bri": 50})
+    );
+}
+
+#[test]
+fn group_off_sets_member_lights() {
+    let env = Env::paired();
+
+    let output = env.run(&["group", "Office", "off"]);
+
+    assert_success(&output);
+    let state = env.bridge.state();
+    assert_eq!(state.lights["1"]["state"]["on"], json!(false));
+    assert_eq!(state.lights["2"]["state"]["on"], json!(false));
+    assert_eq!(state.lights["3"]["state"]["on"], json!(false));
+}
+
+#[test]
+fn all_off_sets_every_light() {
+    let env = Env::paired();
+
+    let output = env.run(&["all", "off"]);
+
+    assert_success(&output);
+    let puts = env.bridge.requests("PUT");
+    assert_eq!(puts[0].path, format!("/api/{}/groups/0/action", USERNAME));
+    let state = env.bridge.state();
+    assert!(state
+        .lights
+        .values()
+        .all(|light| light["state"]["on"] == json!(false)));
+}
+
+#[test]
+fn which_blinks_lights_until_found_and_adds_alias() {
+    let env = Env::paired();
+
+    let output = env.run_with_input(&["which"], "n\ny\nfridge\n");
+
+    assert_success(&output);
+    assert_eq!(stdout(&output), "2: Kitchen\n");
+    let alerts: Vec<(String, serde_json::Value)> = env
+        .bridge
+        .requests("PUT")
+        .into_iter()
+        .map(|r| (r.path, r.body.unwrap()["alert"].clone()))
+        .collect();
+    let path = |id| format!("/api/{}/lights/{}/state", USERNAME, id);
+    assert_eq!(
+        alerts,
+        vec![
+            (path(1), json!("lselect")),
+            (path(1), json!("none")),
+            (path(2), json!("lselect")),
+            (path(2), json!("none")),
+        ]
+    );
+
+    let output = env.run(&["light", "fridge", "off"]);
+    assert_success(&output);
+    assert_eq!(env.bridge.requests("PUT").last().unwrap().path, path(2));
+}
+
+#[test]
+fn bare_commands_use_default_target() {
+    let env = Env::paired_with("default_target = \"group:office\"");
+
+    let output = env.run(&["dim", "30%"]);
+
+    assert_success(&output);
+    let puts = env.bridge.requests("PUT");
+    assert_eq!(puts[0].path, format!("/api/{}/groups/1/action", USERNAME));
+    assert_eq!(puts[0].body, Some(json!({"on": true, "bri": 76})));
+}
+
+#[test]
+fn selectors_control_lights_one_by_one() {
+    let env = Env::paired();
+
+    assert_success(&env.run(&["on", "office + hall - desk", "--bri", "50%"]));
+    let puts: Vec<_> = env
+        .bridge
+        .requests("PUT")
+        .into_iter()
+        .map(|r| r.path)
+        .collect();
+    assert_eq!(
+        puts,
+        vec![
+            format!("/api/{}/lights/2/state", USERNAME),
+            format!("/api/{}/lights/3/state", USERNAME),
+        ]
+    );
+
+    env.bridge.state().broken_lights.push("3".to_owned());
+    let output = env.run(&["off", "room:off* + light:3"]);
+    assert_success(&output);
+    assert!(
+        stderr(&output).contains("1 of 3 failed"),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(env.bridge.state().lights["1"]["state"]["on"], json!(false));
+    assert_failure(
+        &env.run(&["--strict", "on", "light:3 + @off"]),
+        "Failed on light/3",
+    );
+}
+
+#[test]
+fn tagged_lights_are_targeted_by_tag() {
+    let env = Env::paired();
+
+    assert_success(&env.run(&["tag", "add", "light:3", "reading", "ambient"]));
+    let config = fs::read_to_string(env.config_path()).unwrap();
+    assert!(config.contains("reading = [3]"), "{}", config);
+    assert_eq!(
+        stdout(&env.run(&["tag", "list"])),
+        "ambient: [3]\nreading: [3]\n"
+    );
+
+    assert_success(&env.run(&["tag:reading", "on", "--bri", "70%"]));
+    assert_eq!(env.bridge.state().lights["3"]["state"]["on"], json!(true));
+    assert_eq!(env.bridge.state().lights["3"]["state"]["bri"], json!(178));
+
+    assert_success(&env.run(&["tag", "remove", "light:3", "reading"]));
+    assert_failure(&env.run(&["on", "tag:reading"]), "No lights tagged");
+    assert_failure(
+        &env.run(&["tag", "add", "light:3", "two words"]),
+        "can't contain spaces",
+    );
+}
+
+#[test]
+fn on_uses_defaults_for_target() {
+    let env = Env::paired_with(
+        "[defaults.\"group:gone\"]\nbri = \"10%\"\n\
+         [defaults.\"group:attic\"]\nbri = \"10%\"\n\
+         [defaults.\"group:office\"]\nbri = \"30%\"\nct = \"2700K\"",
+    );
+    let user = format!("/api/{}", USERNAME);
+    let fetches = || {
+        env.bridge
+            .requests("GET")
+            .iter()
+            .filter(|r| r.path == user)
+            .count()
+    };
+
+    let output = env.run(&["on", "office"]);
+    assert_success(&output);
+    assert!(stderr(&output).contains("Skipping invalid target \"group:gone\" in defaults"));
+    let before = fetches();
+    assert_success(&env.run(&["on", "office", "--bri", "100%"]));
+    // The targets missing from the name cache are looked up in one fetch of the full state.
+    assert_eq!(fetches() - before, 1);
+    assert_success(&env.run(&["on", "hall"]));
+
+    let bodies: Vec<_> = env
+        .bridge
+        .requests("PUT")
+        .into_iter()
+        .map(|r| r.body)
+        .collect();
+    assert_eq!(
+        bodies,
+        vec![
+            Some(json!({"on": true, "bri": 76, "ct": 370})),
+            Some(json!({"on": true, "bri": 254, "ct": 370})),
+            Some(json!({"on": true})),
+        ]
+    );
+}
+
+#[test]
+fn brightness_is_capped_unless_overridden() {
+    let env = Env::paired_with("[caps]\n\"group:office\" = \"40%\"");
+
+    assert_success(&env.run(&["on", "office", "--bri", "100%"]));
+    assert_success(&env.run(&["light", "desk", "on", "--bri", "100%"]));
+    assert_success(&env.run(&["light", "hall", "on", "--bri", "100%"]));
+    assert_success(&env.run(&["--override-cap", "on", "office", "--bri", "100%"]));
+
+    let bris: Vec<_> = env
+        .bridge
+        .requests("PUT")
+        .into_iter()
+        .map(|r| r.body.unwrap()["bri"].clone())
+        .collect();
+    assert_eq!(bris, vec![json!(102), json!(102), json!(254), json!(254)]);
+}
+
+#[test]
+fn warm_dim_makes_lights_warmer_as_they_dim() {
+    let env = Env::paired();
+    env.bridge.state().lights["2"]["state"]["ct"] = json!(366);
+
+    assert_success(&env.run(&["--warm-dim", "light", "kitchen", "dim", "50%"]));
+    assert_success(&env.run(&["--warm-dim", "light", "hall", "dim", "50%"]));
+    assert_success(&env.run(&[
+        "--warm-dim",
+        "light",
+        "kitchen",
+        "fade",
+        "10m",
+        "--bri",
+        "10%",
+    ]));
+    assert_success(&env.run(&[
+        "--warm-dim",
+        "light",
+        "kitchen",
+        "on",
+        "--bri",
+        "10%",
+        "--ct",
+        "4000K",
+    ]));
+    assert_success(&env.run(&["light", "kitchen", "dim", "50%"]));
+
+    let bodies: Vec<_> = env
+        .bridge
+        .requests("PUT")
+        .into_iter()
+        .map(|r| r.body.unwrap())
+        .collect();
+    assert_eq!(
+        bodies,
+        vec![
+            json!({"on": true, "bri": 127, "ct": 392}),
+            json!({"on": true, "bri": 127}),
+            json!({"on": true, "bri": 25, "ct": 424, "transitiontime": 6000}),
+            json!({"on": true, "bri": 25, "ct": 250}),
+            json!({"on": true, "bri": 127}),
+        ]
+    );
+}
+
+#[test]
+fn sleep_fades_to_a_warm_glow_then_off() {
+    let env = Env::paired();
+
+    assert_success(&env.run(&["light", "kitchen", "sleep", "1s"]));
+
+    let requests: Vec<_> = env
+        .bridge
+        .requests("PUT")
+        .into_iter()
+        .map(|r| (r.path, r.body.unwrap()))
+        .collect();
+    let user = format!("/api/{}", USERNAME);
+    assert_eq!(
+        requests,
+        vec![
+            (
+                format!("{}/lights/2/state", user),
+                json!({"on": true, "bri": 1, "ct": 455, "transitiontime": 10})
+            ),
+            (format!("{}/lights/2/state", user), json!({"on": false})),
+        ]
+    );
+    assert_eq!(env.bridge.state().lights["2"]["state"]["on"], false);
+}
+
+#[test]
+fn warm_dim_can_be_set_in_the_config() {
+    let env = Env::paired_with("warm_dim = true");
+    env.bridge.state().lights["2"]["state"]["ct"] = json!(366);
+
+    assert_success(&env.run(&["on", "office", "--bri", "100%"]));
+    assert_success(&env.run(&["light", "kitchen", "on"]));
+
+    let bodies: Vec<_> = env
+        .bridge
+        .requests("PUT")
+        .into_iter()
+        .map(|r| r.body.unwrap())
+        .collect();
+    assert_eq!(
+        bodies,
+        vec![
+            json!({"on": true, "bri": 254, "ct": 370}),
+            json!({"on": true})
+        ]
+    );
+}
+
+#[test]
+fn commands_are_tuned_to_light_models() {
+    let env =
+        Env::paired_with("[model.LTW001]\nbri_curve = \"ies\"\nmin_bri = \"10%\"\nmax_ct = 400");
+
+    assert_success(&env.run(&["light", "kitchen", "on", "--bri", "50%"]));
+    assert_success(&env.run(&["light", "kitchen", "on", "--bri", "1"]));
+    assert_success(&env.run(&["on", "office", "--ct", "2000K"]));
+    assert_success(&env.run(&["off", "office"]));
+
+    let requests: Vec<_> = env
+        .bridge
+        .requests("PUT")
+        .into_iter()
+        .map(|r| (r.path, r.body.unwrap()))
+        .collect();
+    let path = |resource: &str| format!("/api/{}/{}", USERNAME, resource);
+    assert_eq!(
+        requests,
+        vec![
+            (path("lights/2/state"), json!({"on": true, "bri": 64})),
+            (path("lights/2/state"), json!({"on": true, "bri": 25})),
+            (path("lights/1/state"), json!({"on": true, "ct": 500})),
+            (path("lights/2/state"), json!({"on": true, "ct": 400})),
+            (path("groups/1/action"), json!({"on": false})),
+        ]
+    );
+}
+
+#[test]
+fn wait_returns_once_target_is_in_state() {
+    let env = Env::paired();
+
+    assert_success(&env.run(&["wait", "office", "--until", "on"]));
+    assert_failure(
+        &env.run(&[
+            "wait",
+            "office",
+            "--until",
+            "off",
+            "--timeout",
+            "1s",
+            "--interval",
+            "100ms",
+        ]),
+        "Timed out after 1s waiting for group/1 to turn off",
+    );
+}
+
+#[test]
+fn if_runs_command_only_when_target_is_in_state() {
+    let env = Env::paired();
+
+    assert_success(&env.run(&["if", "hall", "--is", "on", "--", "off", "office"]));
+    assert_success(&env.run(&["if", "office", "--is", "on", "--", "off", "office"]));
+
+    let puts = env.bridge.requests("PUT");
+    assert_eq!(puts.len(), 1);
+    assert_eq!(puts[0].path, format!("/api/{}/groups/1/action", USERNAME));
+    assert_eq!(puts[0].body, Some(json!({"on": false})));
+}
+
+#[test]
+fn diff_shows_changes_since_snapshot() {
+    let env = Env::paired();
+
+    assert_success(&env.run(&["snapshot", "save", "before"]));
+    assert_success(&env.run(&["off", "office"]));
+    let output = env.run(&["diff", "before"]);
+
+    assert_success(&output);
+    assert_eq!(stdout(&output), format!(" 1: {:30} on on -> off\n", "Desk"));
+    assert_eq!(stdout(&env.run(&["snapshot", "list"])), "before\n");
+}
+
+#[test]
+fn panic_lights_everything_until_all_clear() {
+    let env = Env::paired_with("[caps]\n\"group:office\" = \"40%\"");
+
+    assert_success(&env.run(&["panic"]));
+    assert_success(&env.run(&["panic"]));
+
+    let puts = env.bridge.requests("PUT");
+    assert_eq!(puts.len(), 2);
+    assert_eq!(puts[0].path, format!("/api/{}/groups/0/action", USERNAME));
+    assert_eq!(
+        puts[0].body,
+        Some(json!({"on": true, "bri": 254, "ct": 250, "transitiontime": 0}))
+    );
+    assert_eq!(env.bridge.state().lights["3"]["state"]["on"], true);
+
+    assert_success(&env.run(&["allclear"]));
+
+    let state = env.bridge.state();
+    assert_eq!(state.lights["1"]["state"]["bri"], 200);
+    assert_eq!(state.lights["2"]["state"]["on"], false);
+    assert_eq!(state.lights["3"]["state"]["on"], false);
+    drop(state);
+    assert_failure(&env.run(&["allclear"]), "No panic to clear");
+}
+
+#[test]
+fn preset_crossfades_between_snapshots() {
+    let env = Env::paired();
+
+    assert_success(&env.run(&["snapshot", "save", "day"]));
+    assert_success(&env.run(&["on", "kitchen", "--bri", "100%"]));
+    assert_success(&env.run(&["off", "desk"]));
+    assert_success(&env.run(&["snapshot", "save", "night"]));
+    assert_success(&env.run(&["preset", "day"]));
+    assert_eq!(env.bridge.state().lights["1"]["state"]["on"], true);
+    assert_eq!(env.bridge.state().lights["2"]["state"]["on"], false);
+
+    let output = env.run(&[
+        "preset",
+        "day",
+        "--crossfade-to",
+        "night",
+        "--duration",
+        "2s",
+    ]);
+
+    assert_success(&output);
+    let state = env.bridge.state();
+    assert_eq!(state.lights["1"]["state"]["on"], false);
+    assert_eq!(state.lights["2"]["state"]["on"], true);
+    assert_eq!(state.lights["2"]["state"]["bri"], 254);
+    assert!(
+        env.run(&["preset", "day", "--duration", "2s"])
+            .status
+            .code()
+            != Some(0)
+    );
+}
+
+#[test]
+fn export_is_deterministic() {
+    let env = Env::paired();
+
+    let first = stdout(&env.run(&["export", "--pretty"]));
+    let user = format!("/api/{}", USERNAME);
+    let paths: Vec<_> = env
+        .bridge
+        .requests("GET")
+        .into_iter()
+        .map(|r| r.path)
+        .collect();
+    // Everything comes from the full state, in one request.
+    assert_eq!(paths.iter().filter(|p| p.starts_with(&user)).count(), 1);
+    let second = stdout(&env.run(&["export", "--pretty"]));
+
+    assert_eq!(first, second);
+    let export: serde_json::Value = serde_json::from_str(&first).unwrap();
+    let keys: Vec<_> = export.as_object().unwrap().keys().collect();
+    assert_eq!(keys, vec!["groups", "lights", "rules", "scenes"]);
+    assert_eq!(export["lights"]["3"]["name"], "Hall");
+    assert!(export["lights"]["3"].get("state").is_none());
+    assert_success(&env.run(&["export", "--format", "toml"]));
+}
+
+#[test]
+fn sections_missing_from_the_full_state_are_empty() {
+    let env = Env::paired();
+    env.bridge.state().deconz = true;
+
+    assert_success(&env.run(&["lights"]));
+    let output = env.run(&["export"]);
+    assert_success(&output);
+    let export: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(export["scenes"], json!({}));
+    assert_eq!(export["rules"], json!({}));
+    assert_eq!(export["lights"]["3"]["name"], "Hall");
+}
+
+#[test]
+fn watching_exports_keeps_metrics() {
+    let dir = tempfile::tempdir().unwrap();
+    let metrics = dir.path().join("blilys.prom");
+    let env = Env::paired_with(&format!("metrics = {:?}", metrics));
+    let output = dir.path().join("export.json");
+    let output = output.to_str().unwrap();
+
+    let mut export = env.spawn(&["export", "--watch", "-o", output, "-i", "100ms"]);
+    wait_for(|| {
+        fs::read_to_string(&metrics)
+            .is_ok_and(|text| !text.contains("\nblilys_request_duration_seconds_count 0\n"))
+    });
+    export.kill().unwrap();
+    export.wait().unwrap();
+
+    let text = fs::read_to_string(&metrics).unwrap();
+    assert!(text.contains("# TYPE blilys_request_duration_seconds histogram"));
+    assert!(
+        text.contains("\nblilys_request_retries_total 0\n"),
+        "{}",
+        text
+    );
+}
+
+#[test]
+fn staggered_modes_carry_on_without_failing_lights_unless_strict() {
+    let env = Env::paired();
+    env.bridge.state().broken_lights.push("2".to_owned());
+    let args = [
+        "group",
+        "office",
+        "mode",
+        "-d",
+        "1s",
+        "--stagger",
+        "breathe",
+    ];
+
+    let output = env.run(&args);
+    assert_success(&output);
+    assert!(stderr(&output).contains("Failed to set light 2"));
+    let light = format!("/api/{}/lights/1/state", USERNAME);
+    let puts = env.bridge.requests("PUT");
+    assert!(puts.iter().filter(|put| put.path == light).count() > 1);
+
+    let output = env.run(&[&["--strict"], &args[..]].concat());
+    assert_failure(&output, "Failed to set light 2");
+
+    env.bridge.state().broken_lights.push("1".to_owned());
+    assert_failure(&env.run(&args), "Failed to set every light");
+}
+
+#[test]
+fn apply_reconciles_groups_and_aliases() {
+    let env = Env::paired_with("[aliases]\nold = \"light:2\"");
+    let file = env.home.path().join("desired.toml");
+    std::fs::write(
+        &file,
+        "[aliases]\ndesk = \"light:1\"\n\n\
+         [groups.Office]\nlights = [\"1\", \"3\"]\n\n\
+         [groups.Bedroom]\nlights = [\"2\"]\n",
+    )
+    .unwrap();
+    let file = file.to_str().unwrap();
+
+    let output = env.run(&["apply", file, "--prune", "--dry-run"]);
+    assert_success(&output);
+    assert_eq!(
+        stdout(&output),
+        "+ alias \"desk\"\n- alias \"old\"\n+ group \"Bedroom\"\n~ group \"Office\": lights\n"
+    );
+    assert!(env.bridge.requests("PUT").is_empty());
+
+    assert_success(&env.run(&["apply", file, "--prune", "--yes"]));
+    let state = env.bridge.state();
+    assert_eq!(state.groups["1"]["lights"], json!(["1", "3"]));
+    assert_eq!(state.groups["2"]["name"], "Bedroom");
+    drop(state);
+    let config = std::fs::read_to_string(env.config_path()).unwrap();
+    assert!(config.contains("desk = \"light:1\""));
+    assert!(!config.contains("old ="));
+}
+
+#[test]
+fn applying_scenes_with_light_states_twice_changes_nothing() {
+    let env = Env::paired();
+    let file = env.home.path().join("desired.toml");
+    std::fs::write(
+        &file,
+        "[scenes.Evening]\nlights = [\"1\"]\n\
+         [scenes.Evening.lightstates.1]\non = true\nbri = 100\n",
+    )
+    .unwrap();
+    let file = file.to_str().unwrap();
+
+    assert_success(&env.run(&["apply", file, "--yes"]));
+    assert_eq!(
+        env.bridge.state().scenes["1"]["lightstates"]["1"]["bri"],
+        json!(100)
+    );
+    let output = env.run(&["apply", file, "--dry-run"]);
+    assert_success(&output);
+    assert_eq!(stdout(&output), "");
+    assert!(stderr(&output).contains("Nothing to change."));
+}
+
+#[test]
+fn applying_fails_on_several_resources_with_the_wanted_name() {
+    let env = Env::paired();
+    let office = env.bridge.state().groups["1"].clone();
+    env.bridge.state().groups.insert("2".to_owned(), office);
+    let file = env.home.path().join("desired.toml");
+    std::fs::write(&file, "[groups.Office]\nlights = [\"1\"]\n").unwrap();
+
+    assert_failure(
+        &env.run(&["apply", file.to_str().unwrap(), "--yes"]),
+        "several groups named \"Office\", with IDs 1, 2",
+    );
+    assert!(env.bridge.requests("PUT").is_empty());
+}
+
+#[test]
+fn staggered_modes_drive_each_light_of_the_group() {
+    let env = Env::paired();
+
+    let output = env.run(&[
+        "group",
+        "office",
+        "mode",
+        "--duration",
+        "1s",
+        "--stagger",
+        "breathe",
+    ]);
+
+    assert_success(&output);
+    let puts = env.bridge.requests("PUT");
+    assert_eq!(puts[0].path, format!("/api/{}/lights/1/state", USERNAME));
+    assert_eq!(puts[1].path, format!("/api/{}/lights/2/state", USERNAME));
+    // The second light starts half a cycle later, at the top of the pulse.
+    assert_eq!(puts[0].body.as_ref().unwrap()["bri"], 25);
+    assert_eq!(puts[1].body.as_ref().unwrap()["bri"], 254);
+}
+
+#[test]
+fn staggering_needs_a_cyclic_mode() {
+    let env = Env::paired();
+
+    let output = env.run(&["group", "office", "mode", "--stagger", "halloween"]);
+
+    assert_failure(&output, "The halloween mode can't be staggered");
+}
+
+#[test]
+fn modes_use_colors_from_palette() {
+    let env = Env::paired_with("[palette.alert]\ncolors = [\"red\"]");
+
+    let output = env.run(&[
+        "light",
+        "desk",
+        "mode",
+        "-d",
+        "1s",
+        "--palette",
+        "alert",
+        "breathe",
+    ]);
+
+    assert_success(&output);
+    let puts = env.bridge.requests("PUT");
+    assert_eq!(
+        puts[0].body.as_ref().unwrap()["xy"],
+        json!([0.7006, 0.2993])
+    );
+    assert_failure(
+        &env.run(&["light", "desk", "mode", "--palette", "nope", "breathe"]),
+        "No palette named \"nope\"",
+    );
+}
+
+#[test]
+fn scene_schedule_creates_bridge_schedule() {
+    let env = Env::paired();
+    env.bridge.state().scenes.insert(
+        "abc123".to_owned(),
+        json!({
+            "name": "Energize",
+            "type": "LightScene",
+            "lights": ["1", "2"],
+            "owner": USERNAME,
+            "recycle": false,
+            "locked": false,
+        }),
+    );
+
+    assert_success(&env.run(&["scene", "energize", "recall"]));
+    let output = env.run(&[
+        "scene", "energize", "schedule", "--at", "07:00", "--days", "mon-fri",
+    ]);
+
+    assert_success(&output);
+    let puts = env.bridge.requests("PUT");
+    assert_eq!(puts[0].path, format!("/api/{}/groups/0/action", USERNAME));
+    assert_eq!(puts[0].body, Some(json!({"scene": "abc123"})));
+    let state = env.bridge.state();
+    let schedule = &state.schedules["1"];
+    assert_eq!(schedule["localtime"], "W124/T07:00:00");
+    assert_eq!(schedule["command"]["body"], json!({"scene": "abc123"}));
+}
+
+#[test]
+fn scene_random_recalls_a_scene_matching_the_filters() {
+    let env = Env::paired();
+    let scenes = [
+        ("a1", "Relax evening", json!(["1", "2"]), false),
+        ("a2", "Relax hall", json!(["3"]), false),
+        ("a3", "Energize", json!(["1", "2"]), false),
+        ("a4", "Relax draft", json!(["1"]), true),
+    ];
+    for (id, name, lights, recycle) in scenes.iter() {
+        env.bridge.state().scenes.insert(
+            id.to_string(),
+            json!({
+                "name": name,
+                "type": "LightScene",
+                "lights": lights,
+                "owner": USERNAME,
+                "recycle": recycle,
+                "locked": false,
+            }),
+        );
+    }
+
+    let output = env.run(&["scene", "random", "--group", "office", "--match", "relax*"]);
+
+    assert_success(&output);
+    assert!(stderr(&output).contains("Recalled \"Relax evening\"."));
+    let puts = env.bridge.requests("PUT");
+    assert_eq!(puts[0].path, format!("/api/{}/groups/0/action", USERNAME));
+    assert_eq!(puts[0].body, Some(json!({"scene": "a1"})));
+    assert_failure(
+        &env.run(&["scene", "random", "--match", "night*"]),
+        "No scenes to pick from",
+    );
+    assert_failure(
+        &env.run(&["scene", "energize", "random"]),
+        "A random scene is picked",
+    );
+}
+
+#[test]
+fn scene_show_lists_colors_without_recalling() {
+    let env = Env::paired();
+    env.bridge.state().scenes.insert(
+        "abc123".to_owned(),
+        json!({
+            "name": "Relax",
+            "type": "LightScene",
+            "lights": ["1", "2", "3"],
+            "owner": USERNAME,
+            "recycle": false,
+            "locked": false,
+            "lightstates": {
+                "1": {"on": true, "bri": 127, "ct": 447},
+                "2": {"on": true, "bri": 254, "xy": [0.6915, 0.3083]},
+                "3": {"on": false},
+            },
+        }),
+    );
+
+    let output = env.run(&["scene", "relax", "show"]);
+
+    assert_success(&output);
+    assert_eq!(
+        stdout(&output),
+        "1: Desk    █████░░░░░  50% #ff942b\n\
+         2: Kitchen ██████████ 100% #ff2700\n\
+         3: Hall    off\n"
+    );
+    assert!(env.bridge.requests("PUT").is_empty());
+}
+
+#[test]
+fn scenes_export_and_import_with_lights_by_name() {
+    let env = Env::paired();
+    env.bridge.state().scenes.insert(
+        "abc123".to_owned(),
+        json!({
+            "name": "Relax",
+            "type": "LightScene",
+            "lights": ["1", "3"],
+            "owner": USERNAME,
+            "recycle": false,
+            "locked": false,
+            "lightstates": {
+                "1": {"on": true, "bri": 127, "ct": 447},
+                "3": {"on": false},
+            },
+        }),
+    );
+    let output = env.run(&["scene", "relax", "export"]);
+    assert_success(&output);
+    let file = env.home.path().join("relax.json");
+    fs::write(&file, stdout(&output)).unwrap();
+    let file = file.to_str().unwrap();
+
+    assert_success(&env.run(&["scene", "Evening", "import", file]));
+    // Hall is renamed, so it must be remapped, here to Kitchen, and Desk is left out.
+    env.bridge.state().lights["3"]["name"] = json!("Porch");
+    assert_failure(
+        &env.run(&["scene", "Night", "import", file]),
+        "No light named \"Hall\"",
+    );
+    let output = env.run_with_input(
+        &["scene", "Night", "import", file, "--remap"],
+        "-\nkitchen\n",
+    );
+
+    assert_success(&output);
+    let state = env.bridge.state();
+    assert_eq!(state.scenes["1"]["name"], "Evening");
+    assert_eq!(state.scenes["1"]["lights"], json!(["1", "3"]));
+    assert_eq!(state.scenes["1"]["lightstates"]["1"]["ct"], 447);
+    assert_eq!(state.scenes["2"]["name"], "Night");
+    assert_eq!(
+        state.scenes["2"]["lightstates"],
+        json!({"2": {"on": false}})
+    );
+}
+
+#[test]
+fn accessories_lists_switches_with_their_rules() {
+    let env = Env::paired();
+    {
+        let mut state = env.bridge.state();
+        state.sensors.insert(
+            "2".to_owned(),
+            json!({"name": "Hall dimmer", "type": "ZLLSwitch", "modelid": "RWL021"}),
+        );
+        state.sensors.insert(
+            "3".to_owned(),
+            json!({"name": "Daylight", "type": "Daylight", "modelid": "PHDL00"}),
+        );
+        state.rules.insert(
+            "5".to_owned(),
+            json!({
+                "name": "Dimmer on",
+                "conditions": [
+                    {"address": "/sensors/2/state/buttonevent", "operator": "eq", "value": "1002"},
+                    {"address": "/sensors/2/state/lastupdated", "operator": "dx"},
+                ],
+                "actions": [
+                    {"address": "/groups/1/action", "method": "PUT", "body": {"on": true}},
+                ],
+            }),
+        );
+        state.resourcelinks.insert(
+            "7".to_owned(),
+            json!({"name": "Hall dimmer", "links": ["/sensors/2", "/rules/5"]}),
+        );
+    }
+
+    let output = env.run(&["accessories"]);
+
+    assert_success(&output);
+    assert_eq!(
+        stdout(&output),
+        " 2: Hall dimmer (RWL021, ZLLSwitch)\n    \
+         rule 5 \"Dimmer on\": buttonevent eq 1002 and lastupdated dx -> \
+         PUT /groups/1/action {\"on\":true}\n    \
+         resourcelink 7 \"Hall dimmer\"\n"
+    );
+}
+
+#[test]
+fn accessory_bind_creates_rule() {
+    let env = Env::paired();
+    env.bridge.state().sensors.insert(
+        "2".to_owned(),
+        json!({"name": "Hall dimmer", "type": "ZLLSwitch", "modelid": "RWL021"}),
+    );
+
+    let output = env.run(&[
+        "accessory",
+        "hall dimmer",
+        "bind",
+        "--button",
+        "2",
+        "--action",
+        "group:office brighter 20%",
+    ]);
+
+    assert_success(&output);
+    let state = env.bridge.state();
+    let rule = &state.rules["1"];
+    assert_eq!(
+        rule["conditions"][0]["address"],
+        "/sensors/2/state/buttonevent"
+    );
+    assert_eq!(rule["conditions"][0]["value"], "2002");
+    assert_eq!(
+        rule["actions"],
+        json!([{"address": "/groups/1/action", "method": "PUT", "body": {"bri_inc": 51}}])
+    );
+}
+
+#[test]
+fn sensor_config_updates_motion_and_light_level_sensors() {
+    let env = Env::paired();
+    {
+        let mut state = env.bridge.state();
+        state.sensors.insert(
+            "4".to_owned(),
+            json!({
+                "name": "Hall motion",
+                "type": "ZLLPresence",
+                "uniqueid": "00:17:88:01:02:03:04:05-02-0406",
+                "config": {"on": true, "sensitivity": 0, "sensitivitymax": 2},
+            }),
+        );
+        state.sensors.insert(
+            "5".to_owned(),
+            json!({
+                "name": "Hall light level",
+                "type": "ZLLLightLevel",
+                "uniqueid": "00:17:88:01:02:03:04:05-02-0400",
+                "config": {"on": true, "tholddark": 16000},
+            }),
+        );
+    }
+
+    let output = env.run(&[
+        "sensor",
+        "hall motion",
+        "config",
+        "--sensitivity",
+        "high",
+        "--off",
+        "--daylight-threshold",
+        "100",
+    ]);
+
+    assert_success(&output);
+    let state = env.bridge.state();
+    assert_eq!(
+        state.sensors["4"]["config"],
+        json!({"on": false, "sensitivity": 2, "sensitivitymax": 2})
+    );
+    assert_eq!(state.sensors["5"]["config"]["tholddark"], 20001);
+}
+
+#[test]
+fn nightlight_automation_creates_on_and_off_rules() {
+    let env = Env::paired();
+    env.bridge.state().sensors.insert(
+        "4".to_owned(),
+        json!({
+            "name": "Hall motion",
+            "type": "ZLLPresence",
+            "uniqueid": "00:17:88:01:02:03:04:05-02-0406",
+        }),
+    );
+
+    let output = env.run(&[
+        "automation",
+        "nightlight",
+        "--sensor",
+        "hall motion",
+        "--light",
+        "light:hall",
+        "--between",
+        "23:00-06:00",
+    ]);
+
+    assert_success(&output);
+    let state = env.bridge.state();
+    let (on, off) = (&state.rules["1"], &state.rules["2"]);
+    assert_eq!(
+        on["actions"][0],
+        json!({"address": "/lights/3/state", "method": "PUT", "body": {"on": true, "bri": 25}})
+    );
+    assert_eq!(on["conditions"][2]["value"], "T23:00:00/T06:00:00");
+    assert_eq!(off["conditions"][1]["value"], "PT00:01:00");
+    assert_eq!(off["actions"][0]["body"], json!({"on": false}));
+}
+
+#[test]
+fn automations_are_tracked_for_listing_and_removal() {
+    let env = Env::paired();
+    env.bridge.state().sensors.insert(
+        "4".to_owned(),
+        json!({"name": "Hall motion", "type": "ZLLPresence", "uniqueid": "00:17:88:01-02-0406"}),
+    );
+
+    let output = env.run(&[
+        "automation",
+        "off-when-no-motion",
+        "--sensor",
+        "4",
+        "--light",
+        "office",
+        "--after",
+        "15m",
+    ]);
+    assert_success(&output);
+    assert_eq!(
+        env.bridge.state().rules["1"]["conditions"][1]["value"],
+        "PT00:15:00"
+    );
+
+    let output = env.run(&["automation", "list"]);
+    assert_success(&output);
+    assert_eq!(
+        stdout(&output),
+        " 1: blilys off when no motion - office off without motion at 4 (/rules/1)\n"
+    );
+
+    assert_success(&env.run(&["automation", "remove", "1"]));
+    let state = env.bridge.state();
+    assert!(state.rules.is_empty());
+    assert!(state.resourcelinks.is_empty());
+}
+
+#[test]
+fn ctl_and_signals_control_lights_through_daemon() {
+    let env = Env::paired_with("default_target = \"light:kitchen\"");
+    assert_failure(&env.run(&["ctl", "toggle"]), "The daemon isn't running");
+
+    let mut daemon = env.spawn(&["daemon"]);
+    let socket = env.home.path().join(".local/share/blilys/daemon.sock");
+    wait_for(|| socket.exists());
+
+    assert_success(&env.run(&["ctl", "light", "desk", "off"]));
+    assert_eq!(env.bridge.state().lights["1"]["state"]["on"], json!(false));
+    assert_failure(
+        &env.run(&["ctl", "lights"]),
+        "Only commands controlling lights",
+    );
+
+    let status = Command::new("kill")
+        .args(["-USR1", &daemon.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    wait_for(|| env.bridge.state().lights["2"]["state"]["on"] == json!(true));
+
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+}
+
+#[test]
+fn daemon_resumes_effects_after_a_crash() {
+    let env = Env::paired_with("[daemon]\neffects = [\"light Desk mode breathe --period 2s\"]");
+    let effects = env.home.path().join(".local/share/blilys/effects.json");
+    let breaths = || {
+        env.bridge
+            .requests("PUT")
+            .iter()
+            .filter(|request| request.path.ends_with("/lights/1/state"))
+            .count()
+    };
+
+    let mut daemon = env.spawn(&["daemon"]);
+    wait_for(|| breaths() > 0);
+    let saved = fs::read_to_string(&effects).unwrap();
+    assert!(saved.contains("light Desk mode breathe"), "{}", saved);
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+
+    let before = breaths();
+    let mut daemon = env.spawn(&["daemon"]);
+    wait_for(|| breaths() > before);
+    assert_eq!(fs::read_to_string(&effects).unwrap(), saved);
+
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+}
+
+#[test]
+fn daemon_runs_schedules_missed_while_it_was_stopped_by_policy() {
+    let env = Env::paired_with(
+        "[[schedules]]\nat = \"00:00\"\ncommand = \"light hall on\"\n\
+         [[schedules]]\nat = \"00:00\"\ncommand = \"light desk off\"\nmissed = \"run_once_late\"",
+    );
+    let data = env.home.path().join(".local/share/blilys");
+    fs::create_dir_all(&data).unwrap();
+    let two_days_ago = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        - 2 * 24 * 60 * 60;
+    fs::write(
+        data.join("schedules.json"),
+        format!("{{\"last_check\":{}}}", two_days_ago),
+    )
+    .unwrap();
+
+    let mut daemon = env.spawn(&["daemon"]);
+    wait_for(|| env.bridge.state().lights["1"]["state"]["on"] == json!(false));
+    assert_eq!(env.bridge.state().lights["3"]["state"]["on"], json!(false));
+
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+}
+
+#[test]
+fn daemon_picks_up_changes_to_the_config() {
+    let env = Env::paired_with("[aliases]\nlamp = \"light:1\"");
+    let socket = env.home.path().join(".local/share/blilys/daemon.sock");
+    let mut daemon = env.spawn(&["daemon"]);
+    wait_for(|| socket.exists());
+    assert_success(&env.run(&["ctl", "light", "lamp", "off"]));
+    assert_eq!(env.bridge.state().lights["1"]["state"]["on"], json!(false));
+
+    env.write_config(&format!(
+        "version = 3\n[aliases]\nlamp = \"light:3\"\n[bridge]\nhost = {:?}\nusername = {:?}\n",
+        env.bridge.host(),
+        USERNAME
+    ));
+    wait_for(|| {
+        assert_success(&env.run(&["ctl", "light", "lamp", "on"]));
+        env.bridge.state().lights["3"]["state"]["on"] == json!(true)
+    });
+
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+}
+
+#[test]
+fn daemon_records_history_when_enabled() {
+    let env = Env::paired_with("[history]\nrecord = true\ninterval = \"1s\"");
+    let mut daemon = env.spawn(&["daemon"]);
+
+    wait_for(|| stdout(&env.run(&["history", "Desk"])).contains("[on ] [bri 200]"));
+
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+}
+
+#[test]
+fn daemon_stops_effects_and_restores_lights_on_sigterm() {
+    let env = Env::paired_with(
+        "[daemon]\neffects = [\"light Desk mode breathe --period 2s\"]\nrestore = \"calm\"",
+    );
+    assert_success(&env.run(&["light", "desk", "on", "--bri", "50"]));
+    assert_success(&env.run(&["snapshot", "save", "calm"]));
+    let socket = env.home.path().join(".local/share/blilys/daemon.sock");
+
+    let mut daemon = env.spawn(&["daemon"]);
+    wait_for(|| env.bridge.state().lights["1"]["state"]["bri"] != json!(50));
+    let status = Command::new("kill")
+        .args(["-TERM", &daemon.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert!(daemon.wait().unwrap().success());
+
+    assert_eq!(env.bridge.state().lights["1"]["state"]["bri"], json!(50));
+    assert!(!socket.exists());
+    // The effect is resumed when the daemon starts again.
+    let effects = env.home.path().join(".local/share/blilys/effects.json");
+    assert!(fs::read_to_string(effects).unwrap().contains("breathe"));
+}
+
+#[test]
+fn daemon_brightens_lights_when_the_room_is_dark() {
+    let env = Env::paired_with(
+        "[adaptive.\"light:Desk\"]\nsensor = \"Office sensor\"\nlux = 300\ninterval = \"1s\"",
+    );
+    // 100 lux, in the bridge's unit of 10000 * log10(lux) + 1.
+    env.bridge.state().sensors.insert(
+        "4".to_owned(),
+        json!({
+            "name": "Office sensor",
+            "type": "ZLLLightLevel",
+            "config": {"reachable": true},
+            "state": {"lightlevel": 20001},
+        }),
+    );
+
+    let mut daemon = env.spawn(&["daemon"]);
+    wait_for(|| env.bridge.state().lights["1"]["state"]["bri"] == json!(254));
+
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+}
+
+#[test]
+fn light_commands_are_forwarded_to_daemon() {
+    let env = Env::paired_with("[daemon]\nforward = true");
+    let mut daemon = env.spawn(&["daemon"]);
+    let socket = env.home.path().join(".local/share/blilys/daemon.sock");
+    wait_for(|| socket.exists());
+
+    // Only the daemon can reach the bridge now.
+    env.write_config(&format!(
+        "version = 3\n[daemon]\nforward = true\n[bridge]\nhost = \"127.0.0.1:9\"\n\
+         username = {:?}\nid = {:?}\n",
+        USERNAME, BRIDGE_ID
+    ));
+    assert_success(&env.run(&["light", "desk", "off"]));
+    assert_eq!(env.bridge.state().lights["1"]["state"]["on"], json!(false));
+    assert!(!env
+        .run(&["--no-daemon", "light", "desk", "on"])
+        .status
+        .success());
+    assert_eq!(env.bridge.state().lights["1"]["state"]["on"], json!(false));
+
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+}
+
+#[test]
+fn ci_light_needs_owner_and_repo() {
+    let env = Env::paired();
+
+    let output = env.run(&["ci-light", "--github", "blilys", "--light", "1"]);
+    assert_failure(&output, "expected owner/repo");
+}
+
+#[test]
+fn systemd_units_are_installed_and_uninstalled() {
+    let env = Env::paired();
+    let units = env.home.path().join(".config/systemd/user");
+
+    let output = env.run(&[
+        "systemd",
+        "install",
+        "--daemon",
+        "--timer",
+        "0 22 * * 1-5 all off",
+        "--no-enable",
+    ]);
+    assert_success(&output);
+    let daemon = fs::read_to_string(units.join("blilys-daemon.service")).unwrap();
+    assert!(daemon.contains("blilys daemon\n"), "{}", daemon);
+    let timer = fs::read_to_string(units.join("blilys-all-off.timer")).unwrap();
+    assert!(
+        timer.contains("OnCalendar=Mon..Fri *-*-* 22:00:00\n"),
+        "{}",
+        timer
+    );
+    let service = fs::read_to_string(units.join("blilys-all-off.service")).unwrap();
+    assert!(service.contains("blilys all off\n"), "{}", service);
+
+    assert_success(&env.run(&["systemd", "uninstall", "--no-disable"]));
+    assert_eq!(fs::read_dir(&units).unwrap().count(), 0);
+}
+
+#[test]
+fn launch_agents_are_installed_and_uninstalled() {
+    let env = Env::paired();
+    let agents = env.home.path().join("Library/LaunchAgents");
+
+    let output = env.run(&[
+        "launchd",
+        "install",
+        "--daemon",
+        "--export",
+        "/tmp/bridge.json",
+        "--no-load",
+    ]);
+    assert_success(&output);
+    let daemon = fs::read_to_string(agents.join("com.github.jodal.blilys.daemon.plist")).unwrap();
+    assert!(daemon.contains("<string>daemon</string>"), "{}", daemon);
+    assert!(daemon.contains("daemon.err.log</string>"), "{}", daemon);
+    let export = fs::read_to_string(agents.join("com.github.jodal.blilys.export.plist")).unwrap();
+    let arguments = "<string>--watch</string>\n        \
+                     <string>--output</string>\n        \
+                     <string>/tmp/bridge.json</string>";
+    assert!(export.contains(arguments), "{}", export);
+
+    assert_success(&env.run(&["launchd", "uninstall", "--no-unload"]));
+    assert_eq!(fs::read_dir(&agents).unwrap().count(), 0);
+}
+
+#[test]
+fn bare_commands_fail_without_default_target() {
+    let env = Env::paired();
+
+    let output = env.run(&["off"]);
+
+    assert_failure(&output, "set default_target");
+}
+
+#[test]
+fn shorthand_resolves_bare_names_to_groups_then_lights() {
+    let env = Env::paired();
+
+    assert_success(&env.run(&["off", "office"]));
+    assert_success(&env.run(&["on", "hall", "--bri", "10%"]));
+
+    let puts: Vec<String> = env
+        .bridge
+        .requests("PUT")
+        .into_iter()
+        .map(|r| r.path)
+        .collect();
+    assert_eq!(
+        puts,
+        vec![
+            format!("/api/{}/groups/1/action", USERNAME),
+            format!("/api/{}/lights/3/state", USERNAME),
+        ]
+    );
+}
+
+#[test]
+fn toggle_turns_on_lights_off() {
+    let env = Env::paired();
+
+    assert_success(&env.run(&["toggle", "light:desk"]));
+    assert_success(&env.run(&["toggle", "light:kitchen"]));
+
+    let state = env.bridge.state();
+    assert_eq!(state.lights["1"]["state"]["on"], json!(false));
+    assert_eq!(state.lights["2"]["state"]["on"], json!(true));
+}
+
+#[test]
+fn commands_from_config_are_expanded() {
+    let env = Env::paired();
+    let config = fs::read_to_string(env.config_path()).unwrap();
+    env.write_config(&format!(
+        "{}\n[commands]\nbright = \"all on --bri '100%'\"\n",
+        config
+    ));
+
+    let output = env.run(&["bright", "--transition", "0s"]);
+
+    assert_success(&output);
+    let puts = env.bridge.requests("PUT");
+    assert_eq!(puts[0].path, format!("/api/{}/groups/0/action", USERNAME));
+    assert_eq!(
+        puts[0].body,
+        Some(json!({"on": true, "bri": 254, "transitiontime": 0}))
+    );
+
+    let output = env.run(&["nosuchcommand"]);
+    assert_failure(&output, "unrecognized subcommand 'nosuchcommand'");
+}
+
+#[test]
+fn unknown_light_name_fails() {
+    let env = Env::paired();
+
+    let output = env.run(&["light", "Attic", "on"]);
+
+    assert_failure(&output, "No light named \"Attic\"");
+    assert!(env.bridge.requests("PUT").is_empty());
+}
+
+#[test]
+fn unknown_light_id_reports_bridge_error() {
+    let env = Env::paired();
+
+    let output = env.run(&["light", "9", "off"]);
+
+    assert_failure(&output, "error code 3");
+}
+
+#[test]
+fn unauthorized_user_reports_bridge_error() {
+    let env = Env::paired();
+    env.bridge.state().usernames.clear();
+
+    let output = env.run(&["lights"]);
+
+    assert_failure(&output, "unauthorized user");
+}
+
+#[test]
+fn refuses_to_send_credentials_to_another_bridge() {
+    let env = Env::paired();
+    env.bridge.state().config["bridgeid"] = json!("001788FFFE0000FF");
+
+    let output = env.run(&["lights"]);
+
+    assert_failure(&output, "Refusing to send credentials");
+    assert!(env
+        .bridge
+        .state()
+        .requests
+        .iter()
+        .all(|r| !r.path.contains(USERNAME)));
+}
+
+#[test]
+fn replays_recorded_trace_without_a_bridge() {
+    let env = Env::paired();
+    let trace = env.home.path().join("trace.jsonl");
+    let trace = trace.to_str().unwrap();
+
+    let recorded = env.run(&["--record", trace, "lights"]);
+    assert_success(&recorded);
+    let contents = fs::read_to_string(trace).unwrap();
+    assert!(contents.contains("/api/<username>"));
+    assert!(!contents.contains(USERNAME));
+
+    let requests = env.bridge.state().requests.len();
+    let replayed = env.run(&["--replay", trace, "lights"]);
+    assert_success(&replayed);
+    assert_eq!(stdout(&replayed), stdout(&recorded));
+    assert_eq!(env.bridge.state().requests.len(), requests);
+}
+
+#[test]
+fn replay_fails_on_unrecorded_request() {
+    let env = Env::paired();
+    let trace = env.home.path().join("trace.jsonl");
+    let trace = trace.to_str().unwrap();
+    assert_success(&env.run(&["--record", trace, "lights"]));
+
+    let output = env.run(&["--replay", trace, "light", "1", "off"]);
+
+    assert_failure(&output, "no more responses to PUT /api/_/lights/1/state");
+}
@@ -0,0 +1,412 @@
+//! A mock Hue bridge speaking the v1 REST API over HTTP, for running blilys end to end without
+//! hardware. The datastore can be inspected and changed by tests while the bridge is running.
+
+use serde_json::{json, Map, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+pub const BRIDGE_ID: &str = "001788FFFE000001";
+pub const USERNAME: &str = "mockuser";
+
+/// A request received by the mock bridge.
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub body: Option<Value>,
+}
+
+/// The bridge's datastore.
+#[derive(Debug)]
+pub struct State {
+    pub config: Value,
+    pub lights: Map<String, Value>,
+    pub groups: Map<String, Value>,
+    pub scenes: Map<String, Value>,
+    pub sensors: Map<String, Value>,
+    pub rules: Map<String, Value>,
+    pub schedules: Map<String, Value>,
+    pub resourcelinks: Map<String, Value>,
+    /// Usernames allowed to use the API.
+    pub usernames: Vec<String>,
+    /// Whether pairing succeeds, as if the link button was just pressed.
+    pub link_button: bool,
+    /// Presses the link button after this many failed pairing attempts.
+    pub press_link_button_after: Option<u32>,
+    /// Every request received, oldest first.
+    pub requests: Vec<Request>,
+    /// IDs of lights whose state can't be set, as if they were unreachable.
+    pub broken_lights: Vec<String>,
+    /// Whether the full state leaves out scenes and rules, like deCONZ's does.
+    pub deconz: bool,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        let json = |value: Value| match value {
+            Value::Object(map) => map,
+            _ => unreachable!(),
+        };
+        State {
+            config: json!({
+                "name": "Mock",
+                "bridgeid": BRIDGE_ID,
+                "apiversion": "1.50.0",
+                "mac": "00:17:88:00:00:01",
+                "modelid": "BSB002",
+            }),
+            lights: json(json!({
+                "1": light("Desk", "LCT007", json!({"on": true, "bri": 200, "hue": 100, "sat": 3})),
+                "2": light("Kitchen", "LTW001", json!({"on": false, "bri": 10})),
+                "3": light("Hall", "LWB010", json!({"on": false, "bri": 254})),
+            })),
+            groups: json(json!({
+                "1": {
+                    "name": "Office",
+                    "lights": ["1", "2"],
+                    "sensors": [],
+                    "type": "Room",
+                    "state": {"all_on": false, "any_on": true},
+                    "recycle": false,
+                    "action": {"on": true, "bri": 200},
+                },
+            })),
+            scenes: Map::new(),
+            sensors: Map::new(),
+            rules: Map::new(),
+            schedules: Map::new(),
+            resourcelinks: Map::new(),
+            usernames: vec![USERNAME.to_owned()],
+            link_button: false,
+            press_link_button_after: None,
+            requests: vec![],
+            broken_lights: vec![],
+            deconz: false,
+        }
+    }
+}
+
+fn light(name: &str, model: &str, state: Value) -> Value {
+    json!({
+        "name": name,
+        "modelid": model,
+        "swversion": "1.0",
+        "uniqueid": format!("00:17:88:01:00:00:00:{:02x}-0b", name.len()),
+        "state": state,
+    })
+}
+
+fn error(kind: u32, address: &str, description: &str) -> Value {
+    json!([{"error": {"type": kind, "address": address, "description": description}}])
+}
+
+pub struct MockBridge {
+    port: u16,
+    state: Arc<Mutex<State>>,
+}
+
+impl MockBridge {
+    /// Starts a mock bridge with a default datastore on a free port on localhost.
+    pub fn start() -> MockBridge {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind mock bridge");
+        let port = listener.local_addr().unwrap().port();
+        let state = Arc::new(Mutex::new(State::default()));
+        let server_state = state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let state = server_state.clone();
+                thread::spawn(move || serve(stream, &state));
+            }
+        });
+        MockBridge { port, state }
+    }
+
+    /// The host to pass to blilys with `--bridge`.
+    pub fn host(&self) -> String {
+        format!("127.0.0.1:{}", self.port)
+    }
+
+    pub fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    /// Returns the received requests with the given method.
+    pub fn requests(&self, method: &str) -> Vec<Request> {
+        self.state()
+            .requests
+            .iter()
+            .filter(|r| r.method == method)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Serves requests on a connection until the client closes it.
+fn serve(stream: TcpStream, state: &Mutex<State>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            return;
+        }
+        let mut parts = line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_owned();
+        let path = parts.next().unwrap_or_default().to_owned();
+
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header).unwrap_or(0) == 0 {
+                return;
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+        let mut body = vec![0; content_length];
+        if reader.read_exact(&mut body).is_err() {
+            return;
+        }
+        let body = serde_json::from_slice(&body).ok();
+
+        let response = {
+            let mut state = state.lock().unwrap();
+            state.requests.push(Request {
+                method: method.clone(),
+                path: path.clone(),
+                body: body.clone(),
+            });
+            route(&mut state, &method, &path, body)
+        };
+        let response = response.to_string();
+        let written = write!(
+            writer,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            response.len(),
+            response
+        );
+        if written.is_err() {
+            return;
+        }
+    }
+}
+
+fn route(state: &mut State, method: &str, path: &str, body: Option<Value>) -> Value {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (method, segments.as_slice()) {
+        ("POST", ["api"]) => {
+            let body = body.unwrap_or_default();
+            if body["devicetype"].as_str().is_none() {
+                return error(5, "/", "invalid/missing parameters in body");
+            }
+            if !state.link_button {
+                if let Some(attempts) = state.press_link_button_after.as_mut() {
+                    *attempts = attempts.saturating_sub(1);
+                    state.link_button = *attempts == 0;
+                }
+                return error(101, "", "link button not pressed");
+            }
+            let username = format!("user{}", state.usernames.len());
+            state.usernames.push(username.clone());
+            if body["generateclientkey"] == json!(true) {
+                json!([{"success": {"username": username, "clientkey": "0123456789ABCDEF"}}])
+            } else {
+                json!([{"success": {"username": username}}])
+            }
+        }
+        ("GET", ["api", "config"]) => state.config.clone(),
+        (_, ["api", username, rest @ ..]) => {
+            if !state.usernames.iter().any(|u| u == username) {
+                return error(1, &format!("/{}", rest.join("/")), "unauthorized user");
+            }
+            route_resource(state, method, rest, body)
+        }
+        _ => error(
+            4,
+            path,
+            &format!("method, {}, not available for resource", method),
+        ),
+    }
+}
+
+fn route_resource(state: &mut State, method: &str, path: &[&str], body: Option<Value>) -> Value {
+    let address = format!("/{}", path.join("/"));
+    let not_found = || {
+        error(
+            3,
+            &address,
+            &format!("resource, {}, not available", address),
+        )
+    };
+    match (method, path) {
+        ("GET", []) if state.deconz => json!({
+            "config": state.config,
+            "lights": state.lights,
+            "groups": state.groups,
+            "sensors": state.sensors,
+            "schedules": state.schedules,
+        }),
+        ("GET", []) => json!({
+            "config": state.config,
+            "lights": state.lights,
+            "groups": state.groups,
+            "scenes": state.scenes,
+            "sensors": state.sensors,
+            "rules": state.rules,
+            "schedules": state.schedules,
+            "resourcelinks": state.resourcelinks,
+        }),
+        ("GET", ["config"]) => state.config.clone(),
+        ("GET", ["lights"]) => Value::Object(state.lights.clone()),
+        ("GET", ["groups"]) => Value::Object(state.groups.clone()),
+        // Like the real bridge, scenes' light states are only given for one scene at a time.
+        ("GET", ["scenes"]) => Value::Object(
+            state
+                .scenes
+                .iter()
+                .map(|(id, scene)| {
+                    let mut scene = scene.clone();
+                    if let Value::Object(attributes) = &mut scene {
+                        attributes.remove("lightstates");
+                    }
+                    (id.clone(), scene)
+                })
+                .collect(),
+        ),
+        ("GET", ["sensors"]) => Value::Object(state.sensors.clone()),
+        ("GET", ["rules"]) => Value::Object(state.rules.clone()),
+        ("GET", ["schedules"]) => Value::Object(state.schedules.clone()),
+        ("GET", ["resourcelinks"]) => Value::Object(state.resourcelinks.clone()),
+        ("GET", ["sensors", id]) => state.sensors.get(*id).cloned().unwrap_or_else(not_found),
+        ("GET", ["lights", id]) => state.lights.get(*id).cloned().unwrap_or_else(not_found),
+        ("GET", ["groups", id]) => state.groups.get(*id).cloned().unwrap_or_else(not_found),
+        ("GET", ["scenes", id]) => state.scenes.get(*id).cloned().unwrap_or_else(not_found),
+        ("PUT", ["lights", id, "state"]) => {
+            let changes = match body {
+                Some(Value::Object(changes)) => changes,
+                _ => return error(2, &address, "body contains invalid json"),
+            };
+            if state.broken_lights.iter().any(|broken| broken == id) {
+                return error(201, &address, "parameter, on, is not modifiable");
+            }
+            match state.lights.get_mut(*id) {
+                Some(light) => update(&mut light["state"], &changes, &address),
+                None => not_found(),
+            }
+        }
+        ("PUT", ["groups", id, "action"]) => {
+            let changes = match body {
+                Some(Value::Object(changes)) => changes,
+                _ => return error(2, &address, "body contains invalid json"),
+            };
+            // Group 0 is a special group containing all lights.
+            let (members, response) = if *id == "0" {
+                let mut action = json!({});
+                let response = update(&mut action, &changes, &address);
+                (state.lights.keys().cloned().collect(), response)
+            } else {
+                let group = match state.groups.get_mut(*id) {
+                    Some(group) => group,
+                    None => return not_found(),
+                };
+                let members: Vec<String> = group["lights"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .filter_map(|l| l.as_str().map(str::to_owned))
+                    .collect();
+                (members, update(&mut group["action"], &changes, &address))
+            };
+            for member in members {
+                if let Some(light) = state.lights.get_mut(&member) {
+                    update(&mut light["state"], &changes, "");
+                }
+            }
+            response
+        }
+        ("PUT", ["sensors", id, "config"]) => {
+            let changes = match body {
+                Some(Value::Object(changes)) => changes,
+                _ => return error(2, &address, "body contains invalid json"),
+            };
+            match state.sensors.get_mut(*id) {
+                Some(sensor) => update(&mut sensor["config"], &changes, &address),
+                None => not_found(),
+            }
+        }
+        ("POST", [kind]) => {
+            let resource = match body {
+                Some(resource @ Value::Object(_)) => resource,
+                _ => return error(2, &address, "body contains invalid json"),
+            };
+            match collection(state, kind) {
+                Some(resources) => {
+                    let next = resources
+                        .keys()
+                        .filter_map(|id| id.parse::<u32>().ok())
+                        .max();
+                    let id = (next.unwrap_or(0) + 1).to_string();
+                    resources.insert(id.clone(), resource);
+                    json!([{"success": {"id": id}}])
+                }
+                None => not_found(),
+            }
+        }
+        ("PUT", [kind, id]) => {
+            let changes = match body {
+                Some(Value::Object(changes)) => changes,
+                _ => return error(2, &address, "body contains invalid json"),
+            };
+            match collection(state, kind).and_then(|resources| resources.get_mut(*id)) {
+                Some(resource) => update(resource, &changes, &address),
+                None => not_found(),
+            }
+        }
+        ("DELETE", [kind, id]) => {
+            match collection(state, kind).and_then(|resources| resources.remove(*id)) {
+                Some(_) => json!([{"success": format!("{} deleted", address)}]),
+                None => not_found(),
+            }
+        }
+        ("GET", _) => not_found(),
+        _ => error(
+            4,
+            &address,
+            &format!(
+                "method, {}, not available for resource, {}",
+                method, address
+            ),
+        ),
+    }
+}
+
+/// Returns the resources that can be created and deleted through the API.
+fn collection<'a>(state: &'a mut State, kind: &str) -> Option<&'a mut Map<String, Value>> {
+    match kind {
+        "groups" => Some(&mut state.groups),
+        "scenes" => Some(&mut state.scenes),
+        "rules" => Some(&mut state.rules),
+        "schedules" => Some(&mut state.schedules),
+        "resourcelinks" => Some(&mut state.resourcelinks),
+        _ => None,
+    }
+}
+
+/// Applies changes to a state object, returning the bridge's response listing each change.
+fn update(state: &mut Value, changes: &Map<String, Value>, address: &str) -> Value {
+    let mut response = vec![];
+    for (key, value) in changes {
+        state[key] = value.clone();
+        response.push(json!({"success": {format!("{}/{}", address, key): value}}));
+    }
+    Value::Array(response)
+}